@@ -0,0 +1,189 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+
+/// Length of the random key audit hashes are keyed with.
+const KEY_LEN: usize = 32;
+
+/// What happened to a clip, as written to the audit log.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AuditAction {
+    Add,
+    Delete,
+    Export,
+}
+
+/// One line of the audit log. Content is only ever recorded as a keyed
+/// hash, so short or guessable clips can't be confirmed by hashing guesses.
+#[derive(Debug, Serialize)]
+struct AuditEvent<'a> {
+    timestamp: DateTime<Utc>,
+    action: AuditAction,
+    clip_id: &'a str,
+    content_hash: String,
+}
+
+/// An append-only JSON lines file recording clip additions, deletions and
+/// exports. Its hashes are keyed with a random key kept next to it (the
+/// log's path with a `.key` extension), created on first use.
+#[derive(Debug, Clone)]
+pub struct AuditLog {
+    path: PathBuf,
+}
+
+impl AuditLog {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    /// Open the log for appending several events at once.
+    pub fn writer(&self) -> Result<AuditWriter> {
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let key = load_or_create_key(&self.path.with_extension("key"))?;
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .with_context(|| format!("Cannot open audit log {}", self.path.display()))?;
+        Ok(AuditWriter {
+            file: BufWriter::new(file),
+            key,
+        })
+    }
+
+    /// Append one event per `(clip_id, content)` pair.
+    pub fn record<'a>(&self, action: AuditAction, clips: impl IntoIterator<Item = (&'a str, &'a str)>) -> Result<()> {
+        let mut clips = clips.into_iter().peekable();
+        if clips.peek().is_none() {
+            return Ok(());
+        }
+        let mut writer = self.writer()?;
+        for (clip_id, content) in clips {
+            writer.write(action, clip_id, content)?;
+        }
+        writer.finish()
+    }
+}
+
+pub struct AuditWriter {
+    file: BufWriter<File>,
+    key: Vec<u8>,
+}
+
+impl AuditWriter {
+    pub fn write(&mut self, action: AuditAction, clip_id: &str, content: &str) -> Result<()> {
+        let event = AuditEvent {
+            timestamp: Utc::now(),
+            action,
+            clip_id,
+            content_hash: keyed_hash(&self.key, content),
+        };
+        serde_json::to_writer(&mut self.file, &event)?;
+        self.file.write_all(b"\n")?;
+        Ok(())
+    }
+
+    /// Flush buffered events; dropping the writer without this loses errors.
+    pub fn finish(mut self) -> Result<()> {
+        self.file.flush()?;
+        Ok(())
+    }
+}
+
+/// The key at `path`, or a new random one written there readable only by
+/// the current user.
+fn load_or_create_key(path: &Path) -> Result<Vec<u8>> {
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    match options.open(path) {
+        Ok(mut file) => {
+            let key: [u8; KEY_LEN] = rand::random();
+            file.write_all(&key)?;
+            Ok(key.to_vec())
+        }
+        // Another writer may have just created it
+        Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+            let mut key = Vec::new();
+            File::open(path)?.read_to_end(&mut key)?;
+            if key.len() != KEY_LEN {
+                anyhow::bail!("Audit key {} is damaged; expected {} bytes", path.display(), KEY_LEN);
+            }
+            Ok(key)
+        }
+        Err(e) => Err(e).with_context(|| format!("Cannot create audit key {}", path.display())),
+    }
+}
+
+/// Hex HMAC-SHA256 of `content` under `key`.
+fn keyed_hash(key: &[u8], content: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC takes keys of any length");
+    mac.update(content.as_bytes());
+    mac.finalize()
+        .into_bytes()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Hex SHA-256 of a clip's content. Unkeyed, so it matches across machines;
+/// the audit log uses a keyed hash instead.
+pub fn content_hash(content: &str) -> String {
+    Sha256::digest(content.as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_log() -> (AuditLog, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("clipq-audit-{}", uuid::Uuid::new_v4()));
+        (AuditLog::new(dir.join("audit.jsonl")), dir)
+    }
+
+    fn hashes(dir: &Path) -> Vec<String> {
+        std::fs::read_to_string(dir.join("audit.jsonl"))
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap()["content_hash"].as_str().unwrap().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn hashes_are_keyed_per_log() {
+        let (log, dir) = temp_log();
+        let (other, other_dir) = temp_log();
+        log.record(AuditAction::Add, [("a", "1234")]).unwrap();
+        log.record(AuditAction::Delete, [("a", "1234")]).unwrap();
+        other.record(AuditAction::Add, [("b", "1234")]).unwrap();
+
+        let ours = hashes(&dir);
+        assert_eq!(ours[0], ours[1]);
+        assert_ne!(ours[0], content_hash("1234"));
+        assert_ne!(ours[0], hashes(&other_dir)[0]);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(dir.join("audit.key")).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        std::fs::remove_dir_all(dir).unwrap();
+        std::fs::remove_dir_all(other_dir).unwrap();
+    }
+}
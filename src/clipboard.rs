@@ -1,57 +1,391 @@
 use anyhow::Result;
 use arboard::Clipboard as ArboardClipboard;
+use encoding_rs::Encoding;
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
 use std::time::Duration;
 use tokio::time::sleep;
 
+use crate::database::Clip;
+
+/// Which system selection to read from. `Primary` (the X11/Wayland
+/// middle-click selection) only exists on Linux and the BSDs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Selection {
+    Clipboard,
+    Primary,
+}
+
+impl Selection {
+    /// Whether this platform has a primary selection at all.
+    pub const PRIMARY_SUPPORTED: bool = cfg!(all(
+        unix,
+        not(any(target_os = "macos", target_os = "android", target_os = "emscripten"))
+    ));
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Selection::Clipboard => "clipboard",
+            Selection::Primary => "primary",
+        }
+    }
+}
+
+impl FromStr for Selection {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "clipboard" => Ok(Selection::Clipboard),
+            "primary" => Ok(Selection::Primary),
+            _ => Err(anyhow::anyhow!(
+                "Unknown selection '{}': expected 'clipboard' or 'primary'",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Selection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// The current clipboard contents, as read by `ClipboardManager::capture`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Capture {
+    Text(String),
+    /// RGBA pixels, four bytes each
+    Image { width: usize, height: usize, rgba: Vec<u8> },
+}
+
+/// Typed data for `clipq set`, decoded from raw bytes by MIME type.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TypedContent {
+    Text(String),
+    Html(String),
+    /// RGBA pixels, four bytes each
+    Image { width: usize, height: usize, rgba: Vec<u8> },
+}
+
+impl TypedContent {
+    /// Decode `bytes` as `mime` (parameters like `; charset=utf-8` are
+    /// ignored). Supported: `text/plain`, `text/html` (both UTF-8) and
+    /// `image/png`.
+    pub fn decode(mime: &str, bytes: Vec<u8>) -> Result<Self> {
+        let essence = mime.split(';').next().unwrap_or_default().trim().to_lowercase();
+        let utf8 = |bytes: Vec<u8>| {
+            String::from_utf8(bytes).map_err(|_| anyhow::anyhow!("{} data must be UTF-8", essence))
+        };
+        match essence.as_str() {
+            "text/plain" => Ok(TypedContent::Text(utf8(bytes)?)),
+            "text/html" => Ok(TypedContent::Html(utf8(bytes)?)),
+            "image/png" => decode_png(&bytes),
+            _ => Err(anyhow::anyhow!(
+                "Unsupported MIME type '{}': expected text/plain, text/html or image/png",
+                mime
+            )),
+        }
+    }
+}
+
+fn decode_png(bytes: &[u8]) -> Result<TypedContent> {
+    let mut decoder = png::Decoder::new(bytes);
+    // Palettes, low bit depths and 16-bit channels all come out as 8-bit
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info()?;
+    let mut buffer = vec![0; reader.output_buffer_size()];
+    let frame = reader.next_frame(&mut buffer)?;
+    buffer.truncate(frame.buffer_size());
+
+    let rgba = match frame.color_type {
+        png::ColorType::Rgba => buffer,
+        png::ColorType::Rgb => buffer.chunks_exact(3).flat_map(|p| [p[0], p[1], p[2], 255]).collect(),
+        png::ColorType::GrayscaleAlpha => buffer.chunks_exact(2).flat_map(|p| [p[0], p[0], p[0], p[1]]).collect(),
+        png::ColorType::Grayscale => buffer.iter().flat_map(|&g| [g, g, g, 255]).collect(),
+        png::ColorType::Indexed => anyhow::bail!("PNG palette was not expanded"),
+    };
+    Ok(TypedContent::Image {
+        width: frame.width as usize,
+        height: frame.height as usize,
+        rgba,
+    })
+}
+
+/// Encode RGBA pixels as PNG, the format image clips are stored in.
+pub fn encode_png(width: usize, height: usize, rgba: &[u8]) -> Result<Vec<u8>> {
+    let mut png = Vec::new();
+    let mut encoder = png::Encoder::new(&mut png, u32::try_from(width)?, u32::try_from(height)?);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(rgba)?;
+    writer.finish()?;
+    Ok(png)
+}
+
+/// Text recovered by `decode_text`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecodedText {
+    pub text: String,
+    /// Name of the fallback encoding used when the bytes weren't UTF-8
+    pub fallback: Option<&'static str>,
+}
+
+/// Decode bytes as UTF-8, or else as `fallback` (the `fallback_encoding`
+/// setting). `None` when neither fits, including when the fallback decodes
+/// the bytes but the result doesn't look like text.
+pub fn decode_text(bytes: Vec<u8>, fallback: Option<&'static Encoding>) -> Option<DecodedText> {
+    let bytes = match String::from_utf8(bytes) {
+        Ok(text) => return Some(DecodedText { text, fallback: None }),
+        Err(e) => e.into_bytes(),
+    };
+    let encoding = fallback?;
+    let (text, had_errors) = encoding.decode_without_bom_handling(&bytes);
+    // Single-byte encodings like windows-1252 accept any bytes at all
+    if had_errors || !is_plausible_text(&text) {
+        return None;
+    }
+    log::info!("Decoded {} bytes of non-UTF-8 text as {}", bytes.len(), encoding.name());
+    Some(DecodedText {
+        text: text.into_owned(),
+        fallback: Some(encoding.name()),
+    })
+}
+
+/// Whether decoded text could be text rather than binary data: no control
+/// characters besides tabs, line breaks and form feeds.
+fn is_plausible_text(text: &str) -> bool {
+    !text.chars().any(|c| c.is_control() && !matches!(c, '\t' | '\n' | '\r' | '\u{c}'))
+}
+
+/// Raised when a clipboard operation is attempted but no system clipboard
+/// can be opened, e.g. on a headless server.
+#[derive(Debug, thiserror::Error)]
+#[error("No clipboard available: {0}")]
+pub struct NoClipboard(String);
+
+/// Clipboard access that connects on first use, so commands that never touch
+/// the clipboard keep working where none exists.
+#[derive(Default)]
 pub struct ClipboardManager {
-    clipboard: ArboardClipboard,
+    clipboard: Option<ArboardClipboard>,
     last_content: Option<String>,
+    /// Tried for selection text that isn't UTF-8
+    fallback_encoding: Option<&'static Encoding>,
+    /// Text-only stand-in for the system clipboard, e.g. in tests
+    memory: Option<std::sync::Arc<std::sync::Mutex<Option<String>>>>,
+    /// The last image set while `memory` stands in
+    memory_image: Option<(usize, usize, Vec<u8>)>,
+    /// Set when the system clipboard must not be opened
+    unavailable: Option<String>,
 }
 
 impl ClipboardManager {
-    pub fn new() -> Result<Self> {
-        let clipboard = ArboardClipboard::new()?;
-        Ok(Self {
-            clipboard,
-            last_content: None,
-        })
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A manager reading and writing `contents` instead of the system
+    /// clipboard, e.g. in tests; only plain text and images are supported.
+    pub fn in_memory(contents: std::sync::Arc<std::sync::Mutex<Option<String>>>) -> Self {
+        Self {
+            memory: Some(contents),
+            ..Self::default()
+        }
+    }
+
+    /// A manager that behaves as if no system clipboard could be opened,
+    /// failing every clipboard operation with `NoClipboard(reason)`.
+    pub fn unavailable(reason: &str) -> Self {
+        Self {
+            unavailable: Some(reason.to_string()),
+            ..Self::default()
+        }
+    }
+
+    /// Decode selection text that isn't UTF-8 as `encoding` (the
+    /// `fallback_encoding` setting) instead of failing.
+    pub fn set_fallback_encoding(&mut self, encoding: Option<&'static Encoding>) {
+        self.fallback_encoding = encoding;
+    }
+
+    /// Connect to the system clipboard now rather than on first use.
+    pub fn connect(&mut self) -> Result<(), NoClipboard> {
+        self.clipboard().map(|_| ())
+    }
+
+    fn clipboard(&mut self) -> Result<&mut ArboardClipboard, NoClipboard> {
+        if let Some(reason) = &self.unavailable {
+            return Err(NoClipboard(reason.clone()));
+        }
+        if self.clipboard.is_none() {
+            let clipboard = ArboardClipboard::new().map_err(|e| NoClipboard(e.to_string()))?;
+            self.clipboard = Some(clipboard);
+        }
+        Ok(self.clipboard.as_mut().expect("clipboard was just initialized"))
     }
 
+    /// Read the clipboard text. `Ok(None)` means no text is available;
+    /// an empty string is returned as-is so callers can tell the two apart.
     pub fn get_text(&mut self) -> Result<Option<String>> {
-        match self.clipboard.get_text() {
-            Ok(text) => Ok(Some(text)),
+        self.get_selection_text(Selection::Clipboard)
+    }
+
+    /// Like `get_text`, but reads from the given selection.
+    pub fn get_selection_text(&mut self, selection: Selection) -> Result<Option<String>> {
+        Ok(self.get_selection_decoded(selection)?.map(|decoded| decoded.text))
+    }
+
+    /// Like `get_selection_text`, also telling whether the fallback
+    /// encoding was needed.
+    pub fn get_selection_decoded(&mut self, selection: Selection) -> Result<Option<DecodedText>> {
+        if let Some(memory) = &self.memory {
+            return Ok(memory.lock().unwrap().clone().map(|text| DecodedText { text, fallback: None }));
+        }
+        let fallback = self.fallback_encoding;
+        let clipboard = self.clipboard()?;
+        let result = match selection {
+            Selection::Clipboard => clipboard.get_text(),
+            Selection::Primary => get_primary_text(clipboard),
+        };
+        match result {
+            Ok(text) => Ok(Some(DecodedText { text, fallback: None })),
             Err(arboard::Error::ContentNotAvailable) => Ok(None),
+            // arboard drops the bytes, so read them again with a clipboard tool
+            Err(arboard::Error::ConversionFailure) => read_raw_selection(selection)
+                .and_then(|bytes| decode_text(bytes, fallback))
+                .map(Some)
+                .ok_or_else(|| {
+                    anyhow::anyhow!(
+                        "The {} selection holds text that is not UTF-8 or the fallback_encoding and can't be read",
+                        selection
+                    )
+                }),
             Err(e) => Err(e.into()),
         }
     }
 
     pub fn set_text(&mut self, text: &str) -> Result<()> {
-        self.clipboard.set_text(text)?;
+        if let Some(memory) = &self.memory {
+            *memory.lock().unwrap() = Some(text.to_string());
+            self.last_content = Some(text.to_string());
+            return Ok(());
+        }
+        self.clipboard()?.set_text(text)?;
         self.last_content = Some(text.to_string());
         Ok(())
     }
 
-    pub fn get_image(&mut self) -> Result<Option<arboard::ImageData>> {
-        match self.clipboard.get_image() {
+    /// Wait `delay`, then put `previous` back if the clipboard still holds
+    /// `copied`. Returns whether it was restored; a clipboard changed in
+    /// the meantime is left alone.
+    pub async fn restore_after(&mut self, previous: &str, copied: &str, delay: std::time::Duration) -> Result<bool> {
+        tokio::time::sleep(delay).await;
+        if self.get_text()?.as_deref() != Some(copied) {
+            return Ok(false);
+        }
+        self.set_text(previous)?;
+        Ok(true)
+    }
+
+    /// Put a stored clip back on the clipboard using the format matching its
+    /// type. `image` is the PNG stored with an image clip.
+    pub fn set_clip(&mut self, clip: &Clip, image: Option<&[u8]>) -> Result<()> {
+        match clip.clip_type.as_str() {
+            // arboard has no file-list format, so file clips go out as their path
+            "file" => self.set_text(clip.file_path.as_deref().unwrap_or(&clip.content)),
+            "image" => match image {
+                Some(png) => match decode_png(png)? {
+                    TypedContent::Image { width, height, rgba } => self.set_image(arboard::ImageData {
+                        width,
+                        height,
+                        bytes: rgba.into(),
+                    }),
+                    _ => unreachable!("decode_png only returns images"),
+                },
+                // Older versions kept only the dimensions
+                None => Err(anyhow::anyhow!(
+                    "Cannot restore image clip {}: it was stored without its image data",
+                    clip.id
+                )),
+            },
+            _ if clip.mime.starts_with("text/html") => {
+                self.clipboard()?.set_html(clip.content.as_str(), None::<&str>)?;
+                self.last_content = Some(clip.content.clone());
+                Ok(())
+            }
+            _ => self.set_text(&clip.content),
+        }
+    }
+
+    pub fn get_image(&mut self) -> Result<Option<arboard::ImageData<'_>>> {
+        if self.memory.is_some() {
+            return Ok(self.memory_image.as_ref().map(|(width, height, rgba)| arboard::ImageData {
+                width: *width,
+                height: *height,
+                bytes: rgba.as_slice().into(),
+            }));
+        }
+        match self.clipboard()?.get_image() {
             Ok(image) => Ok(Some(image)),
             Err(arboard::Error::ContentNotAvailable) => Ok(None),
             Err(e) => Err(e.into()),
         }
     }
 
+    /// Read whatever is on the clipboard, preferring text over an image.
+    /// `Ok(None)` means neither is available.
+    pub fn capture(&mut self) -> Result<Option<Capture>> {
+        if let Some(text) = self.get_text()? {
+            if is_capturable(&text) {
+                return Ok(Some(Capture::Text(text)));
+            }
+        }
+        Ok(self.get_image()?.map(|image| Capture::Image {
+            width: image.width,
+            height: image.height,
+            rgba: image.bytes.into_owned(),
+        }))
+    }
+
     pub fn set_image(&mut self, image: arboard::ImageData) -> Result<()> {
-        self.clipboard.set_image(image)?;
+        if self.memory.is_some() {
+            self.memory_image = Some((image.width, image.height, image.bytes.into_owned()));
+            return Ok(());
+        }
+        self.clipboard()?.set_image(image)?;
         Ok(())
     }
 
+    /// Put typed data on the clipboard in its own format.
+    pub fn set_typed(&mut self, content: &TypedContent) -> Result<()> {
+        match content {
+            TypedContent::Text(text) => self.set_text(text),
+            TypedContent::Html(html) => {
+                self.clipboard()?.set_html(html.as_str(), None::<&str>)?;
+                self.last_content = Some(html.clone());
+                Ok(())
+            }
+            TypedContent::Image { width, height, rgba } => self.set_image(arboard::ImageData {
+                width: *width,
+                height: *height,
+                bytes: rgba.as_slice().into(),
+            }),
+        }
+    }
+
     pub async fn monitor_changes<F>(&mut self, mut callback: F) -> Result<()>
     where
         F: FnMut(String) -> Result<()>,
     {
         loop {
             if let Ok(Some(content)) = self.get_text() {
-                // Only process if content has changed
-                if self.last_content.as_ref() != Some(&content) {
+                // Only process if content has changed; empty reads are transient
+                // and must not replace the last real content
+                if is_capturable(&content) && self.last_content.as_ref() != Some(&content) {
                     self.last_content = Some(content.clone());
                     if let Err(e) = callback(content) {
                         log::error!("Error processing clipboard content: {}", e);
@@ -66,7 +400,7 @@ impl ClipboardManager {
 
     pub fn has_changed(&mut self) -> Result<bool> {
         if let Ok(Some(content)) = self.get_text() {
-            let changed = self.last_content.as_ref() != Some(&content);
+            let changed = is_capturable(&content) && self.last_content.as_ref() != Some(&content);
             if changed {
                 self.last_content = Some(content);
             }
@@ -77,5 +411,220 @@ impl ClipboardManager {
     }
 }
 
+/// Whether clipboard text is worth storing. Empty or whitespace-only reads
+/// happen transiently while another app replaces the clipboard.
+pub fn is_capturable(content: &str) -> bool {
+    !content.trim().is_empty()
+}
+
+#[cfg(all(
+    unix,
+    not(any(target_os = "macos", target_os = "android", target_os = "emscripten"))
+))]
+fn get_primary_text(clipboard: &mut ArboardClipboard) -> std::result::Result<String, arboard::Error> {
+    use arboard::{GetExtLinux, LinuxClipboardKind};
+    clipboard.get().clipboard(LinuxClipboardKind::Primary).text()
+}
+
+#[cfg(not(all(
+    unix,
+    not(any(target_os = "macos", target_os = "android", target_os = "emscripten"))
+)))]
+fn get_primary_text(_clipboard: &mut ArboardClipboard) -> std::result::Result<String, arboard::Error> {
+    Err(arboard::Error::ClipboardNotSupported)
+}
+
+/// The raw bytes of `selection`, read with whichever command-line
+/// clipboard tool is installed; `None` if none is or it fails.
+fn read_raw_selection(selection: Selection) -> Option<Vec<u8>> {
+    let tools: &[&[&str]] = match selection {
+        Selection::Clipboard => &[
+            &["wl-paste", "--no-newline"],
+            &["xclip", "-o", "-selection", "clipboard"],
+            &["xsel", "--output", "--clipboard"],
+            &["pbpaste"],
+        ],
+        Selection::Primary => &[
+            &["wl-paste", "--no-newline", "--primary"],
+            &["xclip", "-o", "-selection", "primary"],
+            &["xsel", "--output", "--primary"],
+        ],
+    };
+    tools.iter().find_map(|tool| {
+        let program = which::which(tool[0]).ok()?;
+        let output = std::process::Command::new(program).args(&tool[1..]).output().ok()?;
+        output.status.success().then_some(output.stdout)
+    })
+}
+
+/// Interpret clipboard text as a list of files, the way file managers put
+/// copied files on the clipboard: an RFC 2483 `text/uri-list` of `file://`
+/// URIs, optionally after the `copy` or `cut` line GNOME's
+/// `x-special/gnome-copied-files` format starts with. Returns `None` unless
+/// every entry is a local `file://` URI; bare paths are left as text, since
+/// copying a path from a terminal or an editor is a text copy.
+pub fn parse_file_list(content: &str) -> Option<Vec<PathBuf>> {
+    let mut lines = content.lines().map(str::trim).peekable();
+    if matches!(lines.peek(), Some(&("copy" | "cut"))) {
+        lines.next();
+    }
+    let entries: Vec<&str> = lines
+        // uri-list comments start with '#'; blank lines are tolerated
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .collect();
+    if entries.is_empty() {
+        return None;
+    }
+
+    entries
+        .iter()
+        .map(|entry| entry.strip_prefix("file://").and_then(file_uri_path))
+        .collect()
+}
+
+/// The path of a `file://` URI with the scheme already stripped. Only an
+/// empty or `localhost` authority is accepted; other hosts aren't local.
+fn file_uri_path(rest: &str) -> Option<PathBuf> {
+    let path = rest.strip_prefix("localhost").unwrap_or(rest);
+    if !path.starts_with('/') {
+        return None;
+    }
+    percent_decode(path).map(PathBuf::from)
+}
+
+fn percent_decode(input: &str) -> Option<String> {
+    let bytes = input.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            // from_str_radix alone would accept a sign, as in `%+1`
+            let hex = bytes.get(i + 1..i + 3).filter(|hex| hex.iter().all(u8::is_ascii_hexdigit))?;
+            decoded.push(u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
 // Re-export for convenience
-pub type Clipboard = ClipboardManager;
\ No newline at end of file
+pub type Clipboard = ClipboardManager;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn clip(clip_type: &str, content: &str, file_path: Option<&str>) -> Clip {
+        Clip {
+            id: "clip".to_string(),
+            content: content.to_string(),
+            clip_type: clip_type.to_string(),
+            created_at: chrono::Utc::now(),
+            file_path: file_path.map(str::to_string),
+            pinned: false,
+            expires_at: None,
+            mime: crate::database::default_mime(clip_type).to_string(),
+            original_name: None,
+        }
+    }
+
+    #[test]
+    fn clips_go_back_in_their_own_format() {
+        let text = std::sync::Arc::new(std::sync::Mutex::new(None));
+        let mut clipboard = ClipboardManager::in_memory(std::sync::Arc::clone(&text));
+
+        clipboard.set_clip(&clip("text", "hello", None), None).unwrap();
+        assert_eq!(text.lock().unwrap().as_deref(), Some("hello"));
+
+        clipboard.set_clip(&clip("file", "report.pdf", Some("/home/me/report.pdf")), None).unwrap();
+        assert_eq!(text.lock().unwrap().as_deref(), Some("/home/me/report.pdf"));
+
+        let rgba = vec![255, 0, 0, 255, 0, 0, 255, 128];
+        let png = encode_png(2, 1, &rgba).unwrap();
+        clipboard.set_clip(&clip("image", "Image 2x1", None), Some(&png)).unwrap();
+        let image = clipboard.get_image().unwrap().unwrap();
+        assert_eq!((image.width, image.height), (2, 1));
+        assert_eq!(image.bytes.as_ref(), rgba.as_slice());
+        // The text is left alone
+        assert_eq!(text.lock().unwrap().as_deref(), Some("/home/me/report.pdf"));
+
+        assert!(clipboard.set_clip(&clip("image", "Image 2x1", None), None).is_err());
+    }
+
+    #[test]
+    fn windows_1252_text_is_decoded() {
+        let fallback = Some(encoding_rs::WINDOWS_1252);
+        let decoded = decode_text(b"Caf\xe9 cr\xe8me \x93quoted\x94".to_vec(), fallback).unwrap();
+        assert_eq!(decoded.text, "Caf\u{e9} cr\u{e8}me \u{201C}quoted\u{201D}");
+        assert_eq!(decoded.fallback, Some("windows-1252"));
+
+        let utf8 = decode_text("Caf\u{e9}".as_bytes().to_vec(), fallback).unwrap();
+        assert_eq!(utf8.fallback, None);
+        assert_eq!(decode_text(b"Caf\xe9".to_vec(), None), None);
+    }
+
+    #[test]
+    fn binary_is_not_decoded_as_text() {
+        let fallback = Some(encoding_rs::WINDOWS_1252);
+        let png_header = b"\x89PNG\r\n\x1a\n\x00\x00\x00\rIHDR".to_vec();
+        assert_eq!(decode_text(png_header, fallback), None);
+        assert_eq!(decode_text(vec![0xff, 0xfe, 0x01, 0x02, 0x80], fallback), None);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn copies_are_undone_after_the_delay_unless_replaced() {
+        let contents = std::sync::Arc::new(std::sync::Mutex::new(Some("copied".to_string())));
+        let mut clipboard = ClipboardManager::in_memory(std::sync::Arc::clone(&contents));
+        let delay = std::time::Duration::from_secs(5);
+
+        let started = tokio::time::Instant::now();
+        assert!(clipboard.restore_after("previous", "copied", delay).await.unwrap());
+        assert!(started.elapsed() >= delay);
+        assert_eq!(contents.lock().unwrap().as_deref(), Some("previous"));
+
+        // Something else copied in the meantime is kept
+        *contents.lock().unwrap() = Some("copied".to_string());
+        let copy_again = async {
+            tokio::time::sleep(delay / 2).await;
+            *contents.lock().unwrap() = Some("newer".to_string());
+        };
+        let (restored, ()) = tokio::join!(clipboard.restore_after("previous", "copied", delay), copy_again);
+        assert!(!restored.unwrap());
+        assert_eq!(contents.lock().unwrap().as_deref(), Some("newer"));
+    }
+
+    #[test]
+    fn uri_lists_parse_into_paths() {
+        let content = "# copied by a file manager\r\nfile:///home/me/a%20b.txt\r\nfile://localhost/tmp/c.txt\r\n";
+        assert_eq!(
+            parse_file_list(content),
+            Some(vec![PathBuf::from("/home/me/a b.txt"), PathBuf::from("/tmp/c.txt")])
+        );
+        assert_eq!(
+            parse_file_list("copy\nfile:///home/me/a.txt"),
+            Some(vec![PathBuf::from("/home/me/a.txt")])
+        );
+    }
+
+    #[test]
+    fn paths_and_other_text_are_not_file_lists() {
+        assert_eq!(parse_file_list("/"), None);
+        assert_eq!(parse_file_list("/tmp\n/"), None);
+        assert_eq!(parse_file_list("file:///tmp/a.txt\nnot a uri"), None);
+        assert_eq!(parse_file_list("file://otherhost/tmp/a.txt"), None);
+        assert_eq!(parse_file_list("copy"), None);
+        assert_eq!(parse_file_list(""), None);
+    }
+
+    #[test]
+    fn percent_escapes_need_two_hex_digits() {
+        assert_eq!(percent_decode("a%20b%2Fc%e2%9c%93").as_deref(), Some("a b/c\u{2713}"));
+        for bad in ["%+1", "%-1", "%2", "%", "%g0", "% 1", "%é"] {
+            assert_eq!(percent_decode(bad), None, "{}", bad);
+        }
+        assert_eq!(parse_file_list("file:///tmp/a%+1.txt"), None);
+    }
+}
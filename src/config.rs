@@ -1,9 +1,18 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
+use chrono::{DateTime, NaiveDate, Timelike, Utc};
+use log::warn;
+use regex::Regex;
 use serde::{Deserialize, Serialize};
 use std::fs;
 use std::path::Path;
 
+use crate::audit::AuditLog;
+use crate::clipboard::Selection;
+use crate::plugins::builtin;
+use crate::profile::{self, Profile};
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
 pub struct Config {
     pub max_clips: usize,
     pub hotkey: String,
@@ -14,6 +23,165 @@ pub struct Config {
     pub sync_enabled: bool,
     pub sync_gist_id: Option<String>,
     pub sync_token: Option<String>,
+    /// Regexes whose matches are masked in list/search/picker previews
+    pub redact_patterns: Vec<String>,
+    /// Template for each `clipq list` line; see `display::LIST_PLACEHOLDERS`
+    pub list_format: String,
+    pub default_list_limit: usize,
+    pub default_pick_limit: usize,
+    pub default_search_limit: usize,
+    pub retention: RetentionPolicy,
+    /// Selections the daemon polls: `clipboard` and/or `primary`
+    pub monitor_selections: Vec<String>,
+    /// Also log captured clip content at debug level. Off by default, so logs
+    /// only ever carry a fingerprint of each clip.
+    pub log_content: bool,
+    /// Address `clipq web` listens on
+    pub web_bind: String,
+    /// Token `/api` requests must carry; `$CLIPQ_WEB_TOKEN` takes
+    /// precedence, and a random one is made per run when neither is set
+    pub web_token: Option<String>,
+    /// Upper bound on plugin processes running at the same time
+    pub max_concurrent_plugins: usize,
+    /// Plugins still running after this many seconds are killed
+    pub plugin_timeout_secs: u64,
+    /// Picker preview commands per clip type; `{id}` and `{path}` are
+    /// substituted. Unset ones fall back to `clipq show {id}`.
+    pub preview_text_cmd: Option<String>,
+    pub preview_file_cmd: Option<String>,
+    pub preview_image_cmd: Option<String>,
+    /// IANA timezone (e.g. `Europe/Istanbul`) for day boundaries; the
+    /// system's local timezone when unset
+    pub timezone: Option<String>,
+    /// Largest file `clipq file --embed` will store
+    pub max_embed_bytes: u64,
+    /// Embedded files larger than this are stored zstd-compressed
+    pub compression_threshold_bytes: u64,
+    /// zstd level (1-19) for compressed embedded files
+    pub compression_level: i32,
+    /// How new tag names are cased; tags differing only in case are always
+    /// treated as the same tag
+    pub tag_case: TagCase,
+    /// Longest tag name accepted, in characters
+    pub max_tag_length: usize,
+    pub capture: CaptureConfig,
+    /// Encoding (e.g. `windows-1252`) tried for text that isn't UTF-8:
+    /// stdin of `compose`, files of `add-files`, plugin output and
+    /// daemon captures
+    pub fallback_encoding: Option<String>,
+    /// Previous contents kept per clip when it is edited; 0 keeps none
+    pub max_versions_per_clip: usize,
+    /// Secret for `export --sign` and import verification;
+    /// `$CLIPQ_EXPORT_KEY` takes precedence
+    pub export_signing_key: Option<String>,
+    /// JSON lines file recording clip additions, deletions and exports;
+    /// off when unset
+    pub audit_log_path: Option<String>,
+    /// Clips copied more than this many seconds after the previous one
+    /// start a new session
+    pub session_gap_secs: u64,
+}
+
+/// Casing applied to tag names when they are stored.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TagCase {
+    /// Store every tag in lowercase
+    Lowercase,
+    /// Keep whichever casing of a tag was used first
+    #[default]
+    Preserve,
+}
+
+impl TagCase {
+    /// `name` as it should be stored under this casing.
+    pub fn apply(&self, name: &str) -> String {
+        match self {
+            TagCase::Lowercase => name.to_lowercase(),
+            TagCase::Preserve => name.to_string(),
+        }
+    }
+}
+
+/// The timezone calendar days are counted in.
+#[derive(Debug, Clone, Copy)]
+pub enum Timezone {
+    Local,
+    Named(chrono_tz::Tz),
+}
+
+impl Timezone {
+    /// The calendar date `instant` falls on in this timezone.
+    pub fn date_of(&self, instant: DateTime<Utc>) -> NaiveDate {
+        match self {
+            Timezone::Local => instant.with_timezone(&chrono::Local).date_naive(),
+            Timezone::Named(tz) => instant.with_timezone(tz).date_naive(),
+        }
+    }
+
+    /// The hour of the day (0-23) `instant` falls in, on this timezone's
+    /// clock at that instant, so DST shifts are respected.
+    pub fn hour_of(&self, instant: DateTime<Utc>) -> u32 {
+        match self {
+            Timezone::Local => instant.with_timezone(&chrono::Local).hour(),
+            Timezone::Named(tz) => instant.with_timezone(tz).hour(),
+        }
+    }
+}
+
+/// How the daemon captures clipboard content and processes it before
+/// storing it.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct CaptureConfig {
+    /// Built-in transforms (`plugins::builtin::TRANSFORMS`), applied in order
+    pub transforms: Vec<String>,
+    /// Also store the clipboard every this many seconds, tagged `snapshot`,
+    /// unless it is unchanged since the last snapshot or matches the newest
+    /// stored clip. Snapshots go through the same transforms and skips as
+    /// other captures
+    pub snapshot_interval_secs: Option<u64>,
+    /// Inclusive `[min, max]` character count of clips worth capturing;
+    /// anything shorter or longer is ignored
+    pub length_range: Option<(usize, usize)>,
+    /// Skip clips that exactly match a line clipq itself printed recently,
+    /// e.g. an `Added to clipboard: ...` confirmation copied from the terminal
+    pub skip_own_output: bool,
+    /// Comma-separated `clipq transform` steps `clipq watch-paste` applies
+    /// when run without `--pipeline`
+    pub watch_pipeline: Option<String>,
+}
+
+impl CaptureConfig {
+    /// Whether `text` falls within `length_range`, if one is set.
+    pub fn accepts_length(&self, text: &str) -> bool {
+        match self.length_range {
+            Some((min, max)) => (min..=max).contains(&text.chars().count()),
+            None => true,
+        }
+    }
+}
+
+/// Limits applied together by `Database::apply_retention`; a clip is removed
+/// when it falls outside any of them.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct RetentionPolicy {
+    pub max_clips: Option<usize>,
+    pub max_age_days: Option<u64>,
+    pub max_total_bytes: Option<u64>,
+    pub keep_pinned: bool,
+}
+
+impl Default for RetentionPolicy {
+    fn default() -> Self {
+        Self {
+            max_clips: None,
+            max_age_days: None,
+            max_total_bytes: None,
+            keep_pinned: true,
+        }
+    }
 }
 
 impl Default for Config {
@@ -28,11 +196,97 @@ impl Default for Config {
             sync_enabled: false,
             sync_gist_id: None,
             sync_token: None,
+            redact_patterns: Vec::new(),
+            list_format: "{index}: {content}".to_string(),
+            default_list_limit: 20,
+            default_pick_limit: 50,
+            default_search_limit: 20,
+            retention: RetentionPolicy::default(),
+            monitor_selections: vec!["clipboard".to_string()],
+            log_content: false,
+            web_bind: "127.0.0.1".to_string(),
+            web_token: None,
+            max_concurrent_plugins: 4,
+            plugin_timeout_secs: 30,
+            preview_text_cmd: None,
+            preview_file_cmd: None,
+            preview_image_cmd: None,
+            timezone: None,
+            max_embed_bytes: 10 * 1024 * 1024,
+            compression_threshold_bytes: 4096,
+            compression_level: 3,
+            tag_case: TagCase::default(),
+            max_tag_length: 64,
+            capture: CaptureConfig::default(),
+            fallback_encoding: Some("windows-1252".to_string()),
+            max_versions_per_clip: 10,
+            export_signing_key: None,
+            audit_log_path: None,
+            session_gap_secs: 300,
         }
     }
 }
 
 impl Config {
+    /// The key exports are signed and verified with: `$CLIPQ_EXPORT_KEY`,
+    /// else `export_signing_key`.
+    pub fn export_key(&self) -> Option<String> {
+        std::env::var("CLIPQ_EXPORT_KEY")
+            .ok()
+            .filter(|key| !key.is_empty())
+            .or_else(|| self.export_signing_key.clone().filter(|key| !key.is_empty()))
+    }
+
+    /// The token the web API requires: `$CLIPQ_WEB_TOKEN`, else `web_token`.
+    pub fn web_token(&self) -> Option<String> {
+        std::env::var("CLIPQ_WEB_TOKEN")
+            .ok()
+            .filter(|token| !token.is_empty())
+            .or_else(|| self.web_token.clone().filter(|token| !token.is_empty()))
+    }
+
+    /// The configured audit log, if `audit_log_path` is set.
+    pub fn audit_log(&self) -> Option<AuditLog> {
+        self.audit_log_path
+            .as_deref()
+            .filter(|path| !path.is_empty())
+            .map(|path| AuditLog::new(shellexpand::tilde(path).into_owned()))
+    }
+
+    /// Where the config file lives: `explicit` (a `--config` flag) if
+    /// given, else `$CLIPQ_CONFIG`, else the active profile's `config.toml`
+    /// for a non-default profile, else `$XDG_CONFIG_HOME/clipq/config.toml`
+    /// when that file exists, else `~/.clipq.toml`.
+    pub fn resolve_path(explicit: Option<&str>) -> String {
+        Self::resolve_path_for(explicit, profile::active())
+    }
+
+    fn resolve_path_for(explicit: Option<&str>, profile: &Profile) -> String {
+        if let Some(path) = explicit {
+            return path.to_string();
+        }
+        if let Some(path) = std::env::var("CLIPQ_CONFIG").ok().filter(|path| !path.is_empty()) {
+            return path;
+        }
+        if let Ok(Some(path)) = profile.config_path() {
+            return path;
+        }
+        if let Some(path) = dirs::config_dir().map(|dir| dir.join("clipq").join("config.toml")) {
+            if path.exists() {
+                return path.to_string_lossy().to_string();
+            }
+        }
+        Self::default_path()
+    }
+
+    pub fn default_path() -> String {
+        dirs::home_dir()
+            .unwrap_or_else(|| std::env::current_dir().unwrap())
+            .join(".clipq.toml")
+            .to_string_lossy()
+            .to_string()
+    }
+
     pub fn load(path: &str) -> Result<Self> {
         let expanded_path = shellexpand::tilde(path).to_string();
         
@@ -57,4 +311,236 @@ impl Config {
         fs::write(&expanded_path, content)?;
         Ok(())
     }
-}
\ No newline at end of file
+
+    pub fn redact_regexes(&self) -> Result<Vec<Regex>> {
+        self.redact_patterns
+            .iter()
+            .map(|pattern| {
+                Regex::new(pattern)
+                    .map_err(|e| anyhow::anyhow!("Invalid redact pattern '{}': {}", pattern, e))
+            })
+            .collect()
+    }
+
+    /// Parse `monitor_selections`, dropping duplicates and, on platforms
+    /// without one, the primary selection.
+    pub fn selections(&self) -> Result<Vec<Selection>> {
+        let mut selections = Vec::new();
+        for name in &self.monitor_selections {
+            let selection: Selection = name.parse()?;
+            if selection == Selection::Primary && !Selection::PRIMARY_SUPPORTED {
+                warn!("The primary selection is not available on this platform; ignoring it");
+                continue;
+            }
+            if !selections.contains(&selection) {
+                selections.push(selection);
+            }
+        }
+        Ok(selections)
+    }
+
+    /// Every settable key, with nested sections joined by dots
+    /// (e.g. `retention.max_age_days`).
+    pub fn keys() -> Vec<String> {
+        let mut keys = Vec::new();
+        if let Ok(value) = serde_json::to_value(Config::default()) {
+            collect_keys(&value, "", &mut keys);
+        }
+        keys
+    }
+
+    /// Read one value by dotted key, formatted as it would appear in the file.
+    pub fn get_key(&self, key: &str) -> Result<String> {
+        let value = serde_json::to_value(self)?;
+        let field = lookup(&value, key).ok_or_else(|| unknown_key(key))?;
+        Ok(match field {
+            serde_json::Value::Null => "none".to_string(),
+            serde_json::Value::String(s) => s.clone(),
+            other => other.to_string(),
+        })
+    }
+
+    /// Set one value by dotted key. `value` is parsed as a TOML value (so
+    /// `42`, `true` and `["a", "b"]` keep their types), falling back to a
+    /// plain string; `none` clears optional settings.
+    pub fn set_key(&mut self, key: &str, value: &str) -> Result<()> {
+        let mut root = serde_json::to_value(&*self)?;
+        let field = lookup_mut(&mut root, key).ok_or_else(|| unknown_key(key))?;
+        if field.is_object() {
+            return Err(unknown_key(key));
+        }
+
+        *field = if value == "none" {
+            serde_json::Value::Null
+        } else {
+            match toml::from_str::<toml::Table>(&format!("value = {}", value)) {
+                Ok(mut table) => serde_json::to_value(table.remove("value"))?,
+                Err(_) => serde_json::Value::String(value.to_string()),
+            }
+        };
+
+        let updated: Config = serde_json::from_value(root)
+            .map_err(|e| anyhow::anyhow!("Invalid value for {}: {}", key, e))?;
+        updated.validate()?;
+        *self = updated;
+        Ok(())
+    }
+
+    pub fn timezone(&self) -> Result<Timezone> {
+        match &self.timezone {
+            None => Ok(Timezone::Local),
+            Some(name) => name
+                .parse()
+                .map(Timezone::Named)
+                .map_err(|_| anyhow::anyhow!("Unknown timezone '{}'", name)),
+        }
+    }
+
+    /// Resolve `fallback_encoding` to an encoding.
+    pub fn fallback_encoding(&self) -> Result<Option<&'static encoding_rs::Encoding>> {
+        self.fallback_encoding
+            .as_deref()
+            .map(|label| {
+                encoding_rs::Encoding::for_label(label.as_bytes())
+                    .ok_or_else(|| anyhow::anyhow!("Unknown fallback_encoding '{}'", label))
+            })
+            .transpose()
+    }
+
+    /// Look up the configured capture transforms, in order.
+    pub fn capture_transforms(&self) -> Result<Vec<builtin::Transform>> {
+        self.capture
+            .transforms
+            .iter()
+            .map(|name| {
+                builtin::transform(name).ok_or_else(|| {
+                    let known: Vec<&str> = builtin::TRANSFORMS.iter().map(|(name, _)| *name).collect();
+                    anyhow::anyhow!("Unknown capture transform '{}'. Available: {}", name, known.join(", "))
+                })
+            })
+            .collect()
+    }
+
+    /// Check settings that parse fine but can't be used.
+    pub fn validate(&self) -> Result<()> {
+        if self.max_clips == 0 {
+            anyhow::bail!("max_clips must be at least 1");
+        }
+        if self.max_concurrent_plugins == 0 {
+            anyhow::bail!("max_concurrent_plugins must be at least 1");
+        }
+        if self.plugin_timeout_secs == 0 {
+            anyhow::bail!("plugin_timeout_secs must be at least 1");
+        }
+        if !(1..=19).contains(&self.compression_level) {
+            anyhow::bail!("compression_level must be between 1 and 19");
+        }
+        if self.session_gap_secs == 0 {
+            anyhow::bail!("session_gap_secs must be at least 1");
+        }
+        if self.max_tag_length == 0 {
+            anyhow::bail!("max_tag_length must be at least 1");
+        }
+        self.redact_regexes()?;
+        self.selections()?;
+        self.timezone()?;
+        self.capture_transforms()?;
+        if let Some(pipeline) = &self.capture.watch_pipeline {
+            builtin::parse_pipeline(pipeline).context("Invalid capture.watch_pipeline")?;
+        }
+        if self.capture.snapshot_interval_secs == Some(0) {
+            anyhow::bail!("capture.snapshot_interval_secs must be at least 1");
+        }
+        if let Some((min, max)) = self.capture.length_range {
+            if min > max {
+                anyhow::bail!("capture.length_range minimum {} is above its maximum {}", min, max);
+            }
+        }
+        self.fallback_encoding()?;
+        crate::display::list_placeholders(&self.list_format)?;
+        Ok(())
+    }
+}
+
+fn collect_keys(value: &serde_json::Value, prefix: &str, keys: &mut Vec<String>) {
+    if let serde_json::Value::Object(map) = value {
+        for (name, child) in map {
+            let key = if prefix.is_empty() { name.clone() } else { format!("{}.{}", prefix, name) };
+            if child.is_object() {
+                collect_keys(child, &key, keys);
+            } else {
+                keys.push(key);
+            }
+        }
+    }
+}
+
+fn lookup<'a>(value: &'a serde_json::Value, key: &str) -> Option<&'a serde_json::Value> {
+    key.split('.').try_fold(value, |value, part| value.as_object()?.get(part))
+}
+
+fn lookup_mut<'a>(value: &'a mut serde_json::Value, key: &str) -> Option<&'a mut serde_json::Value> {
+    key.split('.').try_fold(value, |value, part| value.as_object_mut()?.get_mut(part))
+}
+
+fn unknown_key(key: &str) -> anyhow::Error {
+    anyhow::anyhow!("Unknown config key '{}'. Valid keys: {}", key, Config::keys().join(", "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    /// Held by tests that set `CLIPQ_CONFIG`, so they don't see each
+    /// other's value.
+    static CONFIG_ENV: Mutex<()> = Mutex::new(());
+
+    #[test]
+    fn the_environment_beats_a_profiles_config() {
+        let _env = CONFIG_ENV.lock().unwrap();
+        let work = Profile::new("work").unwrap();
+        let profile_config = work.config_path().unwrap().unwrap();
+
+        std::env::set_var("CLIPQ_CONFIG", "/etc/clipq/from-env.toml");
+        assert_eq!(Config::resolve_path_for(None, &work), "/etc/clipq/from-env.toml");
+        assert_eq!(Config::resolve_path_for(Some("flag.toml"), &work), "flag.toml");
+
+        std::env::remove_var("CLIPQ_CONFIG");
+        assert_eq!(Config::resolve_path_for(None, &work), profile_config);
+    }
+
+    #[test]
+    fn clipq_config_overrides_the_default_path() {
+        let _env = CONFIG_ENV.lock().unwrap();
+        let default = Profile::default();
+        std::env::remove_var("CLIPQ_CONFIG");
+        let fallback = Config::resolve_path_for(None, &default);
+        assert_ne!(fallback, "/tmp/clipq-override.toml");
+
+        std::env::set_var("CLIPQ_CONFIG", "/tmp/clipq-override.toml");
+        assert_eq!(Config::resolve_path_for(None, &default), "/tmp/clipq-override.toml");
+        // An empty value counts as unset
+        std::env::set_var("CLIPQ_CONFIG", "");
+        assert_eq!(Config::resolve_path_for(None, &default), fallback);
+        std::env::remove_var("CLIPQ_CONFIG");
+    }
+
+    #[test]
+    fn set_key_checks_values_and_keys() {
+        let mut config = Config::default();
+        config.set_key("max_clips", "250").unwrap();
+        assert_eq!(config.max_clips, 250);
+
+        for invalid in ["0", "-5", "lots"] {
+            assert!(config.set_key("max_clips", invalid).is_err(), "max_clips = {}", invalid);
+        }
+        assert_eq!(config.max_clips, 250, "a rejected value leaves the config alone");
+
+        let error = config.set_key("max_clip", "10").unwrap_err();
+        assert!(error.to_string().starts_with("Unknown config key 'max_clip'"), "{}", error);
+        assert!(error.to_string().contains("max_clips"), "{}", error);
+        // Sections hold settings but aren't settings themselves
+        assert!(config.set_key("retention", "1").is_err());
+    }
+}
@@ -1,41 +1,89 @@
 use anyhow::Result;
-use global_hotkey::hotkey::{Code, HotKey, Modifiers};
-use global_hotkey::GlobalHotKeyManager;
-use log::{error, info};
+use log::{debug, error, info};
+use std::any::Any;
+use std::future::Future;
 use std::sync::Arc;
 use tokio::sync::Mutex;
-use tokio::time::{sleep, Duration};
+use tokio::time::{interval, sleep, Duration, Instant, MissedTickBehavior};
 
-use crate::clipboard::ClipboardManager;
-use crate::config::Config;
-use crate::database::Database;
+use crate::clipboard::{is_capturable, parse_file_list, ClipboardManager, DecodedText, Selection};
+use crate::config::{CaptureConfig, Config, RetentionPolicy};
+use crate::database::{Clip, Database};
+use crate::display;
+use crate::emitted::EmittedLog;
+use crate::lock;
+use crate::metrics::Metrics;
+use crate::plugins::{builtin, PluginManager, PluginTrigger};
 // use crate::picker; // TODO: Re-enable when hotkey support is added back
 
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+const RETENTION_INTERVAL: Duration = Duration::from_secs(60);
+/// First delay before restarting a panicked monitor; doubles per panic.
+const RESTART_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_RESTART_BACKOFF: Duration = Duration::from_secs(60);
+/// Panics in a row after which the daemon gives up.
+const MAX_RESTARTS: u32 = 5;
+/// A monitor that ran this long before panicking resets the count.
+const HEALTHY_RUN: Duration = Duration::from_secs(300);
+
 pub struct Daemon {
     config: Config,
     max_clips: usize,
+    /// Applied every `RETENTION_INTERVAL`; see `retention_policy`
+    retention: RetentionPolicy,
     db: Arc<Mutex<Database>>,
     clipboard: Arc<Mutex<ClipboardManager>>,
-    hotkey_manager: Option<GlobalHotKeyManager>,
+    metrics: Arc<Metrics>,
+    plugins: Arc<PluginManager>,
 }
 
 impl Daemon {
-    pub async fn new(config: Config, max_clips: usize) -> Result<Self> {
-        let db = Arc::new(Mutex::new(Database::new().await?));
-        let clipboard = Arc::new(Mutex::new(ClipboardManager::new()?));
+    /// A daemon keeping `config.max_clips` clips, or `max_clips_override`
+    /// (`daemon --max-clips`) when given, which also beats
+    /// `retention.max_clips`.
+    pub async fn new(config: Config, max_clips_override: Option<usize>) -> Result<Self> {
+        let max_clips = max_clips_override.unwrap_or(config.max_clips);
+        let retention = retention_policy(&config, max_clips_override);
+        let mut db = Database::new().await?;
+        db.set_tag_case(config.tag_case);
+        db.set_max_tag_length(config.max_tag_length);
+        db.set_audit_log(config.audit_log());
+        let db = Arc::new(Mutex::new(db));
+        // The daemon is useless without a clipboard, so fail at startup
+        let mut clipboard = ClipboardManager::new();
+        clipboard.set_fallback_encoding(config.fallback_encoding()?);
+        clipboard.connect()?;
+        let clipboard = Arc::new(Mutex::new(clipboard));
+        let plugins = Arc::new(PluginManager::new(config.max_concurrent_plugins));
         
         let mut daemon = Self {
             config,
             max_clips,
+            retention,
             db,
             clipboard,
-            hotkey_manager: None,
+            metrics: Arc::default(),
+            plugins,
         };
         
         daemon.setup_hotkey().await?;
         Ok(daemon)
     }
 
+    /// Count captures and plugin runs in `metrics`, e.g. the ones a web
+    /// server in the same process exposes.
+    pub fn with_metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Run `plugins`' `OnClipAdd` plugins on every captured clip. Without
+    /// this the daemon runs none.
+    pub fn with_plugins(mut self, plugins: Arc<PluginManager>) -> Self {
+        self.plugins = plugins;
+        self
+    }
+
     async fn setup_hotkey(&mut self) -> Result<()> {
         // For now, skip hotkey setup to focus on core functionality
         // TODO: Implement proper hotkey handling
@@ -43,99 +91,855 @@ impl Daemon {
         Ok(())
     }
 
-    fn parse_hotkey(&self, hotkey_str: &str) -> Result<HotKey> {
-        let parts: Vec<&str> = hotkey_str.split('+').collect();
-        let mut modifiers = Modifiers::empty();
-        let mut key_code = Code::KeyV; // Default to V key
-
-        for part in parts {
-            match part.trim().to_lowercase().as_str() {
-                "ctrl" => modifiers |= Modifiers::CONTROL,
-                "alt" => modifiers |= Modifiers::ALT,
-                "shift" => modifiers |= Modifiers::SHIFT,
-                "meta" | "cmd" | "super" => modifiers |= Modifiers::META,
-                "v" => key_code = Code::KeyV,
-                "c" => key_code = Code::KeyC,
-                "x" => key_code = Code::KeyX,
-                _ => {
-                    // Try to parse as a single character
-                    if let Some(ch) = part.chars().next() {
-                        if ch.is_ascii_alphabetic() {
-                            key_code = match ch.to_ascii_uppercase() {
-                                'A' => Code::KeyA,
-                                'B' => Code::KeyB,
-                                'C' => Code::KeyC,
-                                'D' => Code::KeyD,
-                                'E' => Code::KeyE,
-                                'F' => Code::KeyF,
-                                'G' => Code::KeyG,
-                                'H' => Code::KeyH,
-                                'I' => Code::KeyI,
-                                'J' => Code::KeyJ,
-                                'K' => Code::KeyK,
-                                'L' => Code::KeyL,
-                                'M' => Code::KeyM,
-                                'N' => Code::KeyN,
-                                'O' => Code::KeyO,
-                                'P' => Code::KeyP,
-                                'Q' => Code::KeyQ,
-                                'R' => Code::KeyR,
-                                'S' => Code::KeyS,
-                                'T' => Code::KeyT,
-                                'U' => Code::KeyU,
-                                'V' => Code::KeyV,
-                                'W' => Code::KeyW,
-                                'X' => Code::KeyX,
-                                'Y' => Code::KeyY,
-                                'Z' => Code::KeyZ,
-                                _ => Code::KeyV,
-                            };
+    pub async fn run(&mut self) -> Result<()> {
+        info!("Starting clipq daemon with max_clips={}", self.max_clips);
+        
+        let max_clips = self.max_clips;
+        let selections = self.config.selections()?;
+        if selections.is_empty() {
+            anyhow::bail!("monitor_selections does not name any selection available on this platform");
+        }
+        info!(
+            "Monitoring selections: {}",
+            selections.iter().map(|s| s.as_str()).collect::<Vec<_>>().join(", ")
+        );
+        let monitor = Monitor {
+            db: Arc::clone(&self.db),
+            clipboard: Arc::clone(&self.clipboard),
+            selections,
+            rules: CaptureRules::new(&self.config)?,
+            metrics: Arc::clone(&self.metrics),
+            plugins: Arc::clone(&self.plugins),
+            file_clips: self.config.enable_file_clips,
+            log_content: self.config.log_content,
+            max_clips,
+        };
+
+        if let Some(secs) = self.config.capture.snapshot_interval_secs {
+            info!("Taking clipboard snapshots every {}s", secs);
+            let db = Arc::clone(&self.db);
+            let clipboard = Arc::clone(&self.clipboard);
+            let metrics = Arc::clone(&self.metrics);
+            let rules = CaptureRules::new(&self.config)?;
+            tokio::spawn(async move {
+                let mut ticks = interval(Duration::from_secs(secs));
+                ticks.set_missed_tick_behavior(MissedTickBehavior::Skip);
+                let mut last_snapshot = None;
+                loop {
+                    ticks.tick().await;
+                    match take_snapshot(&db, &clipboard, &rules, &mut last_snapshot, max_clips).await {
+                        Ok(Some(id)) => {
+                            info!("Stored snapshot clip {}", id);
+                            metrics.record_clip_added();
                         }
+                        Ok(None) => {}
+                        Err(e) => error!("Failed to store clipboard snapshot: {}", e),
                     }
                 }
-            }
+            });
         }
 
-        Ok(HotKey::new(Some(modifiers), key_code))
-    }
-
-    pub async fn run(&mut self) -> Result<()> {
-        info!("Starting clipq daemon with max_clips={}", self.max_clips);
-        
-        // Start clipboard monitoring
+        // Age- and size-based retention has to run even when nothing is copied
         let db_clone = Arc::clone(&self.db);
-        let max_clips = self.max_clips;
-        let clipboard_clone = Arc::clone(&self.clipboard);
-        
-        let monitor_task = tokio::spawn(async move {
-            let mut clipboard = clipboard_clone.lock().await;
-            let mut last_content = None;
-            
+        let retention = self.retention.clone();
+        tokio::spawn(async move {
             loop {
-                if let Ok(Some(content)) = clipboard.get_text() {
-                    if last_content.as_ref() != Some(&content) && !content.trim().is_empty() {
-                        last_content = Some(content.clone());
-                        
-                        let mut db = db_clone.lock().await;
-                        if let Err(e) = db.add_clip(&content, "text").await {
-                            error!("Failed to add clip to database: {}", e);
-                        } else {
-                            // Trim history to max_clips
-                            if let Err(e) = db.trim_history(max_clips).await {
-                                error!("Failed to trim history: {}", e);
-                            }
-                        }
+                {
+                    let mut db = db_clone.lock().await;
+                    match db.apply_retention(&retention).await {
+                        Ok(0) => {}
+                        Ok(removed) => info!("Retention removed {} clips", removed),
+                        Err(e) => error!("Failed to apply retention policy: {}", e),
                     }
                 }
-                
-                sleep(Duration::from_millis(500)).await;
+                sleep(RETENTION_INTERVAL).await;
             }
         });
 
         // For now, just run clipboard monitoring
         // TODO: Add hotkey support back
-        monitor_task.await?;
+        // A fresh copy per restart after a panic
+        supervise("Clipboard monitor", move || monitor.clone().run()).await
+    }
 
-        Ok(())
+    /// Watch only the clipboard, feed every new copy through `steps`, put
+    /// the result back and store it. See `PasteWatcher` for what is left
+    /// alone.
+    pub async fn watch_paste(&mut self, steps: Vec<(&'static str, builtin::Step)>) -> Result<()> {
+        let names: Vec<&str> = steps.iter().map(|(name, _)| *name).collect();
+        info!("Watching the clipboard with pipeline {}", names.join(","));
+
+        let mut watcher = PasteWatcher::new(steps, CaptureRules::new(&self.config)?, self.max_clips);
+        loop {
+            sleep(POLL_INTERVAL).await;
+            let locked = lock::locked_content().unwrap_or_else(|e| {
+                error!("Failed to read the clipboard lock: {}", e);
+                None
+            });
+            match watcher.poll(&self.clipboard, &self.db, locked.as_deref()).await {
+                Ok(Some(id)) => {
+                    info!("Captured transformed clip {}", id);
+                    self.metrics.record_clip_added();
+                }
+                Ok(None) => {}
+                Err(e) => error!("Failed to add clip to database: {}", e),
+            }
+        }
+    }
+}
+
+/// `clipq watch-paste` between polls. Copies are prepared like the
+/// monitor's captures, so clipq's own output is skipped with
+/// `skip_own_output`, and a locked clipboard is reverted rather than
+/// transformed. Writing the result back is itself seen as a change; it is
+/// recognized and left alone, so each copy is transformed exactly once.
+/// Copies a step fails on are stored unchanged.
+struct PasteWatcher {
+    steps: Vec<(&'static str, builtin::Step)>,
+    rules: CaptureRules,
+    max_clips: usize,
+    last_content: Option<String>,
+    /// The last result written back, until the clipboard shows it
+    written: Option<String>,
+}
+
+impl PasteWatcher {
+    fn new(steps: Vec<(&'static str, builtin::Step)>, rules: CaptureRules, max_clips: usize) -> Self {
+        Self {
+            steps,
+            rules,
+            max_clips,
+            last_content: None,
+            written: None,
+        }
+    }
+
+    /// Read the clipboard once; transform, write back and store a new
+    /// copy. `locked` is the clipboard lock's content, if any. Returns the
+    /// stored clip's id.
+    async fn poll(
+        &mut self,
+        clipboard: &Mutex<ClipboardManager>,
+        db: &Mutex<Database>,
+        locked: Option<&str>,
+    ) -> Result<Option<String>> {
+        let mut clipboard = clipboard.lock().await;
+        let content = match clipboard.get_selection_text(Selection::Clipboard) {
+            Ok(content) => content,
+            Err(e) => {
+                debug!("Reading clipboard selection failed: {}", e);
+                return Ok(None);
+            }
+        };
+        if let Some(locked) = locked {
+            if content.as_deref() != Some(locked) {
+                match clipboard.set_text(locked) {
+                    Ok(()) => info!("Restored locked clipboard content"),
+                    Err(e) => error!("Failed to restore locked clipboard content: {}", e),
+                }
+            }
+            self.last_content = Some(locked.to_string());
+            return Ok(None);
+        }
+
+        let content = match content {
+            Some(content) if is_capturable(&content) => content,
+            _ => return Ok(None),
+        };
+        if self.last_content.as_ref() == Some(&content) {
+            return Ok(None);
+        }
+        self.last_content = Some(content.clone());
+        if self.written.as_ref() == Some(&content) {
+            self.written = None;
+            return Ok(None);
+        }
+        let Some(prepared) = self.rules.prepare(content) else {
+            return Ok(None);
+        };
+
+        let result = match builtin::run_pipeline(&self.steps, &prepared) {
+            Ok(result) if is_capturable(&result) => result,
+            Ok(_) => prepared,
+            Err(e) => {
+                info!("Storing copy untransformed: {}", e);
+                prepared
+            }
+        };
+        if self.last_content.as_ref() != Some(&result) {
+            match clipboard.set_text(&result) {
+                Ok(()) => self.written = Some(result.clone()),
+                Err(e) => error!("Failed to put the transformed clip on the clipboard: {}", e),
+            }
+        }
+        drop(clipboard);
+
+        let id = db.lock().await.add_and_trim(&result, "text", self.max_clips).await?;
+        Ok(Some(id))
+    }
+}
+
+/// The retention policy the daemon applies: the configured one, its clip
+/// limit replaced by `max_clips_override` when given and falling back to
+/// `max_clips` when neither sets one.
+fn retention_policy(config: &Config, max_clips_override: Option<usize>) -> RetentionPolicy {
+    let mut retention = config.retention.clone();
+    retention.max_clips = max_clips_override
+        .or(retention.max_clips)
+        .or(Some(config.max_clips));
+    retention
+}
+
+/// The daemon's clipboard polling loop and everything it needs, cloned
+/// afresh by `supervise` for each restart.
+#[derive(Clone)]
+struct Monitor {
+    db: Arc<Mutex<Database>>,
+    clipboard: Arc<Mutex<ClipboardManager>>,
+    selections: Vec<Selection>,
+    rules: CaptureRules,
+    metrics: Arc<Metrics>,
+    /// Its `OnClipAdd` plugins run on every stored clip
+    plugins: Arc<PluginManager>,
+    file_clips: bool,
+    log_content: bool,
+    max_clips: usize,
+}
+
+impl Monitor {
+    async fn run(self) {
+        // Each selection changes independently, so track them separately
+        let mut last_contents: Vec<Option<String>> = vec![None; self.selections.len()];
+        loop {
+            self.poll(&mut last_contents).await;
+            sleep(POLL_INTERVAL).await;
+        }
+    }
+
+    /// Read every monitored selection once and store the ones that changed
+    /// since `last_contents`.
+    async fn poll(&self, last_contents: &mut [Option<String>]) {
+        // Released between polls so heartbeat snapshots can read too
+        let mut clipboard = self.clipboard.lock().await;
+        let locked = lock::locked_content().unwrap_or_else(|e| {
+            error!("Failed to read the clipboard lock: {}", e);
+            None
+        });
+        for (selection, last_content) in self.selections.iter().zip(last_contents.iter_mut()) {
+            // While locked, changes are reverted instead of captured
+            if let (Some(locked), Selection::Clipboard) = (&locked, selection) {
+                match clipboard.get_selection_text(*selection) {
+                    Ok(current) if current.as_ref() == Some(locked) => {}
+                    Ok(_) => match clipboard.set_text(locked) {
+                        Ok(()) => info!("Restored locked clipboard content"),
+                        Err(e) => error!("Failed to restore locked clipboard content: {}", e),
+                    },
+                    Err(e) => debug!("Reading {} selection failed: {}", selection, e),
+                }
+                *last_content = Some(locked.clone());
+                continue;
+            }
+
+            match clipboard.get_selection_decoded(*selection) {
+                Ok(Some(DecodedText { text: content, fallback })) if is_capturable(&content) => {
+                    if last_content.as_ref() != Some(&content) {
+                        *last_content = Some(content.clone());
+
+                        let Some(stored) = self.rules.prepare(content) else {
+                            continue;
+                        };
+                        self.store(&stored, *selection, fallback).await;
+                    }
+                }
+                // Empty reads leave last_content untouched so the next real
+                // copy is still seen as a change
+                Ok(_) => {}
+                Err(e) => debug!("Reading {} selection failed: {}", selection, e),
+            }
+        }
     }
-}
\ No newline at end of file
+
+    /// Store one capture, tagged with the selection it came from and with
+    /// the encoding it was decoded from unless that was UTF-8, then start
+    /// the `OnClipAdd` plugins on it in the background.
+    async fn store(&self, stored: &str, selection: Selection, encoding: Option<&str>) {
+        let mut db = self.db.lock().await;
+        match store_capture(&mut db, stored, self.file_clips, self.max_clips).await {
+            Ok(ids) => {
+                info!("Captured {} clip(s) from {} ({})", ids.len(), selection, display::fingerprint(stored));
+                for id in &ids {
+                    self.metrics.record_clip_added();
+                    if self.log_content {
+                        debug!("Clip {} content: {:?}", id, stored);
+                    }
+                    if let Err(e) = db.add_tag_to_clip(id, selection.as_str()).await {
+                        error!("Failed to tag clip with its selection: {}", e);
+                    }
+                    if let Some(encoding) = encoding {
+                        if let Err(e) = db.add_tag_to_clip(id, &format!("encoding:{}", encoding.to_lowercase())).await {
+                            error!("Failed to tag clip with its encoding: {}", e);
+                        }
+                    }
+                    // See store_snapshot for why this blocks
+                    match futures::executor::block_on(db.get_clip_by_id(id)) {
+                        Ok(Some(clip)) => self.trigger_plugins(clip),
+                        Ok(None) => {}
+                        Err(e) => error!("Failed to read clip {} back for plugins: {}", id, e),
+                    }
+                }
+            }
+            Err(e) => error!("Failed to add clip to database: {}", e),
+        }
+    }
+
+    /// Run the `OnClipAdd` plugins on `clip` without holding up polling;
+    /// the plugin manager's limit bounds how many run at once. Each run is
+    /// counted in `metrics`.
+    fn trigger_plugins(&self, clip: Clip) {
+        let plugins = Arc::clone(&self.plugins);
+        let metrics = Arc::clone(&self.metrics);
+        tokio::spawn(async move {
+            for result in plugins.trigger_plugins(&PluginTrigger::OnClipAdd, &clip).await {
+                metrics.record_plugin_run(result.is_ok());
+            }
+        });
+    }
+}
+
+/// Run the task `spawn` creates until it finishes, spawning a fresh one
+/// whenever it panics so a clipboard backend glitch doesn't stop capture.
+/// Restarts back off exponentially; after `MAX_RESTARTS` panics without a
+/// `HEALTHY_RUN` in between, the panic is returned as an error.
+async fn supervise<F, Fut>(name: &str, mut spawn: F) -> Result<()>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    let mut failures = 0;
+    loop {
+        let started = Instant::now();
+        let error = match tokio::spawn(spawn()).await {
+            Ok(()) => return Ok(()),
+            Err(e) if e.is_panic() => e,
+            Err(e) => return Err(e.into()),
+        };
+
+        if started.elapsed() >= HEALTHY_RUN {
+            failures = 0;
+        }
+        failures += 1;
+        let message = panic_message(error.into_panic());
+        if failures > MAX_RESTARTS {
+            anyhow::bail!("{} panicked {} times in a row, giving up: {}", name, failures, message);
+        }
+        let delay = (RESTART_BACKOFF * 2u32.pow(failures - 1)).min(MAX_RESTART_BACKOFF);
+        error!("{} panicked: {}; restarting in {:?}", name, message, delay);
+        sleep(delay).await;
+    }
+}
+
+fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    match payload.downcast::<String>() {
+        Ok(message) => *message,
+        Err(payload) => payload
+            .downcast_ref::<&str>()
+            .map(|message| message.to_string())
+            .unwrap_or_else(|| "unknown panic".to_string()),
+    }
+}
+
+/// Store one captured clipboard payload, trim history to `max_clips`, and
+/// return the new clip ids. Files copied in a file manager often arrive as a
+/// URI list; those become one file clip per path instead of a text clip.
+async fn store_capture(db: &mut Database, content: &str, file_clips: bool, max_clips: usize) -> Result<Vec<String>> {
+    if file_clips {
+        if let Some(paths) = parse_file_list(content) {
+            return db.with_transaction(|tx| {
+                let ids = paths
+                    .iter()
+                    .map(|path| tx.add_file_clip(&path.to_string_lossy()))
+                    .collect::<Result<Vec<_>>>()?;
+                tx.trim_history(max_clips)?;
+                Ok(ids)
+            });
+        }
+    }
+    Ok(vec![db.add_and_trim(content, "text", max_clips).await?])
+}
+
+/// How the daemon turns clipboard text into the clip it stores. Shared by
+/// the monitor and heartbeat snapshots, so one copy is stored the same way
+/// by both.
+#[derive(Clone)]
+struct CaptureRules {
+    transforms: Vec<builtin::Transform>,
+    capture: CaptureConfig,
+    /// Recent clipq output to skip, with `skip_own_output`
+    emitted: Option<EmittedLog>,
+}
+
+impl CaptureRules {
+    fn new(config: &Config) -> Result<Self> {
+        Ok(Self {
+            transforms: config.capture_transforms()?,
+            capture: config.capture.clone(),
+            emitted: config.capture.skip_own_output.then(EmittedLog::active).transpose()?,
+        })
+    }
+
+    /// `content` as it should be stored, or `None` to skip it: clipq's own
+    /// output (with `skip_own_output`), blank once transformed, or outside
+    /// `length_range`.
+    fn prepare(&self, content: String) -> Option<String> {
+        let own_output = self.emitted.as_ref().is_some_and(|log| {
+            log.contains(&content).unwrap_or_else(|e| {
+                error!("Failed to read recent clipq output: {}", e);
+                false
+            })
+        });
+        if own_output {
+            debug!("Skipping clip matching recent clipq output ({})", display::fingerprint(&content));
+            return None;
+        }
+
+        let stored = self.transforms.iter().fold(content, |text, transform| transform(&text));
+        if !is_capturable(&stored) {
+            return None;
+        }
+        if !self.capture.accepts_length(&stored) {
+            debug!("Skipping clip outside capture.length_range ({})", display::fingerprint(&stored));
+            return None;
+        }
+        Some(stored)
+    }
+}
+
+/// One heartbeat: store the clipboard as a snapshot clip, prepared by
+/// `rules` like any capture. Nothing is stored while the clipboard is
+/// locked, or when the result is unchanged since `last_snapshot` or
+/// already the newest clip. Returns the new clip's id.
+async fn take_snapshot(
+    db: &Mutex<Database>,
+    clipboard: &Mutex<ClipboardManager>,
+    rules: &CaptureRules,
+    last_snapshot: &mut Option<String>,
+    max_clips: usize,
+) -> Result<Option<String>> {
+    // The monitor reverts changes to a locked clipboard rather than capture them
+    if lock::locked_content()?.is_some() {
+        return Ok(None);
+    }
+    let content = match clipboard.lock().await.get_selection_text(Selection::Clipboard) {
+        Ok(Some(content)) => content,
+        Ok(None) => return Ok(None),
+        Err(e) => {
+            debug!("Reading clipboard for a snapshot failed: {}", e);
+            return Ok(None);
+        }
+    };
+    let Some(content) = rules.prepare(content) else {
+        return Ok(None);
+    };
+    if last_snapshot.as_ref() == Some(&content) {
+        return Ok(None);
+    }
+
+    let stored = store_snapshot(&mut *db.lock().await, &content, max_clips).await?;
+    *last_snapshot = Some(content);
+    Ok(stored)
+}
+
+/// Store `content` as a heartbeat snapshot tagged `snapshot`, unless it is
+/// already the newest clip. Returns the new clip's id.
+async fn store_snapshot(db: &mut Database, content: &str, max_clips: usize) -> Result<Option<String>> {
+    // Awaiting a `&self` query would hold a borrow of the `!Sync`
+    // connection across the await and make the task `!Send`; it never
+    // suspends anyway
+    let newest = futures::executor::block_on(db.get_recent_clips(1))?;
+    if newest.first().is_some_and(|clip| clip.content == content) {
+        return Ok(None);
+    }
+    let id = db.add_and_trim(content, "text", max_clips).await?;
+    db.add_tag_to_clip(&id, "snapshot").await?;
+    Ok(Some(id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plugins::PluginConfig;
+
+    fn rules(transforms: &[&str]) -> CaptureRules {
+        let mut config = Config::default();
+        config.capture.transforms = transforms.iter().map(|name| name.to_string()).collect();
+        CaptureRules::new(&config).unwrap()
+    }
+
+    async fn memory_db() -> Mutex<Database> {
+        Mutex::new(Database::open(":memory:").await.unwrap())
+    }
+
+    fn memory_clipboard(text: &str) -> (Arc<std::sync::Mutex<Option<String>>>, Mutex<ClipboardManager>) {
+        let contents = Arc::new(std::sync::Mutex::new(Some(text.to_string())));
+        let clipboard = ClipboardManager::in_memory(Arc::clone(&contents));
+        (contents, Mutex::new(clipboard))
+    }
+
+    #[tokio::test]
+    async fn snapshots_are_prepared_like_captures_and_taken_once() {
+        let db = memory_db().await;
+        let (contents, clipboard) = memory_clipboard("\u{201C}quoted\u{201D}");
+        let rules = rules(&["normalize_quotes"]);
+        // What the monitor stored for the same copy
+        let captured = rules.prepare("\u{201C}quoted\u{201D}".to_string()).unwrap();
+        db.lock().await.add_clip(&captured, "text").await.unwrap();
+
+        let mut last_snapshot = None;
+        for _ in 0..3 {
+            let stored = take_snapshot(&db, &clipboard, &rules, &mut last_snapshot, 100).await.unwrap();
+            assert_eq!(stored, None);
+        }
+
+        *contents.lock().unwrap() = Some("later".to_string());
+        let mut stored = Vec::new();
+        for tick in 0..3 {
+            stored.extend(take_snapshot(&db, &clipboard, &rules, &mut last_snapshot, 100).await.unwrap());
+            // Other captures (say, of the primary selection) keep landing on top
+            db.lock().await.add_clip(&format!("newer {}", tick), "text").await.unwrap();
+        }
+
+        assert_eq!(stored.len(), 1);
+        let db = db.lock().await;
+        assert_eq!(db.get_clip_tags(&stored[0]).await.unwrap(), vec!["snapshot"]);
+        assert_eq!(db.get_recent_clips(10).await.unwrap().len(), 5);
+    }
+
+    #[tokio::test]
+    async fn copied_files_are_stored_and_trimmed_together() {
+        let dir = std::env::temp_dir().join(format!("clipq-files-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let files: Vec<_> = ["a.txt", "b.txt"].iter().map(|name| dir.join(name)).collect();
+        for file in &files {
+            std::fs::write(file, "x").unwrap();
+        }
+        let uri_list: String = files.iter().map(|file| format!("file://{}\r\n", file.display())).collect();
+
+        let db = memory_db().await;
+        let mut db = db.lock().await;
+        for i in 0..3 {
+            db.add_clip(&format!("clip {}", i), "text").await.unwrap();
+        }
+        let ids = store_capture(&mut db, &uri_list, true, 3).await.unwrap();
+
+        assert_eq!(ids.len(), 2);
+        let clips = db.get_recent_clips(10).await.unwrap();
+        assert_eq!(clips.len(), 3);
+        assert_eq!(clips.iter().filter(|clip| clip.clip_type == "file").count(), 2);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn recent_clipq_output_is_skipped() {
+        let dir = std::env::temp_dir().join(format!("clipq-emitted-{}", uuid::Uuid::new_v4()));
+        let log = EmittedLog::at(dir.join("emitted"));
+        log.record_all(["Pasted: hello", "1: hello"]).unwrap();
+        let mut rules = rules(&[]);
+        rules.emitted = Some(log);
+
+        assert_eq!(rules.prepare("Pasted: hello\n".to_string()), None);
+        assert_eq!(rules.prepare("1: hello".to_string()), None);
+        assert_eq!(rules.prepare("hello".to_string()).as_deref(), Some("hello"));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    async fn stored_contents(db: &Mutex<Database>) -> Vec<String> {
+        let clips = db.lock().await.get_recent_clips(10).await.unwrap();
+        clips.into_iter().map(|clip| clip.content).collect()
+    }
+
+    #[tokio::test]
+    async fn watch_paste_pretty_prints_a_json_copy_exactly_once() {
+        let db = memory_db().await;
+        let (contents, clipboard) = memory_clipboard(r#"{"a":1}"#);
+        let steps = builtin::parse_pipeline("format_json").unwrap();
+        let mut watcher = PasteWatcher::new(steps, rules(&[]), 100);
+
+        // The pretty-printed result written back is seen on the next polls
+        let mut stored = Vec::new();
+        for _ in 0..3 {
+            stored.extend(watcher.poll(&clipboard, &db, None).await.unwrap());
+        }
+
+        let pretty = "{\n  \"a\": 1\n}";
+        assert_eq!(stored.len(), 1);
+        assert_eq!(contents.lock().unwrap().as_deref(), Some(pretty));
+        assert_eq!(stored_contents(&db).await, vec![pretty]);
+    }
+
+    #[tokio::test]
+    async fn watch_paste_leaves_locked_and_own_output_alone() {
+        let db = memory_db().await;
+        let (contents, clipboard) = memory_clipboard(r#"{"a":1}"#);
+        let dir = std::env::temp_dir().join(format!("clipq-emitted-{}", uuid::Uuid::new_v4()));
+        let log = EmittedLog::at(dir.join("emitted"));
+        log.record_all([r#"{"b":2}"#]).unwrap();
+        let mut rules = rules(&[]);
+        rules.emitted = Some(log);
+        let mut watcher = PasteWatcher::new(builtin::parse_pipeline("format_json").unwrap(), rules, 100);
+
+        assert_eq!(watcher.poll(&clipboard, &db, Some("locked")).await.unwrap(), None);
+        assert_eq!(contents.lock().unwrap().as_deref(), Some("locked"));
+
+        *contents.lock().unwrap() = Some(r#"{"b":2}"#.to_string());
+        assert_eq!(watcher.poll(&clipboard, &db, None).await.unwrap(), None);
+        assert_eq!(contents.lock().unwrap().as_deref(), Some(r#"{"b":2}"#));
+
+        assert!(stored_contents(&db).await.is_empty());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn max_clips_flag_beats_the_retention_config() {
+        let mut config = Config {
+            max_clips: 100,
+            ..Config::default()
+        };
+        assert_eq!(retention_policy(&config, None).max_clips, Some(100));
+        assert_eq!(retention_policy(&config, Some(20)).max_clips, Some(20));
+
+        config.retention.max_clips = Some(500);
+        config.retention.max_age_days = Some(7);
+        assert_eq!(retention_policy(&config, None).max_clips, Some(500));
+        let policy = retention_policy(&config, Some(20));
+        assert_eq!(policy.max_clips, Some(20));
+        assert_eq!(policy.max_age_days, Some(7));
+    }
+
+    #[tokio::test]
+    async fn captures_are_tagged_with_their_selection() {
+        let db = Arc::new(memory_db().await);
+        let (_contents, clipboard) = memory_clipboard("copied text");
+        let monitor = Monitor {
+            db: Arc::clone(&db),
+            clipboard: Arc::new(clipboard),
+            selections: vec![Selection::Clipboard],
+            rules: rules(&[]),
+            metrics: Arc::default(),
+            plugins: Arc::new(PluginManager::new(1)),
+            file_clips: false,
+            log_content: false,
+            max_clips: 100,
+        };
+        monitor.poll(&mut [None]).await;
+
+        let db = db.lock().await;
+        let clip = db.find_clip_by_content("copied text").await.unwrap().unwrap();
+        assert_eq!(db.get_clip_tags(&clip.id).await.unwrap(), vec!["clipboard"]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn a_panicking_monitor_restarts_and_keeps_capturing() {
+        let db = Arc::new(memory_db().await);
+        let (contents, clipboard) = memory_clipboard("before the glitch");
+        let monitor = Monitor {
+            db: Arc::clone(&db),
+            clipboard: Arc::new(clipboard),
+            selections: vec![Selection::Clipboard],
+            rules: rules(&[]),
+            metrics: Arc::default(),
+            plugins: Arc::new(PluginManager::new(1)),
+            file_clips: false,
+            log_content: false,
+            max_clips: 100,
+        };
+        let starts = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let spawn = {
+            let starts = Arc::clone(&starts);
+            move || {
+                let monitor = monitor.clone();
+                let first = starts.fetch_add(1, std::sync::atomic::Ordering::SeqCst) == 0;
+                async move {
+                    if first {
+                        monitor.poll(&mut [None]).await;
+                        panic!("clipboard backend glitch");
+                    }
+                    monitor.run().await
+                }
+            }
+        };
+
+        let stored = |db: Arc<Mutex<Database>>| async move {
+            let clips = db.lock().await.get_recent_clips(10).await.unwrap();
+            clips.into_iter().map(|clip| clip.content).collect::<Vec<_>>()
+        };
+        let check = async {
+            while starts.load(std::sync::atomic::Ordering::SeqCst) < 2 {
+                sleep(POLL_INTERVAL).await;
+            }
+            *contents.lock().unwrap() = Some("after the restart".to_string());
+            while !stored(Arc::clone(&db)).await.contains(&"after the restart".to_string()) {
+                sleep(POLL_INTERVAL).await;
+            }
+        };
+        tokio::select! {
+            result = supervise("Clipboard monitor", spawn) => panic!("supervisor stopped: {:?}", result),
+            () = check => {}
+        }
+
+        assert!(stored(Arc::clone(&db)).await.contains(&"before the glitch".to_string()));
+    }
+
+    #[tokio::test]
+    async fn plugin_runs_on_captures_are_counted() {
+        let mut plugins = PluginManager::new(1);
+        for (name, command) in [("ok", "cat"), ("failing", "false")] {
+            let plugin = PluginConfig::builder(name)
+                .command(command)
+                .trigger(PluginTrigger::OnClipAdd)
+                .build()
+                .unwrap();
+            plugins.add_plugin(plugin).unwrap();
+        }
+        let metrics = Arc::new(Metrics::default());
+        let (_contents, clipboard) = memory_clipboard("copied text");
+        let monitor = Monitor {
+            db: Arc::new(memory_db().await),
+            clipboard: Arc::new(clipboard),
+            selections: vec![Selection::Clipboard],
+            rules: rules(&[]),
+            metrics: Arc::clone(&metrics),
+            plugins: Arc::new(plugins),
+            file_clips: false,
+            log_content: false,
+            max_clips: 100,
+        };
+        monitor.poll(&mut [None]).await;
+
+        // The plugins run in the background
+        let finished = "clipq_plugin_executions_total 2\n";
+        for _ in 0..100 {
+            if metrics.render(0, 0).contains(finished) {
+                break;
+            }
+            sleep(Duration::from_millis(20)).await;
+        }
+        let rendered = metrics.render(0, 0);
+        assert!(rendered.contains(finished), "{}", rendered);
+        assert!(rendered.contains("clipq_plugin_failures_total 1\n"), "{}", rendered);
+    }
+
+    #[tokio::test]
+    async fn empty_reads_are_not_stored_and_dont_hide_the_next_copy() {
+        let db = Arc::new(memory_db().await);
+        let (contents, clipboard) = memory_clipboard("");
+        let monitor = Monitor {
+            db: Arc::clone(&db),
+            clipboard: Arc::new(clipboard),
+            selections: vec![Selection::Clipboard],
+            rules: rules(&[]),
+            metrics: Arc::new(Metrics::default()),
+            plugins: Arc::new(PluginManager::new(1)),
+            file_clips: false,
+            log_content: false,
+            max_clips: 100,
+        };
+        let mut last_contents = [None];
+
+        monitor.poll(&mut last_contents).await;
+        *contents.lock().unwrap() = None;
+        monitor.poll(&mut last_contents).await;
+        assert_eq!(last_contents, [None]);
+        assert!(db.lock().await.get_all_clips().await.unwrap().is_empty());
+
+        *contents.lock().unwrap() = Some("real copy".to_string());
+        monitor.poll(&mut last_contents).await;
+        let clips = db.lock().await.get_all_clips().await.unwrap();
+        assert_eq!(clips.len(), 1);
+        assert_eq!(clips[0].content, "real copy");
+    }
+
+    /// Every log record, from any test, once `captured_logs` has been called
+    struct CapturedLogs(std::sync::Mutex<Vec<String>>);
+
+    impl log::Log for CapturedLogs {
+        fn enabled(&self, _: &log::Metadata) -> bool {
+            true
+        }
+
+        fn log(&self, record: &log::Record) {
+            self.0.lock().unwrap().push(format!("{} {}", record.level(), record.args()));
+        }
+
+        fn flush(&self) {}
+    }
+
+    static LOGS: CapturedLogs = CapturedLogs(std::sync::Mutex::new(Vec::new()));
+
+    fn captured_logs() -> &'static CapturedLogs {
+        if log::set_logger(&LOGS).is_ok() {
+            log::set_max_level(log::LevelFilter::Trace);
+        }
+        &LOGS
+    }
+
+    #[tokio::test]
+    async fn captured_content_stays_out_of_the_logs_unless_asked_for() {
+        let logs = captured_logs();
+        for log_content in [false, true] {
+            let secret = format!("hunter2 {}", uuid::Uuid::new_v4());
+            let (_contents, clipboard) = memory_clipboard(&secret);
+            let monitor = Monitor {
+                db: Arc::new(memory_db().await),
+                clipboard: Arc::new(clipboard),
+                selections: vec![Selection::Clipboard],
+                rules: rules(&[]),
+                metrics: Arc::new(Metrics::default()),
+                plugins: Arc::new(PluginManager::new(1)),
+                file_clips: false,
+                log_content,
+                max_clips: 100,
+            };
+            monitor.poll(&mut [None]).await;
+
+            let records = logs.0.lock().unwrap();
+            let fingerprint = display::fingerprint(&secret);
+            assert!(records.iter().any(|record| record.starts_with("INFO") && record.contains(&fingerprint)));
+            let leaked: Vec<&String> = records.iter().filter(|record| record.contains(&secret)).collect();
+            if log_content {
+                assert!(!leaked.is_empty() && leaked.iter().all(|record| record.starts_with("DEBUG")), "{:?}", leaked);
+            } else {
+                assert!(leaked.is_empty(), "{:?}", leaked);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn only_clips_within_the_length_range_are_captured() {
+        let db = Arc::new(memory_db().await);
+        let (contents, clipboard) = memory_clipboard("");
+        let mut config = Config::default();
+        config.capture.length_range = Some((3, 5));
+        let monitor = Monitor {
+            db: Arc::clone(&db),
+            clipboard: Arc::new(clipboard),
+            selections: vec![Selection::Clipboard],
+            rules: CaptureRules::new(&config).unwrap(),
+            metrics: Arc::default(),
+            plugins: Arc::new(PluginManager::new(1)),
+            file_clips: false,
+            log_content: false,
+            max_clips: 100,
+        };
+
+        let mut last = [None];
+        // Counted in characters, not bytes: 日本語 is nine bytes
+        for copied in ["ab", "abc", "abcde", "abcdef", "日本語"] {
+            *contents.lock().unwrap() = Some(copied.to_string());
+            monitor.poll(&mut last).await;
+        }
+
+        let mut stored: Vec<String> = db.lock().await.get_recent_clips(10).await.unwrap().into_iter().map(|clip| clip.content).collect();
+        stored.sort();
+        assert_eq!(stored, ["abc", "abcde", "日本語"]);
+    }
+}
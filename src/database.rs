@@ -1,10 +1,52 @@
-use anyhow::Result;
-use chrono::{DateTime, Utc};
-use rusqlite::{params, Connection, Row};
+use anyhow::{Context, Result};
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use futures::stream::{self, Stream, TryStreamExt};
+use log::warn;
+use rusqlite::{params, Connection, OpenFlags, OptionalExtension, Row, TransactionBehavior};
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
 use std::path::Path;
+use regex::Regex;
 use uuid::Uuid;
 
+use crate::audit::{AuditAction, AuditLog};
+use crate::config::{RetentionPolicy, TagCase, Timezone};
+use crate::display;
+use crate::events::{ClipEvent, ClipEventRecord};
+use crate::profile;
+
+/// Most recent history events kept for `clipq tail` and `/api/events`.
+pub const EVENT_LOG_SIZE: usize = 1000;
+/// Rows fetched per query by `Database::stream_clips`.
+const STREAM_PAGE_SIZE: usize = 256;
+
+/// `(created_at, id)` of the last row `stream_clips` has yielded.
+type StreamCursor = (i64, String);
+
+/// Longest span `clips_per_day` reports, about ten years; callers taking
+/// the day count from users clamp it to this.
+pub const MAX_ACTIVITY_DAYS: usize = 3650;
+
+/// Previous contents kept per clip unless `set_max_versions` says otherwise.
+pub const DEFAULT_MAX_VERSIONS: usize = 10;
+/// Embedded files over this many bytes are stored zstd-compressed.
+pub const DEFAULT_COMPRESSION_THRESHOLD: usize = 4096;
+pub const DEFAULT_COMPRESSION_LEVEL: i32 = 3;
+/// Longest tag name in characters unless `set_max_tag_length` says otherwise.
+pub const DEFAULT_MAX_TAG_LENGTH: usize = 64;
+
+/// Why `validate_tag_name` rejected a tag name.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum TagNameError {
+    #[error("Tag names must not be empty")]
+    Empty,
+    #[error("Tag name is {length} characters long; the limit is {max}")]
+    TooLong { length: usize, max: usize },
+    #[error("Tag names must not contain control characters")]
+    ControlCharacter,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Clip {
     pub id: String,
@@ -12,6 +54,245 @@ pub struct Clip {
     pub clip_type: String,
     pub created_at: DateTime<Utc>,
     pub file_path: Option<String>,
+    #[serde(default)]
+    pub pinned: bool,
+    #[serde(default)]
+    pub expires_at: Option<DateTime<Utc>>,
+    /// Clipboard format the content was captured as, e.g. `text/html`
+    #[serde(default = "unknown_mime")]
+    pub mime: String,
+    /// File name of the bytes embedded with a `file --embed` clip
+    #[serde(default)]
+    pub original_name: Option<String>,
+}
+
+/// Recorded for clips whose original format was never captured.
+pub const UNKNOWN_MIME: &str = "text/plain";
+
+fn unknown_mime() -> String {
+    UNKNOWN_MIME.to_string()
+}
+
+/// The format a clip of `clip_type` is stored as when the source gives no
+/// more specific identifier.
+pub fn default_mime(clip_type: &str) -> &'static str {
+    match clip_type {
+        "file" => "text/uri-list",
+        "image" => "image/png",
+        "html" => "text/html",
+        // Clipboard text always reaches us decoded to UTF-8
+        _ => "text/plain;charset=utf-8",
+    }
+}
+
+/// A clip together with its tags, as written by `export` and read by `import`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TaggedClip {
+    #[serde(flatten)]
+    pub clip: Clip,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Bytes of an embedded file or image clip, base64 in exports
+    #[serde(default, skip_serializing_if = "Option::is_none", with = "base64_bytes")]
+    pub data: Option<Vec<u8>>,
+}
+
+/// Serde helpers writing optional bytes as standard base64.
+mod base64_bytes {
+    use base64::engine::general_purpose::STANDARD;
+    use base64::Engine;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(data: &Option<Vec<u8>>, serializer: S) -> Result<S::Ok, S::Error> {
+        match data {
+            Some(data) => serializer.serialize_str(&STANDARD.encode(data)),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Vec<u8>>, D::Error> {
+        Option::<String>::deserialize(deserializer)?
+            .map(|text| STANDARD.decode(text).map_err(serde::de::Error::custom))
+            .transpose()
+    }
+}
+
+/// A clip read from an import file, for `import_clip`.
+#[derive(Debug, Clone, Default)]
+pub struct ImportedClip {
+    pub content: String,
+    pub clip_type: String,
+    pub mime: String,
+    pub tags: Vec<String>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub file_path: Option<String>,
+    pub original_name: Option<String>,
+    /// Embedded file or image bytes
+    pub data: Option<Vec<u8>>,
+}
+
+impl ImportedClip {
+    /// A clip of `clip_type` with the type's default MIME and nothing else.
+    pub fn new(content: String, clip_type: &str) -> Self {
+        Self {
+            content,
+            clip_type: clip_type.to_string(),
+            mime: default_mime(clip_type).to_string(),
+            ..Self::default()
+        }
+    }
+}
+
+impl From<TaggedClip> for ImportedClip {
+    fn from(tagged: TaggedClip) -> Self {
+        let clip = tagged.clip;
+        Self {
+            content: clip.content,
+            clip_type: clip.clip_type,
+            mime: clip.mime,
+            tags: tagged.tags,
+            expires_at: clip.expires_at,
+            file_path: clip.file_path,
+            original_name: clip.original_name,
+            data: tagged.data,
+        }
+    }
+}
+
+/// Order in which whole-history reads return clips.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ClipOrder {
+    /// Most recent `created_at` first; clips from the same second may come
+    /// in either order
+    #[default]
+    Newest,
+    /// Order of insertion (rowid), identical on every read of an unchanged
+    /// database
+    Insertion,
+}
+
+impl ClipOrder {
+    fn sql(self) -> &'static str {
+        match self {
+            ClipOrder::Newest => "created_at DESC",
+            ClipOrder::Insertion => "rowid",
+        }
+    }
+}
+
+/// What `import_clip` does when a clip with the same content already exists.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ConflictMode {
+    /// Leave the existing clip alone and drop the imported one
+    Skip,
+    /// Insert the imported clip as a new row anyway
+    Duplicate,
+    /// Attach the imported clip's tags to the existing clip
+    Merge,
+}
+
+/// Conditions a clip must meet to be counted by `Database::count_clips`.
+/// Unset fields don't restrict anything.
+#[derive(Debug, Clone, Default)]
+pub struct ClipFilter {
+    /// Content contains this, matched like `search`
+    pub contains: Option<String>,
+    /// Content is exactly this
+    pub equals: Option<String>,
+    pub tag: Option<String>,
+    pub clip_type: Option<String>,
+}
+
+/// How `get_clips_by_tags` combines several tags.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TagMatch {
+    /// Clips carrying every one of the tags
+    All,
+    /// Clips carrying at least one of the tags
+    Any,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportOutcome {
+    Inserted,
+    Skipped,
+    Merged,
+}
+
+/// Result of looking up a clip by the start of its id.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IdResolution {
+    Unique(String),
+    /// Several clips share the prefix; holds (some of) their ids
+    Ambiguous(Vec<String>),
+    NotFound,
+}
+
+/// Content a clip had before an edit replaced it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClipVersion {
+    pub content: String,
+    /// When this content was replaced
+    pub edited_at: DateTime<Utc>,
+}
+
+/// What `Database::repair` cleaned up and what SQLite's integrity check
+/// reported.
+#[derive(Debug, Clone, Default)]
+pub struct RepairReport {
+    /// Tag links pointing at clips or tags that no longer exist
+    pub orphaned_links: usize,
+    /// Saved versions of clips that no longer exist
+    pub orphaned_versions: usize,
+    /// Tags on no clip and without a color
+    pub unused_tags: usize,
+    /// Problems `PRAGMA integrity_check` found; empty when it passed
+    pub integrity_errors: Vec<String>,
+}
+
+/// A burst of clips, each copied within the session gap of the one before.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Session {
+    /// Id of the session's first clip
+    pub id: String,
+    pub started_at: DateTime<Utc>,
+    pub ended_at: DateTime<Utc>,
+    pub clip_count: usize,
+}
+
+/// Every live clip's `id`, `created_at` and `session_id` (its session's
+/// first clip) for a gap of `?1` seconds. Sessions are derived on each
+/// query, so changing the gap regroups the whole history.
+const SESSION_CLIPS_SQL: &str = "
+    WITH gaps AS (
+        SELECT id, created_at,
+               CASE WHEN created_at - LAG(created_at) OVER (ORDER BY created_at, id) <= ?1
+                    THEN 0 ELSE 1 END AS starts
+        FROM live_clips
+    ), numbered AS (
+        SELECT id, created_at, SUM(starts) OVER (ORDER BY created_at, id) AS session FROM gaps
+    )
+    SELECT id, created_at,
+           FIRST_VALUE(id) OVER (PARTITION BY session ORDER BY created_at, id) AS session_id
+    FROM numbered";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TagInfo {
+    pub name: String,
+    pub color: Option<String>,
+}
+
+/// Condition on `tags` matching uncolored tags no existing clip carries.
+/// Foreign keys are off, so `clip_tags` may still link deleted clips.
+const UNUSED_TAGS_SQL: &str = "color IS NULL AND id NOT IN (
+    SELECT ct.tag_id FROM clip_tags ct JOIN clips c ON c.id = ct.clip_id
+)";
+
+/// A tag and how many clips carry it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TagUsage {
+    pub name: String,
+    pub clips: usize,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -22,47 +303,240 @@ pub struct Statistics {
     pub oldest_clip: String,
     pub newest_clip: String,
     pub db_size_kb: usize,
+    /// Clips with embedded file bytes, and how many of those are compressed
+    pub embedded_clips: usize,
+    pub compressed_clips: usize,
+    /// Embedded bytes as referenced by clips, and as stored after
+    /// compression and sharing identical files
+    pub embedded_original_bytes: u64,
+    pub embedded_stored_bytes: u64,
 }
 
-impl From<&Row<'_>> for Clip {
-    fn from(row: &Row) -> Self {
-        Self {
-            id: row.get("id").unwrap_or_default(),
-            content: row.get("content").unwrap_or_default(),
-            clip_type: row.get("clip_type").unwrap_or_default(),
-            created_at: DateTime::from_timestamp(
-                row.get::<_, i64>("created_at").unwrap_or(0),
-                0,
-            ).unwrap_or_else(|| Utc::now()),
-            file_path: row.get("file_path").ok(),
+impl Statistics {
+    /// Original embedded size over stored size; `None` with nothing embedded.
+    pub fn compression_ratio(&self) -> Option<f64> {
+        (self.embedded_stored_bytes > 0)
+            .then(|| self.embedded_original_bytes as f64 / self.embedded_stored_bytes as f64)
+    }
+}
+
+impl Clip {
+    /// Whether this is a file clip whose file no longer exists.
+    pub fn is_stale(&self) -> bool {
+        self.clip_type == "file"
+            && self
+                .file_path
+                .as_deref()
+                .is_some_and(|path| !Path::new(path).exists())
+    }
+
+    /// The content on one line, at most `max_len` characters; see
+    /// `display::one_line_preview`.
+    pub fn preview(&self, max_len: usize) -> String {
+        display::one_line_preview(&self.content, max_len)
+    }
+
+    /// Like `preview`, with `patterns` masked before the content is cut so
+    /// a secret straddling the cut is still hidden.
+    pub fn redacted_preview(&self, max_len: usize, patterns: &[Regex]) -> String {
+        display::one_line_preview(&display::redact(&self.content, patterns), max_len)
+    }
+}
+
+impl Clip {
+    /// Build a clip from a `clips` row. Columns older databases lack get
+    /// defaults, but a NULL or malformed value in a required column is an
+    /// error rather than a blank clip.
+    pub fn try_from_row(row: &Row) -> rusqlite::Result<Self> {
+        Ok(Self {
+            id: row.get("id")?,
+            content: row.get("content")?,
+            clip_type: row.get("clip_type")?,
+            created_at: timestamp_column(row, "created_at", row.get("created_at")?)?,
+            file_path: optional_column(row, "file_path")?.flatten(),
+            pinned: optional_column(row, "pinned")?.unwrap_or_default(),
+            expires_at: optional_column::<Option<i64>>(row, "expires_at")?
+                .flatten()
+                .map(|timestamp| timestamp_column(row, "expires_at", timestamp))
+                .transpose()?,
+            mime: optional_column(row, "mime")?.unwrap_or_else(unknown_mime),
+            original_name: optional_column(row, "original_name")?.flatten(),
+        })
+    }
+}
+
+/// A column's value, or `None` if the row has no such column.
+fn optional_column<T: rusqlite::types::FromSql>(row: &Row, name: &str) -> rusqlite::Result<Option<T>> {
+    match row.get(name) {
+        Ok(value) => Ok(Some(value)),
+        Err(rusqlite::Error::InvalidColumnName(_)) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Unix `seconds` read from column `name`, rejecting ones chrono can't represent.
+fn timestamp_column(row: &Row, name: &str, seconds: i64) -> rusqlite::Result<DateTime<Utc>> {
+    DateTime::from_timestamp(seconds, 0)
+        .ok_or_else(|| row.as_ref().column_index(name).map_or_else(|e| e, |i| rusqlite::Error::IntegralValueOutOfRange(i, seconds)))
+}
+
+/// Whether a row failed to convert because of its stored values, as opposed
+/// to the query itself failing.
+fn is_corrupt_row(error: &rusqlite::Error) -> bool {
+    matches!(
+        error,
+        rusqlite::Error::InvalidColumnType(..)
+            | rusqlite::Error::FromSqlConversionFailure(..)
+            | rusqlite::Error::IntegralValueOutOfRange(..)
+    )
+}
+
+/// Collect converted clips, skipping corrupt rows with a warning so one bad
+/// row doesn't hide the rest of the history.
+fn collect_clips(rows: impl Iterator<Item = rusqlite::Result<Clip>>) -> Result<Vec<Clip>> {
+    let mut clips = Vec::new();
+    let mut skipped = 0;
+    for row in rows {
+        match row {
+            Ok(clip) => clips.push(clip),
+            Err(e) if is_corrupt_row(&e) => {
+                warn!("Skipping corrupt clip row: {}", e);
+                skipped += 1;
+            }
+            Err(e) => return Err(e.into()),
         }
     }
+    warn_skipped(skipped);
+    Ok(clips)
+}
+
+fn warn_skipped(skipped: usize) {
+    if skipped > 0 {
+        warn!("Skipped {} corrupt clip row(s); run `clipq repair` to check the database", skipped);
+    }
+}
+
+/// Embedded file bytes as stored, possibly compressed. Moved into `blobs`
+/// when migrating old rows and copied verbatim by `import_database`.
+struct StoredBlob {
+    data: Vec<u8>,
+    compressed: bool,
+    /// Unset in rows from before sizes were recorded, which were never
+    /// compressed
+    original_size: Option<i64>,
 }
 
 pub struct Database {
     conn: Connection,
+    path: String,
+    tag_case: TagCase,
+    max_tag_length: usize,
+    max_versions: usize,
+    compression_threshold: usize,
+    compression_level: i32,
+    audit: Option<AuditLog>,
 }
 
 impl Database {
+    /// Open the active profile's database.
     pub async fn new() -> Result<Self> {
-        let db_path = Self::get_db_path()?;
-        
+        let db_path = profile::active().database_path()?;
+        Self::open(&db_path).await
+    }
+
+    /// Open (creating if needed) a database at an explicit path.
+    pub async fn open(db_path: &str) -> Result<Self> {
         // Create directory if it doesn't exist
-        if let Some(parent) = Path::new(&db_path).parent() {
+        if let Some(parent) = Path::new(db_path).parent() {
             std::fs::create_dir_all(parent)?;
         }
         
-        let conn = Connection::open(&db_path)?;
-        let db = Database { conn };
+        let conn = Connection::open(db_path)?;
+        let db = Database {
+            conn,
+            path: db_path.to_string(),
+            tag_case: TagCase::default(),
+            max_tag_length: DEFAULT_MAX_TAG_LENGTH,
+            max_versions: DEFAULT_MAX_VERSIONS,
+            compression_threshold: DEFAULT_COMPRESSION_THRESHOLD,
+            compression_level: DEFAULT_COMPRESSION_LEVEL,
+            audit: None,
+        };
         db.init_tables().await?;
         Ok(db)
     }
 
-    fn get_db_path() -> Result<String> {
-        let home = dirs::home_dir()
-            .ok_or_else(|| anyhow::anyhow!("Could not find home directory"))?;
-        let db_path = home.join(".clipq").join("clipboard.db");
-        Ok(db_path.to_string_lossy().to_string())
+    /// Casing applied to tag names stored from now on.
+    pub fn set_tag_case(&mut self, tag_case: TagCase) {
+        self.tag_case = tag_case;
+    }
+
+    /// Longest tag name, in characters, accepted from now on.
+    pub fn set_max_tag_length(&mut self, max_tag_length: usize) {
+        self.max_tag_length = max_tag_length;
+    }
+
+    /// `name` checked and cased as it should be stored.
+    fn tag_name(&self, name: &str) -> Result<String> {
+        Ok(self.tag_case.apply(validate_tag_name(name, self.max_tag_length)?))
+    }
+
+    /// How many previous contents to keep per clip; 0 stops recording them.
+    pub fn set_max_versions(&mut self, max_versions: usize) {
+        self.max_versions = max_versions;
+    }
+
+    /// Embedded files over `threshold` bytes are stored zstd-compressed at
+    /// `level` from now on.
+    pub fn set_compression(&mut self, threshold: usize, level: i32) {
+        self.compression_threshold = threshold;
+        self.compression_level = level;
+    }
+
+    /// Record clip additions and deletions in `audit` from now on.
+    pub fn set_audit_log(&mut self, audit: Option<AuditLog>) {
+        self.audit = audit;
+    }
+
+    fn audit<'a>(&self, action: AuditAction, clips: impl IntoIterator<Item = (&'a str, &'a str)>) -> Result<()> {
+        match &self.audit {
+            Some(audit) => audit.record(action, clips),
+            None => Ok(()),
+        }
+    }
+
+    /// Record deleted `(id, content)` rows in the audit log and count them.
+    fn audit_deleted(&self, deleted: &[(String, String)]) -> Result<usize> {
+        self.audit(AuditAction::Delete, deleted.iter().map(|(id, content)| (id.as_str(), content.as_str())))?;
+        Ok(deleted.len())
+    }
+
+    /// Run `f` in one transaction, committing what it wrote if it returns
+    /// `Ok` and rolling everything back if it fails. The write lock is taken
+    /// up front, so other processes can't interleave writes. Additions and
+    /// deletions are audited once the transaction has committed.
+    pub fn with_transaction<F, T>(&mut self, f: F) -> Result<T>
+    where
+        F: FnOnce(&mut ClipTransaction<'_>) -> Result<T>,
+    {
+        let (value, added, deleted) = {
+            let mut tx = ClipTransaction {
+                tx: self.conn.transaction_with_behavior(TransactionBehavior::Immediate)?,
+                tag_case: self.tag_case,
+                max_tag_length: self.max_tag_length,
+                compression: (self.compression_threshold, self.compression_level),
+                added: Vec::new(),
+                deleted: Vec::new(),
+            };
+            let value = f(&mut tx)?;
+            let ClipTransaction { tx, added, deleted, .. } = tx;
+            tx.commit()?;
+            (value, added, deleted)
+        };
+
+        self.audit(AuditAction::Add, added.iter().map(|(id, content)| (id.as_str(), content.as_str())))?;
+        self.audit_deleted(&deleted)?;
+        Ok(value)
     }
 
     async fn init_tables(&self) -> Result<()> {
@@ -77,6 +551,59 @@ impl Database {
             [],
         )?;
 
+        self.add_column_if_missing("clips", "pinned", "INTEGER NOT NULL DEFAULT 0")?;
+        self.add_column_if_missing("clips", "expires_at", "INTEGER")?;
+        self.add_column_if_missing("clips", "mime", "TEXT NOT NULL DEFAULT 'text/plain'")?;
+        self.add_column_if_missing("clips", "blob_data", "BLOB")?;
+        self.add_column_if_missing("clips", "original_name", "TEXT")?;
+        self.add_column_if_missing("clips", "compressed", "INTEGER NOT NULL DEFAULT 0")?;
+        self.add_column_if_missing("clips", "original_size", "INTEGER")?;
+        self.add_column_if_missing("clips", "blob_hash", "TEXT")?;
+        // When the clip was added or last moved to the top, in milliseconds;
+        // trimming orders by it so clips from the same second keep their order
+        self.add_column_if_missing("clips", "created_at_ms", "INTEGER")?;
+        self.conn.execute(
+            "UPDATE clips SET created_at_ms = created_at * 1000 WHERE created_at_ms IS NULL",
+            [],
+        )?;
+
+        // Embedded file bytes, stored once per distinct content and shared
+        // by every clip referencing them. Triggers keep `refcount` in step
+        // with the clips, so every way of deleting a clip frees its blob.
+        self.conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS blobs (
+                hash TEXT PRIMARY KEY,
+                data BLOB NOT NULL,
+                compressed INTEGER NOT NULL DEFAULT 0,
+                original_size INTEGER NOT NULL,
+                refcount INTEGER NOT NULL DEFAULT 0
+            );
+            CREATE TRIGGER IF NOT EXISTS blobs_ref_insert AFTER INSERT ON clips
+            WHEN new.blob_hash IS NOT NULL BEGIN
+                UPDATE blobs SET refcount = refcount + 1 WHERE hash = new.blob_hash;
+            END;
+            CREATE TRIGGER IF NOT EXISTS blobs_ref_delete AFTER DELETE ON clips
+            WHEN old.blob_hash IS NOT NULL BEGIN
+                UPDATE blobs SET refcount = refcount - 1 WHERE hash = old.blob_hash;
+                DELETE FROM blobs WHERE hash = old.blob_hash AND refcount <= 0;
+            END;
+            CREATE TRIGGER IF NOT EXISTS blobs_ref_update AFTER UPDATE OF blob_hash ON clips
+            WHEN old.blob_hash IS NOT new.blob_hash BEGIN
+                UPDATE blobs SET refcount = refcount + 1 WHERE hash = new.blob_hash;
+                UPDATE blobs SET refcount = refcount - 1 WHERE hash = old.blob_hash;
+                DELETE FROM blobs WHERE hash = old.blob_hash AND refcount <= 0;
+            END;"
+        )?;
+        self.migrate_inline_blobs()?;
+
+        // Read paths go through this view so expired clips disappear immediately,
+        // even before the next retention pass deletes them
+        self.conn.execute(
+            "CREATE VIEW IF NOT EXISTS live_clips AS
+                SELECT * FROM clips WHERE expires_at IS NULL OR expires_at > unixepoch()",
+            [],
+        )?;
+
         self.conn.execute(
             "CREATE TABLE IF NOT EXISTS tags (
                 id INTEGER PRIMARY KEY AUTOINCREMENT,
@@ -85,6 +612,8 @@ impl Database {
             [],
         )?;
 
+        self.add_column_if_missing("tags", "color", "TEXT")?;
+
         self.conn.execute(
             "CREATE TABLE IF NOT EXISTS clip_tags (
                 clip_id TEXT NOT NULL,
@@ -96,6 +625,80 @@ impl Database {
             [],
         )?;
 
+        // Tags differing only in case are one tag. Databases from before
+        // this rule may hold several variants, so merge them before the
+        // index can be created.
+        let has_nocase_index: bool = self.conn.query_row(
+            "SELECT EXISTS(SELECT 1 FROM sqlite_master WHERE type = 'index' AND name = 'idx_tags_name_nocase')",
+            [],
+            |row| row.get(0),
+        )?;
+        if !has_nocase_index {
+            let tx = self.conn.unchecked_transaction()?;
+            merge_tag_case_variants(&tx)?;
+            tx.execute(
+                "CREATE UNIQUE INDEX idx_tags_name_nocase ON tags(name COLLATE NOCASE)",
+                [],
+            )?;
+            tx.commit()?;
+        }
+
+        self.conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS clip_versions (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                clip_id TEXT NOT NULL,
+                content TEXT NOT NULL,
+                edited_at INTEGER NOT NULL,
+                FOREIGN KEY (clip_id) REFERENCES clips(id) ON DELETE CASCADE
+            );
+            CREATE INDEX IF NOT EXISTS idx_clip_versions_clip ON clip_versions(clip_id);
+            CREATE TRIGGER IF NOT EXISTS clip_versions_delete AFTER DELETE ON clips BEGIN
+                DELETE FROM clip_versions WHERE clip_id = old.id;
+            END;"
+        )?;
+
+        // Each time a clip was put back on the clipboard, for suggestions
+        self.conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS clip_uses (
+                clip_id TEXT NOT NULL,
+                at INTEGER NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS idx_clip_uses_clip ON clip_uses(clip_id);
+            CREATE TRIGGER IF NOT EXISTS clip_uses_delete AFTER DELETE ON clips BEGIN
+                DELETE FROM clip_uses WHERE clip_id = old.id;
+            END;"
+        )?;
+
+        // Every process writing the history records its changes here, so
+        // followers in other processes can poll for them
+        self.conn.execute_batch(&format!(
+            "CREATE TABLE IF NOT EXISTS clip_events (
+                seq INTEGER PRIMARY KEY AUTOINCREMENT,
+                kind TEXT NOT NULL,
+                clip_id TEXT NOT NULL,
+                detail TEXT,
+                at INTEGER NOT NULL
+            );
+            CREATE TRIGGER IF NOT EXISTS clip_events_added AFTER INSERT ON clips BEGIN
+                INSERT INTO clip_events (kind, clip_id, at) VALUES ('added', new.id, unixepoch());
+            END;
+            CREATE TRIGGER IF NOT EXISTS clip_events_deleted AFTER DELETE ON clips BEGIN
+                INSERT INTO clip_events (kind, clip_id, at) VALUES ('deleted', old.id, unixepoch());
+            END;
+            CREATE TRIGGER IF NOT EXISTS clip_events_edited AFTER UPDATE OF content ON clips
+            WHEN old.content != new.content BEGIN
+                INSERT INTO clip_events (kind, clip_id, at) VALUES ('edited', new.id, unixepoch());
+            END;
+            CREATE TRIGGER IF NOT EXISTS clip_events_tagged AFTER INSERT ON clip_tags BEGIN
+                INSERT INTO clip_events (kind, clip_id, detail, at)
+                VALUES ('tagged', new.clip_id, (SELECT name FROM tags WHERE id = new.tag_id), unixepoch());
+            END;
+            CREATE TRIGGER IF NOT EXISTS clip_events_prune AFTER INSERT ON clip_events BEGIN
+                DELETE FROM clip_events WHERE seq <= new.seq - {};
+            END;",
+            EVENT_LOG_SIZE
+        ))?;
+
         self.conn.execute(
             "CREATE INDEX IF NOT EXISTS idx_created_at ON clips(created_at DESC)",
             [],
@@ -111,177 +714,911 @@ impl Database {
             [],
         )?;
 
+        self.init_fts()?;
+
         Ok(())
     }
 
-    pub async fn add_clip(&mut self, content: &str, clip_type: &str) -> Result<()> {
-        let id = Uuid::new_v4().to_string();
-        let now = Utc::now().timestamp();
-        
-        self.conn.execute(
-            "INSERT INTO clips (id, content, clip_type, created_at) VALUES (?1, ?2, ?3, ?4)",
-            params![id, content, clip_type, now],
-        )?;
+    /// Move file bytes that older versions embedded in the clip row itself
+    /// into `blobs`.
+    fn migrate_inline_blobs(&self) -> Result<()> {
+        let tx = self.conn.unchecked_transaction()?;
+        let inline = tx
+            .prepare("SELECT id, blob_data, compressed, original_size FROM clips WHERE blob_data IS NOT NULL")?
+            .query_map([], |row| {
+                let blob = StoredBlob {
+                    data: row.get(1)?,
+                    compressed: row.get(2)?,
+                    original_size: row.get(3)?,
+                };
+                Ok((row.get::<_, String>(0)?, blob))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        if inline.is_empty() {
+            return Ok(());
+        }
 
+        for (id, blob) in &inline {
+            let hash = store_blob(&tx, blob)?;
+            tx.execute(
+                "UPDATE clips SET blob_hash = ?1, blob_data = NULL WHERE id = ?2",
+                params![hash, id],
+            )?;
+        }
+        tx.commit()?;
         Ok(())
     }
 
-    pub async fn add_file_clip(&mut self, file_path: &str) -> Result<()> {
-        let id = Uuid::new_v4().to_string();
-        let now = Utc::now().timestamp();
-        
-        self.conn.execute(
-            "INSERT INTO clips (id, content, clip_type, created_at, file_path) VALUES (?1, ?2, ?3, ?4, ?5)",
-            params![id, file_path, "file", now, file_path],
-        )?;
+    fn add_column_if_missing(&self, table: &str, column: &str, definition: &str) -> Result<()> {
+        let mut stmt = self.conn.prepare(&format!("PRAGMA table_info({})", table))?;
+        let exists = stmt
+            .query_map([], |row| row.get::<_, String>("name"))?
+            .filter_map(|name| name.ok())
+            .any(|name| name == column);
 
+        if !exists {
+            self.conn.execute(
+                &format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition),
+                [],
+            )?;
+        }
         Ok(())
     }
 
-    pub async fn get_recent_clips(&self, limit: usize) -> Result<Vec<Clip>> {
-        let mut stmt = self.conn.prepare(
-            "SELECT id, content, clip_type, created_at, file_path FROM clips 
-             ORDER BY created_at DESC LIMIT ?1"
+    /// Trigram full-text index over clip content. A trigram index answers
+    /// `LIKE '%q%'` without scanning the whole clips table.
+    fn init_fts(&self) -> Result<()> {
+        let existing: Option<String> = self
+            .conn
+            .query_row(
+                "SELECT sql FROM sqlite_master WHERE type = 'table' AND name = 'clips_fts'",
+                [],
+                |row| row.get(0),
+            )
+            .optional()?;
+        // Older tables kept term positions the LIKE lookups never use;
+        // rebuild them without.
+        let exists = match existing {
+            Some(sql) if !sql.contains("detail") => {
+                self.conn.execute_batch("DROP TABLE clips_fts")?;
+                false
+            }
+            Some(_) => true,
+            None => false,
+        };
+
+        // LIKE only needs to know which rows hold a trigram, so skip the
+        // position and size bookkeeping that roughly doubled the insert cost.
+        self.conn.execute_batch(
+            "CREATE VIRTUAL TABLE IF NOT EXISTS clips_fts USING fts5(
+                content, content='clips', tokenize='trigram', detail='none', columnsize=0
+            );
+            CREATE TRIGGER IF NOT EXISTS clips_fts_insert AFTER INSERT ON clips BEGIN
+                INSERT INTO clips_fts(rowid, content) VALUES (new.rowid, new.content);
+            END;
+            CREATE TRIGGER IF NOT EXISTS clips_fts_delete AFTER DELETE ON clips BEGIN
+                INSERT INTO clips_fts(clips_fts, rowid, content) VALUES ('delete', old.rowid, old.content);
+            END;
+            CREATE TRIGGER IF NOT EXISTS clips_fts_update AFTER UPDATE OF content ON clips BEGIN
+                INSERT INTO clips_fts(clips_fts, rowid, content) VALUES ('delete', old.rowid, old.content);
+                INSERT INTO clips_fts(rowid, content) VALUES (new.rowid, new.content);
+            END;"
         )?;
-        
-        let clip_iter = stmt.query_map(params![limit], |row| {
-            Ok(Clip::from(row))
-        })?;
 
-        let mut clips = Vec::new();
-        for clip in clip_iter {
-            clips.push(clip?);
+        // Index clips stored before the FTS table existed
+        if !exists {
+            self.conn.execute("INSERT INTO clips_fts(clips_fts) VALUES ('rebuild')", [])?;
         }
 
-        Ok(clips)
+        Ok(())
     }
 
-    pub async fn get_clip_by_id(&self, id: &str) -> Result<Option<Clip>> {
-        let mut stmt = self.conn.prepare(
-            "SELECT id, content, clip_type, created_at, file_path FROM clips WHERE id = ?1"
+    pub async fn add_clip(&mut self, content: &str, clip_type: &str) -> Result<String> {
+        self.add_clip_with_mime(content, clip_type, default_mime(clip_type)).await
+    }
+
+    pub async fn add_clip_with_mime(&mut self, content: &str, clip_type: &str, mime: &str) -> Result<String> {
+        let id = insert_clip(&self.conn, content, clip_type, mime)?;
+        self.audit(AuditAction::Add, [(id.as_str(), content)])?;
+        Ok(id)
+    }
+
+    /// Insert a clip and trim history to `max_clips` in one transaction, so
+    /// a crash or a concurrent writer can never leave the history over the
+    /// limit between the two steps.
+    /// The new clip is never the one trimmed, even when others were added
+    /// within the same second.
+    pub async fn add_and_trim(&mut self, content: &str, clip_type: &str, max_clips: usize) -> Result<String> {
+        self.with_transaction(|tx| {
+            let id = tx.add_clip(content, clip_type)?;
+            tx.trim_history(max_clips)?;
+            Ok(id)
+        })
+    }
+
+    /// Add a clip for a file by its canonical path. Adding a file that
+    /// already has a (non-embedded) clip moves that clip to the top of the
+    /// history instead of storing a duplicate.
+    pub async fn add_file_clip(&mut self, file_path: &str) -> Result<String> {
+        self.with_transaction(|tx| tx.add_file_clip(file_path))
+    }
+
+    /// Add a file clip that also stores the file's bytes, so it can be
+    /// restored after the original moves or is deleted. Identical bytes
+    /// embedded again share the stored copy.
+    pub async fn add_embedded_file_clip(&mut self, file_path: &str, original_name: &str, data: &[u8]) -> Result<String> {
+        self.insert_with_blob(file_path, "file", Some(file_path), Some(original_name), data)
+    }
+
+    /// Store an image clip, keeping the PNG bytes so it can be put back on
+    /// the clipboard.
+    pub async fn add_image_clip(&mut self, width: usize, height: usize, png: &[u8]) -> Result<String> {
+        self.with_transaction(|tx| tx.add_image_clip(width, height, png))
+    }
+
+    /// Insert a clip whose bytes go into `blobs`, audited like `add_clip`.
+    fn insert_with_blob(
+        &mut self,
+        content: &str,
+        clip_type: &str,
+        file_path: Option<&str>,
+        original_name: Option<&str>,
+        data: &[u8],
+    ) -> Result<String> {
+        let tx = self.conn.transaction()?;
+        let embedded = Embedded { data, file_path, original_name };
+        let compression = (self.compression_threshold, self.compression_level);
+        let id = insert_embedded(&tx, content, clip_type, default_mime(clip_type), embedded, compression)?;
+        tx.commit()?;
+
+        self.audit(AuditAction::Add, [(id.as_str(), content)])?;
+        Ok(id)
+    }
+
+    /// The embedded bytes of a clip, if it has any, decompressed.
+    pub async fn get_clip_blob(&self, id: &str) -> Result<Option<Vec<u8>>> {
+        let blob = self
+            .conn
+            .query_row(
+                "SELECT b.data, b.compressed FROM live_clips c JOIN blobs b ON b.hash = c.blob_hash WHERE c.id = ?1",
+                params![id],
+                |row| Ok((row.get::<_, Vec<u8>>(0)?, row.get::<_, bool>(1)?)),
+            )
+            .optional()?;
+        match blob {
+            Some((data, true)) => Ok(Some(zstd::decode_all(data.as_slice())?)),
+            Some((data, false)) => Ok(Some(data)),
+            None => Ok(None),
+        }
+    }
+
+    pub async fn get_recent_clips(&self, limit: usize) -> Result<Vec<Clip>> {
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT * FROM live_clips 
+             ORDER BY created_at DESC LIMIT ?1"
         )?;
         
-        let mut rows = stmt.query_map(params![id], |row| {
-            Ok(Clip::from(row))
-        })?;
+        let clip_iter = stmt.query_map(params![limit], Clip::try_from_row)?;
 
-        Ok(rows.next().transpose()?)
+        collect_clips(clip_iter)
     }
 
-    pub async fn clear_history(&mut self) -> Result<()> {
-        self.conn.execute("DELETE FROM clips", [])?;
-        Ok(())
+    /// The newest `limit` clips of one type, e.g. `text` or `file`.
+    pub async fn get_recent_clips_by_type(&self, clip_type: &str, limit: usize) -> Result<Vec<Clip>> {
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT * FROM live_clips WHERE clip_type = ?1
+             ORDER BY created_at DESC LIMIT ?2"
+        )?;
+        let clip_iter = stmt.query_map(params![clip_type, limit], Clip::try_from_row)?;
+        collect_clips(clip_iter)
     }
 
-    pub async fn trim_history(&mut self, max_clips: usize) -> Result<()> {
+    pub async fn get_clip_by_id(&self, id: &str) -> Result<Option<Clip>> {
         let mut stmt = self.conn.prepare(
-            "DELETE FROM clips WHERE id NOT IN (
-                SELECT id FROM clips ORDER BY created_at DESC LIMIT ?1
-            )"
+            "SELECT * FROM live_clips WHERE id = ?1"
         )?;
         
-        stmt.execute(params![max_clips])?;
-        Ok(())
+        let mut rows = stmt.query_map(params![id], Clip::try_from_row)?;
+
+        Ok(rows.next().transpose()?)
     }
 
-    pub async fn search_clips(&self, query: &str, limit: usize) -> Result<Vec<Clip>> {
+    /// Find the live clip whose id starts with `prefix`, like a git short hash.
+    pub async fn resolve_id_prefix(&self, prefix: &str) -> Result<IdResolution> {
+        if prefix.is_empty() {
+            return Ok(IdResolution::NotFound);
+        }
+
+        // Fetching one extra id is enough to tell unique from ambiguous; a
+        // few more make the error message useful
         let mut stmt = self.conn.prepare(
-            "SELECT id, content, clip_type, created_at, file_path FROM clips 
-             WHERE content LIKE ?1 
-             ORDER BY created_at DESC LIMIT ?2"
+            "SELECT id FROM live_clips 
+             WHERE substr(id, 1, ?2) = ?1 
+             ORDER BY created_at DESC LIMIT 5"
         )?;
-        
-        let search_pattern = format!("%{}%", query);
-        let clip_iter = stmt.query_map(params![search_pattern, limit], |row| {
-            Ok(Clip::from(row))
+        let id_iter = stmt.query_map(params![prefix, prefix.chars().count()], |row| {
+            row.get::<_, String>(0)
         })?;
 
-        let mut clips = Vec::new();
-        for clip in clip_iter {
-            clips.push(clip?);
+        let mut ids = Vec::new();
+        for id in id_iter {
+            ids.push(id?);
         }
 
-        Ok(clips)
+        Ok(match ids.len() {
+            0 => IdResolution::NotFound,
+            1 => IdResolution::Unique(ids.remove(0)),
+            _ => IdResolution::Ambiguous(ids),
+        })
     }
 
-    pub async fn get_all_clips(&self) -> Result<Vec<Clip>> {
+    pub async fn find_clip_by_content(&self, content: &str) -> Result<Option<Clip>> {
         let mut stmt = self.conn.prepare(
-            "SELECT id, content, clip_type, created_at, file_path FROM clips 
-             ORDER BY created_at DESC"
+            "SELECT * FROM live_clips 
+             WHERE content = ?1 ORDER BY created_at DESC LIMIT 1"
         )?;
         
-        let clip_iter = stmt.query_map([], |row| {
-            Ok(Clip::from(row))
-        })?;
-
-        let mut clips = Vec::new();
-        for clip in clip_iter {
-            clips.push(clip?);
-        }
+        let mut rows = stmt.query_map(params![content], Clip::try_from_row)?;
 
-        Ok(clips)
+        Ok(rows.next().transpose()?)
     }
 
-    pub async fn get_statistics(&self) -> Result<Statistics> {
-        let mut stmt = self.conn.prepare("SELECT COUNT(*) FROM clips")?;
-        let total_clips: usize = stmt.query_row([], |row| row.get(0))?;
+    /// Remember that clip `id` was put back on the clipboard.
+    pub async fn record_use(&mut self, id: &str) -> Result<()> {
+        self.conn.execute(
+            "INSERT INTO clip_uses (clip_id, at) VALUES (?1, unixepoch())",
+            params![id],
+        )?;
+        Ok(())
+    }
 
-        let mut stmt = self.conn.prepare("SELECT COUNT(*) FROM clips WHERE clip_type = 'text'")?;
-        let text_clips: usize = stmt.query_row([], |row| row.get(0))?;
+    /// How many times clip `id` was put back on the clipboard.
+    pub async fn use_count(&self, id: &str) -> Result<u32> {
+        Ok(self.conn.query_row(
+            "SELECT COUNT(*) FROM clip_uses WHERE clip_id = ?1",
+            params![id],
+            |row| row.get(0),
+        )?)
+    }
 
-        let mut stmt = self.conn.prepare("SELECT COUNT(*) FROM clips WHERE clip_type = 'file'")?;
-        let file_clips: usize = stmt.query_row([], |row| row.get(0))?;
+    /// For every other clip, how many of its copies (its capture and each
+    /// use) fell within `window_secs` of a copy of clip `id`.
+    pub async fn co_copy_counts(&self, id: &str, window_secs: i64) -> Result<HashMap<String, u32>> {
+        let mut stmt = self.conn.prepare(
+            "WITH copies AS (
+                SELECT clip_id, at FROM clip_uses
+                UNION ALL SELECT id, created_at FROM live_clips
+             )
+             SELECT other.clip_id, COUNT(*) FROM copies ours
+             JOIN copies other ON other.clip_id != ours.clip_id AND abs(other.at - ours.at) <= ?2
+             WHERE ours.clip_id = ?1
+             GROUP BY other.clip_id"
+        )?;
+        let counts = stmt.query_map(params![id, window_secs], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        Ok(counts.collect::<rusqlite::Result<_>>()?)
+    }
 
-        let mut stmt = self.conn.prepare("SELECT MIN(created_at) FROM clips")?;
-        let oldest_timestamp: i64 = stmt.query_row([], |row| row.get(0)).unwrap_or(0);
-        let oldest_clip = DateTime::from_timestamp(oldest_timestamp, 0)
-            .unwrap_or_else(|| Utc::now())
-            .format("%Y-%m-%d %H:%M:%S")
-            .to_string();
+    /// Import a single clip, resolving a clash with existing identical content
+    /// according to `on_conflict`. Use `ClipTransaction::import_clip` to
+    /// import several clips atomically.
+    pub async fn import_clip(&mut self, clip: &ImportedClip, on_conflict: ConflictMode) -> Result<ImportOutcome> {
+        self.with_transaction(|tx| tx.import_clip(clip, on_conflict))
+    }
 
-        let mut stmt = self.conn.prepare("SELECT MAX(created_at) FROM clips")?;
-        let newest_timestamp: i64 = stmt.query_row([], |row| row.get(0)).unwrap_or(0);
-        let newest_clip = DateTime::from_timestamp(newest_timestamp, 0)
-            .unwrap_or_else(|| Utc::now())
-            .format("%Y-%m-%d %H:%M:%S")
-            .to_string();
+    /// Merge every clip of another clipq database file into this one in a
+    /// single transaction, keeping clip ids, timestamps, tags and embedded
+    /// file bytes. The other file is opened read-only. Clashes with existing
+    /// content are resolved by `on_conflict`; an id already taken here gets
+    /// a fresh one.
+    pub async fn import_database(&mut self, path: &str, on_conflict: ConflictMode) -> Result<Vec<ImportOutcome>> {
+        let source = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+            .with_context(|| format!("Cannot open clipq database {}", path))?;
 
-        // Get database file size
-        let db_path = Self::get_db_path()?;
-        let db_size = std::fs::metadata(&db_path)
-            .map(|m| m.len() as usize / 1024)
-            .unwrap_or(0);
+        let mut incoming = Vec::new();
+        {
+            let mut clip_stmt = source
+                .prepare("SELECT * FROM clips ORDER BY created_at")
+                .with_context(|| format!("{} is not a clipq database", path))?;
+            let mut tag_stmt = source.prepare(
+                "SELECT t.name FROM tags t JOIN clip_tags ct ON t.id = ct.tag_id WHERE ct.clip_id = ?1",
+            )?;
+            let rows = clip_stmt.query_map([], |row| {
+                // Older databases keep embedded bytes in the clip row, or
+                // have no blob or compression columns at all
+                let inline = row.get::<_, Option<Vec<u8>>>("blob_data").unwrap_or_default().map(|data| StoredBlob {
+                    data,
+                    compressed: row.get("compressed").unwrap_or_default(),
+                    original_size: row.get("original_size").unwrap_or_default(),
+                });
+                let blob_hash: Option<String> = row.get("blob_hash").unwrap_or_default();
+                Ok((Clip::try_from_row(row)?, inline, blob_hash))
+            })?;
+            for row in rows {
+                let (clip, inline, blob_hash) = row.with_context(|| format!("{} has a corrupt clip row", path))?;
+                let blob = match blob_hash {
+                    Some(hash) => Some(
+                        source
+                            .query_row(
+                                "SELECT data, compressed, original_size FROM blobs WHERE hash = ?1",
+                                params![hash],
+                                |row| {
+                                    Ok(StoredBlob {
+                                        data: row.get(0)?,
+                                        compressed: row.get(1)?,
+                                        original_size: row.get(2)?,
+                                    })
+                                },
+                            )
+                            .with_context(|| format!("{} has no embedded data for clip {}", path, clip.id))?,
+                    ),
+                    None => inline,
+                };
+                let tags = tag_stmt
+                    .query_map(params![clip.id], |row| row.get::<_, String>(0))?
+                    .collect::<rusqlite::Result<Vec<_>>>()?;
+                incoming.push((clip, blob, tags));
+            }
+        }
 
-        Ok(Statistics {
-            total_clips,
-            text_clips,
-            file_clips,
-            oldest_clip,
-            newest_clip,
-            db_size_kb: db_size,
+        self.with_transaction(|tx| {
+            incoming
+                .into_iter()
+                .map(|(clip, blob, tags)| tx.import_stored_clip(clip, blob, &tags, on_conflict))
+                .collect()
         })
     }
 
-    pub async fn add_tag_to_clip(&mut self, clip_id: &str, tag_name: &str) -> Result<()> {
-        // First, ensure the tag exists
-        let mut stmt = self.conn.prepare("INSERT OR IGNORE INTO tags (name) VALUES (?1)")?;
-        stmt.execute(params![tag_name])?;
+    pub async fn delete_clip(&mut self, id: &str) -> Result<bool> {
+        self.with_transaction(|tx| tx.delete_clip(id))
+    }
 
-        // Get the tag ID
-        let mut stmt = self.conn.prepare("SELECT id FROM tags WHERE name = ?1")?;
-        let tag_id: i64 = stmt.query_row(params![tag_name], |row| row.get(0))?;
+    /// Replace a clip's content, keeping the old content as a version.
+    pub async fn update_clip_content(&mut self, id: &str, content: &str) -> Result<bool> {
+        let max_versions = self.max_versions;
+        let tx = self.conn.transaction()?;
+        record_version(&tx, id, content, max_versions)?;
+        let updated = tx.execute(
+            "UPDATE clips SET content = ?1 WHERE id = ?2",
+            params![content, id],
+        )?;
+        tx.commit()?;
+        Ok(updated > 0)
+    }
 
-        // Add the relationship
-        let mut stmt = self.conn.prepare("INSERT OR IGNORE INTO clip_tags (clip_id, tag_id) VALUES (?1, ?2)")?;
-        stmt.execute(params![clip_id, tag_id])?;
+    /// Earlier contents of a clip, most recently replaced first.
+    pub async fn get_clip_versions(&self, id: &str) -> Result<Vec<ClipVersion>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT content, edited_at FROM clip_versions WHERE clip_id = ?1 ORDER BY id DESC"
+        )?;
+        let versions = stmt
+            .query_map(params![id], |row| {
+                Ok(ClipVersion {
+                    content: row.get(0)?,
+                    edited_at: DateTime::from_timestamp(row.get(1)?, 0).unwrap_or_else(Utc::now),
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(versions)
+    }
+
+    /// Restore version `version` (1 = most recently replaced) of a clip.
+    /// The content being replaced becomes a version itself, so a revert
+    /// can be undone. Returns `false` if the clip has no such version.
+    pub async fn revert_clip(&mut self, id: &str, version: usize) -> Result<bool> {
+        let Some(offset) = version.checked_sub(1) else {
+            return Ok(false);
+        };
+        let content: Option<String> = self
+            .conn
+            .query_row(
+                "SELECT content FROM clip_versions WHERE clip_id = ?1 ORDER BY id DESC LIMIT 1 OFFSET ?2",
+                params![id, offset],
+                |row| row.get(0),
+            )
+            .optional()?;
+        match content {
+            Some(content) => self.update_clip_content(id, &content).await,
+            None => Ok(false),
+        }
+    }
+
+    /// Update a clip's content and/or replace its tag set atomically.
+    /// Returns `false` if no clip has the given ID.
+    pub async fn edit_clip(&mut self, id: &str, content: Option<&str>, tags: Option<&[String]>) -> Result<bool> {
+        let tags: Option<Vec<String>> = tags
+            .map(|tags| tags.iter().map(|tag| self.tag_name(tag)).collect())
+            .transpose()?;
+        let max_versions = self.max_versions;
+        let tx = self.conn.transaction()?;
+
+        let exists: bool = tx.query_row(
+            "SELECT EXISTS(SELECT 1 FROM clips WHERE id = ?1)",
+            params![id],
+            |row| row.get(0),
+        )?;
+        if !exists {
+            return Ok(false);
+        }
+
+        if let Some(content) = content {
+            record_version(&tx, id, content, max_versions)?;
+            tx.execute("UPDATE clips SET content = ?1 WHERE id = ?2", params![content, id])?;
+        }
+
+        if let Some(tags) = tags {
+            // Remove tags no longer wanted, then add the missing ones
+            let placeholders = vec!["?"; tags.len()].join(", ");
+            let mut delete_params: Vec<&dyn rusqlite::ToSql> = vec![&id];
+            delete_params.extend(tags.iter().map(|tag| tag as &dyn rusqlite::ToSql));
+            tx.execute(
+                &format!(
+                    "DELETE FROM clip_tags WHERE clip_id = ?1 AND tag_id NOT IN (
+                        SELECT id FROM tags WHERE name COLLATE NOCASE IN ({})
+                    )",
+                    if tags.is_empty() { "NULL".to_string() } else { placeholders }
+                ),
+                delete_params.as_slice(),
+            )?;
+
+            for tag in &tags {
+                tx.execute("INSERT OR IGNORE INTO tags (name) VALUES (?1)", params![tag])?;
+                tx.execute(
+                    "INSERT OR IGNORE INTO clip_tags (clip_id, tag_id)
+                     SELECT ?1, id FROM tags WHERE name = ?2 COLLATE NOCASE",
+                    params![id, tag],
+                )?;
+            }
+        }
+
+        tx.commit()?;
+        Ok(true)
+    }
+
+    /// Number of stored clips and how many of them are pinned.
+    pub async fn clip_counts(&self) -> Result<(usize, usize)> {
+        Ok(self.conn.query_row(
+            "SELECT COUNT(*), COALESCE(SUM(pinned), 0) FROM clips",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?)
+    }
+
+    /// Bytes the database occupies, from SQLite's own page accounting.
+    pub async fn size_bytes(&self) -> Result<u64> {
+        Ok(self.conn.query_row(
+            "SELECT page_count * page_size FROM pragma_page_count(), pragma_page_size()",
+            [],
+            |row| row.get(0),
+        )?)
+    }
+
+    pub async fn clear_history(&mut self) -> Result<()> {
+        let deleted = delete_returning(&self.conn, "DELETE FROM clips", [])?;
+        self.audit_deleted(&deleted)?;
+        Ok(())
+    }
+
+    /// Delete the `count` oldest unpinned clips and return how many went.
+    pub async fn delete_oldest(&mut self, count: usize) -> Result<usize> {
+        self.delete_unpinned_from_end(count, "ASC")
+    }
+
+    /// Delete the `count` newest unpinned clips and return how many went.
+    pub async fn delete_newest(&mut self, count: usize) -> Result<usize> {
+        self.delete_unpinned_from_end(count, "DESC")
+    }
+
+    fn delete_unpinned_from_end(&mut self, count: usize, order: &str) -> Result<usize> {
+        let targets = format!(
+            "SELECT id FROM clips WHERE pinned = 0 ORDER BY created_at_ms {order}, rowid {order} LIMIT {count}"
+        );
+        let tx = self.conn.transaction()?;
+        tx.execute(&format!("DELETE FROM clip_tags WHERE clip_id IN ({targets})"), [])?;
+        let removed = delete_returning(&tx, &format!("DELETE FROM clips WHERE id IN ({targets})"), [])?;
+        tx.commit()?;
 
+        self.audit_deleted(&removed)
+    }
+
+    /// Delete unpinned clips whose content is empty or only whitespace,
+    /// left over from before blank captures were skipped. Returns how many
+    /// went.
+    pub async fn delete_where_blank(&mut self) -> Result<usize> {
+        let blank = "SELECT id FROM clips
+             WHERE pinned = 0 AND TRIM(content, ' ' || char(9) || char(10) || char(11) || char(12) || char(13)) = ''";
+        let tx = self.conn.transaction()?;
+        tx.execute(&format!("DELETE FROM clip_tags WHERE clip_id IN ({blank})"), [])?;
+        let removed = delete_returning(&tx, &format!("DELETE FROM clips WHERE id IN ({blank})"), [])?;
+        tx.commit()?;
+
+        self.audit_deleted(&removed)
+    }
+
+    pub async fn set_expiry(&mut self, id: &str, expires_at: Option<DateTime<Utc>>) -> Result<bool> {
+        self.with_transaction(|tx| tx.set_expiry(id, expires_at))
+    }
+
+    pub async fn set_pinned(&mut self, id: &str, pinned: bool) -> Result<bool> {
+        let updated = self.conn.execute(
+            "UPDATE clips SET pinned = ?1 WHERE id = ?2",
+            params![pinned, id],
+        )?;
+        Ok(updated > 0)
+    }
+
+    /// Keep only the `max_clips` newest unpinned clips, by the millisecond
+    /// they were added and then by insertion order, so the one added last
+    /// always survives.
+    pub async fn trim_history(&mut self, max_clips: usize) -> Result<()> {
+        self.apply_retention(&RetentionPolicy {
+            max_clips: Some(max_clips),
+            ..RetentionPolicy::default()
+        })
+        .await?;
         Ok(())
     }
 
+    /// Delete every clip that has expired or falls outside any of the policy's
+    /// limits and return how many were removed. With `keep_pinned`, pinned
+    /// clips are never trimmed by the limits and don't count against them.
+    pub async fn apply_retention(&mut self, policy: &RetentionPolicy) -> Result<usize> {
+        let tx = self.conn.transaction()?;
+        let removed = delete_outside_policy(&tx, policy)?;
+        tx.commit()?;
+
+        self.audit_deleted(&removed)
+    }
+
+    pub async fn search_clips(&self, query: &str, limit: usize) -> Result<Vec<Clip>> {
+        // The trigram index needs at least three characters to narrow the search.
+        // Expiry and recency apply before the limit: rowid order is insertion
+        // order, which a re-added file or an import does not follow.
+        let mut stmt = if query.chars().count() >= 3 {
+            self.conn.prepare_cached(
+                "SELECT c.* FROM clips c 
+                 WHERE c.rowid IN (SELECT rowid FROM clips_fts WHERE content LIKE ?1) 
+                   AND (c.expires_at IS NULL OR c.expires_at > unixepoch()) 
+                 ORDER BY c.created_at DESC, c.rowid DESC LIMIT ?2"
+            )?
+        } else {
+            self.conn.prepare_cached(
+                "SELECT * FROM live_clips 
+                 WHERE content LIKE ?1 
+                 ORDER BY created_at DESC LIMIT ?2"
+            )?
+        };
+        
+        let search_pattern = format!("%{}%", query);
+        let clip_iter = stmt.query_map(params![search_pattern, limit], Clip::try_from_row)?;
+
+        collect_clips(clip_iter)
+    }
+
+    pub async fn search_clips_by_tag(&self, query: &str, tag_name: &str, limit: usize) -> Result<Vec<Clip>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT c.* 
+             FROM live_clips c 
+             JOIN clip_tags ct ON c.id = ct.clip_id 
+             JOIN tags t ON ct.tag_id = t.id 
+             WHERE t.name = ?1 COLLATE NOCASE AND c.content LIKE ?2 
+             ORDER BY c.created_at DESC LIMIT ?3"
+        )?;
+        
+        let search_pattern = format!("%{}%", query);
+        let clip_iter = stmt.query_map(params![tag_name, search_pattern, limit], Clip::try_from_row)?;
+
+        collect_clips(clip_iter)
+    }
+
+    /// How many live clips match `filter`, without fetching them.
+    pub async fn count_clips(&self, filter: &ClipFilter) -> Result<usize> {
+        let pattern = filter.contains.as_ref().map(|query| format!("%{}%", query));
+        Ok(self.conn.query_row(
+            "SELECT COUNT(*) FROM live_clips c
+             WHERE (?1 IS NULL OR c.content LIKE ?1)
+               AND (?2 IS NULL OR c.content = ?2)
+               AND (?3 IS NULL OR c.clip_type = ?3)
+               AND (?4 IS NULL OR EXISTS (
+                    SELECT 1 FROM clip_tags ct JOIN tags t ON ct.tag_id = t.id
+                    WHERE ct.clip_id = c.id AND t.name = ?4 COLLATE NOCASE
+               ))",
+            params![pattern, filter.equals, filter.clip_type, filter.tag],
+            |row| row.get(0),
+        )?)
+    }
+
+    /// The newest `limit` clips whose content is exactly `content`,
+    /// optionally only those tagged `tag_name` and/or of type `clip_type`.
+    pub async fn find_exact(
+        &self,
+        content: &str,
+        tag_name: Option<&str>,
+        clip_type: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<Clip>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT c.* FROM live_clips c
+             WHERE c.content = ?1
+               AND (?2 IS NULL OR c.clip_type = ?2)
+               AND (?3 IS NULL OR EXISTS (
+                    SELECT 1 FROM clip_tags ct JOIN tags t ON ct.tag_id = t.id
+                    WHERE ct.clip_id = c.id AND t.name = ?3 COLLATE NOCASE
+               ))
+             ORDER BY c.created_at DESC LIMIT ?4"
+        )?;
+        let clip_iter = stmt.query_map(params![content, clip_type, tag_name, limit], Clip::try_from_row)?;
+        collect_clips(clip_iter)
+    }
+
+    /// The newest `limit` clips whose content matches `pattern`, optionally
+    /// only among those tagged `tag_name`. Every candidate is scanned in Rust,
+    /// so this can't use the trigram index.
+    pub async fn search_clips_regex(&self, pattern: &Regex, tag_name: Option<&str>, limit: usize) -> Result<Vec<Clip>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT c.* FROM live_clips c
+             WHERE ?1 IS NULL OR EXISTS (
+                SELECT 1 FROM clip_tags ct JOIN tags t ON ct.tag_id = t.id
+                WHERE ct.clip_id = c.id AND t.name = ?1 COLLATE NOCASE
+             )
+             ORDER BY c.created_at DESC"
+        )?;
+        let mut rows = stmt.query(params![tag_name])?;
+
+        let mut clips = Vec::new();
+        let mut skipped = 0;
+        while clips.len() < limit {
+            let Some(row) = rows.next()? else { break };
+            let clip = match Clip::try_from_row(row) {
+                Ok(clip) => clip,
+                Err(e) if is_corrupt_row(&e) => {
+                    warn!("Skipping corrupt clip row: {}", e);
+                    skipped += 1;
+                    continue;
+                }
+                Err(e) => return Err(e.into()),
+            };
+            if pattern.is_match(&clip.content) {
+                clips.push(clip);
+            }
+        }
+        warn_skipped(skipped);
+
+        Ok(clips)
+    }
+
+    pub async fn get_all_clips(&self) -> Result<Vec<Clip>> {
+        self.get_all_clips_ordered(ClipOrder::Newest).await
+    }
+
+    pub async fn get_all_clips_ordered(&self, order: ClipOrder) -> Result<Vec<Clip>> {
+        // The live_clips view hides rowid, so filter expired clips here
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT * FROM clips
+             WHERE expires_at IS NULL OR expires_at > unixepoch()
+             ORDER BY {}",
+            order.sql()
+        ))?;
+        
+        let clip_iter = stmt.query_map([], Clip::try_from_row)?;
+
+        collect_clips(clip_iter)
+    }
+
+    /// Every live clip, newest first, fetched lazily a page at a time so
+    /// callers never hold the whole history in memory.
+    pub fn stream_clips(&self) -> impl Stream<Item = Result<Clip>> + '_ {
+        // Keyset pagination: each page resumes after the last (created_at, id)
+        // seen, so no statement has to stay borrowed between polls
+        let start = Some((i64::MAX, String::new()));
+        stream::try_unfold(start, move |cursor| async move {
+            let Some((created_at, id)) = cursor else {
+                return Ok::<_, anyhow::Error>(None);
+            };
+
+            let (page, next) = self.clips_page_before(created_at, &id, STREAM_PAGE_SIZE)?;
+            Ok(Some((stream::iter(page.into_iter().map(Ok)), next)))
+        })
+        .try_flatten()
+    }
+
+    /// One page of clips and, if the page was full, the cursor of its last
+    /// row. The cursor counts corrupt rows too, so skipping them can't end
+    /// the stream early.
+    fn clips_page_before(&self, created_at: i64, id: &str, limit: usize) -> Result<(Vec<Clip>, Option<StreamCursor>)> {
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT * FROM live_clips 
+             WHERE (created_at, id) < (?1, ?2) 
+             ORDER BY created_at DESC, id DESC LIMIT ?3"
+        )?;
+
+        let rows = stmt
+            .query_map(params![created_at, id, limit], |row| {
+                let cursor = (row.get::<_, i64>("created_at")?, row.get::<_, String>("id")?);
+                Ok((cursor, Clip::try_from_row(row)))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        let next = match rows.last() {
+            Some((cursor, _)) if rows.len() == limit => Some(cursor.clone()),
+            _ => None,
+        };
+
+        Ok((collect_clips(rows.into_iter().map(|(_, clip)| clip))?, next))
+    }
+
+    /// Visit every live clip with its tags, newest first, one row at a time
+    /// so huge histories never have to be held in memory. Returns the count.
+    pub async fn for_each_tagged_clip<F>(&self, order: ClipOrder, mut f: F) -> Result<usize>
+    where
+        F: FnMut(TaggedClip) -> Result<()>,
+    {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT c.*, (
+                SELECT json_group_array(t.name) FROM clip_tags ct 
+                JOIN tags t ON ct.tag_id = t.id 
+                WHERE ct.clip_id = c.id
+             ) AS tags, b.data AS stored_data, b.compressed AS stored_compressed 
+             FROM clips c 
+             LEFT JOIN blobs b ON b.hash = c.blob_hash 
+             WHERE c.expires_at IS NULL OR c.expires_at > unixepoch()
+             ORDER BY c.{}",
+            order.sql()
+        ))?;
+
+        let mut rows = stmt.query([])?;
+        let mut count = 0;
+        let mut skipped = 0;
+        while let Some(row) = rows.next()? {
+            let tags: String = row.get("tags")?;
+            let clip = match Clip::try_from_row(row) {
+                Ok(clip) => clip,
+                Err(e) if is_corrupt_row(&e) => {
+                    warn!("Skipping corrupt clip row: {}", e);
+                    skipped += 1;
+                    continue;
+                }
+                Err(e) => return Err(e.into()),
+            };
+            f(TaggedClip {
+                clip,
+                tags: serde_json::from_str(&tags)?,
+                data: stored_blob(row)?,
+            })?;
+            count += 1;
+        }
+        warn_skipped(skipped);
+
+        Ok(count)
+    }
+
+    pub async fn get_statistics(&self) -> Result<Statistics> {
+        let mut stmt = self.conn.prepare("SELECT COUNT(*) FROM clips")?;
+        let total_clips: usize = stmt.query_row([], |row| row.get(0))?;
+
+        let mut stmt = self.conn.prepare("SELECT COUNT(*) FROM clips WHERE clip_type = 'text'")?;
+        let text_clips: usize = stmt.query_row([], |row| row.get(0))?;
+
+        let mut stmt = self.conn.prepare("SELECT COUNT(*) FROM clips WHERE clip_type = 'file'")?;
+        let file_clips: usize = stmt.query_row([], |row| row.get(0))?;
+
+        let mut stmt = self.conn.prepare("SELECT MIN(created_at) FROM clips")?;
+        let oldest_timestamp: i64 = stmt.query_row([], |row| row.get(0)).unwrap_or(0);
+        let oldest_clip = DateTime::from_timestamp(oldest_timestamp, 0)
+            .unwrap_or_else(Utc::now)
+            .format("%Y-%m-%d %H:%M:%S")
+            .to_string();
+
+        let mut stmt = self.conn.prepare("SELECT MAX(created_at) FROM clips")?;
+        let newest_timestamp: i64 = stmt.query_row([], |row| row.get(0)).unwrap_or(0);
+        let newest_clip = DateTime::from_timestamp(newest_timestamp, 0)
+            .unwrap_or_else(Utc::now)
+            .format("%Y-%m-%d %H:%M:%S")
+            .to_string();
+
+        // Get database file size
+        let db_size = std::fs::metadata(&self.path)
+            .map(|m| m.len() as usize / 1024)
+            .unwrap_or(0);
+
+        let (embedded_clips, compressed_clips, embedded_original_bytes, embedded_stored_bytes) = self.conn.query_row(
+            "SELECT COUNT(*), COALESCE(SUM(b.compressed), 0), COALESCE(SUM(b.original_size), 0),
+                    (SELECT COALESCE(SUM(length(data)), 0) FROM blobs)
+             FROM clips c JOIN blobs b ON b.hash = c.blob_hash",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get::<_, i64>(2)? as u64, row.get::<_, i64>(3)? as u64)),
+        )?;
+
+        Ok(Statistics {
+            total_clips,
+            text_clips,
+            file_clips,
+            oldest_clip,
+            newest_clip,
+            db_size_kb: db_size,
+            embedded_clips,
+            compressed_clips,
+            embedded_original_bytes,
+            embedded_stored_bytes,
+        })
+    }
+
+    /// Clip counts for each of the last `days` calendar days in `tz`,
+    /// oldest first and including days with no clips. `days` is capped at
+    /// `MAX_ACTIVITY_DAYS`.
+    pub async fn clips_per_day(&self, days: usize, tz: &Timezone) -> Result<Vec<(NaiveDate, usize)>> {
+        self.clips_per_day_until(Utc::now(), days, tz)
+    }
+
+    /// `clips_per_day` as seen at `now`.
+    fn clips_per_day_until(&self, now: DateTime<Utc>, days: usize, tz: &Timezone) -> Result<Vec<(NaiveDate, usize)>> {
+        let days = days.min(MAX_ACTIVITY_DAYS);
+        let today = tz.date_of(now);
+        let first_day = today - Duration::days(days.saturating_sub(1) as i64);
+        // A day later than needed, so the first local day is fully covered
+        // whatever the UTC offset
+        let cutoff = (now - Duration::days(days as i64 + 1)).timestamp();
+
+        // Every UTC offset is a whole number of quarter hours, so counting per
+        // 15-minute bucket in SQL loses nothing when mapping to local days
+        let mut stmt = self.conn.prepare(
+            "SELECT (created_at / 900) * 900 AS bucket, COUNT(*) 
+             FROM live_clips 
+             WHERE created_at >= ?1 
+             GROUP BY bucket"
+        )?;
+        let bucket_iter = stmt.query_map(params![cutoff], |row| {
+            Ok((row.get::<_, i64>(0)?, row.get::<_, usize>(1)?))
+        })?;
+
+        let mut counts = vec![0; days];
+        for bucket in bucket_iter {
+            let (start, count) = bucket?;
+            let Some(instant) = DateTime::from_timestamp(start, 0) else {
+                continue;
+            };
+            let offset = (tz.date_of(instant) - first_day).num_days();
+            if let Some(slot) = usize::try_from(offset).ok().and_then(|i| counts.get_mut(i)) {
+                *slot += count;
+            }
+        }
+
+        Ok(counts
+            .into_iter()
+            .enumerate()
+            .map(|(i, count)| (first_day + Duration::days(i as i64), count))
+            .collect())
+    }
+
+    /// Clip counts for each hour of the day (0-23) in `tz`, over the whole
+    /// history.
+    pub async fn clips_per_hour(&self, tz: &Timezone) -> Result<[usize; 24]> {
+        // Quarter-hour buckets map exactly to local hours under any offset;
+        // each is converted on its own, so clips before and after a DST
+        // change land in the hour the clock showed then
+        let mut stmt = self.conn.prepare(
+            "SELECT (created_at / 900) * 900 AS bucket, COUNT(*) FROM live_clips GROUP BY bucket"
+        )?;
+        let bucket_iter = stmt.query_map([], |row| {
+            Ok((row.get::<_, i64>(0)?, row.get::<_, usize>(1)?))
+        })?;
+
+        let mut counts = [0; 24];
+        for bucket in bucket_iter {
+            let (start, count) = bucket?;
+            if let Some(instant) = DateTime::from_timestamp(start, 0) {
+                counts[tz.hour_of(instant) as usize] += count;
+            }
+        }
+        Ok(counts)
+    }
+
+    pub async fn add_tag_to_clip(&mut self, clip_id: &str, tag_name: &str) -> Result<()> {
+        self.with_transaction(|tx| tx.add_tag_to_clip(clip_id, tag_name))
+    }
+
     pub async fn remove_tag_from_clip(&mut self, clip_id: &str, tag_name: &str) -> Result<()> {
         let mut stmt = self.conn.prepare(
             "DELETE FROM clip_tags WHERE clip_id = ?1 AND tag_id = (
-                SELECT id FROM tags WHERE name = ?2
+                SELECT id FROM tags WHERE name = ?2 COLLATE NOCASE
             )"
         )?;
         stmt.execute(params![clip_id, tag_name])?;
@@ -297,7 +1634,98 @@ impl Database {
         )?;
         
         let tag_iter = stmt.query_map(params![clip_id], |row| {
-            Ok(row.get::<_, String>(0)?)
+            row.get::<_, String>(0)
+        })?;
+
+        let mut tags = Vec::new();
+        for tag in tag_iter {
+            tags.push(tag?);
+        }
+
+        Ok(tags)
+    }
+
+    /// Every tag with its clip count, most used first, ties by name. Tags
+    /// whose clips were all deleted are included with a count of zero;
+    /// links left behind by deleted clips don't count.
+    pub async fn list_all_tags(&self) -> Result<Vec<TagUsage>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT t.name, COUNT(c.id) AS clips FROM tags t
+             LEFT JOIN clip_tags ct ON ct.tag_id = t.id
+             LEFT JOIN clips c ON c.id = ct.clip_id
+             GROUP BY t.id
+             ORDER BY clips DESC, t.name COLLATE NOCASE",
+        )?;
+        let tags = stmt
+            .query_map([], |row| {
+                Ok(TagUsage {
+                    name: row.get(0)?,
+                    clips: row.get::<_, i64>(1)? as usize,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(tags)
+    }
+
+    /// Names of the tags `prune_unused_tags` would delete.
+    pub async fn unused_tags(&self) -> Result<Vec<String>> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT name FROM tags WHERE {} ORDER BY name COLLATE NOCASE",
+            UNUSED_TAGS_SQL
+        ))?;
+        let tags = stmt
+            .query_map([], |row| row.get::<_, String>(0))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(tags)
+    }
+
+    /// Delete every uncolored tag attached to no existing clip, along with
+    /// its links to deleted clips, and return how many tags went. Colored
+    /// tags stay, as with `repair`.
+    pub async fn prune_unused_tags(&mut self) -> Result<usize> {
+        let tx = self.conn.transaction()?;
+        let unused = format!("SELECT id FROM tags WHERE {}", UNUSED_TAGS_SQL);
+        tx.execute(&format!("DELETE FROM clip_tags WHERE tag_id IN ({})", unused), [])?;
+        let pruned = tx.execute(&format!("DELETE FROM tags WHERE id IN ({})", unused), [])?;
+        tx.commit()?;
+        Ok(pruned)
+    }
+
+    /// Existing tags within `max_distance` edits of `name`, closest first.
+    /// Empty when `name` itself already exists, since reusing it is fine.
+    pub async fn find_similar_tags(&self, name: &str, max_distance: usize) -> Result<Vec<String>> {
+        let mut stmt = self.conn.prepare("SELECT name FROM tags")?;
+        let tag_iter = stmt.query_map([], |row| row.get::<_, String>(0))?;
+
+        let wanted = name.to_lowercase();
+        let mut similar = Vec::new();
+        for tag in tag_iter {
+            let tag = tag?;
+            if tag.eq_ignore_ascii_case(name) {
+                return Ok(Vec::new());
+            }
+            let distance = strsim::levenshtein(&wanted, &tag.to_lowercase());
+            if distance <= max_distance {
+                similar.push((distance, tag));
+            }
+        }
+
+        similar.sort();
+        Ok(similar.into_iter().map(|(_, tag)| tag).collect())
+    }
+
+    pub async fn get_clip_tag_infos(&self, clip_id: &str) -> Result<Vec<TagInfo>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT t.name, t.color FROM tags t 
+             JOIN clip_tags ct ON t.id = ct.tag_id 
+             WHERE ct.clip_id = ?1"
+        )?;
+        
+        let tag_iter = stmt.query_map(params![clip_id], |row| {
+            Ok(TagInfo {
+                name: row.get(0)?,
+                color: row.get(1)?,
+            })
         })?;
 
         let mut tags = Vec::new();
@@ -308,37 +1736,1762 @@ impl Database {
         Ok(tags)
     }
 
+    /// Set or clear the display color of a tag, creating the tag if needed.
+    /// Colors must be `#rgb` or `#rrggbb` hex.
+    pub async fn set_tag_color(&mut self, tag_name: &str, color: Option<&str>) -> Result<()> {
+        if let Some(color) = color {
+            validate_hex_color(color)?;
+        }
+
+        let tag_name = self.tag_name(tag_name)?;
+        self.conn.execute("INSERT OR IGNORE INTO tags (name) VALUES (?1)", params![tag_name])?;
+        self.conn.execute(
+            "UPDATE tags SET color = ?1 WHERE name = ?2 COLLATE NOCASE",
+            params![color.map(|color| color.to_lowercase()), tag_name],
+        )?;
+        Ok(())
+    }
+
+    /// Replace tag `old` on one clip with `new`, in one transaction. Returns
+    /// false when the clip doesn't carry `old`.
+    pub async fn retag_clip(&mut self, clip_id: &str, old: &str, new: &str) -> Result<bool> {
+        let new = self.tag_name(new)?;
+        let tx = self.conn.transaction()?;
+        let removed = tx.execute(
+            "DELETE FROM clip_tags WHERE clip_id = ?1 AND tag_id = (
+                SELECT id FROM tags WHERE name = ?2 COLLATE NOCASE
+            )",
+            params![clip_id, old],
+        )?;
+        if removed == 0 {
+            return Ok(false);
+        }
+        attach_tag(&tx, clip_id, &new)?;
+        tx.commit()?;
+        Ok(true)
+    }
+
+    /// Move every clip tagged `old` to `new`, creating `new` if needed or
+    /// merging into it if it exists, then delete `old`. Returns how many
+    /// clips were tagged `old`.
+    pub async fn retag_all(&mut self, old: &str, new: &str) -> Result<usize> {
+        let new = self.tag_name(new)?;
+        if old.trim().eq_ignore_ascii_case(&new) {
+            anyhow::bail!("'{}' and '{}' are the same tag", old, new);
+        }
+        let tx = self.conn.transaction()?;
+        let Some(old_id) = tx
+            .query_row("SELECT id FROM tags WHERE name = ?1 COLLATE NOCASE", params![old], |row| row.get::<_, i64>(0))
+            .optional()?
+        else {
+            return Ok(0);
+        };
+        tx.execute("INSERT OR IGNORE INTO tags (name) VALUES (?1)", params![new])?;
+        let new_id: i64 = tx.query_row("SELECT id FROM tags WHERE name = ?1 COLLATE NOCASE", params![new], |row| row.get(0))?;
+        // Moving a tag onto itself would delete it from every clip
+        if new_id == old_id {
+            anyhow::bail!("'{}' and '{}' are the same tag", old, new);
+        }
+
+        let moved = tx.execute("UPDATE OR IGNORE clip_tags SET tag_id = ?1 WHERE tag_id = ?2", params![new_id, old_id])?;
+        // Clips that already had `new` keep their old link until here
+        let already_tagged = tx.execute("DELETE FROM clip_tags WHERE tag_id = ?1", params![old_id])?;
+        tx.execute("DELETE FROM tags WHERE id = ?1", params![old_id])?;
+        tx.commit()?;
+        Ok(moved + already_tagged)
+    }
+
+    /// Delete rows left behind by deletes that skipped foreign keys, rebuild
+    /// the full-text index and run SQLite's integrity check.
+    pub async fn repair(&mut self) -> Result<RepairReport> {
+        let tx = self.conn.transaction()?;
+        let orphaned_links = tx.execute(
+            "DELETE FROM clip_tags
+             WHERE clip_id NOT IN (SELECT id FROM clips) OR tag_id NOT IN (SELECT id FROM tags)",
+            [],
+        )?;
+        let orphaned_versions = tx.execute(
+            "DELETE FROM clip_versions WHERE clip_id NOT IN (SELECT id FROM clips)",
+            [],
+        )?;
+        // A color is a deliberate setting, so colored tags stay even when unused
+        let unused_tags = tx.execute(
+            "DELETE FROM tags WHERE color IS NULL AND id NOT IN (SELECT tag_id FROM clip_tags)",
+            [],
+        )?;
+        tx.execute("INSERT INTO clips_fts(clips_fts) VALUES ('rebuild')", [])?;
+        tx.commit()?;
+
+        let mut stmt = self.conn.prepare("PRAGMA integrity_check")?;
+        let integrity_errors = stmt
+            .query_map([], |row| row.get::<_, String>(0))?
+            .collect::<rusqlite::Result<Vec<_>>>()?
+            .into_iter()
+            .filter(|line| line != "ok")
+            .collect();
+
+        Ok(RepairReport {
+            orphaned_links,
+            orphaned_versions,
+            unused_tags,
+            integrity_errors,
+        })
+    }
+
+    /// Merge tags that differ only in case into the first-created one,
+    /// moving their clips over, and lowercase every tag when the tag case
+    /// is `Lowercase`. Returns how many tags were merged away.
+    pub async fn normalize_tags(&mut self) -> Result<usize> {
+        let tx = self.conn.transaction()?;
+        let merged = merge_tag_case_variants(&tx)?;
+
+        if self.tag_case == TagCase::Lowercase {
+            let names: Vec<(i64, String)> = {
+                let mut stmt = tx.prepare("SELECT id, name FROM tags")?;
+                let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+                rows.collect::<rusqlite::Result<_>>()?
+            };
+            for (id, name) in names {
+                let lowered = name.to_lowercase();
+                if lowered != name {
+                    // A non-ASCII name may already exist in lowercase; keep both then
+                    tx.execute("UPDATE OR IGNORE tags SET name = ?1 WHERE id = ?2", params![lowered, id])?;
+                }
+            }
+        }
+
+        tx.commit()?;
+        Ok(merged)
+    }
+
     pub async fn get_clips_by_tag(&self, tag_name: &str) -> Result<Vec<Clip>> {
         let mut stmt = self.conn.prepare(
-            "SELECT c.id, c.content, c.clip_type, c.created_at, c.file_path 
-             FROM clips c 
+            "SELECT c.* 
+             FROM live_clips c 
              JOIN clip_tags ct ON c.id = ct.clip_id 
              JOIN tags t ON ct.tag_id = t.id 
-             WHERE t.name = ?1 
+             WHERE t.name = ?1 COLLATE NOCASE 
              ORDER BY c.created_at DESC"
         )?;
         
-        let clip_iter = stmt.query_map(params![tag_name], |row| {
-            Ok(Clip::from(row))
-        })?;
+        let clip_iter = stmt.query_map(params![tag_name], Clip::try_from_row)?;
 
-        let mut clips = Vec::new();
-        for clip in clip_iter {
-            clips.push(clip?);
+        collect_clips(clip_iter)
+    }
+
+    pub async fn get_clips_by_tags(&self, tags: &[String], mode: TagMatch) -> Result<Vec<Clip>> {
+        // Tag names match like SQLite's NOCASE collation, which folds ASCII only
+        let mut tags: Vec<String> = tags.iter().map(|t| t.to_ascii_lowercase()).collect();
+        tags.sort_unstable();
+        tags.dedup();
+        if tags.is_empty() {
+            return Ok(Vec::new());
         }
 
-        Ok(clips)
+        let placeholders = vec!["?"; tags.len()].join(", ");
+        let having = match mode {
+            TagMatch::All => format!("HAVING COUNT(DISTINCT ct.tag_id) = {}", tags.len()),
+            TagMatch::Any => String::new(),
+        };
+        let sql = format!(
+            "SELECT c.* 
+             FROM live_clips c 
+             WHERE c.id IN (
+                 SELECT ct.clip_id 
+                 FROM clip_tags ct 
+                 JOIN tags t ON ct.tag_id = t.id 
+                 WHERE t.name COLLATE NOCASE IN ({}) 
+                 GROUP BY ct.clip_id 
+                 {}
+             ) 
+             ORDER BY c.created_at DESC",
+            placeholders, having
+        );
+
+        let mut stmt = self.conn.prepare(&sql)?;
+        let clip_iter = stmt.query_map(rusqlite::params_from_iter(tags), Clip::try_from_row)?;
+
+        collect_clips(clip_iter)
+    }
+
+    /// The newest `limit` sessions for a gap of `gap_secs`, newest first.
+    pub async fn get_sessions(&self, gap_secs: u64, limit: usize) -> Result<Vec<Session>> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT session_id, MIN(created_at) AS started_at, MAX(created_at) AS ended_at, COUNT(*)
+             FROM ({SESSION_CLIPS_SQL})
+             GROUP BY session_id ORDER BY started_at DESC LIMIT ?2"
+        ))?;
+        let sessions = stmt
+            .query_map(params![gap_secs, limit], |row| {
+                Ok(Session {
+                    id: row.get(0)?,
+                    started_at: timestamp_column(row, "started_at", row.get(1)?)?,
+                    ended_at: timestamp_column(row, "ended_at", row.get(2)?)?,
+                    clip_count: row.get(3)?,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(sessions)
+    }
+
+    /// The full id of the session whose id starts with `prefix`.
+    pub async fn resolve_session_id(&self, prefix: &str, gap_secs: u64) -> Result<String> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT DISTINCT session_id FROM ({SESSION_CLIPS_SQL})
+             WHERE substr(session_id, 1, ?3) = ?2 LIMIT 5"
+        ))?;
+        let mut ids = stmt
+            .query_map(params![gap_secs, prefix, prefix.chars().count()], |row| row.get::<_, String>(0))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        match ids.len() {
+            0 => anyhow::bail!("Session not found: {}", prefix),
+            1 => Ok(ids.remove(0)),
+            _ => anyhow::bail!("Session id '{}' is ambiguous: {}", prefix, ids.join(", ")),
+        }
+    }
+
+    /// The newest `limit` clips of session `session_id`, newest first.
+    pub async fn get_session_clips(&self, session_id: &str, gap_secs: u64, limit: usize) -> Result<Vec<Clip>> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT c.* FROM live_clips c JOIN ({SESSION_CLIPS_SQL}) s ON c.id = s.id
+             WHERE s.session_id = ?2
+             ORDER BY c.created_at DESC LIMIT ?3"
+        ))?;
+        let clip_iter = stmt.query_map(params![gap_secs, session_id, limit], Clip::try_from_row)?;
+        collect_clips(clip_iter)
+    }
+
+    /// Sequence number of the newest recorded event, 0 if there are none.
+    pub async fn latest_event_seq(&self) -> Result<i64> {
+        Ok(self.conn.query_row("SELECT COALESCE(MAX(seq), 0) FROM clip_events", [], |row| row.get(0))?)
+    }
+
+    /// The `limit` most recent events, oldest first.
+    pub async fn recent_events(&self, limit: usize) -> Result<Vec<ClipEventRecord>> {
+        self.query_events(
+            "SELECT * FROM (
+                SELECT seq, kind, clip_id, detail, at FROM clip_events ORDER BY seq DESC LIMIT ?1
+             ) ORDER BY seq",
+            params![limit],
+        )
+    }
+
+    /// Up to `limit` events recorded after `seq`, oldest first.
+    pub async fn events_after(&self, seq: i64, limit: usize) -> Result<Vec<ClipEventRecord>> {
+        self.query_events(
+            "SELECT seq, kind, clip_id, detail, at FROM clip_events WHERE seq > ?1 ORDER BY seq LIMIT ?2",
+            params![seq, limit],
+        )
+    }
+
+    fn query_events(&self, sql: &str, params: impl rusqlite::Params) -> Result<Vec<ClipEventRecord>> {
+        let mut stmt = self.conn.prepare_cached(sql)?;
+        let rows = stmt.query_map(params, |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, Option<String>>(3)?,
+                row.get::<_, i64>(4)?,
+            ))
+        })?;
+
+        let mut events = Vec::new();
+        for row in rows {
+            let (seq, kind, clip_id, detail, at) = row?;
+            // Kinds from a newer clipq writing the same database are skipped
+            if let Some(event) = ClipEvent::from_row_parts(&kind, clip_id, detail) {
+                events.push(ClipEventRecord {
+                    seq,
+                    at: DateTime::from_timestamp(at, 0).unwrap_or_else(Utc::now),
+                    event,
+                });
+            }
+        }
+        Ok(events)
     }
 
     pub async fn backup(&self, output_path: &str) -> Result<()> {
-        let db_path = Self::get_db_path()?;
-        std::fs::copy(&db_path, output_path)?;
+        std::fs::copy(&self.path, output_path)?;
         Ok(())
     }
 
     pub async fn restore(&mut self, input_path: &str) -> Result<()> {
-        let db_path = Self::get_db_path()?;
-        std::fs::copy(input_path, &db_path)?;
+        std::fs::copy(input_path, &self.path)?;
         Ok(())
     }
-}
\ No newline at end of file
+}
+
+/// Writes made inside `Database::with_transaction`; they are committed
+/// together or not at all.
+pub struct ClipTransaction<'a> {
+    tx: rusqlite::Transaction<'a>,
+    tag_case: TagCase,
+    max_tag_length: usize,
+    /// Threshold and level for compressing imported embedded bytes
+    compression: (usize, i32),
+    /// `(id, content)` of clips to audit once committed
+    added: Vec<(String, String)>,
+    deleted: Vec<(String, String)>,
+}
+
+impl ClipTransaction<'_> {
+    pub fn add_clip(&mut self, content: &str, clip_type: &str) -> Result<String> {
+        self.add_clip_with_mime(content, clip_type, default_mime(clip_type))
+    }
+
+    pub fn add_clip_with_mime(&mut self, content: &str, clip_type: &str, mime: &str) -> Result<String> {
+        let id = insert_clip(&self.tx, content, clip_type, mime)?;
+        self.added.push((id.clone(), content.to_string()));
+        Ok(id)
+    }
+
+    /// Like `Database::add_file_clip`, as part of this transaction.
+    pub fn add_file_clip(&mut self, file_path: &str) -> Result<String> {
+        // Paths that no longer resolve are kept as given
+        let canonical = std::fs::canonicalize(file_path)
+            .map(|path| path.to_string_lossy().into_owned())
+            .unwrap_or_else(|_| file_path.to_string());
+        let file_path = canonical.as_str();
+        let now = Utc::now();
+
+        let existing: Option<String> = self
+            .tx
+            .query_row(
+                "SELECT id FROM live_clips
+                 WHERE clip_type = 'file' AND file_path = ?1 AND blob_hash IS NULL
+                 ORDER BY created_at DESC LIMIT 1",
+                params![file_path],
+                |row| row.get(0),
+            )
+            .optional()?;
+        if let Some(id) = existing {
+            self.tx.execute(
+                "UPDATE clips SET created_at = ?1, created_at_ms = ?2 WHERE id = ?3",
+                params![now.timestamp(), now.timestamp_millis(), id],
+            )?;
+            return Ok(id);
+        }
+
+        let id = Uuid::new_v4().to_string();
+        self.tx.execute(
+            "INSERT INTO clips (id, content, clip_type, created_at, created_at_ms, file_path, mime)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![id, file_path, "file", now.timestamp(), now.timestamp_millis(), file_path, default_mime("file")],
+        )?;
+        self.added.push((id.clone(), file_path.to_string()));
+        Ok(id)
+    }
+
+    /// Like `Database::add_image_clip`, as part of this transaction.
+    pub fn add_image_clip(&mut self, width: usize, height: usize, png: &[u8]) -> Result<String> {
+        let content = format!("Image {}x{}", width, height);
+        let embedded = Embedded { data: png, file_path: None, original_name: None };
+        let id = insert_embedded(&self.tx, &content, "image", default_mime("image"), embedded, self.compression)?;
+        self.added.push((id.clone(), content));
+        Ok(id)
+    }
+
+    pub fn add_tag_to_clip(&mut self, clip_id: &str, tag_name: &str) -> Result<()> {
+        let tag_name = validate_tag_name(tag_name, self.max_tag_length)?;
+        attach_tag(&self.tx, clip_id, &self.tag_case.apply(tag_name))
+    }
+
+    pub fn set_expiry(&mut self, id: &str, expires_at: Option<DateTime<Utc>>) -> Result<bool> {
+        let updated = self.tx.execute(
+            "UPDATE clips SET expires_at = ?1 WHERE id = ?2",
+            params![expires_at.map(|time| time.timestamp()), id],
+        )?;
+        Ok(updated > 0)
+    }
+
+    pub fn delete_clip(&mut self, id: &str) -> Result<bool> {
+        self.tx.execute("DELETE FROM clip_tags WHERE clip_id = ?1", params![id])?;
+        let deleted = delete_returning(&self.tx, "DELETE FROM clips WHERE id = ?1", params![id])?;
+        let found = !deleted.is_empty();
+        self.deleted.extend(deleted);
+        Ok(found)
+    }
+
+    /// Like `Database::trim_history`; returns how many clips were removed.
+    pub fn trim_history(&mut self, max_clips: usize) -> Result<usize> {
+        let trimmed = delete_outside_policy(
+            &self.tx,
+            &RetentionPolicy {
+                max_clips: Some(max_clips),
+                ..RetentionPolicy::default()
+            },
+        )?;
+        let count = trimmed.len();
+        self.deleted.extend(trimmed);
+        Ok(count)
+    }
+
+    /// Like `Database::import_clip`, as part of this transaction.
+    pub fn import_clip(&mut self, clip: &ImportedClip, on_conflict: ConflictMode) -> Result<ImportOutcome> {
+        if let Some(outcome) = self.resolve_conflict(&clip.content, &clip.tags, on_conflict)? {
+            return Ok(outcome);
+        }
+
+        let id = match &clip.data {
+            Some(data) => {
+                let embedded = Embedded {
+                    data,
+                    file_path: clip.file_path.as_deref(),
+                    original_name: clip.original_name.as_deref(),
+                };
+                let id = insert_embedded(&self.tx, &clip.content, &clip.clip_type, &clip.mime, embedded, self.compression)?;
+                self.added.push((id.clone(), clip.content.clone()));
+                id
+            }
+            None => {
+                let id = self.add_clip_with_mime(&clip.content, &clip.clip_type, &clip.mime)?;
+                if clip.file_path.is_some() {
+                    self.tx.execute("UPDATE clips SET file_path = ?1 WHERE id = ?2", params![clip.file_path, id])?;
+                }
+                id
+            }
+        };
+        if clip.expires_at.is_some() {
+            self.set_expiry(&id, clip.expires_at)?;
+        }
+        for tag in &clip.tags {
+            self.add_imported_tag(&id, tag)?;
+        }
+        Ok(ImportOutcome::Inserted)
+    }
+
+    /// Copy a clip row from another database verbatim for `import_database`,
+    /// giving it a fresh id if its own is already taken.
+    fn import_stored_clip(
+        &mut self,
+        clip: Clip,
+        blob: Option<StoredBlob>,
+        tags: &[String],
+        on_conflict: ConflictMode,
+    ) -> Result<ImportOutcome> {
+        if let Some(outcome) = self.resolve_conflict(&clip.content, tags, on_conflict)? {
+            return Ok(outcome);
+        }
+        let blob_hash = blob.map(|blob| store_blob(&self.tx, &blob)).transpose()?;
+
+        let id_taken: bool = self.tx.query_row(
+            "SELECT EXISTS(SELECT 1 FROM clips WHERE id = ?1)",
+            params![clip.id],
+            |row| row.get(0),
+        )?;
+        let id = if id_taken { Uuid::new_v4().to_string() } else { clip.id };
+        self.tx.execute(
+            "INSERT INTO clips (id, content, clip_type, created_at, created_at_ms, file_path, pinned, expires_at, mime, original_name, blob_hash)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+            params![
+                id,
+                clip.content,
+                clip.clip_type,
+                clip.created_at.timestamp(),
+                clip.created_at.timestamp_millis(),
+                clip.file_path,
+                clip.pinned,
+                clip.expires_at.map(|time| time.timestamp()),
+                clip.mime,
+                clip.original_name,
+                blob_hash,
+            ],
+        )?;
+        for tag in tags {
+            self.add_imported_tag(&id, tag)?;
+        }
+        self.added.push((id, clip.content));
+        Ok(ImportOutcome::Inserted)
+    }
+
+    /// Apply `on_conflict` if a live clip already has `content`; `None`
+    /// when the imported clip should be inserted.
+    fn resolve_conflict(&mut self, content: &str, tags: &[String], on_conflict: ConflictMode) -> Result<Option<ImportOutcome>> {
+        if on_conflict == ConflictMode::Duplicate {
+            return Ok(None);
+        }
+        let existing: Option<String> = self
+            .tx
+            .query_row(
+                "SELECT id FROM live_clips WHERE content = ?1 ORDER BY created_at DESC LIMIT 1",
+                params![content],
+                |row| row.get(0),
+            )
+            .optional()?;
+        let Some(existing) = existing else {
+            return Ok(None);
+        };
+        if on_conflict == ConflictMode::Skip {
+            return Ok(Some(ImportOutcome::Skipped));
+        }
+        for tag in tags {
+            self.add_imported_tag(&existing, tag)?;
+        }
+        Ok(Some(ImportOutcome::Merged))
+    }
+
+    /// Tag an imported clip, skipping with a warning tags this version
+    /// wouldn't accept, so one bad tag in an old backup doesn't fail the
+    /// whole import.
+    fn add_imported_tag(&mut self, clip_id: &str, tag_name: &str) -> Result<()> {
+        match validate_tag_name(tag_name, self.max_tag_length) {
+            Ok(_) => self.add_tag_to_clip(clip_id, tag_name),
+            Err(e) => {
+                warn!("Skipping imported tag {:?}: {}", tag_name, e);
+                Ok(())
+            }
+        }
+    }
+}
+
+fn insert_clip(conn: &Connection, content: &str, clip_type: &str, mime: &str) -> Result<String> {
+    let id = Uuid::new_v4().to_string();
+    let now = Utc::now();
+    
+    let mut stmt = conn.prepare_cached(
+        "INSERT INTO clips (id, content, clip_type, created_at, created_at_ms, mime) VALUES (?1, ?2, ?3, ?4, ?5, ?6)"
+    )?;
+    stmt.execute(params![id, content, clip_type, now.timestamp(), now.timestamp_millis(), mime])?;
+
+    Ok(id)
+}
+
+/// Save a clip's current content as a version before it becomes
+/// `new_content`, dropping the oldest versions beyond `max_versions`.
+/// Nothing is recorded when the content doesn't change.
+fn record_version(conn: &Connection, clip_id: &str, new_content: &str, max_versions: usize) -> Result<()> {
+    if max_versions == 0 {
+        return Ok(());
+    }
+    conn.execute(
+        "INSERT INTO clip_versions (clip_id, content, edited_at)
+         SELECT id, content, unixepoch() FROM clips WHERE id = ?1 AND content != ?2",
+        params![clip_id, new_content],
+    )?;
+    conn.execute(
+        "DELETE FROM clip_versions WHERE clip_id = ?1 AND id NOT IN (
+            SELECT id FROM clip_versions WHERE clip_id = ?1 ORDER BY id DESC LIMIT ?2
+        )",
+        params![clip_id, max_versions],
+    )?;
+    Ok(())
+}
+
+/// Bytes to store with a clip, and the file they were read from, if any.
+#[derive(Debug, Clone, Copy)]
+struct Embedded<'a> {
+    data: &'a [u8],
+    file_path: Option<&'a str>,
+    original_name: Option<&'a str>,
+}
+
+/// Insert a clip whose bytes go into `blobs`, shared with any clip holding
+/// the same bytes. New bytes larger than `compression.0` are compressed at
+/// level `compression.1`.
+fn insert_embedded(
+    conn: &Connection,
+    content: &str,
+    clip_type: &str,
+    mime: &str,
+    embedded: Embedded<'_>,
+    (threshold, level): (usize, i32),
+) -> Result<String> {
+    let id = Uuid::new_v4().to_string();
+    let now = Utc::now();
+    let data = embedded.data;
+    let hash = blob_hash(data);
+
+    let stored: bool = conn.query_row(
+        "SELECT EXISTS(SELECT 1 FROM blobs WHERE hash = ?1)",
+        params![hash],
+        |row| row.get(0),
+    )?;
+    if !stored {
+        let compressed = data.len() > threshold;
+        let blob = StoredBlob {
+            data: if compressed { zstd::encode_all(data, level)? } else { data.to_vec() },
+            compressed,
+            original_size: Some(data.len() as i64),
+        };
+        insert_blob(conn, &hash, &blob)?;
+    }
+    conn.execute(
+        "INSERT INTO clips (id, content, clip_type, created_at, created_at_ms, file_path, mime, original_name, blob_hash) 
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+        params![
+            id,
+            content,
+            clip_type,
+            now.timestamp(),
+            now.timestamp_millis(),
+            embedded.file_path,
+            mime,
+            embedded.original_name,
+            hash
+        ],
+    )?;
+    Ok(id)
+}
+
+/// The decompressed blob a query selected as `stored_data` and
+/// `stored_compressed`, if the clip has one.
+fn stored_blob(row: &Row) -> Result<Option<Vec<u8>>> {
+    match row.get::<_, Option<Vec<u8>>>("stored_data")? {
+        Some(data) if row.get::<_, bool>("stored_compressed")? => Ok(Some(zstd::decode_all(data.as_slice())?)),
+        data => Ok(data),
+    }
+}
+
+/// Hex SHA-256 of embedded file bytes, before compression.
+fn blob_hash(data: &[u8]) -> String {
+    Sha256::digest(data).iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Store `blob` unless identical bytes already are, returning its hash.
+fn store_blob(conn: &Connection, blob: &StoredBlob) -> Result<String> {
+    let hash = if blob.compressed {
+        blob_hash(&zstd::decode_all(blob.data.as_slice())?)
+    } else {
+        blob_hash(&blob.data)
+    };
+    insert_blob(conn, &hash, blob)?;
+    Ok(hash)
+}
+
+/// Add `blob` under `hash`; a no-op if it is already stored. Its refcount
+/// starts at zero and is raised by the clip referencing it.
+fn insert_blob(conn: &Connection, hash: &str, blob: &StoredBlob) -> Result<()> {
+    conn.execute(
+        "INSERT OR IGNORE INTO blobs (hash, data, compressed, original_size) VALUES (?1, ?2, ?3, ?4)",
+        params![hash, blob.data, blob.compressed, blob.original_size.unwrap_or(blob.data.len() as i64)],
+    )?;
+    Ok(())
+}
+
+/// Tag a clip, creating the tag if needed. An existing tag in another
+/// casing is reused by the case-insensitive unique index.
+fn attach_tag(conn: &Connection, clip_id: &str, tag_name: &str) -> Result<()> {
+    conn.prepare_cached("INSERT OR IGNORE INTO tags (name) VALUES (?1)")?
+        .execute(params![tag_name])?;
+    let tag_id: i64 = conn
+        .prepare_cached("SELECT id FROM tags WHERE name = ?1 COLLATE NOCASE")?
+        .query_row(params![tag_name], |row| row.get(0))?;
+    conn.prepare_cached("INSERT OR IGNORE INTO clip_tags (clip_id, tag_id) VALUES (?1, ?2)")?
+        .execute(params![clip_id, tag_id])?;
+    Ok(())
+}
+
+/// Fold every group of tags whose names are equal under `COLLATE NOCASE`
+/// into the group's oldest tag. Clips keep all their tags, and the survivor
+/// inherits a color if it had none. Returns how many tags were removed.
+fn merge_tag_case_variants(conn: &Connection) -> Result<usize> {
+    // Each tag's canonical id: the oldest tag with the same name ignoring case
+    conn.execute_batch(
+        "CREATE TEMP TABLE tag_canonical AS
+            SELECT t.id AS id,
+                   (SELECT MIN(o.id) FROM tags o WHERE o.name = t.name COLLATE NOCASE) AS canonical_id
+            FROM tags t;
+         DELETE FROM temp.tag_canonical WHERE id = canonical_id;",
+    )?;
+
+    conn.execute(
+        "INSERT OR IGNORE INTO clip_tags (clip_id, tag_id)
+         SELECT ct.clip_id, m.canonical_id
+         FROM clip_tags ct JOIN temp.tag_canonical m ON ct.tag_id = m.id",
+        [],
+    )?;
+    conn.execute(
+        "UPDATE tags SET color = (
+            SELECT d.color FROM tags d JOIN temp.tag_canonical m ON d.id = m.id
+            WHERE m.canonical_id = tags.id AND d.color IS NOT NULL
+            ORDER BY d.id LIMIT 1
+         )
+         WHERE color IS NULL AND id IN (SELECT canonical_id FROM temp.tag_canonical)",
+        [],
+    )?;
+    conn.execute("DELETE FROM clip_tags WHERE tag_id IN (SELECT id FROM temp.tag_canonical)", [])?;
+    let merged = conn.execute("DELETE FROM tags WHERE id IN (SELECT id FROM temp.tag_canonical)", [])?;
+
+    conn.execute("DROP TABLE temp.tag_canonical", [])?;
+    Ok(merged)
+}
+
+/// The deletes behind `Database::apply_retention`, run on the caller's
+/// transaction.
+fn delete_outside_policy(conn: &Connection, policy: &RetentionPolicy) -> Result<Vec<(String, String)>> {
+    let candidates = if policy.keep_pinned {
+        "SELECT rowid, id, content, created_at, created_at_ms FROM clips WHERE pinned = 0"
+    } else {
+        "SELECT rowid, id, content, created_at, created_at_ms FROM clips"
+    };
+
+    let mut rules = Vec::new();
+    // Newest by the millisecond, then by insertion order (rowid) for clips
+    // added within the same millisecond
+    if let Some(max_clips) = policy.max_clips {
+        rules.push(format!(
+            "SELECT id FROM ({candidates}) ORDER BY created_at_ms DESC, rowid DESC LIMIT -1 OFFSET {max_clips}"
+        ));
+    }
+    if let Some(max_age_days) = policy.max_age_days {
+        let cutoff = Utc::now().timestamp() - (max_age_days as i64) * 86_400;
+        rules.push(format!(
+            "SELECT id FROM ({candidates}) WHERE created_at < {cutoff}"
+        ));
+    }
+    if let Some(max_total_bytes) = policy.max_total_bytes {
+        rules.push(format!(
+            "SELECT id FROM (
+                SELECT id, SUM(LENGTH(CAST(content AS BLOB)))
+                    OVER (ORDER BY created_at_ms DESC, rowid DESC) AS running_bytes
+                FROM ({candidates})
+            ) WHERE running_bytes > {max_total_bytes}"
+        ));
+    }
+
+    // Expired clips go regardless of pins
+    rules.push(
+        "SELECT id FROM clips WHERE expires_at IS NOT NULL AND expires_at <= unixepoch()".to_string(),
+    );
+
+    let expired = rules
+        .iter()
+        .map(|rule| format!("SELECT id FROM ({rule})"))
+        .collect::<Vec<_>>()
+        .join(" UNION ");
+    conn.execute(
+        &format!("DELETE FROM clip_tags WHERE clip_id IN ({expired})"),
+        [],
+    )?;
+    delete_returning(conn, &format!("DELETE FROM clips WHERE id IN ({expired})"), [])
+}
+
+/// Run a `DELETE FROM clips` statement and return the `(id, content)` of
+/// every deleted row.
+fn delete_returning(conn: &Connection, sql: &str, params: impl rusqlite::Params) -> Result<Vec<(String, String)>> {
+    let mut stmt = conn.prepare(&format!("{} RETURNING id, content", sql))?;
+    let deleted = stmt
+        .query_map(params, |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(deleted)
+}
+
+/// `name` without surrounding whitespace, if it makes a usable tag: not
+/// empty, at most `max_length` characters and free of control characters.
+pub fn validate_tag_name(name: &str, max_length: usize) -> Result<&str, TagNameError> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err(TagNameError::Empty);
+    }
+    let length = name.chars().count();
+    if length > max_length {
+        return Err(TagNameError::TooLong { length, max: max_length });
+    }
+    if name.chars().any(char::is_control) {
+        return Err(TagNameError::ControlCharacter);
+    }
+    Ok(name)
+}
+
+pub fn validate_hex_color(color: &str) -> Result<()> {
+    let digits = color
+        .strip_prefix('#')
+        .ok_or_else(|| anyhow::anyhow!("Invalid color '{}': expected #rgb or #rrggbb", color))?;
+    if !matches!(digits.len(), 3 | 6) || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(anyhow::anyhow!("Invalid color '{}': expected #rgb or #rrggbb", color));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base64::Engine;
+
+    async fn memory_db() -> Database {
+        Database::open(":memory:").await.unwrap()
+    }
+
+    #[test]
+    fn tag_names_are_trimmed_and_checked() {
+        assert_eq!(validate_tag_name("  work ", 64), Ok("work"));
+        assert_eq!(validate_tag_name("", 64), Err(TagNameError::Empty));
+        assert_eq!(validate_tag_name(" \t ", 64), Err(TagNameError::Empty));
+        assert_eq!(
+            validate_tag_name(&"x".repeat(65), 64),
+            Err(TagNameError::TooLong { length: 65, max: 64 })
+        );
+        assert_eq!(validate_tag_name(&"é".repeat(64), 64).map(str::len), Ok(128));
+        assert_eq!(validate_tag_name("bad\u{7}tag", 64), Err(TagNameError::ControlCharacter));
+        assert_eq!(validate_tag_name("two\nlines", 64), Err(TagNameError::ControlCharacter));
+    }
+
+    #[tokio::test]
+    async fn invalid_tags_are_rejected_with_a_typed_error() {
+        let mut db = memory_db().await;
+        db.set_max_tag_length(8);
+        let id = db.add_clip("hello", "text").await.unwrap();
+
+        for tag in ["", "   ", "much-too-long", "tab\there"] {
+            let error = db.add_tag_to_clip(&id, tag).await.unwrap_err();
+            assert!(error.is::<TagNameError>(), "{:?} gave {}", tag, error);
+        }
+        db.add_tag_to_clip(&id, " ok ").await.unwrap();
+        assert_eq!(db.get_clip_tags(&id).await.unwrap(), vec!["ok"]);
+    }
+
+    #[tokio::test]
+    async fn retag_all_onto_the_same_tag_keeps_it() {
+        let mut db = memory_db().await;
+        let id = db.add_clip("hello", "text").await.unwrap();
+        db.add_tag_to_clip(&id, "work").await.unwrap();
+
+        assert!(db.retag_all("work", "work ").await.is_err());
+        assert!(db.retag_all("work", "WORK").await.is_err());
+        assert_eq!(db.get_clip_tags(&id).await.unwrap(), vec!["work"]);
+    }
+
+    #[tokio::test]
+    async fn imports_skip_invalid_tags() {
+        let mut db = memory_db().await;
+        let tags = vec!["".to_string(), "x".repeat(100), "keep".to_string(), "nul\0".to_string()];
+        let clip = ImportedClip {
+            tags,
+            ..ImportedClip::new("imported".to_string(), "text")
+        };
+        let outcome = db.import_clip(&clip, ConflictMode::Duplicate).await.unwrap();
+
+        assert_eq!(outcome, ImportOutcome::Inserted);
+        let clip = db.find_clip_by_content("imported").await.unwrap().unwrap();
+        assert_eq!(db.get_clip_tags(&clip.id).await.unwrap(), vec!["keep"]);
+    }
+
+    #[tokio::test]
+    async fn hour_bins_follow_the_clock_across_dst() {
+        let mut db = memory_db().await;
+        for at in [
+            // 01:30 EST, then 03:30 EDT: the clocks skip 02:00-03:00
+            "2024-03-10T06:30:00Z",
+            "2024-03-10T07:30:00Z",
+            "2024-03-10T12:00:00Z",
+            // 01:30 EDT and, after falling back, 01:30 EST again
+            "2024-11-03T05:30:00Z",
+            "2024-11-03T06:30:00Z",
+        ] {
+            add_clip_at(&mut db, at, utc(at)).await;
+        }
+
+        let counts = db.clips_per_hour(&Timezone::Named(chrono_tz::America::New_York)).await.unwrap();
+        let mut expected = [0; 24];
+        expected[1] = 3;
+        expected[3] = 1;
+        expected[8] = 1;
+        assert_eq!(counts, expected);
+    }
+
+    #[tokio::test]
+    async fn hour_bins_respect_half_hour_offsets() {
+        let mut db = memory_db().await;
+        // 05:50 and 06:10 in India, on either side of a local hour
+        add_clip_at(&mut db, "before", utc("2024-05-01T00:20:00Z")).await;
+        add_clip_at(&mut db, "after", utc("2024-05-01T00:40:00Z")).await;
+
+        let counts = db.clips_per_hour(&Timezone::Named(chrono_tz::Asia::Kolkata)).await.unwrap();
+        assert_eq!((counts[5], counts[6]), (1, 1));
+        assert_eq!(counts.iter().sum::<usize>(), 2);
+    }
+
+    #[tokio::test]
+    async fn clips_split_into_sessions_at_gaps() {
+        let mut db = memory_db().await;
+        let morning = add_clip_at(&mut db, "morning 1", utc("2024-05-01T10:00:00Z")).await;
+        add_clip_at(&mut db, "morning 2", utc("2024-05-01T10:00:30Z")).await;
+        let later = add_clip_at(&mut db, "later 1", utc("2024-05-01T11:00:00Z")).await;
+        // Exactly the gap apart still counts as the same burst
+        add_clip_at(&mut db, "later 2", utc("2024-05-01T11:05:00Z")).await;
+        add_clip_at(&mut db, "later 3", utc("2024-05-01T11:06:00Z")).await;
+
+        let sessions = db.get_sessions(300, 10).await.unwrap();
+        let summary: Vec<_> = sessions
+            .iter()
+            .map(|session| (session.id.as_str(), session.started_at, session.ended_at, session.clip_count))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (later.as_str(), utc("2024-05-01T11:00:00Z"), utc("2024-05-01T11:06:00Z"), 3),
+                (morning.as_str(), utc("2024-05-01T10:00:00Z"), utc("2024-05-01T10:00:30Z"), 2),
+            ]
+        );
+
+        assert_eq!(db.resolve_session_id(&morning[..8], 300).await.unwrap(), morning);
+        let clips = db.get_session_clips(&morning, 300, 10).await.unwrap();
+        let clips: Vec<_> = clips.iter().map(|clip| clip.content.as_str()).collect();
+        assert_eq!(clips, vec!["morning 2", "morning 1"]);
+
+        // A wider gap merges them
+        assert_eq!(db.get_sessions(3600, 10).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn exports_carry_embedded_bytes() {
+        let mut db = memory_db().await;
+        let bytes = b"\x89PNG not really".to_vec();
+        db.add_embedded_file_clip("/tmp/picture.png", "picture.png", &bytes).await.unwrap();
+
+        let mut exported = Vec::new();
+        db.for_each_tagged_clip(ClipOrder::default(), |tagged| {
+            exported.push(serde_json::to_string(&tagged)?);
+            Ok(())
+        })
+        .await
+        .unwrap();
+        let json: serde_json::Value = serde_json::from_str(&exported[0]).unwrap();
+        assert_eq!(json["data"], base64::engine::general_purpose::STANDARD.encode(&bytes));
+
+        let mut other = memory_db().await;
+        let tagged: TaggedClip = serde_json::from_str(&exported[0]).unwrap();
+        let outcome = other.import_clip(&ImportedClip::from(tagged), ConflictMode::Duplicate).await.unwrap();
+        assert_eq!(outcome, ImportOutcome::Inserted);
+        let clip = other.get_recent_clips(1).await.unwrap().remove(0);
+        assert_eq!(clip.original_name.as_deref(), Some("picture.png"));
+        assert_eq!(other.get_clip_blob(&clip.id).await.unwrap(), Some(bytes));
+    }
+
+    /// Add a text clip that was copied at `at`.
+    async fn add_clip_at(db: &mut Database, content: &str, at: DateTime<Utc>) -> String {
+        let id = db.add_clip(content, "text").await.unwrap();
+        db.conn
+            .execute(
+                "UPDATE clips SET created_at = ?1, created_at_ms = ?2 WHERE id = ?3",
+                params![at.timestamp(), at.timestamp_millis(), id],
+            )
+            .unwrap();
+        id
+    }
+
+    fn utc(text: &str) -> DateTime<Utc> {
+        text.parse().unwrap()
+    }
+
+    #[tokio::test]
+    async fn days_are_counted_in_local_time_across_dst() {
+        let mut db = memory_db().await;
+        let tz = Timezone::Named(chrono_tz::America::New_York);
+        // Clocks sprang forward at 02:00 on 2024-03-10
+        add_clip_at(&mut db, "late on the 9th", utc("2024-03-10T04:59:00Z")).await; // 23:59 EST
+        add_clip_at(&mut db, "early on the 10th", utc("2024-03-10T05:30:00Z")).await; // 00:30 EST
+        add_clip_at(&mut db, "late on the 10th", utc("2024-03-11T03:30:00Z")).await; // 23:30 EDT
+        add_clip_at(&mut db, "early on the 11th", utc("2024-03-11T04:15:00Z")).await; // 00:15 EDT
+
+        let days = db.clips_per_day_until(utc("2024-03-11T16:00:00Z"), 3, &tz).unwrap();
+        let date = |text: &str| text.parse::<NaiveDate>().unwrap();
+        assert_eq!(days, vec![(date("2024-03-09"), 1), (date("2024-03-10"), 2), (date("2024-03-11"), 1)]);
+    }
+
+    #[tokio::test]
+    async fn quarter_hour_offsets_split_days_correctly() {
+        let mut db = memory_db().await;
+        let tz = Timezone::Named(chrono_tz::Asia::Kathmandu);
+        // Kathmandu is UTC+05:45, so local midnight is 18:15 UTC
+        add_clip_at(&mut db, "before midnight", utc("2024-06-01T18:14:00Z")).await;
+        add_clip_at(&mut db, "after midnight", utc("2024-06-01T18:15:00Z")).await;
+
+        let days = db.clips_per_day_until(utc("2024-06-02T06:00:00Z"), 2, &tz).unwrap();
+        assert_eq!(days.iter().map(|(_, count)| *count).collect::<Vec<_>>(), vec![1, 1]);
+    }
+
+    #[tokio::test]
+    async fn activity_span_is_capped() {
+        let db = memory_db().await;
+        let days = db.clips_per_day(usize::MAX, &Timezone::Local).await.unwrap();
+        assert_eq!(days.len(), MAX_ACTIVITY_DAYS);
+    }
+
+    #[tokio::test]
+    async fn tag_leaderboard_orders_by_use_then_name() {
+        let mut db = memory_db().await;
+        for (content, tags) in [("a", &["work", "Blue"][..]), ("b", &["work", "alpha"]), ("c", &["work"])] {
+            let id = db.add_clip(content, "text").await.unwrap();
+            for tag in tags {
+                db.add_tag_to_clip(&id, tag).await.unwrap();
+            }
+        }
+        db.set_tag_color("idle", Some("#fff")).await.unwrap();
+
+        let usage: Vec<(String, usize)> = db
+            .list_all_tags()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|tag| (tag.name, tag.clips))
+            .collect();
+        let expected = [("work", 3), ("alpha", 1), ("Blue", 1), ("idle", 0)];
+        assert_eq!(usage, expected.map(|(name, clips)| (name.to_string(), clips)));
+    }
+
+    #[tokio::test]
+    async fn links_to_cleared_clips_dont_count_and_prune_keeps_colored_tags() {
+        let mut db = memory_db().await;
+        let id = db.add_clip("gone soon", "text").await.unwrap();
+        db.add_tag_to_clip(&id, "plain").await.unwrap();
+        db.add_tag_to_clip(&id, "colored").await.unwrap();
+        db.set_tag_color("colored", Some("#f00")).await.unwrap();
+        // Databases written without foreign keys enforced kept the links
+        db.conn.execute_batch("PRAGMA foreign_keys = OFF").unwrap();
+        db.clear_history().await.unwrap();
+        db.conn.execute_batch("PRAGMA foreign_keys = ON").unwrap();
+        let kept = db.add_clip("new", "text").await.unwrap();
+        db.add_tag_to_clip(&kept, "live").await.unwrap();
+
+        let usage = db.list_all_tags().await.unwrap();
+        assert!(usage.iter().all(|tag| tag.clips == usize::from(tag.name == "live")), "{:?}", usage);
+        assert_eq!(db.unused_tags().await.unwrap(), vec!["plain"]);
+
+        assert_eq!(db.prune_unused_tags().await.unwrap(), 1);
+        let names: Vec<String> = db.list_all_tags().await.unwrap().into_iter().map(|tag| tag.name).collect();
+        assert_eq!(names, vec!["live", "colored"]);
+        let orphaned: i64 = db
+            .conn
+            .query_row("SELECT COUNT(*) FROM clip_tags WHERE clip_id NOT IN (SELECT id FROM clips)", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(orphaned, 1, "only the colored tag's stale link is left for repair");
+    }
+
+    async fn contents(db: &Database) -> Vec<String> {
+        let mut contents: Vec<String> = db
+            .get_recent_clips(100)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|clip| clip.content)
+            .collect();
+        contents.sort();
+        contents
+    }
+
+    #[tokio::test]
+    async fn trimming_keeps_the_last_inserted_of_one_second() {
+        let mut db = memory_db().await;
+        for i in 0..5 {
+            db.add_clip(&format!("clip {}", i), "text").await.unwrap();
+        }
+        // Worst case: all five share the second and the millisecond
+        db.conn
+            .execute("UPDATE clips SET created_at = 1700000000, created_at_ms = 1700000000000", [])
+            .unwrap();
+
+        db.trim_history(3).await.unwrap();
+        assert_eq!(contents(&db).await, vec!["clip 2", "clip 3", "clip 4"]);
+
+        db.add_and_trim("clip 5", "text", 3).await.unwrap();
+        assert_eq!(contents(&db).await, vec!["clip 3", "clip 4", "clip 5"]);
+    }
+
+    #[tokio::test]
+    async fn a_failed_transaction_leaves_nothing_behind() {
+        let mut db = memory_db().await;
+        let kept = db.add_clip("kept", "text").await.unwrap();
+
+        let result = db.with_transaction(|tx| {
+            let id = tx.add_clip("first", "text")?;
+            tx.add_tag_to_clip(&id, "batch")?;
+            tx.add_image_clip(1, 1, b"png bytes")?;
+            tx.delete_clip(&kept)?;
+            // Empty tag names are rejected, failing the whole batch
+            tx.add_tag_to_clip(&id, "")?;
+            Ok(id)
+        });
+
+        assert!(result.is_err());
+        assert_eq!(contents(&db).await, vec!["kept"]);
+        assert!(db.get_clips_by_tag("batch").await.unwrap().is_empty());
+        assert!(blob_refcounts(&db).is_empty());
+
+        // The connection is left usable
+        db.add_and_trim("after", "text", 10).await.unwrap();
+        assert_eq!(contents(&db).await, vec!["after", "kept"]);
+    }
+
+    #[tokio::test]
+    async fn images_added_in_a_transaction_are_trimmed_to_the_limit() {
+        let mut db = memory_db().await;
+        for i in 0..3 {
+            db.add_clip(&format!("clip {}", i), "text").await.unwrap();
+        }
+
+        let id = db
+            .with_transaction(|tx| {
+                let id = tx.add_image_clip(2, 1, b"png bytes")?;
+                tx.trim_history(3)?;
+                Ok(id)
+            })
+            .unwrap();
+        assert_eq!(contents(&db).await, vec!["Image 2x1", "clip 1", "clip 2"]);
+        assert_eq!(db.get_clip_blob(&id).await.unwrap().as_deref(), Some(&b"png bytes"[..]));
+    }
+
+    #[tokio::test]
+    async fn a_file_moved_to_the_top_survives_trimming() {
+        let mut db = memory_db().await;
+        db.add_file_clip("/no/such/file").await.unwrap();
+        for i in 0..3 {
+            db.add_clip(&format!("clip {}", i), "text").await.unwrap();
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+        // Older rowid, but re-added last
+        db.add_file_clip("/no/such/file").await.unwrap();
+
+        db.trim_history(3).await.unwrap();
+        assert_eq!(contents(&db).await, vec!["/no/such/file", "clip 1", "clip 2"]);
+    }
+
+    #[tokio::test]
+    async fn search_limits_the_newest_live_matches() {
+        let mut db = memory_db().await;
+        let old = add_clip_at(&mut db, "needle oldest", utc("2024-01-01T00:00:00Z")).await;
+        add_clip_at(&mut db, "needle middle", utc("2024-01-02T00:00:00Z")).await;
+        let expired = add_clip_at(&mut db, "needle expired", utc("2024-01-03T00:00:00Z")).await;
+        db.set_expiry(&expired, Some(utc("2024-01-04T00:00:00Z"))).await.unwrap();
+        // Lowest rowid, but the most recent clip
+        db.conn
+            .execute(
+                "UPDATE clips SET created_at = ?1 WHERE id = ?2",
+                params![utc("2024-01-05T00:00:00Z").timestamp(), old],
+            )
+            .unwrap();
+
+        let found: Vec<String> = db
+            .search_clips("needle", 2)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|clip| clip.content)
+            .collect();
+        assert_eq!(found, vec!["needle oldest", "needle middle"]);
+    }
+
+    #[tokio::test]
+    async fn old_search_indexes_are_rebuilt() {
+        let mut db = memory_db().await;
+        db.conn
+            .execute_batch(
+                "DROP TABLE clips_fts;
+                 CREATE VIRTUAL TABLE clips_fts USING fts5(content, content='clips', tokenize='trigram');",
+            )
+            .unwrap();
+        db.add_clip("indexed before the upgrade", "text").await.unwrap();
+
+        db.init_fts().unwrap();
+        let sql: String = db
+            .conn
+            .query_row("SELECT sql FROM sqlite_master WHERE name = 'clips_fts'", [], |row| row.get(0))
+            .unwrap();
+        assert!(sql.contains("detail='none'"), "{}", sql);
+        let found = db.search_clips("before the", 10).await.unwrap();
+        assert_eq!(found.len(), 1);
+    }
+
+    fn blob_refcounts(db: &Database) -> Vec<i64> {
+        let mut stmt = db.conn.prepare("SELECT refcount FROM blobs ORDER BY hash").unwrap();
+        let refcounts = stmt.query_map([], |row| row.get(0)).unwrap();
+        refcounts.collect::<rusqlite::Result<_>>().unwrap()
+    }
+
+    #[tokio::test]
+    async fn identical_files_share_one_blob() {
+        let mut db = memory_db().await;
+        let first = db.add_embedded_file_clip("/a/report.pdf", "report.pdf", b"same bytes").await.unwrap();
+        let second = db.add_embedded_file_clip("/b/copy.pdf", "copy.pdf", b"same bytes").await.unwrap();
+        assert_eq!(blob_refcounts(&db), vec![2]);
+
+        db.delete_clip(&first).await.unwrap();
+        assert_eq!(blob_refcounts(&db), vec![1]);
+        assert_eq!(db.get_clip_blob(&second).await.unwrap().as_deref(), Some(&b"same bytes"[..]));
+    }
+
+    #[tokio::test]
+    async fn deleting_the_last_reference_drops_the_blob() {
+        let mut db = memory_db().await;
+        let kept = db.add_embedded_file_clip("/a/kept.bin", "kept.bin", b"kept").await.unwrap();
+        let id = db.add_embedded_file_clip("/a/gone.bin", "gone.bin", b"gone").await.unwrap();
+        assert_eq!(blob_refcounts(&db), vec![1, 1]);
+
+        db.delete_clip(&id).await.unwrap();
+        assert_eq!(blob_refcounts(&db), vec![1]);
+        assert_eq!(db.get_clip_blob(&id).await.unwrap(), None);
+        assert_eq!(db.get_clip_blob(&kept).await.unwrap().as_deref(), Some(&b"kept"[..]));
+    }
+
+    #[tokio::test]
+    async fn corrupt_rows_are_skipped() {
+        let mut db = memory_db().await;
+        db.add_clip("before", "text").await.unwrap();
+        let bad = db.add_clip("corrupt", "text").await.unwrap();
+        db.add_clip("after", "text").await.unwrap();
+        db.conn
+            .execute("UPDATE clips SET created_at = 'not a time' WHERE id = ?1", params![bad])
+            .unwrap();
+
+        assert_eq!(contents(&db).await, vec!["after", "before"]);
+        let found = db.search_clips_regex(&Regex::new("e").unwrap(), None, 10).await.unwrap();
+        assert_eq!(found.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn imported_databases_are_audited() {
+        let dir = std::env::temp_dir().join(format!("clipq-import-{}", uuid::Uuid::new_v4()));
+        let source_path = dir.join("other.db").to_string_lossy().to_string();
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut source = Database::open(&source_path).await.unwrap();
+        let imported = source.add_clip("from the other machine", "text").await.unwrap();
+        drop(source);
+
+        let mut db = memory_db().await;
+        db.set_audit_log(Some(AuditLog::new(dir.join("audit.jsonl"))));
+        db.import_database(&source_path, ConflictMode::Skip).await.unwrap();
+
+        let log = std::fs::read_to_string(dir.join("audit.jsonl")).unwrap();
+        let events: Vec<serde_json::Value> = log.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0]["action"], "add");
+        assert_eq!(events[0]["clip_id"], imported.as_str());
+        assert!(!log.contains("other machine"));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn uses_count_toward_co_copies() {
+        let mut db = memory_db().await;
+        let host = add_clip_at(&mut db, "staging host", utc("2024-05-01T09:00:00Z")).await;
+        let password = add_clip_at(&mut db, "staging password", utc("2024-05-01T09:01:00Z")).await;
+        let unrelated = add_clip_at(&mut db, "unrelated", utc("2024-05-01T15:00:00Z")).await;
+        db.record_use(&host).await.unwrap();
+        db.record_use(&password).await.unwrap();
+
+        assert_eq!(db.use_count(&host).await.unwrap(), 1);
+        let counts = db.co_copy_counts(&host, 600).await.unwrap();
+        // The captures a minute apart, and the two uses just now
+        assert_eq!(counts.get(&password), Some(&2));
+        assert_eq!(counts.get(&unrelated), None);
+
+        db.delete_clip(&host).await.unwrap();
+        assert_eq!(db.use_count(&host).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn tag_search_only_matches_tagged_clips() {
+        let mut db = memory_db().await;
+        let tagged = db.add_clip("deploy script", "text").await.unwrap();
+        db.add_tag_to_clip(&tagged, "Work").await.unwrap();
+        db.add_clip("deploy notes", "text").await.unwrap();
+
+        let found = db.search_clips_by_tag("deploy", "work", 10).await.unwrap();
+        assert_eq!(found.iter().map(|clip| clip.id.as_str()).collect::<Vec<_>>(), vec![tagged.as_str()]);
+        assert_eq!(db.search_clips("deploy", 10).await.unwrap().len(), 2);
+        assert!(db.search_clips_by_tag("notes", "work", 10).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn retention_deletes_what_any_limit_rules_out_but_keeps_pins() {
+        let mut db = memory_db().await;
+        let now = Utc::now();
+        let pinned = add_clip_at(&mut db, "pinned and ancient", now - chrono::Duration::days(90)).await;
+        db.set_pinned(&pinned, true).await.unwrap();
+        add_clip_at(&mut db, "40 days old", now - chrono::Duration::days(40)).await;
+        add_clip_at(&mut db, "35 days old", now - chrono::Duration::days(35)).await;
+        for hours in (1..=4).rev() {
+            add_clip_at(&mut db, &format!("{} hours old", hours), now - chrono::Duration::hours(hours)).await;
+        }
+
+        // The age limit goes further than the count limit
+        let policy = RetentionPolicy {
+            max_clips: Some(5),
+            max_age_days: Some(30),
+            ..RetentionPolicy::default()
+        };
+        assert_eq!(db.apply_retention(&policy).await.unwrap(), 2);
+        let expected = ["1 hours old", "2 hours old", "3 hours old", "4 hours old", "pinned and ancient"];
+        assert_eq!(contents(&db).await, expected);
+
+        // Now the count limit does
+        let policy = RetentionPolicy {
+            max_clips: Some(2),
+            ..policy
+        };
+        assert_eq!(db.apply_retention(&policy).await.unwrap(), 2);
+        assert_eq!(contents(&db).await, vec!["1 hours old", "2 hours old", "pinned and ancient"]);
+    }
+
+    #[tokio::test]
+    async fn retention_deletes_expired_rows_not_just_hides_them() {
+        let mut db = memory_db().await;
+        let expired = db.add_clip("one-time code", "text").await.unwrap();
+        db.set_expiry(&expired, Some(utc("2024-01-01T00:00:00Z"))).await.unwrap();
+        db.add_clip("kept", "text").await.unwrap();
+        let rows = |db: &Database| -> i64 { db.conn.query_row("SELECT COUNT(*) FROM clips", [], |row| row.get(0)).unwrap() };
+        assert_eq!(rows(&db), 2, "an expired clip stays on disk until it is collected");
+
+        assert_eq!(db.apply_retention(&RetentionPolicy::default()).await.unwrap(), 1);
+        assert_eq!(rows(&db), 1);
+        assert_eq!(contents(&db).await, vec!["kept"]);
+    }
+
+    async fn import_conflict(on_conflict: ConflictMode) -> (Database, ImportOutcome) {
+        let mut db = memory_db().await;
+        let existing = db.add_clip("shared", "text").await.unwrap();
+        db.add_tag_to_clip(&existing, "mine").await.unwrap();
+        let clip = ImportedClip {
+            tags: vec!["theirs".to_string()],
+            ..ImportedClip::new("shared".to_string(), "text")
+        };
+        let outcome = db.import_clip(&clip, on_conflict).await.unwrap();
+        (db, outcome)
+    }
+
+    #[tokio::test]
+    async fn import_conflicts_can_be_skipped() {
+        let (db, outcome) = import_conflict(ConflictMode::Skip).await;
+        assert_eq!(outcome, ImportOutcome::Skipped);
+        let clips = db.get_all_clips().await.unwrap();
+        assert_eq!(clips.len(), 1);
+        assert_eq!(db.get_clip_tags(&clips[0].id).await.unwrap(), vec!["mine"]);
+    }
+
+    #[tokio::test]
+    async fn import_conflicts_can_be_duplicated() {
+        let (db, outcome) = import_conflict(ConflictMode::Duplicate).await;
+        assert_eq!(outcome, ImportOutcome::Inserted);
+        let mut tags = Vec::new();
+        for clip in db.get_all_clips().await.unwrap() {
+            assert_eq!(clip.content, "shared");
+            tags.extend(db.get_clip_tags(&clip.id).await.unwrap());
+        }
+        tags.sort();
+        assert_eq!(tags, vec!["mine", "theirs"]);
+    }
+
+    #[tokio::test]
+    async fn import_conflicts_can_be_merged() {
+        let (db, outcome) = import_conflict(ConflictMode::Merge).await;
+        assert_eq!(outcome, ImportOutcome::Merged);
+        let clips = db.get_all_clips().await.unwrap();
+        assert_eq!(clips.len(), 1);
+        assert_eq!(db.get_clip_tags(&clips[0].id).await.unwrap(), vec!["mine", "theirs"]);
+    }
+
+    #[tokio::test]
+    async fn tag_colors_must_be_hex() {
+        let mut db = memory_db().await;
+        let id = db.add_clip("colorful", "text").await.unwrap();
+        db.add_tag_to_clip(&id, "urgent").await.unwrap();
+
+        db.set_tag_color("urgent", Some("#FF8800")).await.unwrap();
+        let color = |infos: Vec<TagInfo>| infos.into_iter().map(|info| info.color).collect::<Vec<_>>();
+        assert_eq!(color(db.get_clip_tag_infos(&id).await.unwrap()), vec![Some("#ff8800".to_string())]);
+
+        for invalid in ["#zzz", "ff8800", "#ff88", ""] {
+            assert!(db.set_tag_color("urgent", Some(invalid)).await.is_err(), "{:?}", invalid);
+        }
+        assert_eq!(color(db.get_clip_tag_infos(&id).await.unwrap()), vec![Some("#ff8800".to_string())]);
+
+        db.set_tag_color("urgent", None).await.unwrap();
+        assert_eq!(color(db.get_clip_tag_infos(&id).await.unwrap()), vec![None]);
+    }
+
+    #[tokio::test]
+    async fn similar_tags_catch_typos() {
+        let mut db = memory_db().await;
+        let id = db.add_clip("tagged", "text").await.unwrap();
+        for tag in ["work", "personal", "wrath"] {
+            db.add_tag_to_clip(&id, tag).await.unwrap();
+        }
+
+        assert_eq!(db.find_similar_tags("wrk", 2).await.unwrap(), vec!["work"]);
+        assert_eq!(db.find_similar_tags("Personl", 2).await.unwrap(), vec!["personal"]);
+        // An existing tag is no typo
+        assert!(db.find_similar_tags("WORK", 2).await.unwrap().is_empty());
+        assert!(db.find_similar_tags("groceries", 2).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn streamed_clips_match_the_full_listing() {
+        let mut db = memory_db().await;
+        let start = utc("2024-01-01T00:00:00Z");
+        // Over two pages, with clips sharing a second across page boundaries
+        for i in 0..STREAM_PAGE_SIZE * 2 + 10 {
+            add_clip_at(&mut db, &format!("clip {}", i), start + chrono::Duration::seconds(i as i64 / 3)).await;
+        }
+        let expired = db.add_clip("expired", "text").await.unwrap();
+        db.set_expiry(&expired, Some(start)).await.unwrap();
+
+        let streamed: Vec<Clip> = db.stream_clips().try_collect().await.unwrap();
+        assert!(streamed.windows(2).all(|pair| pair[0].created_at >= pair[1].created_at), "newest first");
+        let ids = |clips: &[Clip]| {
+            let mut ids: Vec<String> = clips.iter().map(|clip| clip.id.clone()).collect();
+            ids.sort();
+            ids
+        };
+        let all = db.get_all_clips().await.unwrap();
+        assert_eq!(streamed.len(), STREAM_PAGE_SIZE * 2 + 10);
+        assert_eq!(ids(&streamed), ids(&all));
+    }
+
+    #[tokio::test]
+    async fn id_prefixes_resolve_only_when_unique() {
+        let mut db = memory_db().await;
+        for (content, id) in [("first", "abc12345-0000"), ("second", "abc99999-0000"), ("third", "def00000-0000")] {
+            let added = db.add_clip(content, "text").await.unwrap();
+            db.conn.execute("UPDATE clips SET id = ?1 WHERE id = ?2", params![id, added]).unwrap();
+        }
+
+        assert_eq!(db.resolve_id_prefix("abc1").await.unwrap(), IdResolution::Unique("abc12345-0000".to_string()));
+        assert_eq!(db.resolve_id_prefix("d").await.unwrap(), IdResolution::Unique("def00000-0000".to_string()));
+        match db.resolve_id_prefix("abc").await.unwrap() {
+            IdResolution::Ambiguous(mut ids) => {
+                ids.sort();
+                assert_eq!(ids, vec!["abc12345-0000", "abc99999-0000"]);
+            }
+            other => panic!("expected an ambiguous prefix, got {:?}", other),
+        }
+        assert_eq!(db.resolve_id_prefix("xyz").await.unwrap(), IdResolution::NotFound);
+        assert_eq!(db.resolve_id_prefix("").await.unwrap(), IdResolution::NotFound);
+    }
+
+    #[tokio::test]
+    async fn redaction_masks_previews_but_not_stored_content() {
+        let mut db = memory_db().await;
+        let content = "pay jane@example.com with 4111 1111 1111 1111 today";
+        let id = db.add_clip(content, "text").await.unwrap();
+        let config = crate::config::Config {
+            redact_patterns: vec![r"[\w.+-]+@[\w-]+\.[\w.]+".to_string(), r"\b\d(?:[ -]?\d){12,15}\b".to_string()],
+            ..crate::config::Config::default()
+        };
+        let patterns = config.redact_regexes().unwrap();
+
+        let clip = db.get_clip_by_id(&id).await.unwrap().unwrap();
+        assert_eq!(clip.redacted_preview(80, &patterns), "pay **** with **** today");
+        // Cut inside the card number, which must not leak its first digits
+        assert_eq!(clip.redacted_preview(17, &patterns), "pay **** with...");
+        assert_eq!(clip.redacted_preview(80, &[]), content);
+        assert_eq!(clip.content, content);
+    }
+
+    #[tokio::test]
+    async fn tag_filters_match_all_or_any_of_the_tags() {
+        let mut db = memory_db().await;
+        for (content, tags) in [("both", &["work", "urgent"][..]), ("work only", &["work"]), ("urgent only", &["urgent"]), ("neither", &["home"])] {
+            let id = db.add_clip(content, "text").await.unwrap();
+            for tag in tags {
+                db.add_tag_to_clip(&id, tag).await.unwrap();
+            }
+        }
+        let matching = |clips: Vec<Clip>| {
+            let mut contents: Vec<String> = clips.into_iter().map(|clip| clip.content).collect();
+            contents.sort();
+            contents
+        };
+        let tags = vec!["work".to_string(), "URGENT".to_string(), "work".to_string()];
+
+        assert_eq!(matching(db.get_clips_by_tags(&tags, TagMatch::All).await.unwrap()), vec!["both"]);
+        assert_eq!(
+            matching(db.get_clips_by_tags(&tags, TagMatch::Any).await.unwrap()),
+            vec!["both", "urgent only", "work only"]
+        );
+        assert!(db.get_clips_by_tags(&[], TagMatch::Any).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn normalizing_merges_case_variants_of_a_tag() {
+        let mut db = memory_db().await;
+        // As left by a version without the case-insensitive index
+        db.conn.execute("DROP INDEX idx_tags_name_nocase", []).unwrap();
+        let first = db.add_clip("first", "text").await.unwrap();
+        let second = db.add_clip("second", "text").await.unwrap();
+        for (id, name, color) in [(1, "Work", None), (2, "work", Some("#00ff00")), (3, "WORK", None)] {
+            db.conn.execute("INSERT INTO tags (id, name, color) VALUES (?1, ?2, ?3)", params![id, name, color]).unwrap();
+        }
+        for (clip, tag) in [(&first, 1), (&first, 2), (&second, 3)] {
+            db.conn.execute("INSERT INTO clip_tags (clip_id, tag_id) VALUES (?1, ?2)", params![clip, tag]).unwrap();
+        }
+
+        assert_eq!(db.normalize_tags().await.unwrap(), 2);
+        let tags = db.list_all_tags().await.unwrap();
+        assert_eq!(tags.len(), 1);
+        assert_eq!((tags[0].name.as_str(), tags[0].clips), ("Work", 2));
+        let infos = db.get_clip_tag_infos(&second).await.unwrap();
+        assert_eq!(infos[0].color.as_deref(), Some("#00ff00"));
+
+        let mut tagged: Vec<String> = db.get_clips_by_tag("work").await.unwrap().into_iter().map(|clip| clip.content).collect();
+        tagged.sort();
+        assert_eq!(tagged, vec!["first", "second"]);
+        db.add_tag_to_clip(&first, "wORK").await.unwrap();
+        assert_eq!(db.get_clip_tags(&first).await.unwrap(), vec!["Work"]);
+    }
+
+    #[tokio::test]
+    async fn imported_databases_merge_into_this_one() {
+        let dir = std::env::temp_dir().join(format!("clipq-import-{}", uuid::Uuid::new_v4()));
+        let source_path = dir.join("other.db").to_string_lossy().to_string();
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut source = Database::open(&source_path).await.unwrap();
+        let shared = add_clip_at(&mut source, "shared", utc("2024-02-01T00:00:00Z")).await;
+        source.add_tag_to_clip(&shared, "theirs").await.unwrap();
+        let new = add_clip_at(&mut source, "only over there", utc("2024-02-02T00:00:00Z")).await;
+        source.add_tag_to_clip(&new, "travel").await.unwrap();
+        let file = source.add_embedded_file_clip("/old/laptop/notes.txt", "notes.txt", b"remember this").await.unwrap();
+        drop(source);
+
+        let mut db = memory_db().await;
+        let mine = db.add_clip("shared", "text").await.unwrap();
+        db.add_tag_to_clip(&mine, "mine").await.unwrap();
+        let outcomes = db.import_database(&source_path, ConflictMode::Merge).await.unwrap();
+        assert_eq!(outcomes, vec![ImportOutcome::Merged, ImportOutcome::Inserted, ImportOutcome::Inserted]);
+
+        assert_eq!(db.get_clip_tags(&mine).await.unwrap(), vec!["mine", "theirs"]);
+        let copied = db.get_clip_by_id(&new).await.unwrap().expect("the id is kept");
+        assert_eq!(copied.content, "only over there");
+        assert_eq!(copied.created_at, utc("2024-02-02T00:00:00Z"));
+        assert_eq!(db.get_clip_tags(&new).await.unwrap(), vec!["travel"]);
+        assert_eq!(db.get_clip_blob(&file).await.unwrap().as_deref(), Some(&b"remember this"[..]));
+
+        // Importing again changes nothing
+        let again = db.import_database(&source_path, ConflictMode::Skip).await.unwrap();
+        assert_eq!(again, vec![ImportOutcome::Skipped; 3]);
+        assert_eq!(db.get_all_clips().await.unwrap().len(), 3);
+
+        std::fs::write(dir.join("not.db"), "just text").unwrap();
+        assert!(db.import_database(&dir.join("not.db").to_string_lossy(), ConflictMode::Skip).await.is_err());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn clearing_from_either_end_spares_pins() {
+        let mut db = memory_db().await;
+        let mut ids = Vec::new();
+        for i in 1..=8 {
+            ids.push(add_clip_at(&mut db, &format!("clip {}", i), utc("2024-03-01T00:00:00Z") + chrono::Duration::minutes(i)).await);
+        }
+        db.set_pinned(&ids[0], true).await.unwrap();
+        db.set_pinned(&ids[7], true).await.unwrap();
+
+        assert_eq!(db.delete_oldest(3).await.unwrap(), 3);
+        assert_eq!(contents(&db).await, vec!["clip 1", "clip 5", "clip 6", "clip 7", "clip 8"]);
+        assert_eq!(db.delete_newest(2).await.unwrap(), 2);
+        assert_eq!(contents(&db).await, vec!["clip 1", "clip 5", "clip 8"]);
+        // Only the unpinned clip is left to delete
+        assert_eq!(db.delete_newest(10).await.unwrap(), 1);
+        assert_eq!(contents(&db).await, vec!["clip 1", "clip 8"]);
+    }
+
+    #[tokio::test]
+    async fn edits_keep_versions_that_can_be_reverted() {
+        let mut db = memory_db().await;
+        db.set_max_versions(2);
+        let id = db.add_clip("v1", "text").await.unwrap();
+        for content in ["v2", "v3", "v3", "v4"] {
+            db.update_clip_content(&id, content).await.unwrap();
+        }
+        async fn versions(db: &Database, id: &str) -> Vec<String> {
+            db.get_clip_versions(id).await.unwrap().into_iter().map(|version| version.content).collect()
+        }
+        // Unchanged saves add nothing, and only the newest two are kept
+        assert_eq!(versions(&db, &id).await, vec!["v3", "v2"]);
+
+        assert!(db.revert_clip(&id, 2).await.unwrap());
+        assert_eq!(db.get_clip_by_id(&id).await.unwrap().unwrap().content, "v2");
+        // The reverted content is a version too, so the revert can be undone
+        assert_eq!(versions(&db, &id).await, vec!["v4", "v3"]);
+        assert!(db.revert_clip(&id, 1).await.unwrap());
+        assert_eq!(db.get_clip_by_id(&id).await.unwrap().unwrap().content, "v4");
+
+        assert!(!db.revert_clip(&id, 0).await.unwrap());
+        assert!(!db.revert_clip(&id, 3).await.unwrap());
+        assert!(!db.revert_clip("no-such-clip", 1).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn exact_search_skips_superstrings() {
+        let mut db = memory_db().await;
+        let id = db.add_clip("token-42", "text").await.unwrap();
+        db.add_clip("token-42 was rotated", "text").await.unwrap();
+        db.add_tag_to_clip(&id, "secrets").await.unwrap();
+
+        let found = db.find_exact("token-42", None, None, 10).await.unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].id, id);
+        assert!(db.find_exact("token-4", None, None, 10).await.unwrap().is_empty());
+        assert!(db.find_exact("token-%", None, None, 10).await.unwrap().is_empty());
+
+        assert_eq!(db.find_exact("token-42", Some("SECRETS"), Some("text"), 10).await.unwrap().len(), 1);
+        assert!(db.find_exact("token-42", Some("work"), None, 10).await.unwrap().is_empty());
+        assert!(db.find_exact("token-42", None, Some("file"), 10).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn one_file_added_by_two_spellings_is_one_clip() {
+        let dir = std::env::temp_dir().join(format!("clipq-files-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        let file = dir.join("notes.txt");
+        std::fs::write(&file, "notes").unwrap();
+        let mut db = memory_db().await;
+
+        let first = db.add_file_clip(&file.to_string_lossy()).await.unwrap();
+        let roundabout = dir.join("sub").join("..").join(".").join("notes.txt");
+        let second = db.add_file_clip(&roundabout.to_string_lossy()).await.unwrap();
+
+        assert_eq!(first, second);
+        let clips = db.get_recent_clips_by_type("file", 10).await.unwrap();
+        assert_eq!(clips.len(), 1);
+        assert!(!clips[0].is_stale());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(db.get_clip_by_id(&first).await.unwrap().unwrap().is_stale());
+    }
+
+    #[tokio::test]
+    async fn repair_removes_orphaned_rows() {
+        let mut db = memory_db().await;
+        let kept = db.add_clip("kept", "text").await.unwrap();
+        db.add_tag_to_clip(&kept, "live").await.unwrap();
+        db.conn
+            .execute_batch(
+                "PRAGMA foreign_keys = OFF;
+                 INSERT INTO tags (name) VALUES ('stale');
+                 INSERT INTO clip_tags (clip_id, tag_id) SELECT 'no-such-clip', id FROM tags WHERE name = 'stale';
+                 INSERT INTO clip_versions (clip_id, content, edited_at) VALUES ('no-such-clip', 'old', 0);
+                 PRAGMA foreign_keys = ON;",
+            )
+            .unwrap();
+        let links = |db: &Database| -> i64 { db.conn.query_row("SELECT COUNT(*) FROM clip_tags", [], |row| row.get(0)).unwrap() };
+        assert_eq!(links(&db), 2);
+
+        let report = db.repair().await.unwrap();
+        assert_eq!(report.orphaned_links, 1);
+        assert_eq!(report.orphaned_versions, 1);
+        assert_eq!(report.unused_tags, 1, "the stale tag is unused once its link is gone");
+        assert!(report.integrity_errors.is_empty(), "{:?}", report.integrity_errors);
+        assert_eq!(links(&db), 1);
+        assert_eq!(db.get_clip_tags(&kept).await.unwrap(), vec!["live"]);
+        assert_eq!(db.search_clips("kept", 10).await.unwrap().len(), 1, "the rebuilt index still finds clips");
+
+        let again = db.repair().await.unwrap();
+        assert_eq!((again.orphaned_links, again.orphaned_versions, again.unused_tags), (0, 0, 0));
+    }
+
+    #[tokio::test]
+    async fn counts_match_the_rows_a_query_returns() {
+        let mut db = memory_db().await;
+        for (content, clip_type, tag) in [
+            ("deploy notes", "text", Some("work")),
+            ("deploy.sh", "file", Some("work")),
+            ("deploy script draft", "text", None),
+            ("lunch order", "text", Some("work")),
+            ("<b>deploy</b>", "html", None),
+        ] {
+            let id = db.add_clip(content, clip_type).await.unwrap();
+            if let Some(tag) = tag {
+                db.add_tag_to_clip(&id, tag).await.unwrap();
+            }
+        }
+        let filter = |contains: Option<&str>, tag: Option<&str>, clip_type: Option<&str>| ClipFilter {
+            contains: contains.map(str::to_string),
+            tag: tag.map(str::to_string),
+            clip_type: clip_type.map(str::to_string),
+            ..ClipFilter::default()
+        };
+
+        let rows = db.search_clips("deploy", 100).await.unwrap().len();
+        assert_eq!((rows, db.count_clips(&filter(Some("deploy"), None, None)).await.unwrap()), (4, 4));
+        let rows = db.search_clips_by_tag("deploy", "work", 100).await.unwrap().len();
+        assert_eq!((rows, db.count_clips(&filter(Some("deploy"), Some("WORK"), None)).await.unwrap()), (2, 2));
+        let rows = db.get_recent_clips_by_type("text", 100).await.unwrap().len();
+        assert_eq!((rows, db.count_clips(&filter(None, None, Some("text"))).await.unwrap()), (3, 3));
+        let rows = db.find_exact("deploy.sh", Some("work"), Some("file"), 100).await.unwrap().len();
+        let exact = ClipFilter { equals: Some("deploy.sh".to_string()), ..filter(None, Some("work"), Some("file")) };
+        assert_eq!((rows, db.count_clips(&exact).await.unwrap()), (1, 1));
+        assert_eq!(db.count_clips(&ClipFilter::default()).await.unwrap(), 5);
+    }
+
+    #[tokio::test]
+    async fn retagging_moves_clips_and_merges_into_existing_tags() {
+        let mut db = memory_db().await;
+        let a = db.add_clip("a", "text").await.unwrap();
+        let b = db.add_clip("b", "text").await.unwrap();
+        let c = db.add_clip("c", "text").await.unwrap();
+        for (id, tag) in [(&a, "inbox"), (&b, "inbox"), (&b, "archive"), (&c, "archive"), (&c, "inbox")] {
+            db.add_tag_to_clip(id, tag).await.unwrap();
+        }
+        async fn tags(db: &Database, id: &str) -> Vec<String> {
+            let mut tags = db.get_clip_tags(id).await.unwrap();
+            tags.sort();
+            tags
+        }
+
+        assert!(db.retag_clip(&a, "INBOX", "todo").await.unwrap());
+        assert_eq!(tags(&db, &a).await, ["todo"]);
+        // Retagging onto a tag the clip already has just drops the old one
+        assert!(db.retag_clip(&b, "inbox", "archive").await.unwrap());
+        assert_eq!(tags(&db, &b).await, ["archive"]);
+        assert!(!db.retag_clip(&a, "inbox", "todo").await.unwrap());
+
+        // Only c is still in the inbox, and it is already archived too
+        assert_eq!(db.retag_all("inbox", "archive").await.unwrap(), 1);
+        assert_eq!(tags(&db, &c).await, ["archive"]);
+        assert_eq!(db.retag_all("todo", "archive").await.unwrap(), 1);
+        assert_eq!(tags(&db, &a).await, ["archive"]);
+        let names: Vec<(String, usize)> = db.list_all_tags().await.unwrap().into_iter().map(|tag| (tag.name, tag.clips)).collect();
+        assert_eq!(names, [("archive".to_string(), 3)]);
+
+        assert_eq!(db.retag_all("missing", "archive").await.unwrap(), 0);
+        assert!(db.retag_all("archive", "ARCHIVE").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn statistics_report_the_compression_ratio() {
+        let mut db = memory_db().await;
+        db.add_clip("plain text has no blob", "text").await.unwrap();
+        assert_eq!(db.get_statistics().await.unwrap().compression_ratio(), None);
+
+        db.set_compression(1024, 3);
+        let big = "log line repeated\n".repeat(1000).into_bytes();
+        let small = b"tiny file".to_vec();
+        db.add_embedded_file_clip("/tmp/a.log", "a.log", &big).await.unwrap();
+        db.add_embedded_file_clip("/tmp/copy.log", "copy.log", &big).await.unwrap();
+        db.add_embedded_file_clip("/tmp/small.txt", "small.txt", &small).await.unwrap();
+
+        let stats = db.get_statistics().await.unwrap();
+        assert_eq!(stats.embedded_clips, 3);
+        assert_eq!(stats.compressed_clips, 2, "only the large file is compressed, once per clip");
+        assert_eq!(stats.embedded_original_bytes, (2 * big.len() + small.len()) as u64);
+        let compressed: i64 = db
+            .conn
+            .query_row("SELECT length(data) FROM blobs WHERE compressed = 1", [], |row| row.get(0))
+            .unwrap();
+        // Both clips share one compressed copy
+        assert_eq!(stats.embedded_stored_bytes, compressed as u64 + small.len() as u64);
+        let ratio = stats.compression_ratio().unwrap();
+        assert_eq!(ratio, stats.embedded_original_bytes as f64 / stats.embedded_stored_bytes as f64);
+        assert!(ratio > 10.0, "{}", ratio);
+    }
+
+    #[tokio::test]
+    async fn pruning_blank_clips_keeps_text_and_pins() {
+        let mut db = memory_db().await;
+        for content in ["", "   ", "\n\t\r\n", "\u{b}\u{c}", " kept ", "also\nkept"] {
+            db.add_clip(content, "text").await.unwrap();
+        }
+        let pinned = db.add_clip("  ", "text").await.unwrap();
+        db.set_pinned(&pinned, true).await.unwrap();
+        let tagged = db.add_clip("\n", "text").await.unwrap();
+        db.add_tag_to_clip(&tagged, "work").await.unwrap();
+
+        assert_eq!(db.delete_where_blank().await.unwrap(), 5);
+        assert_eq!(contents(&db).await, ["  ", " kept ", "also\nkept"]);
+        let links: i64 = db.conn.query_row("SELECT COUNT(*) FROM clip_tags", [], |row| row.get(0)).unwrap();
+        assert_eq!(links, 0);
+        assert_eq!(db.delete_where_blank().await.unwrap(), 0);
+    }
+}
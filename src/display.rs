@@ -0,0 +1,464 @@
+use chrono::{DateTime, Utc};
+use regex::Regex;
+use similar::{ChangeTag, TextDiff};
+
+use crate::database::Clip;
+use crate::events::{ClipEvent, ClipEventRecord};
+
+/// Mask every match of `patterns` in `preview` with `****`.
+///
+/// Only applied when printing; stored clip content is never modified.
+pub fn redact(preview: &str, patterns: &[Regex]) -> String {
+    let mut redacted = preview.to_string();
+    for pattern in patterns {
+        redacted = pattern.replace_all(&redacted, "****").into_owned();
+    }
+    redacted
+}
+
+/// Identify clip content in log output without revealing it: its length
+/// and a short SHA-256 prefix.
+pub fn fingerprint(content: &str) -> String {
+    use sha2::{Digest, Sha256};
+    let hash = format!("{:x}", Sha256::digest(content.as_bytes()));
+    format!("{} bytes, sha256 {}", content.len(), &hash[..12])
+}
+
+/// Collapse all whitespace runs (including newlines) to single spaces and
+/// cut the result to at most `max_chars` characters, ending in `...` when
+/// shortened. Every preview is guaranteed to fit on one line.
+pub fn one_line_preview(text: &str, max_chars: usize) -> String {
+    let collapsed = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    if collapsed.chars().count() <= max_chars {
+        return collapsed;
+    }
+
+    let kept: String = collapsed.chars().take(max_chars.saturating_sub(3)).collect();
+    format!("{}...", kept.trim_end())
+}
+
+/// Width assumed when stdout is not a terminal.
+pub const DEFAULT_WIDTH: usize = 80;
+
+/// Previews never shrink below this, however long the rest of the line is.
+const MIN_PREVIEW_WIDTH: usize = 10;
+
+/// Columns available for a line of output: `width` if given, else the
+/// terminal's width, else `DEFAULT_WIDTH`.
+pub fn output_width(width: Option<usize>) -> usize {
+    width
+        .or_else(|| terminal_size::terminal_size().map(|(terminal_size::Width(w), _)| w as usize))
+        .unwrap_or(DEFAULT_WIDTH)
+}
+
+/// How many characters a preview may take on a `width`-column line that
+/// already holds `used` columns of other text.
+pub fn preview_width(width: usize, used: usize) -> usize {
+    width.saturating_sub(used).max(MIN_PREVIEW_WIDTH)
+}
+
+/// How long ago `then` was, coarsely: `just now`, `5m ago`, `3h ago`,
+/// `2d ago`; anything older than four weeks is shown as a date.
+pub fn relative_time(then: DateTime<Utc>, now: DateTime<Utc>) -> String {
+    let seconds = (now - then).num_seconds().max(0);
+    match seconds {
+        0..=59 => "just now".to_string(),
+        60..=3_599 => format!("{}m ago", seconds / 60),
+        3_600..=86_399 => format!("{}h ago", seconds / 3_600),
+        86_400..=2_419_199 => format!("{}d ago", seconds / 86_400),
+        _ => then.format("%Y-%m-%d").to_string(),
+    }
+}
+
+/// Byte count in B, KB or MB.
+pub fn human_size(bytes: usize) -> String {
+    const KB: f64 = 1024.0;
+    let size = bytes as f64;
+    if size < KB {
+        format!("{} B", bytes)
+    } else if size < KB * KB {
+        format!("{:.1} KB", size / KB)
+    } else {
+        format!("{:.1} MB", size / (KB * KB))
+    }
+}
+
+/// Single-character marker for a clip type.
+pub fn type_glyph(clip_type: &str) -> char {
+    match clip_type {
+        "text" => 'T',
+        "file" => 'F',
+        "image" => 'I',
+        "html" => 'H',
+        _ => '?',
+    }
+}
+
+/// Placeholders a `list_format` template may use.
+pub const LIST_PLACEHOLDERS: &[&str] =
+    &["index", "id", "short_id", "time", "age", "type", "tags", "preview", "content"];
+
+fn placeholder_regex() -> Regex {
+    Regex::new(r"\{(\w+)\}").unwrap()
+}
+
+/// The placeholders `template` uses, or an error naming the first one
+/// that isn't in `LIST_PLACEHOLDERS`.
+pub fn list_placeholders(template: &str) -> anyhow::Result<Vec<String>> {
+    placeholder_regex()
+        .captures_iter(template)
+        .map(|caps| {
+            let name = &caps[1];
+            if LIST_PLACEHOLDERS.contains(&name) {
+                Ok(name.to_string())
+            } else {
+                Err(anyhow::anyhow!(
+                    "Unknown placeholder {{{}}} in list_format. Available: {}",
+                    name,
+                    LIST_PLACEHOLDERS.join(", ")
+                ))
+            }
+        })
+        .collect()
+}
+
+/// One `clipq list` line rendered from a `list_format` template. `content`
+/// is the clip's content as it may be shown (i.e. already redacted); `tags`
+/// only needs to be filled when the template uses `{tags}`. `{preview}`
+/// gets whatever is left of `width` columns after the rest of the line.
+pub fn render_list_entry(
+    template: &str,
+    index: usize,
+    clip: &Clip,
+    content: &str,
+    tags: &[String],
+    now: DateTime<Utc>,
+    width: usize,
+) -> String {
+    let fill = |preview_width: Option<usize>| {
+        placeholder_regex()
+            .replace_all(template, |caps: &regex::Captures| match &caps[1] {
+                "index" => index.to_string(),
+                "id" => clip.id.clone(),
+                "short_id" => clip.id.chars().take(8).collect(),
+                "time" => clip
+                    .created_at
+                    .with_timezone(&chrono::Local)
+                    .format("%Y-%m-%d %H:%M:%S")
+                    .to_string(),
+                "age" => relative_time(clip.created_at, now),
+                "type" => clip.clip_type.clone(),
+                "tags" => tags.join(","),
+                "preview" => preview_width.map_or(String::new(), |max| one_line_preview(content, max)),
+                "content" => content.to_string(),
+                _ => caps[0].to_string(),
+            })
+            .into_owned()
+    };
+
+    if !template.contains("{preview}") {
+        return fill(None);
+    }
+    let used = fill(None).lines().next_back().map_or(0, |line| line.chars().count());
+    fill(Some(preview_width(width, used)))
+}
+
+/// Lay out `rows` under `header` in space-separated columns padded to the
+/// widest cell. The last column is left unpadded.
+pub fn table(header: &[&str], rows: &[Vec<String>]) -> String {
+    let mut widths: Vec<usize> = header.iter().map(|h| h.chars().count()).collect();
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let header: Vec<String> = header.iter().map(|h| h.to_string()).collect();
+    let mut output = String::new();
+    for row in std::iter::once(&header).chain(rows) {
+        let last = row.len().saturating_sub(1);
+        let cells: Vec<String> = row
+            .iter()
+            .enumerate()
+            .map(|(i, cell)| {
+                if i == last {
+                    cell.clone()
+                } else {
+                    format!("{:<width$}", cell, width = widths[i])
+                }
+            })
+            .collect();
+        output.push_str(cells.join("  ").trim_end());
+        output.push('\n');
+    }
+    output
+}
+
+/// Columns `table` spends before its last column, given `rows` that don't
+/// hold a last cell yet (`header` still names every column).
+pub fn table_prefix_width(header: &[&str], rows: &[Vec<String>]) -> usize {
+    let leading = header.len().saturating_sub(1);
+    (0..leading)
+        .map(|i| {
+            let cells = rows.iter().filter_map(|row| row.get(i));
+            cells.map(|cell| cell.chars().count()).max().unwrap_or(0).max(header[i].chars().count()) + 2
+        })
+        .sum()
+}
+
+/// One `clipq tail` line: local time, event kind, short clip id, then the
+/// tag name for `tagged` events or `preview` for the others.
+pub fn render_event(record: &ClipEventRecord, preview: Option<&str>) -> String {
+    let time = record.at.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M:%S");
+    let short_id: String = record.event.clip_id().chars().take(8).collect();
+    let line = format!("{}  {:<7}  {}", time, record.event.kind(), short_id);
+    let detail = match &record.event {
+        ClipEvent::Tagged { tag, .. } => Some(tag.as_str()),
+        _ => preview,
+    };
+    match detail {
+        Some(detail) => format!("{}  {}", line, detail),
+        None => line,
+    }
+}
+
+/// Horizontal bar chart with one `label  ████ count` line per row, bars
+/// scaled so the largest count spans `width` cells.
+pub fn bar_chart(rows: &[(String, usize)], width: usize) -> String {
+    let max = rows.iter().map(|(_, count)| *count).max().unwrap_or(0).max(1);
+    let label_width = rows.iter().map(|(label, _)| label.chars().count()).max().unwrap_or(0);
+
+    let mut output = String::new();
+    for (label, count) in rows {
+        let bar = "█".repeat((count * width).div_ceil(max));
+        output.push_str(&format!("{:<label_width$}  {} {}\n", label, bar, count));
+    }
+    output
+}
+
+/// Lines of `text` matching `pattern`, ripgrep style: `N:line` for a match
+/// and `N-line` for the `context` lines around it, with `--` between
+/// groups that aren't adjacent. Matches are highlighted when `color` is set.
+pub fn grep(text: &str, pattern: &Regex, context: usize, color: bool) -> String {
+    let lines: Vec<&str> = text.lines().collect();
+    let matching: Vec<usize> = (0..lines.len()).filter(|&i| pattern.is_match(lines[i])).collect();
+
+    let mut output = String::new();
+    let mut next_unprinted = 0;
+    for (n, &hit) in matching.iter().enumerate() {
+        let start = hit.saturating_sub(context).max(next_unprinted);
+        let end = (hit + context).min(lines.len() - 1);
+        // Stop at the next match; it prints its own leading context
+        let end = matching.get(n + 1).map_or(end, |&next| end.min(next - 1));
+        if n > 0 && start > next_unprinted {
+            output.push_str("--\n");
+        }
+
+        for (i, line) in lines.iter().enumerate().take(end + 1).skip(start) {
+            if i == hit {
+                let line = if color {
+                    pattern.replace_all(line, "\x1b[1;31m$0\x1b[0m").into_owned()
+                } else {
+                    line.to_string()
+                };
+                output.push_str(&format!("{}:{}\n", i + 1, line));
+            } else {
+                output.push_str(&format!("{}-{}\n", i + 1, line));
+            }
+        }
+        next_unprinted = end + 1;
+    }
+    output
+}
+
+/// Render a unified diff of two clips, line by line or word by word.
+/// ANSI colors are only added when `color` is set (i.e. stdout is a tty).
+pub fn diff(old: &Clip, new: &Clip, word: bool, color: bool) -> String {
+    let text_diff = if word {
+        TextDiff::from_words(old.content.as_str(), new.content.as_str())
+    } else {
+        TextDiff::from_lines(old.content.as_str(), new.content.as_str())
+    };
+
+    let paint = |code: &str, text: String| {
+        if color {
+            format!("\x1b[{}m{}\x1b[0m", code, text)
+        } else {
+            text
+        }
+    };
+
+    let mut output = String::new();
+    output.push_str(&paint("1", format!("--- {}\n+++ {}\n", old.id, new.id)));
+
+    if word {
+        // Word changes read best inline: [-removed-]{+added+}
+        for change in text_diff.iter_all_changes() {
+            let value = change.value();
+            match change.tag() {
+                ChangeTag::Delete => output.push_str(&paint("31", format!("[-{}-]", value))),
+                ChangeTag::Insert => output.push_str(&paint("32", format!("{{+{}+}}", value))),
+                ChangeTag::Equal => output.push_str(value),
+            }
+        }
+        if !output.ends_with('\n') {
+            output.push('\n');
+        }
+        return output;
+    }
+
+    for hunk in text_diff.unified_diff().iter_hunks() {
+        output.push_str(&paint("36", format!("{}\n", hunk.header())));
+        for change in hunk.iter_changes() {
+            let (sign, code) = match change.tag() {
+                ChangeTag::Delete => ("-", "31"),
+                ChangeTag::Insert => ("+", "32"),
+                ChangeTag::Equal => (" ", "0"),
+            };
+            let mut line = format!("{}{}", sign, change.value());
+            if change.missing_newline() {
+                line.push('\n');
+            }
+            if change.tag() == ChangeTag::Equal {
+                output.push_str(&line);
+            } else {
+                output.push_str(&paint(code, line));
+            }
+        }
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn clip(id: &str, content: &str) -> Clip {
+        Clip {
+            id: id.to_string(),
+            content: content.to_string(),
+            clip_type: "text".to_string(),
+            created_at: Utc::now(),
+            file_path: None,
+            pinned: false,
+            expires_at: None,
+            mime: "text/plain".to_string(),
+            original_name: None,
+        }
+    }
+
+    #[test]
+    fn diffs_show_removed_and_added_lines() {
+        let old = clip("old", "host = a\nport = 80\nuser = me\n");
+        let new = clip("new", "host = a\nport = 8080\nuser = me\n");
+
+        let lines = diff(&old, &new, false, false);
+        assert_eq!(lines, "--- old\n+++ new\n@@ -1,3 +1,3 @@\n host = a\n-port = 80\n+port = 8080\n user = me\n");
+        let words = diff(&old, &new, true, false);
+        assert_eq!(words, "--- old\n+++ new\nhost = a\nport = [-80-]{+8080+}\nuser = me\n");
+        assert!(diff(&old, &new, false, true).contains("\x1b[31m-port = 80\n\x1b[0m"));
+    }
+
+    #[test]
+    fn previews_fit_one_line_and_the_length() {
+        assert_eq!(clip("a", "short").preview(10), "short");
+        assert_eq!(clip("a", "exactly ten").preview(11), "exactly ten");
+        assert_eq!(clip("a", "a much longer clip than fits").preview(12), "a much lo...");
+        assert_eq!(clip("a", "line one\n\n  line\ttwo\r\n").preview(80), "line one line two");
+        // Cut on characters, never inside one
+        assert_eq!(clip("a", "héllo wörld ünïcode 日本語").preview(8), "héllo...");
+        assert_eq!(clip("a", "日本語のテキストです").preview(6), "日本語...");
+        assert_eq!(clip("a", "").preview(5), "");
+    }
+
+    #[test]
+    fn grep_shows_matches_with_their_context() {
+        let text = "one\ntwo\nneedle a\nfour\nfive\nsix\nseven\nneedle b\nnine\nneedle c";
+        let pattern = Regex::new("needle").unwrap();
+
+        assert_eq!(grep(text, &pattern, 0, false), "3:needle a\n--\n8:needle b\n--\n10:needle c\n");
+        assert_eq!(
+            grep(text, &pattern, 1, false),
+            "2-two\n3:needle a\n4-four\n--\n7-seven\n8:needle b\n9-nine\n10:needle c\n"
+        );
+        // Overlapping windows merge, and context stops at the text's ends
+        assert_eq!(
+            grep(text, &pattern, 3, false),
+            "1-one\n2-two\n3:needle a\n4-four\n5-five\n6-six\n7-seven\n8:needle b\n9-nine\n10:needle c\n"
+        );
+        assert_eq!(grep(text, &Regex::new("absent").unwrap(), 2, false), "");
+        assert_eq!(grep("a needle", &pattern, 0, true), "1:a \x1b[1;31mneedle\x1b[0m\n");
+    }
+
+    #[test]
+    fn list_templates_render_every_placeholder() {
+        let mut c = clip("0123456789abcdef", "first line\nsecond line of the clip");
+        c.created_at = DateTime::parse_from_rfc3339("2026-03-04T05:06:07Z").unwrap().with_timezone(&Utc);
+        let now = c.created_at + chrono::Duration::hours(3);
+        let tags = vec!["work".to_string(), "urgent".to_string()];
+        let time = c.created_at.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M:%S").to_string();
+
+        let line = render_list_entry("{index}. {short_id} {time} ({age}) {type} [{tags}] {preview}", 3, &c, &c.content, &tags, now, 200);
+        assert_eq!(line, format!("3. 01234567 {} (3h ago) text [work,urgent] first line second line of the clip", time));
+
+        // The preview takes what is left of the width, never less than ten
+        let line = render_list_entry("{index}: [{tags}] {preview}", 1, &c, &c.content, &tags, now, 30);
+        assert_eq!(line, "1: [work,urgent] first line...");
+        assert_eq!(line.chars().count(), 30);
+        let line = render_list_entry("{id} {preview}", 1, &c, &c.content, &[], now, 20);
+        assert_eq!(line, "0123456789abcdef first l...");
+
+        assert_eq!(render_list_entry("{index}: {preview}", 2, &c, "****", &[], now, 80), "2: ****");
+        assert_eq!(list_placeholders("{index} {tags} {preview}").unwrap(), ["index", "tags", "preview"]);
+        let err = list_placeholders("{index} {size}").unwrap_err().to_string();
+        assert!(err.contains("{size}"), "{}", err);
+    }
+
+    #[test]
+    fn previews_fill_the_width_left_after_the_prefix() {
+        assert_eq!(output_width(Some(120)), 120);
+        assert_eq!(output_width(Some(40)), 40);
+
+        let long = clip("a", &"word ".repeat(60));
+        for width in [40, 80, 120, 200] {
+            let prefix = "12: ";
+            let line = format!("{}{}", prefix, long.preview(preview_width(width, prefix.len())));
+            assert_eq!(line.chars().count(), width, "{}", line);
+        }
+        // A narrow terminal still shows a few words rather than nothing
+        assert_eq!(preview_width(20, 18), 10);
+        assert_eq!(preview_width(5, 40), 10);
+        assert_eq!(clip("a", "fits").preview(preview_width(40, 4)), "fits");
+    }
+
+    #[test]
+    fn events_render_as_log_lines() {
+        let at = DateTime::parse_from_rfc3339("2026-05-06T07:08:09Z").unwrap().with_timezone(&Utc);
+        let time = at.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M:%S").to_string();
+        let record = |seq, event| ClipEventRecord { seq, at, event };
+        let id = "0123456789abcdef".to_string();
+
+        let lines = [
+            render_event(&record(1, ClipEvent::Added { clip_id: id.clone() }), Some("hello world")),
+            render_event(&record(2, ClipEvent::Edited { clip_id: id.clone() }), Some("hello there")),
+            render_event(&record(3, ClipEvent::Tagged { clip_id: id.clone(), tag: "work".to_string() }), Some("ignored")),
+            render_event(&record(4, ClipEvent::Deleted { clip_id: id.clone() }), None),
+        ];
+        assert_eq!(
+            lines,
+            [
+                format!("{}  added    01234567  hello world", time),
+                format!("{}  edited   01234567  hello there", time),
+                format!("{}  tagged   01234567  work", time),
+                format!("{}  deleted  01234567", time),
+            ]
+        );
+
+        // The same records are what the WebSocket feed sends
+        let tagged = record(3, ClipEvent::Tagged { clip_id: id, tag: "work".to_string() });
+        let json = serde_json::to_value(&tagged).unwrap();
+        assert_eq!(json["kind"], "tagged");
+        assert_eq!(json["tag"], "work");
+        assert_eq!(serde_json::from_value::<ClipEventRecord>(json).unwrap(), tagged);
+    }
+}
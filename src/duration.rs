@@ -0,0 +1,67 @@
+use anyhow::Result;
+use chrono::{DateTime, Duration, Utc};
+
+/// Parse a human duration like `30s`, `15m`, `2h`, `7d` or `1w`.
+/// A bare number is taken as seconds.
+pub fn parse_duration(input: &str) -> Result<Duration> {
+    let input = input.trim();
+    let split = input
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(input.len());
+    let (amount, unit) = input.split_at(split);
+
+    let amount: i64 = amount
+        .parse()
+        .map_err(|_| anyhow::anyhow!("Invalid duration '{}': expected e.g. 30s, 15m, 2h, 7d", input))?;
+
+    let duration = match unit {
+        "" | "s" => Duration::try_seconds(amount),
+        "m" => Duration::try_minutes(amount),
+        "h" => Duration::try_hours(amount),
+        "d" => Duration::try_days(amount),
+        "w" => Duration::try_weeks(amount),
+        _ => {
+            return Err(anyhow::anyhow!(
+                "Invalid duration unit '{}' in '{}': use s, m, h, d or w",
+                unit,
+                input
+            ))
+        }
+    };
+    duration.ok_or_else(|| anyhow::anyhow!("Duration '{}' is too long", input))
+}
+
+/// The instant `duration` from now, e.g. for an expiry; an error instead
+/// of a panic when that is past the largest representable time.
+pub fn from_now(duration: Duration) -> Result<DateTime<Utc>> {
+    Utc::now()
+        .checked_add_signed(duration)
+        .ok_or_else(|| anyhow::anyhow!("Duration of {} days is too far in the future", duration.num_days()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_each_unit() {
+        assert_eq!(parse_duration("45").unwrap(), Duration::seconds(45));
+        assert_eq!(parse_duration("30s").unwrap(), Duration::seconds(30));
+        assert_eq!(parse_duration("15m").unwrap(), Duration::minutes(15));
+        assert_eq!(parse_duration(" 2h ").unwrap(), Duration::hours(2));
+        assert_eq!(parse_duration("7d").unwrap(), Duration::days(7));
+        assert_eq!(parse_duration("1w").unwrap(), Duration::weeks(1));
+        assert!(parse_duration("5y").is_err());
+        assert!(parse_duration("d").is_err());
+    }
+
+    #[test]
+    fn overlong_durations_are_errors() {
+        for input in ["9223372036854775807s", "9223372036854775807m", "999999999999999d", "99999999999999w"] {
+            assert!(parse_duration(input).is_err(), "{}", input);
+        }
+        // Fits in a duration but not in a date
+        let duration = parse_duration("100000000d").unwrap();
+        assert!(from_now(duration).is_err());
+    }
+}
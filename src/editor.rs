@@ -0,0 +1,33 @@
+use anyhow::Result;
+use std::fs;
+use std::process::Command;
+use uuid::Uuid;
+
+/// The user's editor command from `$VISUAL` or `$EDITOR`, if either is set.
+pub fn configured_editor() -> Option<String> {
+    ["VISUAL", "EDITOR"]
+        .iter()
+        .filter_map(|var| std::env::var(var).ok())
+        .find(|editor| !editor.trim().is_empty())
+}
+
+/// Open `initial` in `editor` via a temporary file and return the saved
+/// text. The editor value may carry arguments, e.g. `code --wait`.
+pub fn edit_text(editor: &str, initial: &str) -> Result<String> {
+    let path = std::env::temp_dir().join(format!("clipq-{}.txt", Uuid::new_v4()));
+    fs::write(&path, initial)?;
+
+    let mut parts = editor.split_whitespace();
+    let program = parts
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("Editor command is empty"))?;
+    let status = Command::new(program).args(parts).arg(&path).status();
+
+    let result = match status {
+        Ok(status) if status.success() => fs::read_to_string(&path).map_err(Into::into),
+        Ok(status) => Err(anyhow::anyhow!("Editor '{}' exited with {}", editor, status)),
+        Err(e) => Err(anyhow::anyhow!("Failed to launch editor '{}': {}", editor, e)),
+    };
+    let _ = fs::remove_file(&path);
+    result
+}
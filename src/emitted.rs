@@ -0,0 +1,158 @@
+use anyhow::Result;
+use log::debug;
+use std::fs::{File, OpenOptions};
+use std::io::{ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::audit::content_hash;
+use crate::profile;
+
+/// Lines remembered for the daemon to skip when they get copied; enough
+/// for a long `clipq list` on top of the status lines around it.
+const EMITTED_LOG_SIZE: usize = 200;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Start or stop remembering the lines passed to `record`; off until
+/// `capture.skip_own_output` turns it on.
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+/// Hashes of the most recent lines clipq printed, one per line in a file,
+/// so the daemon can check them from another process. Every access holds
+/// a file lock, so concurrent clipq commands never lose each other's lines.
+#[derive(Debug, Clone)]
+pub struct EmittedLog {
+    path: PathBuf,
+}
+
+impl EmittedLog {
+    /// The log in the active profile's directory.
+    pub fn active() -> Result<Self> {
+        Ok(Self::at(profile::active().dir()?.join("emitted")))
+    }
+
+    pub fn at(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    /// Remember `lines`, dropping the oldest beyond `EMITTED_LOG_SIZE`.
+    /// Blank lines are skipped.
+    pub fn record_all<I>(&self, lines: I) -> Result<()>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+    {
+        let new: Vec<String> = lines
+            .into_iter()
+            .filter_map(|line| {
+                let line = line.as_ref().trim();
+                (!line.is_empty()).then(|| content_hash(line))
+            })
+            .collect();
+        if new.is_empty() {
+            return Ok(());
+        }
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let mut file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(&self.path)?;
+        // Released when `file` is closed
+        file.lock()?;
+        let mut hashes = read_hashes(&mut file)?;
+        hashes.extend(new);
+        let keep_from = hashes.len().saturating_sub(EMITTED_LOG_SIZE);
+        file.set_len(0)?;
+        file.seek(SeekFrom::Start(0))?;
+        file.write_all(hashes[keep_from..].join("\n").as_bytes())?;
+        Ok(())
+    }
+
+    /// Whether `text` is one of the recently recorded lines.
+    pub fn contains(&self, text: &str) -> Result<bool> {
+        let mut file = match File::open(&self.path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(false),
+            Err(e) => return Err(e.into()),
+        };
+        file.lock_shared()?;
+        Ok(read_hashes(&mut file)?.contains(&content_hash(text.trim())))
+    }
+}
+
+fn read_hashes(file: &mut File) -> Result<Vec<String>> {
+    let mut text = String::new();
+    file.read_to_string(&mut text)?;
+    Ok(text.lines().map(str::to_string).collect())
+}
+
+/// Remember that clipq printed `line`, if enabled. Failures are only
+/// logged; they must never fail the command that printed it.
+pub fn record(line: &str) {
+    record_all([line]);
+}
+
+/// Like `record`, for several lines at once, e.g. a whole listing. `lines`
+/// is only iterated when enabled.
+pub fn record_all<I>(lines: I)
+where
+    I: IntoIterator,
+    I::Item: AsRef<str>,
+{
+    if !ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+    if let Err(e) = EmittedLog::active().and_then(|log| log.record_all(lines)) {
+        debug!("Failed to remember emitted output: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_log() -> (PathBuf, EmittedLog) {
+        let dir = std::env::temp_dir().join(format!("clipq-emitted-{}", uuid::Uuid::new_v4()));
+        let log = EmittedLog::at(dir.join("emitted"));
+        (dir, log)
+    }
+
+    #[test]
+    fn recorded_lines_are_found_until_pushed_out() {
+        let (dir, log) = temp_log();
+        assert!(!log.contains("Pasted: hello").unwrap());
+
+        log.record_all(["Pasted: hello", "  "]).unwrap();
+        assert!(log.contains("  Pasted: hello\n").unwrap());
+        assert!(!log.contains("hello").unwrap());
+
+        let filler: Vec<String> = (0..EMITTED_LOG_SIZE).map(|i| format!("{}: clip", i)).collect();
+        log.record_all(&filler).unwrap();
+        assert!(!log.contains("Pasted: hello").unwrap());
+        assert!(log.contains("0: clip").unwrap());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn concurrent_recorders_keep_every_line() {
+        let (dir, log) = temp_log();
+        let threads: Vec<_> = (0..8)
+            .map(|i| {
+                let log = log.clone();
+                std::thread::spawn(move || log.record_all([format!("line {}", i)]).unwrap())
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        for i in 0..8 {
+            assert!(log.contains(&format!("line {}", i)).unwrap(), "line {} was lost", i);
+        }
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,60 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+/// A change to the history. Database triggers record one for every write,
+/// whichever process made it, so `clipq tail` and the web server's
+/// `/api/events` feed see the daemon's captures as well as CLI edits.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum ClipEvent {
+    Added { clip_id: String },
+    Deleted { clip_id: String },
+    /// The clip's content changed
+    Edited { clip_id: String },
+    Tagged { clip_id: String, tag: String },
+}
+
+impl ClipEvent {
+    /// Rebuild an event from its `clip_events` row; `None` for kinds this
+    /// version doesn't know.
+    pub(crate) fn from_row_parts(kind: &str, clip_id: String, detail: Option<String>) -> Option<Self> {
+        Some(match kind {
+            "added" => ClipEvent::Added { clip_id },
+            "deleted" => ClipEvent::Deleted { clip_id },
+            "edited" => ClipEvent::Edited { clip_id },
+            "tagged" => ClipEvent::Tagged {
+                clip_id,
+                tag: detail.unwrap_or_default(),
+            },
+            _ => return None,
+        })
+    }
+
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ClipEvent::Added { .. } => "added",
+            ClipEvent::Deleted { .. } => "deleted",
+            ClipEvent::Edited { .. } => "edited",
+            ClipEvent::Tagged { .. } => "tagged",
+        }
+    }
+
+    pub fn clip_id(&self) -> &str {
+        match self {
+            ClipEvent::Added { clip_id }
+            | ClipEvent::Deleted { clip_id }
+            | ClipEvent::Edited { clip_id }
+            | ClipEvent::Tagged { clip_id, .. } => clip_id,
+        }
+    }
+}
+
+/// An event with its position in the log and when it happened.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClipEventRecord {
+    /// Increases with every event; resume a feed after the last one seen
+    pub seq: i64,
+    pub at: DateTime<Utc>,
+    #[serde(flatten)]
+    pub event: ClipEvent,
+}
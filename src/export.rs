@@ -0,0 +1,343 @@
+use aes_gcm::aead::{Aead, KeyInit, Payload};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use anyhow::Result;
+use argon2::Argon2;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::collections::HashSet;
+
+use crate::audit::content_hash;
+use crate::database::{Clip, TaggedClip};
+
+/// Version written into every export envelope.
+pub const ENVELOPE_VERSION: u32 = 1;
+
+/// First bytes of an encrypted export, followed by a format version byte,
+/// the key derivation salt and the AES-GCM nonce.
+pub const ENCRYPTED_MAGIC: &[u8] = b"CLIPQENC";
+const ENCRYPTED_VERSION: u8 = 1;
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+const ENCRYPTED_HEADER_LEN: usize = ENCRYPTED_MAGIC.len() + 1 + SALT_LEN + NONCE_LEN;
+
+/// A JSON export wrapped with its format version and, when signed, an
+/// HMAC-SHA256 of the clips.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExportEnvelope {
+    pub version: u32,
+    pub clips: Vec<TaggedClip>,
+    /// Hex HMAC-SHA256 over the compact JSON of `clips`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+}
+
+/// Either shape `clipq import --format json` accepts: a signed envelope or
+/// the bare clip array older versions wrote.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum JsonExport {
+    Envelope(ExportEnvelope),
+    Bare(Vec<TaggedClip>),
+}
+
+/// Wrap `clips` in an envelope signed with `key`.
+pub fn sign(clips: Vec<TaggedClip>, key: &[u8]) -> Result<ExportEnvelope> {
+    let signature = hex(&signature_mac(key, &clips)?.finalize().into_bytes());
+    Ok(ExportEnvelope {
+        version: ENVELOPE_VERSION,
+        clips,
+        signature: Some(signature),
+    })
+}
+
+/// Parse a JSON export and check its signature against `key`. With
+/// `verify` off, signatures are ignored. With it on, a signed file needs a
+/// key and a matching signature, and an unsigned file is only accepted
+/// when no key is configured.
+pub fn open(text: &str, key: Option<&[u8]>, verify: bool) -> Result<Vec<TaggedClip>> {
+    let (clips, signature) = match serde_json::from_str(text)? {
+        JsonExport::Envelope(envelope) => {
+            if envelope.version > ENVELOPE_VERSION {
+                anyhow::bail!(
+                    "Export format version {} is newer than this clipq supports ({})",
+                    envelope.version,
+                    ENVELOPE_VERSION
+                );
+            }
+            (envelope.clips, envelope.signature)
+        }
+        JsonExport::Bare(clips) => (clips, None),
+    };
+    if !verify {
+        return Ok(clips);
+    }
+
+    match (signature, key) {
+        (Some(signature), Some(key)) => {
+            let matches = unhex(&signature).is_some_and(|signature| {
+                signature_mac(key, &clips).is_ok_and(|mac| mac.verify_slice(&signature).is_ok())
+            });
+            if !matches {
+                anyhow::bail!("Export signature does not match; the file was modified or signed with another key");
+            }
+        }
+        (Some(_), None) => {
+            anyhow::bail!("Export is signed but no export_signing_key is set; pass --no-verify to import it anyway")
+        }
+        (None, Some(_)) => {
+            anyhow::bail!("Export is not signed; pass --no-verify to import it anyway")
+        }
+        (None, None) => {}
+    }
+    Ok(clips)
+}
+
+/// A clip without its content, for checking which clips two histories
+/// share without revealing any text.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HashedClip {
+    pub id: String,
+    /// Hex SHA-256 of the content
+    pub content_hash: String,
+    pub created_at: DateTime<Utc>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+/// What `clipq export --hashes-only` writes.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HashesEnvelope {
+    pub version: u32,
+    pub hashes_only: bool,
+    pub clips: Vec<HashedClip>,
+}
+
+/// Replace every clip's content with its hash.
+pub fn hashes_only(clips: Vec<TaggedClip>) -> HashesEnvelope {
+    let clips = clips
+        .into_iter()
+        .map(|tagged| HashedClip {
+            content_hash: content_hash(&tagged.clip.content),
+            id: tagged.clip.id,
+            created_at: tagged.clip.created_at,
+            tags: tagged.tags,
+        })
+        .collect();
+    HashesEnvelope {
+        version: ENVELOPE_VERSION,
+        hashes_only: true,
+        clips,
+    }
+}
+
+/// Parse a `hashes_only` export, e.g. one another machine sent over.
+pub fn open_hashes(content: &str) -> Result<HashesEnvelope> {
+    let envelope: HashesEnvelope = serde_json::from_str(content)
+        .map_err(|e| anyhow::anyhow!("Not a hashes-only export: {}", e))?;
+    if !envelope.hashes_only {
+        anyhow::bail!("Not a hashes-only export; write one with `clipq export --hashes-only`");
+    }
+    if envelope.version > ENVELOPE_VERSION {
+        anyhow::bail!(
+            "Export format version {} is newer than this clipq supports ({})",
+            envelope.version,
+            ENVELOPE_VERSION
+        );
+    }
+    Ok(envelope)
+}
+
+/// The clips whose content `theirs` doesn't have, i.e. the ones a sync
+/// needs to send the other side.
+pub fn missing(clips: Vec<Clip>, theirs: &HashesEnvelope) -> Vec<Clip> {
+    let known: HashSet<&str> = theirs.clips.iter().map(|clip| clip.content_hash.as_str()).collect();
+    clips
+        .into_iter()
+        .filter(|clip| !known.contains(content_hash(&clip.content).as_str()))
+        .collect()
+}
+
+/// Whether `data` looks like an export written by `encrypt`.
+pub fn is_encrypted(data: &[u8]) -> bool {
+    data.starts_with(ENCRYPTED_MAGIC)
+}
+
+/// Encrypt an export of any format with AES-256-GCM under a key derived
+/// from `passphrase` with Argon2id. Salt and nonce are fresh for every
+/// call; the header is authenticated along with the content.
+pub fn encrypt(plaintext: &[u8], passphrase: &str) -> Result<Vec<u8>> {
+    let salt: [u8; SALT_LEN] = rand::random();
+    let nonce: [u8; NONCE_LEN] = rand::random();
+    let header = [ENCRYPTED_MAGIC, &[ENCRYPTED_VERSION][..], &salt[..], &nonce[..]].concat();
+    let ciphertext = export_cipher(passphrase, &salt)?
+        .encrypt(Nonce::from_slice(&nonce), Payload { msg: plaintext, aad: &header })
+        .map_err(|_| anyhow::anyhow!("Encrypting the export failed"))?;
+    Ok([header, ciphertext].concat())
+}
+
+/// Reverse `encrypt`. A wrong passphrase and a modified file can't be told
+/// apart, so both give the same error.
+pub fn decrypt(data: &[u8], passphrase: &str) -> Result<Vec<u8>> {
+    if !is_encrypted(data) || data.len() < ENCRYPTED_HEADER_LEN {
+        anyhow::bail!("Not an encrypted clipq export");
+    }
+    let version = data[ENCRYPTED_MAGIC.len()];
+    if version > ENCRYPTED_VERSION {
+        anyhow::bail!(
+            "Encrypted export version {} is newer than this clipq supports ({})",
+            version,
+            ENCRYPTED_VERSION
+        );
+    }
+    let (header, ciphertext) = data.split_at(ENCRYPTED_HEADER_LEN);
+    let salt = &header[ENCRYPTED_MAGIC.len() + 1..][..SALT_LEN];
+    let nonce = &header[ENCRYPTED_HEADER_LEN - NONCE_LEN..];
+    export_cipher(passphrase, salt)?
+        .decrypt(Nonce::from_slice(nonce), Payload { msg: ciphertext, aad: header })
+        .map_err(|_| anyhow::anyhow!("Cannot decrypt the export: wrong passphrase, or the file was modified"))
+}
+
+fn export_cipher(passphrase: &str, salt: &[u8]) -> Result<Aes256Gcm> {
+    let mut key = [0u8; 32];
+    Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|e| anyhow::anyhow!("Deriving the export key failed: {}", e))?;
+    Ok(Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key)))
+}
+
+/// An HMAC-SHA256 under `key` fed the canonical bytes of `clips`: their
+/// compact JSON as this version serializes them. Signing and verifying
+/// both serialize parsed clips this way, so the whitespace and key order of
+/// the file itself never matter.
+fn signature_mac(key: &[u8], clips: &[TaggedClip]) -> Result<Hmac<Sha256>> {
+    // `KeyInit` has a `new_from_slice` too
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(key).expect("HMAC takes keys of any length");
+    mac.update(&serde_json::to_vec(clips)?);
+    Ok(mac)
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// The bytes of a hex string, either case; `None` if it isn't hex.
+fn unhex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) || !text.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn clip(id: &str, content: &str) -> Clip {
+        Clip {
+            id: id.to_string(),
+            content: content.to_string(),
+            clip_type: "text".to_string(),
+            created_at: "2024-05-01T12:00:00Z".parse().unwrap(),
+            file_path: None,
+            pinned: false,
+            expires_at: None,
+            mime: "text/plain".to_string(),
+            original_name: None,
+        }
+    }
+
+    #[test]
+    fn hashes_only_exports_reveal_no_content() {
+        let clips = vec![
+            TaggedClip { clip: clip("a", "correct horse battery staple"), tags: vec!["secrets".to_string()], data: None },
+            TaggedClip { clip: clip("b", "https://example.com/private"), tags: Vec::new(), data: None },
+        ];
+        let json = serde_json::to_string_pretty(&hashes_only(clips)).unwrap();
+
+        assert!(!json.contains("horse"), "{}", json);
+        assert!(!json.contains("example.com"), "{}", json);
+        assert!(json.contains(&content_hash("correct horse battery staple")));
+        assert!(json.contains("secrets"));
+    }
+
+    #[test]
+    fn only_clips_the_other_side_lacks_are_missing() {
+        let theirs = hashes_only(vec![TaggedClip { clip: clip("x", "shared"), tags: Vec::new(), data: None }]);
+        let theirs = open_hashes(&serde_json::to_string(&theirs).unwrap()).unwrap();
+
+        let missing = missing(vec![clip("a", "shared"), clip("b", "only here")], &theirs);
+        assert_eq!(missing.len(), 1);
+        assert_eq!(missing[0].id, "b");
+
+        assert!(open_hashes("[]").is_err());
+        let full = ExportEnvelope { version: ENVELOPE_VERSION, clips: Vec::new(), signature: None };
+        assert!(open_hashes(&serde_json::to_string(&full).unwrap()).is_err());
+    }
+
+    fn signed_export(key: &[u8]) -> String {
+        let clips = vec![TaggedClip { clip: clip("a", "signed text"), tags: vec!["work".to_string()], data: None }];
+        serde_json::to_string_pretty(&sign(clips, key).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn signed_exports_verify_however_they_are_formatted() {
+        let text = signed_export(b"key");
+        assert_eq!(open(&text, Some(b"key"), true).unwrap()[0].clip.content, "signed text");
+
+        let compact = serde_json::to_string(&serde_json::from_str::<serde_json::Value>(&text).unwrap()).unwrap();
+        assert_eq!(open(&compact, Some(b"key"), true).unwrap().len(), 1);
+
+        let signature = serde_json::from_str::<ExportEnvelope>(&text).unwrap().signature.unwrap();
+        let upper = text.replace(&signature, &signature.to_uppercase());
+        assert_eq!(open(&upper, Some(b"key"), true).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn tampered_exports_are_rejected() {
+        let text = signed_export(b"key");
+        let tampered = text.replace("signed text", "swapped text");
+        let error = open(&tampered, Some(b"key"), true).unwrap_err();
+        assert!(error.to_string().contains("does not match"), "{}", error);
+        assert!(open(&tampered, None, false).is_ok());
+
+        assert!(open(&text, Some(b"other key"), true).is_err());
+        assert!(open(&text, None, true).is_err());
+
+        let signature = serde_json::from_str::<ExportEnvelope>(&text).unwrap().signature.unwrap();
+        assert!(open(&text.replace(&signature, "not hex"), Some(b"key"), true).is_err());
+
+        let unsigned = serde_json::to_string(&vec![TaggedClip { clip: clip("a", "x"), tags: Vec::new(), data: None }]).unwrap();
+        assert!(open(&unsigned, Some(b"key"), true).is_err());
+        assert_eq!(open(&unsigned, None, true).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn encrypted_exports_round_trip() {
+        let plaintext = signed_export(b"key");
+        let sealed = encrypt(plaintext.as_bytes(), "hunter2").unwrap();
+        assert!(is_encrypted(&sealed));
+        assert!(!sealed.windows(b"signed text".len()).any(|window| window == b"signed text"));
+        assert_eq!(decrypt(&sealed, "hunter2").unwrap(), plaintext.as_bytes());
+
+        // Fresh salt and nonce every time
+        assert_ne!(encrypt(plaintext.as_bytes(), "hunter2").unwrap(), sealed);
+    }
+
+    #[test]
+    fn wrong_passphrases_and_damage_are_clear_errors() {
+        let sealed = encrypt(b"[]", "hunter2").unwrap();
+        let error = decrypt(&sealed, "hunter3").unwrap_err();
+        assert!(error.to_string().contains("wrong passphrase"), "{}", error);
+
+        let mut damaged = sealed.clone();
+        *damaged.last_mut().unwrap() ^= 1;
+        assert!(decrypt(&damaged, "hunter2").is_err());
+        assert!(decrypt(&sealed[..ENCRYPTED_HEADER_LEN - 1], "hunter2").is_err());
+        assert!(decrypt(b"[]", "hunter2").unwrap_err().to_string().contains("Not an encrypted"));
+    }
+}
@@ -0,0 +1,19 @@
+pub mod audit;
+pub mod clipboard;
+pub mod config;
+pub mod daemon;
+pub mod database;
+pub mod display;
+pub mod duration;
+pub mod editor;
+pub mod emitted;
+pub mod events;
+pub mod lock;
+pub mod export;
+pub mod metrics;
+pub mod near_dupes;
+pub mod picker;
+pub mod plugins;
+pub mod profile;
+pub mod suggest;
+pub mod web;
@@ -0,0 +1,86 @@
+use anyhow::Result;
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+use crate::profile;
+
+/// The file holding locked clipboard text, in the active profile's
+/// directory. It persists across daemon restarts; the daemon checks it on
+/// every poll, so locking needs no connection to a running daemon.
+fn lock_path() -> Result<PathBuf> {
+    Ok(profile::active().dir()?.join("clipboard.lock"))
+}
+
+/// Keep `content` on the clipboard until `unlock`.
+pub fn lock(content: &str) -> Result<()> {
+    write_private(&lock_path()?, content)
+}
+
+/// Write `content` to `path`, readable only by the current user: locked
+/// text is often a password or token.
+fn write_private(path: &Path, content: &str) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+        options.mode(0o600);
+        // `mode` only applies to new files; tighten one left by an older version
+        let file = options.open(path)?;
+        file.set_permissions(std::fs::Permissions::from_mode(0o600))?;
+        (&file).write_all(content.as_bytes())?;
+    }
+    #[cfg(not(unix))]
+    options.open(path)?.write_all(content.as_bytes())?;
+    Ok(())
+}
+
+/// Release the lock; `false` if nothing was locked.
+pub fn unlock() -> Result<bool> {
+    match std::fs::remove_file(lock_path()?) {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// The locked text, if the clipboard is locked.
+pub fn locked_content() -> Result<Option<String>> {
+    read_lock(&lock_path()?)
+}
+
+fn read_lock(path: &Path) -> Result<Option<String>> {
+    match std::fs::read_to_string(path) {
+        Ok(content) => Ok(Some(content)),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn locked_text_is_private() {
+        let dir = std::env::temp_dir().join(format!("clipq-lock-{}", uuid::Uuid::new_v4()));
+        let path = dir.join("clipboard.lock");
+        assert_eq!(read_lock(&path).unwrap(), None);
+
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(&path, "left by an older version").unwrap();
+        write_private(&path, "hunter2").unwrap();
+        assert_eq!(read_lock(&path).unwrap().as_deref(), Some("hunter2"));
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,103 +1,380 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
 use clap::{Parser, Subcommand};
+use std::io::{IsTerminal, Write};
 use std::sync::Arc;
-use tokio::sync::Mutex;
+use tokio::sync::{Mutex, OwnedMutexGuard};
 
-mod config;
-mod database;
-mod daemon;
-mod picker;
-mod clipboard;
-mod plugins;
-
-use config::Config;
-use database::Database;
-use daemon::Daemon;
+use clipq::{clipboard, display, duration, editor, emitted, export, lock, near_dupes, picker, plugins, profile, suggest, web};
+use clipq::audit::AuditAction;
+use clipq::config::Config;
+use clipq::database::{
+    Clip, ClipFilter, ClipOrder, ConflictMode, Database, IdResolution, ImportOutcome, ImportedClip, TagMatch,
+    TaggedClip, MAX_ACTIVITY_DAYS, validate_tag_name,
+};
+use clipq::daemon::Daemon;
+use clipq::events::{ClipEvent, ClipEventRecord};
+use clipq::metrics::Metrics;
 
 #[derive(Parser)]
 #[command(name = "clipq")]
 #[command(about = "Smart Clipboard Queue for power-users")]
 #[command(version)]
 struct Cli {
+    /// Suppress confirmation messages; data output is still printed
+    #[arg(short, long, global = true)]
+    quiet: bool,
+    /// Use a separate history and config, kept in ~/.clipq/profiles/<name>
+    #[arg(long, global = true, default_value = profile::DEFAULT_PROFILE)]
+    profile: String,
     #[command(subcommand)]
     command: Commands,
 }
 
+/// `println!` for confirmation messages that `--quiet` suppresses.
+macro_rules! status {
+    ($quiet:expr, $($arg:tt)*) => {
+        if !$quiet {
+            let line = format!($($arg)*);
+            println!("{}", line);
+            emitted::record(&line);
+            #[cfg(test)]
+            tests::STATUS_LINES.with(|lines| lines.borrow_mut().push(line));
+        }
+    };
+}
+
 #[derive(Subcommand)]
 enum Commands {
     /// Run the clipboard daemon
     Daemon {
-        /// Maximum number of clips to keep in history
-        #[arg(short, long, default_value = "100")]
-        max_clips: usize,
+        /// Maximum number of clips to keep in history (default: config max_clips)
+        #[arg(short, long)]
+        max_clips: Option<usize>,
         /// Configuration file path
         #[arg(short, long)]
         config: Option<String>,
+        /// Also serve the web interface on this port, in the same process,
+        /// so its /metrics counts the daemon's captures
+        #[arg(long, value_name = "PORT")]
+        web: Option<u16>,
+    },
+    /// Run a clipboard-only daemon that transforms every copy with a
+    /// pipeline, puts the result back and stores it
+    WatchPaste {
+        /// Comma-separated `clipq transform` steps (default: config
+        /// capture.watch_pipeline)
+        #[arg(long)]
+        pipeline: Option<String>,
     },
     /// Add text to clipboard and history
     Add {
         /// Text to add to clipboard
         text: String,
+        /// Delete the clip automatically after this long (e.g. 30s, 15m, 2h, 7d)
+        #[arg(long)]
+        expire_in: Option<String>,
+    },
+    /// Write a new clip in $EDITOR (or read it from stdin) and copy it
+    Compose,
+    /// Put a file's bytes on the clipboard as the given type and store it
+    Set {
+        /// MIME type of the data: text/plain, text/html or image/png
+        #[arg(long)]
+        mime: String,
+        /// File to read the raw bytes from
+        #[arg(long)]
+        file: String,
+    },
+    /// Store whatever is on the clipboard right now, tagged with a label
+    Snapshot {
+        /// Tag to attach to the stored clip
+        label: String,
     },
     /// Pick and paste from history
     Pick {
-        /// Maximum number of clips to show
-        #[arg(short, long, default_value = "50")]
-        limit: usize,
+        /// Maximum number of clips to show (default: config default_pick_limit)
+        #[arg(short, long)]
+        limit: Option<usize>,
+        /// Show previews without applying redact_patterns
+        #[arg(long)]
+        no_redact: bool,
+        /// Only offer clips with this tag
+        #[arg(long)]
+        tag: Option<String>,
+        /// Only offer clips of this type (text, file, image, ...)
+        #[arg(long = "type", value_name = "TYPE")]
+        clip_type: Option<String>,
+        /// Put the previous clipboard back after this many seconds
+        #[arg(long, value_name = "SECS")]
+        restore_on: Option<u64>,
     },
     /// List clipboard history
     List {
-        /// Maximum number of clips to show
+        /// Maximum number of clips to show (default: config default_list_limit)
+        #[arg(short, long)]
+        limit: Option<usize>,
+        /// Show content without applying redact_patterns
+        #[arg(long)]
+        no_redact: bool,
+        /// Show a table with id, age, type, size and tags
+        #[arg(short, long)]
+        verbose: bool,
+        /// Only print how many clips are stored
+        #[arg(long, conflicts_with_all = ["limit", "verbose"])]
+        count: bool,
+        /// Fit previews to this many columns instead of the terminal width
+        #[arg(long)]
+        width: Option<usize>,
+        /// Only show clips of this session (see `clipq sessions`)
+        #[arg(long, conflicts_with = "count")]
+        session: Option<String>,
+    },
+    /// List bursts of clips copied close together
+    Sessions {
+        /// Number of sessions to show, newest first
         #[arg(short, long, default_value = "20")]
         limit: usize,
     },
+    /// Print the most recent clip(s) without decoration
+    Last {
+        /// Number of clips to print, newest first
+        #[arg(short, long, default_value = "1")]
+        n: usize,
+        /// Print clips as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Print the full content of a clip
+    Show {
+        /// Clip ID or index
+        clip: String,
+    },
+    /// Copy a clip from history back to the clipboard
+    Copy {
+        /// Clip ID or index
+        clip: String,
+        /// Put the previous clipboard back after this many seconds
+        #[arg(long, value_name = "SECS")]
+        restore_on: Option<u64>,
+    },
+    /// Pin a clip so retention never removes it
+    Pin {
+        /// Clip ID or index
+        clip: String,
+    },
+    /// Unpin a clip
+    Unpin {
+        /// Clip ID or index
+        clip: String,
+    },
+    /// Keep the current clipboard text in place: a running daemon puts it
+    /// back whenever something else is copied, until `clipq unlock`
+    Lock,
+    /// Let the clipboard change again after `clipq lock`
+    Unlock,
+    /// Apply the [retention] policy from the config now
+    Gc,
+    /// Delete unpinned clips that are empty or only whitespace
+    PruneEmpty,
+    /// Suggest history clips related to what's on the clipboard now
+    Suggest {
+        /// How many suggestions to show
+        #[arg(short, long, default_value = "5")]
+        limit: usize,
+        /// How many recent clips to consider
+        #[arg(long, default_value = "500")]
+        window: usize,
+    },
+    /// Report groups of nearly identical clips without deleting anything
+    NearDupes {
+        /// Minimum similarity, from 0 (anything) to 1 (identical after
+        /// whitespace normalization)
+        #[arg(long, default_value = "0.9")]
+        threshold: f64,
+        /// Only look at this many recent clips
+        #[arg(short, long, default_value = "1000")]
+        limit: usize,
+    },
+    /// Remove orphaned tag links and versions, rebuild the search index and
+    /// check the database for corruption
+    Repair,
+    /// Show a unified diff between two clips
+    Diff {
+        /// First clip ID or index
+        a: String,
+        /// Second clip ID or index
+        b: String,
+        /// Diff word by word instead of line by line
+        #[arg(long)]
+        word: bool,
+    },
     /// Clear clipboard history
-    Clear,
-    /// Show configuration
-    Config,
+    Clear {
+        /// Don't ask for confirmation (required when stdin is not a terminal)
+        #[arg(short, long)]
+        yes: bool,
+        /// Only delete the N oldest unpinned clips
+        #[arg(long, value_name = "N", conflicts_with = "newest")]
+        oldest: Option<usize>,
+        /// Only delete the N newest unpinned clips
+        #[arg(long, value_name = "N")]
+        newest: Option<usize>,
+    },
+    /// Show configuration, or read or change a single setting
+    Config {
+        #[command(subcommand)]
+        action: Option<ConfigAction>,
+    },
     /// Search clipboard history
     Search {
         /// Search query
         query: String,
-        /// Maximum number of results
-        #[arg(short, long, default_value = "20")]
-        limit: usize,
+        /// Maximum number of results (default: config default_search_limit)
+        #[arg(short, long)]
+        limit: Option<usize>,
+        /// Only search clips with this tag
+        #[arg(short, long)]
+        tag: Option<String>,
+        /// Show previews without applying redact_patterns
+        #[arg(long)]
+        no_redact: bool,
+        /// Treat the query as a regular expression
+        #[arg(long)]
+        regex: bool,
+        /// Only match clips whose whole content equals the query
+        #[arg(long, conflicts_with = "regex")]
+        exact: bool,
+        /// Only match clips of this type (text, file, image, ...); needs --exact or --count
+        #[arg(long = "type", value_name = "TYPE")]
+        clip_type: Option<String>,
+        /// Only print how many clips match
+        #[arg(long, conflicts_with_all = ["regex", "context", "limit"])]
+        count: bool,
+        /// Fit previews to this many columns instead of the terminal width
+        #[arg(long)]
+        width: Option<usize>,
+        /// Show each matching line with N lines of context instead of a preview
+        #[arg(short = 'C', long, value_name = "N")]
+        context: Option<usize>,
     },
     /// Show statistics
-    Stats,
+    Stats {
+        /// Show a per-day histogram of clip activity instead
+        #[arg(long, conflicts_with_all = ["by_hour", "tags"])]
+        by_day: bool,
+        /// Show how many clips were copied in each hour of the day instead
+        #[arg(long, conflicts_with = "tags")]
+        by_hour: bool,
+        /// List every tag by how many clips carry it, flagging unused ones
+        #[arg(long)]
+        tags: bool,
+        /// With --tags, delete the uncolored tags no clip carries
+        #[arg(long, requires = "tags")]
+        prune_unused: bool,
+        /// Number of days in the histogram (at most 3650)
+        #[arg(long, default_value = "14", requires = "by_day")]
+        days: usize,
+    },
     /// Export clipboard history
     Export {
-        /// Output file path
+        /// Output file path; `-` writes to stdout
         #[arg(short, long, default_value = "clipboard_export.json")]
         output: String,
-        /// Export format (json, csv, txt)
+        /// Write to stdout, same as `--output -`
+        #[arg(long, conflicts_with = "output")]
+        stdout: bool,
+        /// Export format (json, ndjson, csv, txt)
         #[arg(short, long, default_value = "json")]
         format: String,
+        /// Sign a json export with the configured export key
+        #[arg(long)]
+        sign: bool,
+        /// Write clips in insertion order, so exporting an unchanged
+        /// history twice gives identical files
+        #[arg(long)]
+        stable: bool,
+        /// Write only each clip's id, content hash, timestamp and tags
+        /// (json only), to compare histories without sharing content
+        #[arg(long)]
+        hashes_only: bool,
+        /// Write only clips whose content is missing from FILE, a
+        /// `--hashes-only` export from another machine
+        #[arg(long, value_name = "FILE", conflicts_with = "hashes_only")]
+        missing_from: Option<String>,
+        /// Encrypt the export with a passphrase from
+        /// $CLIPQ_EXPORT_PASSPHRASE or prompted for
+        #[arg(long)]
+        encrypt: bool,
     },
     /// Import clipboard history
     Import {
-        /// Input file path
-        input: String,
-        /// Import format (json, csv, txt)
+        /// Input file path; `-` reads stdin
+        #[arg(required_unless_present = "stdin")]
+        input: Option<String>,
+        /// Read from stdin, same as passing `-`
+        #[arg(long, conflicts_with = "input")]
+        stdin: bool,
+        /// Import format (json, ndjson, csv, txt, clipqdb)
         #[arg(short, long, default_value = "json")]
         format: String,
+        /// What to do when a clip with the same content already exists
+        #[arg(long, value_enum, default_value = "duplicate")]
+        on_conflict: ConflictMode,
+        /// Import a json export without checking its signature
+        #[arg(long)]
+        no_verify: bool,
+        /// Decrypt an export written with `export --encrypt`
+        #[arg(long)]
+        decrypt: bool,
     },
     /// Add file to clipboard
     File {
         /// File path to add
         path: String,
+        /// Also store the file's bytes so `clipq open` can restore it later
+        #[arg(long)]
+        embed: bool,
+    },
+    /// Write the file embedded in a clip back to disk
+    Open {
+        /// Clip ID or index
+        clip: String,
+        /// Where to write the file (default: its original name in the current directory)
+        #[arg(short, long)]
+        output: Option<String>,
+    },
+    /// Store the text of each matching file as a clip, tagged with its file name
+    AddFiles {
+        /// File paths or glob patterns (e.g. "notes/*.md")
+        #[arg(required = true)]
+        patterns: Vec<String>,
     },
     /// Show clipboard history with tags
     Tags {
         /// Tag to filter by
+        #[arg(conflicts_with_all = ["all", "any"])]
         tag: Option<String>,
+        /// Only clips having all of these comma-separated tags
+        #[arg(long, value_delimiter = ',', conflicts_with = "any")]
+        all: Vec<String>,
+        /// Clips having any of these comma-separated tags
+        #[arg(long, value_delimiter = ',')]
+        any: Vec<String>,
+        /// Fit previews to this many columns instead of the terminal width
+        #[arg(long)]
+        width: Option<usize>,
     },
+    /// Merge tags that differ only in case and apply the configured tag casing
+    TagNormalize,
     /// Add tag to a clip
     Tag {
         /// Clip ID or index
         clip: String,
         /// Tag to add
         tag: String,
+        /// Create the tag even if it looks like a typo of an existing one
+        #[arg(long)]
+        force: bool,
     },
     /// Remove tag from a clip
     Untag {
@@ -106,6 +383,29 @@ enum Commands {
         /// Tag to remove
         tag: String,
     },
+    /// Replace one tag with another on a clip
+    Retag {
+        /// Clip ID or index
+        clip: String,
+        /// Tag to remove
+        old: String,
+        /// Tag to add in its place
+        new: String,
+    },
+    /// Move every clip from one tag to another, merging into it if it exists
+    RetagAll {
+        /// Tag to empty and delete
+        old: String,
+        /// Tag that receives its clips
+        new: String,
+    },
+    /// Set the display color of a tag
+    TagColor {
+        /// Tag name
+        tag: String,
+        /// Hex color such as #ff8800, or "none" to clear it
+        color: String,
+    },
     /// Backup database
     Backup {
         /// Backup file path
@@ -123,6 +423,31 @@ enum Commands {
         #[arg(short, long, default_value = "8080")]
         port: u16,
     },
+    /// Start the web server and open it in the default browser
+    OpenWeb {
+        /// Port to run web server on (default: any free port)
+        #[arg(short, long, default_value = "0")]
+        port: u16,
+    },
+    /// Run list/search/copy/tag/delete interactively against one open database
+    Repl,
+    /// Show recent additions, deletions, edits and tags as a log
+    Tail {
+        /// How many past events to show first
+        #[arg(short, default_value = "10")]
+        n: usize,
+        /// Keep printing new events as they happen
+        #[arg(short, long)]
+        follow: bool,
+    },
+    /// List profiles; the one in use is marked with *
+    Profiles,
+    /// Check whether a running web server is healthy
+    ServerStatus {
+        /// Base URL of the web server
+        #[arg(long, default_value = "http://localhost:8080")]
+        url: String,
+    },
     /// List available plugins
     Plugins,
     /// Execute a plugin
@@ -132,6 +457,15 @@ enum Commands {
         /// Input text
         input: String,
     },
+    /// Run a plugin on a file's content and report its output, exit status
+    /// and timing, for debugging plugins
+    PluginTest {
+        /// Plugin name
+        name: String,
+        /// File whose content is passed to the plugin on stdin
+        #[arg(long)]
+        input_file: String,
+    },
     /// Extract URLs from text
     ExtractUrls {
         /// Text to extract URLs from
@@ -143,6 +477,46 @@ enum Commands {
         #[arg(short, long, default_value = "16")]
         length: usize,
     },
+    /// Rewrite a stored clip in place: pretty-print JSON, decode a JWT, or decode base64
+    Format {
+        /// Clip index, ID, or ID prefix
+        clip: String,
+        /// Transform to apply
+        #[arg(long = "as", value_enum)]
+        format: plugins::builtin::Format,
+    },
+    /// Run the clipboard text through a chain of built-in transforms, put
+    /// the result back and store it
+    Transform {
+        /// Comma-separated steps, e.g. trim,base64_decode,format_json
+        pipeline: String,
+    },
+    /// Fill a template snippet's {name} placeholders and copy the result
+    Expand {
+        /// Clip index, ID, or ID prefix of a clip tagged "template"
+        clip: String,
+        /// Placeholder values as name=value
+        #[arg(value_parser = parse_var)]
+        vars: Vec<(String, String)>,
+        /// Fail when a placeholder has no value instead of leaving it as is
+        #[arg(long)]
+        strict: bool,
+        /// Also store the result as a new clip
+        #[arg(long)]
+        save: bool,
+    },
+    /// List the earlier contents of an edited clip
+    History {
+        /// Clip index, ID, or ID prefix
+        clip: String,
+    },
+    /// Restore an earlier content of a clip, as numbered by `history`
+    Revert {
+        /// Clip index, ID, or ID prefix
+        clip: String,
+        /// Version number from `clipq history`
+        version: usize,
+    },
     /// Calculate hash
     Hash {
         /// Text to hash
@@ -153,61 +527,446 @@ enum Commands {
     },
 }
 
+#[derive(Subcommand)]
+enum ConfigAction {
+    /// Print the value of a setting
+    Get {
+        /// Setting name; nested sections use dots (e.g. retention.max_age_days)
+        key: String,
+    },
+    /// Change a setting and save the config file
+    Set {
+        /// Setting name; nested sections use dots (e.g. retention.max_age_days)
+        key: String,
+        /// New value, or "none" to clear an optional setting
+        #[arg(allow_hyphen_values = true)]
+        value: String,
+    },
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
-    env_logger::init();
+    // Warnings such as skipped corrupt rows show without RUST_LOG
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("warn")).init();
 
     let cli = Cli::parse();
+    let quiet = cli.quiet;
+    profile::set_active(profile::Profile::new(&cli.profile)?);
+    // Commands needing a valid config report a broken one themselves
+    if let Ok(config) = load_config() {
+        emitted::set_enabled(config.capture.skip_own_output);
+    }
+
+    run_command(cli.command, &mut Handles::default(), quiet).await
+}
 
-    match cli.command {
-        Commands::Daemon { max_clips, config } => {
-            let config_path = config.unwrap_or_else(|| {
-                dirs::home_dir()
-                    .unwrap_or_else(|| std::env::current_dir().unwrap())
-                    .join(".clipq.toml")
-                    .to_string_lossy()
-                    .to_string()
-            });
+/// Run one command. `clipq repl` dispatches each of its lines here too.
+async fn run_command(command: Commands, handles: &mut Handles, quiet: bool) -> Result<()> {
+    match command {
+        Commands::Daemon { max_clips, config, web } => {
+            let config_path = Config::resolve_path(config.as_deref());
 
             let config = Config::load(&config_path)?;
-            let mut daemon = Daemon::new(config, max_clips).await?;
-            daemon.run().await?;
+            let metrics = Arc::new(Metrics::default());
+            let plugins = Arc::new(load_plugin_manager(&config)?);
+            let mut daemon = Daemon::new(config.clone(), max_clips)
+                .await?
+                .with_metrics(Arc::clone(&metrics))
+                .with_plugins(Arc::clone(&plugins));
+            match web {
+                Some(port) => {
+                    let server = web_server(handles.shared_db().await?, plugins, &config, port)?.with_metrics(metrics);
+                    // The server stops on Ctrl-C; take the daemon down with it
+                    tokio::select! {
+                        result = daemon.run() => result?,
+                        result = server.start() => result?,
+                    }
+                }
+                None => daemon.run().await?,
+            }
+        }
+        Commands::WatchPaste { pipeline } => {
+            let config = load_config()?;
+            let pipeline = pipeline
+                .or_else(|| config.capture.watch_pipeline.clone())
+                .ok_or_else(|| anyhow::anyhow!("No pipeline given; pass --pipeline or set capture.watch_pipeline"))?;
+            let steps = plugins::builtin::parse_pipeline(&pipeline)?;
+            if steps.is_empty() {
+                anyhow::bail!("No transform steps given");
+            }
+            let mut daemon = Daemon::new(config, None).await?;
+            daemon.watch_paste(steps).await?;
         }
-        Commands::Add { text } => {
-            let mut db = Database::new().await?;
-            let mut clipboard = clipboard::ClipboardManager::new()?;
+        Commands::Add { text, expire_in } => {
+            let mut db = handles.db().await?;
+            let clipboard = &mut handles.clipboard;
+            let expires_at = expire_in
+                .map(|duration| duration::parse_duration(&duration).and_then(duration::from_now))
+                .transpose()?;
             
             clipboard.set_text(&text)?;
-            db.add_clip(&text, "text").await?;
+            let max_clips = load_config()?.max_clips;
+            // Stored with its expiry at once, so it can't outlive it as a plain clip
+            db.with_transaction(|tx| {
+                let id = tx.add_clip(&text, "text")?;
+                if expires_at.is_some() {
+                    tx.set_expiry(&id, expires_at)?;
+                }
+                tx.trim_history(max_clips)?;
+                Ok(id)
+            })?;
             
-            println!("Added to clipboard: {}", text);
+            status!(quiet, "Added to clipboard: {}", text);
+        }
+        Commands::Compose => compose(handles, editor::configured_editor(), &mut std::io::stdin(), quiet).await?,
+        Commands::Set { mime, file } => {
+            let bytes = std::fs::read(&file).with_context(|| format!("Cannot read {}", file))?;
+            let content = clipboard::TypedContent::decode(&mime, bytes.clone())?;
+            handles.clipboard.set_typed(&content)?;
+
+            let mut db = handles.db().await?;
+            let max_clips = load_config()?.max_clips;
+            let clip_id = db.with_transaction(|tx| {
+                let id = match &content {
+                    clipboard::TypedContent::Text(text) => tx.add_clip_with_mime(text, "text", &mime)?,
+                    clipboard::TypedContent::Html(html) => tx.add_clip_with_mime(html, "text", &mime)?,
+                    // The file is already a PNG, so it is kept as it is
+                    clipboard::TypedContent::Image { width, height, .. } => tx.add_image_clip(*width, *height, &bytes)?,
+                };
+                tx.trim_history(max_clips)?;
+                Ok(id)
+            })?;
+            status!(quiet, "Set clipboard to {} from {} (clip {})", mime, file, clip_id);
+        }
+        Commands::Snapshot { label } => {
+            let config = load_config()?;
+            // Checked before anything is stored, so a bad label stores nothing
+            validate_tag_name(&label, config.max_tag_length)?;
+            let mut db = handles.db().await?;
+
+            let capture = handles.clipboard.capture()?.ok_or_else(|| anyhow::anyhow!("The clipboard is empty"))?;
+            let clip_id = db.with_transaction(|tx| {
+                let id = match &capture {
+                    clipboard::Capture::Text(text) => tx.add_clip(text, "text")?,
+                    clipboard::Capture::Image { width, height, rgba } => {
+                        tx.add_image_clip(*width, *height, &clipboard::encode_png(*width, *height, rgba)?)?
+                    }
+                };
+                tx.add_tag_to_clip(&id, &label)?;
+                tx.trim_history(config.max_clips)?;
+                Ok(id)
+            })?;
+
+            status!(quiet, "Saved snapshot '{}' as clip {}", label, clip_id);
         }
-        Commands::Pick { limit } => {
-            let mut db = Database::new().await?;
-            let mut clipboard = clipboard::ClipboardManager::new()?;
+        Commands::Pick { limit, no_redact, tag, clip_type, restore_on } => {
+            let mut db = handles.db().await?;
+            let clipboard = &mut handles.clipboard;
+            let config = load_config()?;
+            let limit = limit.unwrap_or(config.default_pick_limit);
+            let redact_patterns = redact_patterns(&config, no_redact)?;
             
-            if let Some(selected) = picker::show_picker(&mut db, limit).await? {
-                clipboard.set_text(&selected)?;
-                println!("Pasted: {}", selected);
+            let filter = picker::PickFilter { tag, clip_type };
+            
+            if let Some(selected) = picker::show_picker(&mut db, limit, &filter, &redact_patterns, &config).await? {
+                let previous = if restore_on.is_some() { clipboard.get_text()? } else { None };
+                set_clipboard_clip(&db, clipboard, &selected).await?;
+                db.record_use(&selected.id).await?;
+                status!(quiet, "Pasted: {}", selected.content);
+                if let (Some(secs), Some(previous)) = (restore_on, previous) {
+                    restore_clipboard(clipboard, &previous, secs, quiet).await?;
+                }
             }
         }
-        Commands::List { limit } => {
-            let db = Database::new().await?;
-            let clips = db.get_recent_clips(limit).await?;
+        Commands::List { limit, no_redact, verbose, count, width, session } => {
+            let db = handles.db().await?;
+            if count {
+                println!("{}", db.count_clips(&ClipFilter::default()).await?);
+                return Ok(());
+            }
+            let config = load_config()?;
+            let limit = limit.unwrap_or(config.default_list_limit);
+            let clips = match session {
+                Some(session) => {
+                    let session_id = db.resolve_session_id(&session, config.session_gap_secs).await?;
+                    db.get_session_clips(&session_id, config.session_gap_secs, limit).await?
+                }
+                None => db.get_recent_clips(limit).await?,
+            };
+            let redact_patterns = redact_patterns(&config, no_redact)?;
+            let width = display::output_width(width);
             
-            for (i, clip) in clips.iter().enumerate() {
-                println!("{}: {}", i + 1, clip.content);
+            if verbose {
+                let table = verbose_table(&db, &clips, width, &redact_patterns, chrono::Utc::now()).await?;
+                print!("{}", table);
+                emitted::record_all(table.lines());
+            } else {
+                let placeholders = display::list_placeholders(&config.list_format)?;
+                let wants_tags = placeholders.iter().any(|name| name == "tags");
+                let now = chrono::Utc::now();
+                let mut lines = Vec::with_capacity(clips.len());
+                for (i, clip) in clips.iter().enumerate() {
+                    let content = display::redact(&clip.content, &redact_patterns);
+                    let tags = if wants_tags { db.get_clip_tags(&clip.id).await? } else { Vec::new() };
+                    let line = display::render_list_entry(&config.list_format, i + 1, clip, &content, &tags, now, width);
+                    println!("{}", line);
+                    lines.push(line);
+                }
+                // Entries can span lines, and any of them may get copied
+                emitted::record_all(lines.iter().flat_map(|line| line.lines()));
+            }
+        }
+        Commands::Sessions { limit } => {
+            let db = handles.db().await?;
+            let sessions = db.get_sessions(load_config()?.session_gap_secs, limit).await?;
+            if sessions.is_empty() {
+                println!("No clipboard history found");
+                return Ok(());
+            }
+            let format_time = |time: chrono::DateTime<chrono::Utc>| {
+                time.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M:%S").to_string()
+            };
+            let rows: Vec<Vec<String>> = sessions
+                .iter()
+                .map(|session| {
+                    vec![
+                        session.id.chars().take(8).collect(),
+                        format_time(session.started_at),
+                        format_time(session.ended_at),
+                        session.clip_count.to_string(),
+                    ]
+                })
+                .collect();
+            print!("{}", display::table(&["SESSION", "STARTED", "ENDED", "CLIPS"], &rows));
+        }
+        Commands::Last { n, json } => {
+            let db = handles.db().await?;
+            let clips = db.get_recent_clips(n).await?;
+            write_last(&clips, json, &mut std::io::stdout().lock())?;
+        }
+        Commands::Show { clip } => {
+            let db = handles.db().await?;
+            let clip_id = resolve_clip_id(&db, &clip).await?;
+            let clip = db
+                .get_clip_by_id(&clip_id)
+                .await?
+                .ok_or_else(|| anyhow::anyhow!("Clip not found: {}", clip_id))?;
+            println!("{}", clip.content);
+        }
+        Commands::Copy { clip, restore_on } => {
+            let mut db = handles.db().await?;
+            let clip_id = resolve_clip_id(&db, &clip).await?;
+            let clip = db
+                .get_clip_by_id(&clip_id)
+                .await?
+                .ok_or_else(|| anyhow::anyhow!("Clip not found: {}", clip_id))?;
+
+            let clipboard = &mut handles.clipboard;
+            let previous = if restore_on.is_some() { clipboard.get_text()? } else { None };
+            set_clipboard_clip(&db, clipboard, &clip).await?;
+            db.record_use(&clip.id).await?;
+            status!(quiet, "Copied to clipboard: {}", clip.content);
+            if let (Some(secs), Some(previous)) = (restore_on, previous) {
+                restore_clipboard(clipboard, &previous, secs, quiet).await?;
             }
         }
-        Commands::Clear => {
-            let mut db = Database::new().await?;
+        Commands::Pin { clip } => {
+            let mut db = handles.db().await?;
+            let clip_id = resolve_clip_id(&db, &clip).await?;
+            if db.set_pinned(&clip_id, true).await? {
+                status!(quiet, "Pinned clip {}", clip_id);
+            } else {
+                println!("Clip not found: {}", clip_id);
+            }
+        }
+        Commands::Unpin { clip } => {
+            let mut db = handles.db().await?;
+            let clip_id = resolve_clip_id(&db, &clip).await?;
+            if db.set_pinned(&clip_id, false).await? {
+                status!(quiet, "Unpinned clip {}", clip_id);
+            } else {
+                println!("Clip not found: {}", clip_id);
+            }
+        }
+        Commands::Lock => {
+            let text = handles.clipboard
+                .get_text()?
+                .filter(|text| clipboard::is_capturable(text))
+                .ok_or_else(|| anyhow::anyhow!("The clipboard holds no text to lock"))?;
+            lock::lock(&text)?;
+            status!(quiet, "Locked clipboard to: {}", text);
+        }
+        Commands::Unlock => {
+            if lock::unlock()? {
+                status!(quiet, "Clipboard unlocked");
+            } else {
+                status!(quiet, "Clipboard was not locked");
+            }
+        }
+        Commands::Suggest { limit, window } => {
+            let content = handles.clipboard
+                .get_text()?
+                .filter(|text| !text.trim().is_empty())
+                .ok_or_else(|| anyhow::anyhow!("The clipboard holds no text to base suggestions on"))?;
+            let db = handles.db().await?;
+            let mut context = suggest::SuggestContext {
+                content,
+                ..Default::default()
+            };
+            let mut co_copies = std::collections::HashMap::new();
+            if let Some(current) = db.find_clip_by_content(&context.content).await? {
+                context.tags = db.get_clip_tags(&current.id).await?;
+                context.copied_at = Some(current.created_at);
+                co_copies = db.co_copy_counts(&current.id, suggest::CO_COPY_WINDOW_SECS).await?;
+            }
+
+            let mut candidates = Vec::new();
+            for clip in db.get_recent_clips(window).await? {
+                if clip.content == context.content {
+                    continue;
+                }
+                let tags = db.get_clip_tags(&clip.id).await?;
+                let use_count = db.use_count(&clip.id).await?;
+                let co_copies = co_copies.get(&clip.id).copied().unwrap_or(0);
+                candidates.push(suggest::Candidate { clip, tags, use_count, co_copies });
+            }
+
+            let suggestions = suggest::Suggester::default().rank(&context, &candidates, limit);
+            if suggestions.is_empty() {
+                println!("No related clips found");
+            }
+            let redact_patterns = redact_patterns(&load_config()?, false)?;
+            let width = display::output_width(None);
+            for suggestion in suggestions {
+                let clip = &candidates[suggestion.index].clip;
+                let prefix = format!("{:>5.2}  {}  ", suggestion.score, &clip.id.chars().take(8).collect::<String>());
+                let preview = clip.redacted_preview(display::preview_width(width, prefix.len()), &redact_patterns);
+                println!("{}{}", prefix, preview);
+            }
+        }
+        Commands::NearDupes { threshold, limit } => {
+            if !(0.0..=1.0).contains(&threshold) {
+                anyhow::bail!("--threshold must be between 0 and 1");
+            }
+            let db = handles.db().await?;
+            let redact_patterns = redact_patterns(&load_config()?, false)?;
+            let clips = db.get_recent_clips(limit).await?;
+            let groups = near_dupes::group_near_duplicates(&clips, threshold);
+            if groups.is_empty() {
+                println!("No near-duplicate clips found");
+            }
+
+            let width = display::output_width(None);
+            for (n, group) in groups.iter().enumerate() {
+                println!("Group {} ({} clips):", n + 1, group.len());
+                for &i in group {
+                    let prefix = format!("  {:>4}  {}  ", i + 1, &clips[i].id.chars().take(8).collect::<String>());
+                    let preview = clips[i].redacted_preview(display::preview_width(width, prefix.len()), &redact_patterns);
+                    println!("{}{}", prefix, preview);
+                }
+            }
+        }
+        Commands::PruneEmpty => {
+            let mut db = handles.db().await?;
+            let removed = db.delete_where_blank().await?;
+            status!(quiet, "Removed {} blank clips", removed);
+        }
+        Commands::Gc => {
+            let mut db = handles.db().await?;
+            let config = load_config()?;
+            let removed = db.apply_retention(&config.retention).await?;
+            status!(quiet, "Removed {} clips", removed);
+        }
+        Commands::Repair => {
+            let mut db = handles.db().await?;
+            let report = db.repair().await?;
+            println!("Removed {} orphaned tag link(s)", report.orphaned_links);
+            println!("Removed {} orphaned clip version(s)", report.orphaned_versions);
+            println!("Removed {} unused tag(s)", report.unused_tags);
+            println!("Rebuilt the search index");
+            if report.integrity_errors.is_empty() {
+                println!("Integrity check passed");
+            } else {
+                for error in &report.integrity_errors {
+                    println!("Integrity problem: {}", error);
+                }
+                anyhow::bail!("Integrity check found {} problem(s)", report.integrity_errors.len());
+            }
+        }
+        Commands::Diff { a, b, word } => {
+            let db = handles.db().await?;
+            let mut clips = Vec::new();
+            for clip in [&a, &b] {
+                let clip_id = resolve_clip_id(&db, clip).await?;
+                let clip = db
+                    .get_clip_by_id(&clip_id)
+                    .await?
+                    .ok_or_else(|| anyhow::anyhow!("Clip not found: {}", clip_id))?;
+                clips.push(clip);
+            }
+
+            let color = std::io::stdout().is_terminal();
+            print!("{}", display::diff(&clips[0], &clips[1], word, color));
+        }
+        Commands::Clear { yes, oldest, newest } => {
+            let mut db = handles.db().await?;
+            let (total, pinned) = db.clip_counts().await?;
+            if total == 0 {
+                status!(quiet, "Clipboard history is already empty");
+                return Ok(());
+            }
+
+            if let Some((count, end)) = oldest.map(|n| (n, "oldest")).or(newest.map(|n| (n, "newest"))) {
+                let count = count.min(total - pinned);
+                if !yes {
+                    if !can_confirm() {
+                        anyhow::bail!("Refusing to delete {} clips without a terminal; pass --yes to confirm", count);
+                    }
+                    if !confirm(&format!("Delete the {} {} unpinned clips?", count, end))? {
+                        println!("Aborted");
+                        return Ok(());
+                    }
+                }
+
+                let removed = if oldest.is_some() {
+                    db.delete_oldest(count).await?
+                } else {
+                    db.delete_newest(count).await?
+                };
+                status!(quiet, "Deleted {} {} clips", removed, end);
+                return Ok(());
+            }
+
+            if !yes {
+                if !can_confirm() {
+                    anyhow::bail!("Refusing to clear {} clips without a terminal; pass --yes to confirm", total);
+                }
+                let pinned_note = if pinned > 0 {
+                    format!(" ({} pinned)", pinned)
+                } else {
+                    String::new()
+                };
+                if !confirm(&format!("Delete all {} clips{}?", total, pinned_note))? {
+                    println!("Aborted");
+                    return Ok(());
+                }
+            }
+
             db.clear_history().await?;
-            println!("Clipboard history cleared");
+            status!(quiet, "Clipboard history cleared");
+        }
+        Commands::Config { action: Some(ConfigAction::Get { key }) } => {
+            println!("{}", load_config()?.get_key(&key)?);
         }
-        Commands::Config => {
-            let config_path = dirs::home_dir()
-                .unwrap_or_else(|| std::env::current_dir().unwrap())
-                .join(".clipq.toml");
+        Commands::Config { action: Some(ConfigAction::Set { key, value }) } => {
+            let config_path = Config::resolve_path(None);
+            let mut config = Config::load(&config_path)?;
+            config.set_key(&key, &value)?;
+            config.save(&config_path)?;
+            println!("{} = {}", key, config.get_key(&key)?);
+        }
+        Commands::Config { action: None } => {
+            let config_path = std::path::PathBuf::from(Config::resolve_path(None));
             
             if config_path.exists() {
                 let config = Config::load(&config_path.to_string_lossy())?;
@@ -221,26 +980,107 @@ async fn main() -> Result<()> {
                 println!("Default configuration saved to: {}", config_path.display());
             }
         }
-        Commands::Search { query, limit } => {
-            let db = Database::new().await?;
-            let clips = db.search_clips(&query, limit).await?;
+        Commands::Search { query, limit, tag, no_redact, regex, exact, clip_type, count, width, context } => {
+            let db = handles.db().await?;
+            if clip_type.is_some() && !exact && !count {
+                anyhow::bail!("--type needs --exact or --count");
+            }
+            if count {
+                let filter = if exact {
+                    ClipFilter { equals: Some(query), tag, clip_type, ..ClipFilter::default() }
+                } else {
+                    ClipFilter { contains: Some(query), tag, clip_type, ..ClipFilter::default() }
+                };
+                println!("{}", db.count_clips(&filter).await?);
+                return Ok(());
+            }
+            let config = load_config()?;
+            let limit = limit.unwrap_or(config.default_search_limit);
+            let redact_patterns = redact_patterns(&config, no_redact)?;
+            // Plain queries match like SQLite's LIKE: literally, ignoring case
+            let pattern = if regex {
+                regex::Regex::new(&query).with_context(|| format!("Invalid regex '{}'", query))?
+            } else {
+                regex::Regex::new(&format!("(?i){}", regex::escape(&query)))?
+            };
+            let clips = if exact {
+                db.find_exact(&query, tag.as_deref(), clip_type.as_deref(), limit).await?
+            } else if regex {
+                db.search_clips_regex(&pattern, tag.as_deref(), limit).await?
+            } else if let Some(tag) = tag {
+                db.search_clips_by_tag(&query, &tag, limit).await?
+            } else {
+                db.search_clips(&query, limit).await?
+            };
             
             if clips.is_empty() {
                 println!("No clips found matching '{}'", query);
+            } else if let Some(context) = context {
+                let color = std::io::stdout().is_terminal();
+                for (i, clip) in clips.iter().enumerate() {
+                    let content = display::redact(&clip.content, &redact_patterns);
+                    let id: String = clip.id.chars().take(8).collect();
+                    println!("{}: {}", i + 1, id);
+                    print!("{}", display::grep(&content, &pattern, context, color));
+                }
             } else {
+                let width = display::output_width(width);
                 println!("Found {} clips matching '{}':", clips.len(), query);
                 for (i, clip) in clips.iter().enumerate() {
-                    let preview = if clip.content.len() > 80 {
-                        format!("{}...", &clip.content[..77])
-                    } else {
-                        clip.content.clone()
-                    };
-                    println!("{}: {}", i + 1, preview);
+                    let prefix = format!("{}: ", i + 1);
+                    let preview = clip.redacted_preview(display::preview_width(width, prefix.len()), &redact_patterns);
+                    println!("{}{}", prefix, preview);
                 }
             }
         }
-        Commands::Stats => {
-            let db = Database::new().await?;
+        Commands::Stats { tags: true, prune_unused, .. } => {
+            let mut db = handles.db().await?;
+            let tags = db.list_all_tags().await?;
+            if tags.is_empty() {
+                println!("No tags");
+                return Ok(());
+            }
+            let rows: Vec<Vec<String>> = tags
+                .iter()
+                .map(|tag| {
+                    let note = if tag.clips == 0 { "unused" } else { "" };
+                    vec![tag.name.clone(), tag.clips.to_string(), note.to_string()]
+                })
+                .collect();
+            print!("{}", display::table(&["TAG", "CLIPS", ""], &rows));
+
+            let unused = db.unused_tags().await?;
+            if prune_unused {
+                let removed = db.prune_unused_tags().await?;
+                status!(quiet, "Deleted {} unused tag(s)", removed);
+            } else if !unused.is_empty() {
+                println!("{} unused tag(s); delete them with --prune-unused", unused.len());
+            }
+        }
+        Commands::Stats { by_hour: true, .. } => {
+            let db = handles.db().await?;
+            let timezone = load_config()?.timezone()?;
+            let rows: Vec<(String, usize)> = db
+                .clips_per_hour(&timezone)
+                .await?
+                .into_iter()
+                .enumerate()
+                .map(|(hour, count)| (format!("{:02}:00", hour), count))
+                .collect();
+            print!("{}", display::bar_chart(&rows, 40));
+        }
+        Commands::Stats { by_day: true, days, .. } => {
+            let db = handles.db().await?;
+            let timezone = load_config()?.timezone()?;
+            let activity = db.clips_per_day(days.min(MAX_ACTIVITY_DAYS), &timezone).await?;
+            let rows: Vec<(String, usize)> = activity
+                .into_iter()
+                .map(|(day, count)| (day.format("%Y-%m-%d %a").to_string(), count))
+                .collect();
+            print!("{}", display::bar_chart(&rows, 40));
+        }
+        Commands::Stats { by_day: false, .. } => {
+            let db = handles.db().await?;
             let stats = db.get_statistics().await?;
             
             println!("Clipboard Statistics");
@@ -251,114 +1091,360 @@ async fn main() -> Result<()> {
             println!("Oldest clip: {}", stats.oldest_clip);
             println!("Newest clip: {}", stats.newest_clip);
             println!("Database size: {} KB", stats.db_size_kb);
+            if stats.embedded_clips > 0 {
+                println!(
+                    "Embedded files: {} ({} compressed, {} stored as {})",
+                    stats.embedded_clips,
+                    stats.compressed_clips,
+                    display::human_size(stats.embedded_original_bytes as usize),
+                    display::human_size(stats.embedded_stored_bytes as usize)
+                );
+            }
+            if let Some(ratio) = stats.compression_ratio() {
+                println!("Compression ratio: {:.2}x", ratio);
+            }
         }
-        Commands::Export { output, format } => {
-            let db = Database::new().await?;
-            let clips = db.get_all_clips().await?;
+        Commands::Export { output, stdout, format, sign, stable, hashes_only, missing_from, encrypt } => {
+            let output = if stdout { "-".to_string() } else { output };
+            // Stdout carries the export itself, so confirmations are dropped
+            let quiet = quiet || output == "-";
+            let db = handles.db().await?;
+            let config = load_config()?;
+            if hashes_only && (format != "json" || sign) {
+                anyhow::bail!("--hashes-only is only supported for unsigned json exports");
+            }
+            let theirs = missing_from
+                .map(|path| {
+                    let content = std::fs::read_to_string(&path).with_context(|| format!("Cannot read {}", path))?;
+                    export::open_hashes(&content).with_context(|| format!("Cannot use {}", path))
+                })
+                .transpose()?;
+            if theirs.is_some() && format == "ndjson" {
+                anyhow::bail!("--missing-from is not supported for ndjson exports");
+            }
+            let key = if sign {
+                if format != "json" {
+                    anyhow::bail!("--sign is only supported for json exports");
+                }
+                let key = config.export_key().ok_or_else(|| {
+                    anyhow::anyhow!("--sign needs export_signing_key in the config or $CLIPQ_EXPORT_KEY")
+                })?;
+                Some(key)
+            } else {
+                None
+            };
+
+            let passphrase = if encrypt { Some(export_passphrase(true)?) } else { None };
+            // The whole export is encrypted at once, so it is built in memory
+            let seal = |data: &[u8]| match &passphrase {
+                Some(passphrase) => export::encrypt(data, passphrase),
+                None => Ok(data.to_vec()),
+            };
+
+            let order = if stable { ClipOrder::Insertion } else { ClipOrder::Newest };
+
+            if format == "ndjson" {
+                // One clip per line, streamed straight from the database
+                // unless it has to be encrypted
+                let mut buffer = Vec::new();
+                let destination: Box<dyn Write + '_> = if encrypt { Box::new(&mut buffer) } else { open_output(&output)? };
+                let mut writer = std::io::BufWriter::new(destination);
+                let mut audit = config.audit_log().map(|log| log.writer()).transpose()?;
+                let count = db
+                    .for_each_tagged_clip(order, |clip| {
+                        serde_json::to_writer(&mut writer, &clip)?;
+                        writer.write_all(b"\n")?;
+                        if let Some(audit) = audit.as_mut() {
+                            audit.write(AuditAction::Export, &clip.clip.id, &clip.clip.content)?;
+                        }
+                        Ok(())
+                    })
+                    .await?;
+                writer.flush()?;
+                drop(writer);
+                if encrypt {
+                    write_output(&output, &seal(&buffer)?)?;
+                }
+                if let Some(audit) = audit {
+                    audit.finish()?;
+                }
+                status!(quiet, "Exported {} clips to {}", count, output);
+                return Ok(());
+            }
+
+            let mut clips = db.get_all_clips_ordered(order).await?;
+            if let Some(theirs) = &theirs {
+                clips = export::missing(clips, theirs);
+            }
+            if !matches!(format.as_str(), "json" | "csv" | "txt") {
+                println!("Unsupported format: {}. Use json, ndjson, csv, or txt", format);
+                return Ok(());
+            }
             
             match format.as_str() {
                 "json" => {
-                    let json = serde_json::to_string_pretty(&clips)?;
-                    std::fs::write(&output, json)?;
-                    println!("Exported {} clips to {}", clips.len(), output);
+                    let mut tagged_clips = Vec::with_capacity(clips.len());
+                    for clip in clips.iter().cloned() {
+                        let tags = db.get_clip_tags(&clip.id).await?;
+                        let data = if hashes_only { None } else { db.get_clip_blob(&clip.id).await? };
+                        tagged_clips.push(TaggedClip { clip, tags, data });
+                    }
+                    let json = match key {
+                        Some(key) => serde_json::to_string_pretty(&export::sign(tagged_clips, key.as_bytes())?)?,
+                        None if hashes_only => serde_json::to_string_pretty(&export::hashes_only(tagged_clips))?,
+                        None => serde_json::to_string_pretty(&tagged_clips)?,
+                    };
+                    write_output(&output, &seal(json.as_bytes())?)?;
+                    status!(quiet, "Exported {} clips to {}", clips.len(), output);
                 }
                 "csv" => {
                     let mut csv = String::new();
-                    csv.push_str("id,content,type,created_at,file_path\n");
-                    let count = clips.len();
-                    for clip in clips {
+                    csv.push_str("id,content,type,created_at,file_path,mime\n");
+                    for clip in &clips {
                         csv.push_str(&format!(
-                            "{},{},{},{},{}\n",
+                            "{},{},{},{},{},{}\n",
                             clip.id,
                             clip.content.replace(',', "\\,"),
                             clip.clip_type,
                             clip.created_at.timestamp(),
-                            clip.file_path.unwrap_or_default()
+                            clip.file_path.as_deref().unwrap_or_default(),
+                            clip.mime
                         ));
                     }
-                    std::fs::write(&output, csv)?;
-                    println!("Exported {} clips to {}", count, output);
+                    write_output(&output, &seal(csv.as_bytes())?)?;
+                    status!(quiet, "Exported {} clips to {}", clips.len(), output);
                 }
                 "txt" => {
                     let mut txt = String::new();
                     for (i, clip) in clips.iter().enumerate() {
                         txt.push_str(&format!("{}: {}\n", i + 1, clip.content));
                     }
-                    std::fs::write(&output, txt)?;
-                    println!("Exported {} clips to {}", clips.len(), output);
-                }
-                _ => {
-                    println!("Unsupported format: {}. Use json, csv, or txt", format);
+                    write_output(&output, &seal(txt.as_bytes())?)?;
+                    status!(quiet, "Exported {} clips to {}", clips.len(), output);
                 }
+                _ => unreachable!("format checked above"),
+            }
+            if let Some(audit) = config.audit_log() {
+                audit.record(AuditAction::Export, clips.iter().map(|clip| (clip.id.as_str(), clip.content.as_str())))?;
             }
         }
-        Commands::Import { input, format } => {
-            let mut db = Database::new().await?;
-            let content = std::fs::read_to_string(&input)?;
+        Commands::Import { input, stdin, format, on_conflict, no_verify, decrypt } => {
+            let input = match input {
+                Some(input) if !stdin => input,
+                _ => "-".to_string(),
+            };
+            let mut db = handles.db().await?;
+
+            // Another clipq database is merged directly rather than parsed
+            if format == "clipqdb" {
+                if input == "-" {
+                    anyhow::bail!("clipqdb imports need a database file, not stdin");
+                }
+                if decrypt {
+                    anyhow::bail!("clipqdb imports can't be decrypted; decrypt only applies to exports");
+                }
+                let outcomes = db.import_database(&input, on_conflict).await?;
+                let count = |wanted: ImportOutcome| outcomes.iter().filter(|&&outcome| outcome == wanted).count();
+                status!(
+                    quiet,
+                    "Imported {} clips from {} ({} skipped, {} merged)",
+                    count(ImportOutcome::Inserted),
+                    input,
+                    count(ImportOutcome::Skipped),
+                    count(ImportOutcome::Merged)
+                );
+                return Ok(());
+            }
+
+            let mut content = read_input(&input)?;
+            match (decrypt, export::is_encrypted(&content)) {
+                (true, true) => content = export::decrypt(&content, &export_passphrase(false)?)?,
+                (true, false) => anyhow::bail!("{} is not an encrypted export", input),
+                (false, true) => anyhow::bail!("{} is encrypted; pass --decrypt to import it", input),
+                (false, false) => {}
+            }
+            let content = String::from_utf8(content).map_err(|_| anyhow::anyhow!("{} is not valid UTF-8 text", input))?;
             
-            match format.as_str() {
+            let clips: Vec<ImportedClip> = match format.as_str() {
                 "json" => {
-                    let clips: Vec<crate::database::Clip> = serde_json::from_str(&content)?;
-                    let count = clips.len();
-                    for clip in clips {
-                        db.add_clip(&clip.content, &clip.clip_type).await?;
+                    let key = load_config()?.export_key();
+                    let clips = export::open(&content, key.as_deref().map(str::as_bytes), !no_verify)?;
+                    clips.into_iter().map(ImportedClip::from).collect()
+                }
+                "ndjson" => {
+                    let mut clips = Vec::new();
+                    for line in content.lines().filter(|line| !line.trim().is_empty()) {
+                        let tagged: TaggedClip = serde_json::from_str(line)?;
+                        clips.push(ImportedClip::from(tagged));
                     }
-                    println!("Imported {} clips from {}", count, input);
+                    clips
                 }
                 "csv" => {
                     let mut lines = content.lines();
                     lines.next(); // Skip header
-                    let mut count = 0;
+                    let mut clips = Vec::new();
                     for line in lines {
                         let parts: Vec<&str> = line.split(',').collect();
                         if parts.len() >= 3 {
                             let content = parts[1].replace("\\,", ",");
-                            let clip_type = parts[2];
-                            db.add_clip(&content, clip_type).await?;
-                            count += 1;
+                            clips.push(ImportedClip::new(content, parts[2]));
                         }
                     }
-                    println!("Imported {} clips from {}", count, input);
+                    clips
                 }
                 "txt" => {
-                    let mut count = 0;
-                    for line in content.lines() {
-                        if !line.trim().is_empty() {
-                            db.add_clip(line.trim(), "text").await?;
-                            count += 1;
-                        }
-                    }
-                    println!("Imported {} clips from {}", count, input);
+                    content
+                        .lines()
+                        .filter(|line| !line.trim().is_empty())
+                        .map(|line| ImportedClip::new(line.trim().to_string(), "text"))
+                        .collect()
                 }
                 _ => {
-                    println!("Unsupported format: {}. Use json, csv, or txt", format);
+                    println!("Unsupported format: {}. Use json, ndjson, csv, txt, or clipqdb", format);
+                    return Ok(());
                 }
-            }
+            };
+
+            // All or nothing, so a bad row can't leave a half-imported file
+            let (mut imported, mut skipped, mut merged) = (0, 0, 0);
+            db.with_transaction(|tx| {
+                for clip in &clips {
+                    match tx.import_clip(clip, on_conflict)? {
+                        ImportOutcome::Inserted => imported += 1,
+                        ImportOutcome::Skipped => skipped += 1,
+                        ImportOutcome::Merged => merged += 1,
+                    }
+                }
+                Ok(())
+            })?;
+            status!(
+                quiet,
+                "Imported {} clips from {} ({} skipped, {} merged)",
+                imported, input, skipped, merged
+            );
         }
-        Commands::File { path } => {
-            let mut db = Database::new().await?;
-            let mut clipboard = clipboard::ClipboardManager::new()?;
+        Commands::File { path, embed } => {
+            let mut db = handles.db().await?;
+            let clipboard = &mut handles.clipboard;
             
             if std::path::Path::new(&path).exists() {
                 let abs_path = std::fs::canonicalize(&path)?;
                 let path_str = abs_path.to_string_lossy();
                 
+                if embed {
+                    let max_bytes = load_config()?.max_embed_bytes;
+                    let size = std::fs::metadata(&abs_path)?.len();
+                    if size > max_bytes {
+                        anyhow::bail!(
+                            "{} is {} bytes, over the max_embed_bytes limit of {}",
+                            path_str,
+                            size,
+                            max_bytes
+                        );
+                    }
+                    let name = abs_path
+                        .file_name()
+                        .map(|name| name.to_string_lossy().into_owned())
+                        .unwrap_or_default();
+                    let data = std::fs::read(&abs_path)?;
+                    db.add_embedded_file_clip(&path_str, &name, &data).await?;
+                } else {
+                    db.add_file_clip(&path_str).await?;
+                }
                 clipboard.set_text(&path_str)?;
-                db.add_file_clip(&path_str).await?;
                 
-                println!("Added file to clipboard: {}", path_str);
+                status!(quiet, "Added file to clipboard: {}", path_str);
             } else {
                 println!("File not found: {}", path);
             }
         }
-        Commands::Tags { tag } => {
-            let db = Database::new().await?;
+        Commands::Open { clip, output } => {
+            let db = handles.db().await?;
+            let clip_id = resolve_clip_id(&db, &clip).await?;
+            let clip = db
+                .get_clip_by_id(&clip_id)
+                .await?
+                .ok_or_else(|| anyhow::anyhow!("Clip not found: {}", clip_id))?;
+            let data = db
+                .get_clip_blob(&clip_id)
+                .await?
+                .ok_or_else(|| anyhow::anyhow!("Clip {} has no embedded file; add it with `clipq file --embed`", clip_id))?;
+
+            let output = output
+                .or(clip.original_name)
+                .ok_or_else(|| anyhow::anyhow!("Clip {} has no file name; pass --output", clip_id))?;
+            if std::path::Path::new(&output).exists() {
+                anyhow::bail!("{} already exists", output);
+            }
+            std::fs::write(&output, data)?;
+            status!(quiet, "Restored {}", output);
+        }
+        Commands::AddFiles { patterns } => {
+            let mut db = handles.db().await?;
+            let config = load_config()?;
+            let fallback_encoding = config.fallback_encoding()?;
+            let mut added = 0;
+
+            for pattern in &patterns {
+                let expanded = shellexpand::tilde(pattern);
+                let paths = glob::glob(&expanded)
+                    .with_context(|| format!("Invalid glob pattern: {}", pattern))?;
+
+                for path in paths {
+                    let path = path?;
+                    if !path.is_file() {
+                        continue;
+                    }
+
+                    let bytes = std::fs::read(&path)?;
+                    let decoded = match clipboard::decode_text(bytes, fallback_encoding) {
+                        Some(decoded) if !decoded.text.contains('\0') => decoded,
+                        _ => {
+                            eprintln!("Skipping {}: not a text file", path.display());
+                            continue;
+                        }
+                    };
+
+                    // A file name that can't be a tag doesn't stop the batch
+                    let name_tag = path.file_name().map(|name| name.to_string_lossy().into_owned()).filter(|name| {
+                        match validate_tag_name(name, config.max_tag_length) {
+                            Ok(_) => true,
+                            Err(e) => {
+                                eprintln!("Not tagging {} with its name: {}", path.display(), e);
+                                false
+                            }
+                        }
+                    });
+                    db.with_transaction(|tx| {
+                        let id = tx.add_clip(&decoded.text, "text")?;
+                        if let Some(name) = &name_tag {
+                            tx.add_tag_to_clip(&id, name)?;
+                        }
+                        if let Some(encoding) = decoded.fallback {
+                            tx.add_tag_to_clip(&id, &format!("encoding:{}", encoding.to_lowercase()))?;
+                        }
+                        tx.trim_history(config.max_clips)?;
+                        Ok(id)
+                    })?;
+                    added += 1;
+                }
+            }
+
+            status!(quiet, "Added {} files to history", added);
+        }
+        Commands::Tags { tag, all, any, width } => {
+            let db = handles.db().await?;
             let clips = if let Some(tag) = tag {
                 db.get_clips_by_tag(&tag).await?
+            } else if !all.is_empty() {
+                db.get_clips_by_tags(&all, TagMatch::All).await?
+            } else if !any.is_empty() {
+                db.get_clips_by_tags(&any, TagMatch::Any).await?
             } else {
                 db.get_all_clips().await?
             };
             
+            let width = display::output_width(width);
             for (i, clip) in clips.iter().enumerate() {
                 let tags = db.get_clip_tags(&clip.id).await?;
                 let tag_str = if tags.is_empty() {
@@ -366,66 +1452,109 @@ async fn main() -> Result<()> {
                 } else {
                     format!(" [{}]", tags.join(", "))
                 };
-                println!("{}: {}{}", i + 1, clip.content, tag_str);
+                let prefix = format!("{}: ", i + 1);
+                let used = prefix.len() + tag_str.chars().count();
+                println!("{}{}{}", prefix, clip.preview(display::preview_width(width, used)), tag_str);
             }
         }
-        Commands::Tag { clip, tag } => {
-            let mut db = Database::new().await?;
-            
-            // Try to parse as index first, then as ID
-            let clip_id = if let Ok(index) = clip.parse::<usize>() {
-                let clips = db.get_recent_clips(index).await?;
-                if index > 0 && index <= clips.len() {
-                    clips[index - 1].id.clone()
-                } else {
-                    println!("Invalid clip index: {}", index);
-                    return Ok(());
+        Commands::Tag { clip, tag, force } => {
+            let mut db = handles.db().await?;
+            let clip_id = resolve_clip_id(&db, &clip).await?;
+
+            if !force {
+                // Allow one typo in short tags, two in longer ones
+                let max_distance = (tag.chars().count() / 3).clamp(1, 2);
+                let similar = db.find_similar_tags(&tag, max_distance).await?;
+                if !similar.is_empty() {
+                    anyhow::bail!(
+                        "Tag '{}' does not exist; did you mean '{}'? Use --force to create it anyway",
+                        tag,
+                        similar.join("', '")
+                    );
                 }
-            } else {
-                clip.clone()
-            };
+            }
             
             db.add_tag_to_clip(&clip_id, &tag).await?;
-            println!("Added tag '{}' to clip {}", tag, clip_id);
+            status!(quiet, "Added tag '{}' to clip {}", tag, clip_id);
+        }
+        Commands::TagNormalize => {
+            let mut db = handles.db().await?;
+            match db.normalize_tags().await? {
+                0 => println!("No duplicate tags found"),
+                merged => println!("Merged {} duplicate tag(s)", merged),
+            }
         }
         Commands::Untag { clip, tag } => {
-            let mut db = Database::new().await?;
-            
-            // Try to parse as index first, then as ID
-            let clip_id = if let Ok(index) = clip.parse::<usize>() {
-                let clips = db.get_recent_clips(index).await?;
-                if index > 0 && index <= clips.len() {
-                    clips[index - 1].id.clone()
-                } else {
-                    println!("Invalid clip index: {}", index);
-                    return Ok(());
-                }
-            } else {
-                clip.clone()
-            };
+            let mut db = handles.db().await?;
+            let clip_id = resolve_clip_id(&db, &clip).await?;
             
             db.remove_tag_from_clip(&clip_id, &tag).await?;
-            println!("Removed tag '{}' from clip {}", tag, clip_id);
+            status!(quiet, "Removed tag '{}' from clip {}", tag, clip_id);
+        }
+        Commands::Retag { clip, old, new } => {
+            let mut db = handles.db().await?;
+            let clip_id = resolve_clip_id(&db, &clip).await?;
+
+            if !db.retag_clip(&clip_id, &old, &new).await? {
+                anyhow::bail!("Clip {} is not tagged '{}'", clip_id, old);
+            }
+            status!(quiet, "Retagged clip {} from '{}' to '{}'", clip_id, old, new);
+        }
+        Commands::RetagAll { old, new } => {
+            let mut db = handles.db().await?;
+            let moved = db.retag_all(&old, &new).await?;
+            status!(quiet, "Moved {} clip(s) from '{}' to '{}'", moved, old, new);
+        }
+        Commands::TagColor { tag, color } => {
+            let mut db = handles.db().await?;
+            if color == "none" {
+                db.set_tag_color(&tag, None).await?;
+                status!(quiet, "Cleared color of tag '{}'", tag);
+            } else {
+                db.set_tag_color(&tag, Some(&color)).await?;
+                status!(quiet, "Set color of tag '{}' to {}", tag, color);
+            }
         }
         Commands::Backup { output } => {
-            let db = Database::new().await?;
+            let db = handles.db().await?;
             db.backup(&output).await?;
-            println!("Database backed up to: {}", output);
+            status!(quiet, "Database backed up to: {}", output);
         }
         Commands::Restore { input } => {
-            let mut db = Database::new().await?;
+            let mut db = handles.db().await?;
             db.restore(&input).await?;
-            println!("Database restored from: {}", input);
+            status!(quiet, "Database restored from: {}", input);
         }
         Commands::Web { port } => {
-            println!("Web interface temporarily disabled.");
-            println!("Would start web server on port: {}", port);
-            println!("This feature will be re-enabled in a future update.");
+            let config = load_config()?;
+            let server = web_server(handles.shared_db().await?, Arc::new(load_plugin_manager(&config)?), &config, port)?;
+            server.start().await?;
+        }
+        Commands::OpenWeb { port } => {
+            let config = load_config()?;
+            let server = web_server(handles.shared_db().await?, Arc::new(load_plugin_manager(&config)?), &config, port)?;
+            server
+                .serve(|addr| {
+                    // Headless machines just keep serving at the printed URL
+                    if let Err(e) = web::open_browser(&web::authorized_url(addr, server.token())) {
+                        eprintln!("Could not open a browser ({}); open the URL above manually", e);
+                    }
+                })
+                .await?;
+        }
+        Commands::ServerStatus { url } => {
+            let health = web::check_health(&url)
+                .await
+                .with_context(|| format!("Server at {} is unreachable", url))?;
+            println!("Status: {}", health.status);
+            println!("Clips: {}", health.clips);
+            println!("Version: {}", health.version);
+            if !health.is_ok() {
+                anyhow::bail!("Server at {} is unhealthy", url);
+            }
         }
         Commands::Plugins => {
-            let db = Arc::new(Mutex::new(Database::new().await?));
-            let mut plugin_manager = plugins::PluginManager::new(db);
-            plugin_manager.load_plugins()?;
+            let plugin_manager = load_plugin_manager(&load_config()?)?;
             
             println!("Available Plugins:");
             println!("==================");
@@ -435,13 +1564,22 @@ async fn main() -> Result<()> {
             }
         }
         Commands::Plugin { name, input } => {
-            let db = Arc::new(Mutex::new(Database::new().await?));
-            let mut plugin_manager = plugins::PluginManager::new(db);
-            plugin_manager.load_plugins()?;
+            let plugin_manager = load_plugin_manager(&load_config()?)?;
             
             let result = plugin_manager.execute_plugin(&name, &input).await?;
             print!("{}", result);
         }
+        Commands::PluginTest { name, input_file } => {
+            let input = std::fs::read_to_string(&input_file)
+                .with_context(|| format!("Cannot read plugin input {}", input_file))?;
+            let plugin_manager = load_plugin_manager(&load_config()?)?;
+
+            let run = plugin_manager.run_plugin(&name, &input).await?;
+            print!("{}", run.report());
+            if !run.status.success() {
+                anyhow::bail!("Plugin {} exited unsuccessfully ({})", name, run.status);
+            }
+        }
         Commands::ExtractUrls { text } => {
             let urls = plugins::builtin::extract_urls(&text);
             if urls.is_empty() {
@@ -457,6 +1595,125 @@ async fn main() -> Result<()> {
             let password = plugins::builtin::generate_password(length);
             println!("Generated password: {}", password);
         }
+        Commands::Format { clip, format } => {
+            let mut db = handles.db().await?;
+            let clip_id = resolve_clip_id(&db, &clip).await?;
+            let clip = db
+                .get_clip_by_id(&clip_id)
+                .await?
+                .ok_or_else(|| anyhow::anyhow!("Clip not found: {}", clip_id))?;
+
+            let formatted = plugins::builtin::apply_format(format, &clip.content)
+                .with_context(|| format!("Cannot format clip {}", clip_id))?;
+            db.update_clip_content(&clip_id, &formatted).await?;
+            println!("{}", formatted);
+        }
+        Commands::Transform { pipeline } => {
+            let steps = plugins::builtin::parse_pipeline(&pipeline)?;
+            if steps.is_empty() {
+                anyhow::bail!("No transform steps given");
+            }
+            let text = handles
+                .clipboard
+                .get_text()?
+                .ok_or_else(|| anyhow::anyhow!("The clipboard holds no text"))?;
+
+            let result = plugins::builtin::run_pipeline(&steps, &text)?;
+            let mut db = handles.db().await?;
+            db.add_and_trim(&result, "text", load_config()?.max_clips).await?;
+            handles.clipboard.set_text(&result)?;
+            println!("{}", result);
+        }
+        Commands::Expand { clip, vars, strict, save } => {
+            let mut db = handles.db().await?;
+            let clip_id = resolve_clip_id(&db, &clip).await?;
+            let clip = db
+                .get_clip_by_id(&clip_id)
+                .await?
+                .ok_or_else(|| anyhow::anyhow!("Clip not found: {}", clip_id))?;
+
+            if !plugins::builtin::is_template(&db.get_clip_tags(&clip_id).await?) {
+                anyhow::bail!(
+                    "Clip {0} is not a template; mark it with `clipq tag {0} {1}`",
+                    clip_id,
+                    plugins::builtin::TEMPLATE_TAG
+                );
+            }
+
+            let vars = vars.into_iter().collect();
+            let expanded = plugins::builtin::expand_template(&clip.content, &vars, strict)
+                .with_context(|| format!("Cannot expand clip {}", clip_id))?;
+            if save {
+                db.add_and_trim(&expanded, "text", load_config()?.max_clips).await?;
+            }
+            let clipboard = &mut handles.clipboard;
+            clipboard.set_text(&expanded)?;
+            status!(quiet, "Copied to clipboard: {}", expanded);
+        }
+        Commands::History { clip } => {
+            let db = handles.db().await?;
+            let clip_id = resolve_clip_id(&db, &clip).await?;
+            let versions = db.get_clip_versions(&clip_id).await?;
+            if versions.is_empty() {
+                println!("Clip {} has no earlier versions", clip_id);
+            } else {
+                let now = chrono::Utc::now();
+                let rows: Vec<Vec<String>> = versions
+                    .iter()
+                    .enumerate()
+                    .map(|(i, version)| {
+                        vec![
+                            (i + 1).to_string(),
+                            display::relative_time(version.edited_at, now),
+                            display::one_line_preview(&version.content, 60),
+                        ]
+                    })
+                    .collect();
+                print!("{}", display::table(&["#", "REPLACED", "CONTENT"], &rows));
+            }
+        }
+        Commands::Revert { clip, version } => {
+            let mut db = handles.db().await?;
+            let clip_id = resolve_clip_id(&db, &clip).await?;
+            if !db.revert_clip(&clip_id, version).await? {
+                anyhow::bail!("Clip {} has no version {}; see `clipq history {}`", clip_id, version, clip);
+            }
+            status!(quiet, "Reverted clip {} to version {}", clip_id, version);
+        }
+        Commands::Tail { n, follow } => {
+            let db = handles.db().await?;
+            let redact_patterns = redact_patterns(&load_config()?, false)?;
+            let width = display::output_width(None);
+
+            let recent = db.recent_events(n).await?;
+            let mut last_seq = match recent.last() {
+                Some(record) => record.seq,
+                None => db.latest_event_seq().await?,
+            };
+            for record in &recent {
+                println!("{}", render_event_line(&db, record, &redact_patterns, width).await?);
+            }
+
+            if follow {
+                loop {
+                    tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+                    for record in db.events_after(last_seq, 100).await? {
+                        println!("{}", render_event_line(&db, &record, &redact_patterns, width).await?);
+                        last_seq = record.seq;
+                    }
+                }
+            }
+        }
+        Commands::Profiles => {
+            let active = profile::active().name();
+            for name in profile::list()? {
+                let marker = if name == active { "*" } else { " " };
+                println!("{} {}", marker, name);
+            }
+        }
+        Commands::Repl => {
+            run_repl(handles, quiet).await?;
+        }
         Commands::Hash { text, algorithm } => {
             let hash = plugins::builtin::calculate_hash(&text, &algorithm);
             println!("{} hash: {}", algorithm, hash);
@@ -464,4 +1721,800 @@ async fn main() -> Result<()> {
     }
 
     Ok(())
-}
\ No newline at end of file
+}
+
+/// Parse a `name=value` argument of `clipq expand`.
+fn parse_var(arg: &str) -> Result<(String, String), String> {
+    match arg.split_once('=') {
+        Some((name, value)) if !name.is_empty() => Ok((name.to_string(), value.to_string())),
+        _ => Err(format!("expected name=value, got '{}'", arg)),
+    }
+}
+
+/// Whether `confirm` has a terminal to ask on. Tests never do, so they
+/// can't hang waiting for an answer.
+fn can_confirm() -> bool {
+    !cfg!(test) && std::io::stdin().is_terminal()
+}
+
+/// Ask a y/N question on the terminal; anything but "y"/"yes" is a no.
+fn confirm(question: &str) -> Result<bool> {
+    print!("{} [y/N] ", question);
+    std::io::stdout().flush()?;
+
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+/// Put `clip` on the clipboard, along with the PNG stored for an image clip.
+async fn set_clipboard_clip(db: &Database, clipboard: &mut clipboard::ClipboardManager, clip: &Clip) -> Result<()> {
+    let image = if clip.clip_type == "image" { db.get_clip_blob(&clip.id).await? } else { None };
+    clipboard.set_clip(clip, image.as_deref())
+}
+
+/// Keep running for `secs` seconds, then put `previous` back on the
+/// clipboard unless something else was copied in the meantime. Ctrl-C
+/// keeps the new content.
+async fn restore_clipboard(clipboard: &mut clipboard::ClipboardManager, previous: &str, secs: u64, quiet: bool) -> Result<()> {
+    let copied = clipboard.get_text()?.unwrap_or_default();
+    status!(quiet, "Restoring the previous clipboard in {}s; press Ctrl-C to keep this one", secs);
+    if clipboard.restore_after(previous, &copied, std::time::Duration::from_secs(secs)).await? {
+        status!(quiet, "Restored the previous clipboard");
+    } else {
+        status!(quiet, "Clipboard changed since; left it as is");
+    }
+    Ok(())
+}
+
+fn load_config() -> Result<Config> {
+    Config::load(&Config::resolve_path(None))
+}
+
+/// Where `export` writes: stdout for `-`, else a new file at `path`.
+fn open_output(path: &str) -> Result<Box<dyn Write>> {
+    if path == "-" {
+        return Ok(Box::new(std::io::stdout().lock()));
+    }
+    Ok(Box::new(std::fs::File::create(path)?))
+}
+
+fn write_output(path: &str, data: &[u8]) -> Result<()> {
+    write_output_to(path, data, &mut std::io::stdout().lock())
+}
+
+/// `write_output` with `stdout` standing in for the standard stream.
+fn write_output_to(path: &str, data: &[u8], stdout: &mut dyn Write) -> Result<()> {
+    if path == "-" {
+        stdout.write_all(data)?;
+        stdout.flush()?;
+        return Ok(());
+    }
+    std::fs::write(path, data)?;
+    Ok(())
+}
+
+/// What `last` prints for `clips`: their bare contents, newest first, or
+/// JSON; nothing at all when there are none.
+fn write_last(clips: &[Clip], json: bool, stdout: &mut dyn Write) -> Result<()> {
+    if clips.is_empty() {
+        return Ok(());
+    }
+    if json {
+        writeln!(stdout, "{}", serde_json::to_string_pretty(clips)?)?;
+    } else {
+        let contents: Vec<&str> = clips.iter().map(|clip| clip.content.as_str()).collect();
+        write!(stdout, "{}", contents.join("\n"))?;
+    }
+    Ok(())
+}
+
+/// What `import` reads: all of stdin for `-`, else the file at `path`.
+fn read_input(path: &str) -> Result<Vec<u8>> {
+    read_input_from(path, &mut std::io::stdin().lock())
+}
+
+/// `read_input` with `stdin` standing in for the standard stream.
+fn read_input_from(path: &str, stdin: &mut dyn std::io::Read) -> Result<Vec<u8>> {
+    if path == "-" {
+        let mut content = Vec::new();
+        stdin.read_to_end(&mut content)?;
+        return Ok(content);
+    }
+    Ok(std::fs::read(path)?)
+}
+
+/// The passphrase for `export --encrypt` and `import --decrypt`:
+/// `$CLIPQ_EXPORT_PASSPHRASE`, else asked for on the terminal, twice when
+/// `confirm` is set so a typo doesn't lock the export away.
+fn export_passphrase(confirm: bool) -> Result<String> {
+    if let Some(passphrase) = std::env::var("CLIPQ_EXPORT_PASSPHRASE").ok().filter(|passphrase| !passphrase.is_empty()) {
+        return Ok(passphrase);
+    }
+    let passphrase = rpassword::prompt_password("Export passphrase: ")?;
+    if passphrase.is_empty() {
+        anyhow::bail!("The passphrase must not be empty");
+    }
+    if confirm && rpassword::prompt_password("Repeat passphrase: ")? != passphrase {
+        anyhow::bail!("Passphrases do not match");
+    }
+    Ok(passphrase)
+}
+
+/// What commands run against: the history database and the clipboard.
+/// `clipq repl` keeps one `Handles` for all of its lines, so they share a
+/// database connection and clipboard instead of reopening them.
+#[derive(Default)]
+struct Handles {
+    /// Opened on first use, so commands that never touch the history (e.g.
+    /// `config`) work even when it can't be opened
+    db: Option<Arc<Mutex<Database>>>,
+    clipboard: clipboard::ClipboardManager,
+}
+
+impl Handles {
+    /// Handles on `db` and `clipboard`, already open.
+    #[cfg(test)]
+    fn new(db: Database, clipboard: clipboard::ClipboardManager) -> Self {
+        Self {
+            db: Some(Arc::new(Mutex::new(db))),
+            clipboard,
+        }
+    }
+
+    /// The database, opening it if this is its first use.
+    async fn shared_db(&mut self) -> Result<Arc<Mutex<Database>>> {
+        if self.db.is_none() {
+            self.db = Some(Arc::new(Mutex::new(open_database().await?)));
+        }
+        Ok(Arc::clone(self.db.as_ref().expect("database was just opened")))
+    }
+
+    /// Exclusive use of the database until the guard is dropped.
+    async fn db(&mut self) -> Result<OwnedMutexGuard<Database>> {
+        Ok(self.shared_db().await?.lock_owned().await)
+    }
+}
+
+/// Open the history database with the configured tag casing and version
+/// cap applied.
+async fn open_database() -> Result<Database> {
+    let config = load_config()?;
+    let mut db = Database::new().await?;
+    db.set_tag_case(config.tag_case);
+    db.set_max_tag_length(config.max_tag_length);
+    db.set_max_versions(config.max_versions_per_clip);
+    db.set_compression(config.compression_threshold_bytes as usize, config.compression_level);
+    db.set_audit_log(config.audit_log());
+    Ok(db)
+}
+
+/// The web server for `config`'s bind address and token on `port`.
+fn web_server(
+    db: Arc<Mutex<Database>>,
+    plugins: Arc<plugins::PluginManager>,
+    config: &Config,
+    port: u16,
+) -> Result<web::WebServer> {
+    let addr = web::parse_bind_addr(&config.web_bind, port)?;
+    Ok(web::WebServer::new(db, plugins, addr, config.timezone()?)
+        .with_token(config.web_token().unwrap_or_else(web::generate_token)))
+}
+
+/// Plugin manager with the built-in plugins loaded and the configured
+/// concurrency limit, timeout and fallback encoding applied.
+fn load_plugin_manager(config: &Config) -> Result<plugins::PluginManager> {
+    let mut plugin_manager = plugins::PluginManager::new(config.max_concurrent_plugins);
+    plugin_manager.set_fallback_encoding(config.fallback_encoding()?);
+    plugin_manager.set_timeout(std::time::Duration::from_secs(config.plugin_timeout_secs));
+    plugin_manager.load_plugins()?;
+    Ok(plugin_manager)
+}
+
+/// `compose`: store what the user writes in `editor`, or reads from
+/// `stdin` without one, as a new clip and copy it to the clipboard.
+async fn compose(handles: &mut Handles, editor: Option<String>, stdin: &mut (dyn std::io::Read + Send), quiet: bool) -> Result<()> {
+    let text = match editor {
+        Some(editor) => editor::edit_text(&editor, "")?,
+        None => {
+            let mut bytes = Vec::new();
+            stdin.read_to_end(&mut bytes)?;
+            let decoded = clipboard::decode_text(bytes, load_config()?.fallback_encoding()?)
+                .ok_or_else(|| anyhow::anyhow!("stdin is not text in UTF-8 or the fallback_encoding"))?;
+            if let Some(encoding) = decoded.fallback {
+                eprintln!("stdin was not UTF-8; decoded it as {}", encoding);
+            }
+            decoded.text
+        }
+    };
+    // Editors end the file with a newline the user didn't type
+    let text = text.strip_suffix('\n').unwrap_or(&text);
+    if !clipboard::is_capturable(text) {
+        status!(quiet, "Nothing written; no clip stored");
+        return Ok(());
+    }
+
+    let mut db = handles.db().await?;
+    let clip_id = db.add_and_trim(text, "text", load_config()?.max_clips).await?;
+    handles.clipboard.set_text(text)?;
+    status!(quiet, "Stored clip {} and copied it to the clipboard", clip_id);
+    Ok(())
+}
+
+/// The `list --verbose` table of `clips`, fitted to `width` columns.
+async fn verbose_table(
+    db: &Database,
+    clips: &[Clip],
+    width: usize,
+    redact_patterns: &[regex::Regex],
+    now: chrono::DateTime<chrono::Utc>,
+) -> Result<String> {
+    let header = ["#", "ID", "AGE", "T", "SIZE", "TAGS", "CONTENT"];
+    let mut rows = Vec::with_capacity(clips.len());
+    for (i, clip) in clips.iter().enumerate() {
+        rows.push(vec![
+            (i + 1).to_string(),
+            clip.id.chars().take(8).collect(),
+            display::relative_time(clip.created_at, now),
+            display::type_glyph(&clip.clip_type).to_string(),
+            display::human_size(clip.content.len()),
+            db.get_clip_tags(&clip.id).await?.join(","),
+        ]);
+    }
+    // The content column gets whatever the other columns leave
+    let content_width = display::preview_width(width, display::table_prefix_width(&header, &rows));
+    for (row, clip) in rows.iter_mut().zip(clips) {
+        row.push(if clip.is_stale() {
+            let marker = "[missing] ";
+            format!("{}{}", marker, clip.redacted_preview(content_width.saturating_sub(marker.len()), redact_patterns))
+        } else {
+            clip.redacted_preview(content_width, redact_patterns)
+        });
+    }
+    Ok(display::table(&header, &rows))
+}
+
+fn redact_patterns(config: &Config, no_redact: bool) -> Result<Vec<regex::Regex>> {
+    if no_redact {
+        return Ok(Vec::new());
+    }
+    config.redact_regexes()
+}
+
+/// Resolve a clip argument to a clip ID. In order of precedence:
+/// - `^N` is the Nth most recent clip and `^-N` the Nth oldest; it never
+///   falls back to an id lookup
+/// - a plain number is a 1-based index into recent history, unless it is
+///   out of range
+/// - a full clip id
+/// - a unique prefix of one (as shown by `list --verbose`)
+async fn resolve_clip_id(db: &Database, clip: &str) -> Result<String> {
+    if let Some(relative) = clip.strip_prefix('^') {
+        return resolve_relative_index(db, relative).await;
+    }
+
+    let index = clip.parse::<usize>().ok();
+    if let Some(index) = index {
+        let clips = db.get_recent_clips(index).await?;
+        if index > 0 && index <= clips.len() {
+            return Ok(clips[index - 1].id.clone());
+        }
+    }
+
+    if db.get_clip_by_id(clip).await?.is_some() {
+        return Ok(clip.to_string());
+    }
+
+    match db.resolve_id_prefix(clip).await? {
+        IdResolution::Unique(id) => Ok(id),
+        IdResolution::Ambiguous(ids) => Err(anyhow::anyhow!(
+            "Clip id prefix '{}' is ambiguous; it matches {}",
+            clip,
+            ids.join(", ")
+        )),
+        IdResolution::NotFound if index.is_some() => {
+            Err(anyhow::anyhow!("Invalid clip index: {}", clip))
+        }
+        IdResolution::NotFound => Err(anyhow::anyhow!("No clip matches '{}'", clip)),
+    }
+}
+
+/// The clip `^N` or `^-N` refers to; `relative` is the part after `^`.
+async fn resolve_relative_index(db: &Database, relative: &str) -> Result<String> {
+    let (from_oldest, n) = match relative.strip_prefix('-') {
+        Some(n) => (true, n),
+        None => (false, relative),
+    };
+    let n = n
+        .parse::<usize>()
+        .ok()
+        .filter(|&n| n > 0)
+        .ok_or_else(|| anyhow::anyhow!("Invalid relative index '^{}'; use ^N or ^-N with N >= 1", relative))?;
+
+    let clip = if from_oldest {
+        let clips = db.get_all_clips().await?;
+        clips.len().checked_sub(n).map(|i| clips[i].id.clone())
+    } else {
+        db.get_recent_clips(n).await?.get(n - 1).map(|clip| clip.id.clone())
+    };
+    clip.ok_or_else(|| anyhow::anyhow!("No clip at ^{}; history is shorter than that", relative))
+}
+
+/// One line typed at `clipq repl`: any clipq command, without `clipq`.
+#[derive(Parser)]
+#[command(name = "clipq", no_binary_name = true, disable_version_flag = true)]
+struct ReplLine {
+    #[command(subcommand)]
+    command: Commands,
+}
+
+/// `clipq repl`: read commands until `quit` or end of input and run each
+/// like the same `clipq` command line would, all against `handles`. Line
+/// history is kept in ~/.clipq/repl_history.
+async fn run_repl(handles: &mut Handles, quiet: bool) -> Result<()> {
+    use rustyline::error::ReadlineError;
+
+    // Report a database that can't be opened once, not on every line
+    handles.db().await?;
+    let mut editor = rustyline::DefaultEditor::new()?;
+    let history_path = dirs::home_dir().map(|home| home.join(".clipq").join("repl_history"));
+    if let Some(path) = &history_path {
+        // Missing on first run
+        let _ = editor.load_history(path);
+    }
+
+    let result = run_repl_lines(handles, quiet, || loop {
+        match editor.readline("clipq> ") {
+            Ok(line) => {
+                if !line.trim().is_empty() {
+                    editor.add_history_entry(line.trim())?;
+                }
+                return Ok(Some(line));
+            }
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => return Ok(None),
+            Err(e) => return Err(e.into()),
+        }
+    })
+    .await;
+
+    if let Some(path) = &history_path {
+        editor.save_history(path)?;
+    }
+    result
+}
+
+/// Run each line `read_line` returns until `quit`, `exit` or `None`.
+async fn run_repl_lines(
+    handles: &mut Handles,
+    quiet: bool,
+    mut read_line: impl FnMut() -> Result<Option<String>>,
+) -> Result<()> {
+    while let Some(line) = read_line()? {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        if matches!(line, "quit" | "exit") {
+            break;
+        }
+
+        // A bad or failed command shouldn't end the session
+        let command = match parse_repl_line(line) {
+            Ok(command) => command,
+            Err(e) => {
+                match e.downcast::<clap::Error>() {
+                    // Includes `help` and `--help`, which clap reports as errors
+                    Ok(e) => e.print()?,
+                    Err(e) => eprintln!("Error: {:#}", e),
+                }
+                continue;
+            }
+        };
+        if let Err(e) = Box::pin(run_command(command, handles, quiet)).await {
+            eprintln!("Error: {:#}", e);
+        }
+    }
+    Ok(())
+}
+
+/// The command a REPL line names, split like a shell would split it.
+fn parse_repl_line(line: &str) -> Result<Commands> {
+    let words = shell_words::split(line)?;
+    let command = ReplLine::try_parse_from(words)?.command;
+    if matches!(command, Commands::Repl) {
+        anyhow::bail!("Already in the REPL");
+    }
+    Ok(command)
+}
+
+/// A `clipq tail` line, previewing the clip's current content when it
+/// still exists.
+async fn render_event_line(db: &Database, record: &ClipEventRecord, redact_patterns: &[regex::Regex], width: usize) -> Result<String> {
+    // Fixed columns: time, kind and short id with their separators
+    const PREFIX_WIDTH: usize = 19 + 2 + 7 + 2 + 8 + 2;
+    let preview = match &record.event {
+        ClipEvent::Added { clip_id } | ClipEvent::Edited { clip_id } => db
+            .get_clip_by_id(clip_id)
+            .await?
+            .map(|clip| clip.redacted_preview(display::preview_width(width, PREFIX_WIDTH), redact_patterns)),
+        _ => None,
+    };
+    Ok(display::render_event(record, preview.as_deref()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clipq::database::TagNameError;
+
+    #[test]
+    fn repl_lines_parse_like_command_lines() {
+        let command = parse_repl_line("copy 2 --restore-on 5").unwrap();
+        assert!(matches!(command, Commands::Copy { clip, restore_on: Some(5) } if clip == "2"));
+
+        let command = parse_repl_line(r#"tag 1 "work notes""#).unwrap();
+        assert!(matches!(command, Commands::Tag { clip, tag, force: false } if clip == "1" && tag == "work notes"));
+    }
+
+    #[test]
+    fn exports_round_trip_through_the_standard_streams() {
+        let clip = Clip {
+            id: "a".to_string(),
+            content: "piped, with a comma".to_string(),
+            clip_type: "text".to_string(),
+            created_at: "2024-05-01T12:00:00Z".parse().unwrap(),
+            file_path: None,
+            pinned: false,
+            expires_at: None,
+            mime: "text/plain".to_string(),
+            original_name: None,
+        };
+        let clips = vec![TaggedClip { clip, tags: vec!["pipe".to_string()], data: None }];
+        let json = serde_json::to_string_pretty(&clips).unwrap();
+
+        let mut stdout = Vec::new();
+        write_output_to("-", json.as_bytes(), &mut stdout).unwrap();
+        let read = read_input_from("-", &mut stdout.as_slice()).unwrap();
+        let imported = export::open(&String::from_utf8(read).unwrap(), None, true).unwrap();
+        assert_eq!(imported.len(), 1);
+        assert_eq!(imported[0].clip.content, "piped, with a comma");
+        assert_eq!(imported[0].tags, vec!["pipe"]);
+    }
+
+    #[test]
+    fn file_paths_bypass_the_standard_streams() {
+        let path = std::env::temp_dir().join(format!("clipq-export-{}.json", uuid::Uuid::new_v4()));
+        let path = path.to_str().unwrap();
+        let mut stdout = Vec::new();
+        write_output_to(path, b"[]", &mut stdout).unwrap();
+        assert!(stdout.is_empty());
+
+        let mut stdin: &[u8] = b"ignored";
+        assert_eq!(read_input_from(path, &mut stdin).unwrap(), b"[]");
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn bad_repl_lines_are_errors() {
+        let error = parse_repl_line("frobnicate").err().unwrap();
+        assert!(error.downcast_ref::<clap::Error>().is_some());
+        assert!(parse_repl_line("tag 1 \"unclosed").is_err());
+        assert_eq!(parse_repl_line("repl").err().unwrap().to_string(), "Already in the REPL");
+    }
+
+    thread_local! {
+        /// What `status!` printed on this thread
+        pub(super) static STATUS_LINES: std::cell::RefCell<Vec<String>> = const { std::cell::RefCell::new(Vec::new()) };
+    }
+
+    /// Handles on an in-memory database and clipboard.
+    async fn memory_handles() -> (Handles, Arc<std::sync::Mutex<Option<String>>>) {
+        let contents = Arc::new(std::sync::Mutex::new(None));
+        let db = Database::open(":memory:").await.unwrap();
+        let handles = Handles::new(db, clipboard::ClipboardManager::in_memory(Arc::clone(&contents)));
+        (handles, contents)
+    }
+
+    #[tokio::test]
+    async fn a_scripted_repl_session_runs_against_one_database() {
+        let (mut handles, clipboard) = memory_handles().await;
+        let script = "add \"first clip\"\ntag 1 work\n\nfrobnicate\nadd second\nclear --newest 1 --yes\nquit\nadd \"after quit\"\n";
+        let mut lines = std::io::BufRead::lines(script.as_bytes());
+
+        run_repl_lines(&mut handles, true, || Ok(lines.next().transpose()?)).await.unwrap();
+
+        let db = handles.db().await.unwrap();
+        let clips = db.get_all_clips().await.unwrap();
+        assert_eq!(clips.iter().map(|clip| clip.content.as_str()).collect::<Vec<_>>(), vec!["first clip"]);
+        assert_eq!(db.get_clip_tags(&clips[0].id).await.unwrap(), vec!["work"]);
+        assert_eq!(clipboard.lock().unwrap().as_deref(), Some("second"));
+    }
+
+    #[tokio::test]
+    async fn snapshots_store_the_clipboard_tagged_with_their_label() {
+        let (mut handles, clipboard) = memory_handles().await;
+        *clipboard.lock().unwrap() = Some("before the deploy".to_string());
+
+        let bad_label = Commands::Snapshot { label: " ".to_string() };
+        let error = run_command(bad_label, &mut handles, true).await.unwrap_err();
+        assert!(error.is::<TagNameError>(), "{}", error);
+        assert!(handles.db().await.unwrap().get_all_clips().await.unwrap().is_empty());
+
+        let snapshot = Commands::Snapshot { label: "pre-deploy".to_string() };
+        run_command(snapshot, &mut handles, true).await.unwrap();
+        let db = handles.db().await.unwrap();
+        let clips = db.get_all_clips().await.unwrap();
+        assert_eq!(clips.len(), 1);
+        assert_eq!(clips[0].content, "before the deploy");
+        assert_eq!(db.get_clip_tags(&clips[0].id).await.unwrap(), vec!["pre-deploy"]);
+    }
+
+    #[tokio::test]
+    async fn add_stores_the_clip_with_its_expiry() {
+        let (mut handles, clipboard) = memory_handles().await;
+        let add = Commands::Add { text: "temporary".to_string(), expire_in: Some("1h".to_string()) };
+        run_command(add, &mut handles, true).await.unwrap();
+
+        assert_eq!(clipboard.lock().unwrap().as_deref(), Some("temporary"));
+        let clips = handles.db().await.unwrap().get_all_clips().await.unwrap();
+        assert_eq!(clips.len(), 1);
+        let expires_at = clips[0].expires_at.expect("the expiry was stored with the clip");
+        let remaining = expires_at - chrono::Utc::now();
+        assert!(remaining > chrono::Duration::minutes(59) && remaining <= chrono::Duration::hours(1), "{}", remaining);
+    }
+
+    #[tokio::test]
+    async fn quiet_adds_print_nothing() {
+        let (mut handles, clipboard) = memory_handles().await;
+        let add = |text: &str| Commands::Add { text: text.to_string(), expire_in: None };
+
+        run_command(add("loud"), &mut handles, false).await.unwrap();
+        assert_eq!(STATUS_LINES.take(), vec!["Added to clipboard: loud"]);
+
+        run_command(add("hushed"), &mut handles, true).await.unwrap();
+        assert!(STATUS_LINES.take().is_empty());
+        assert_eq!(clipboard.lock().unwrap().as_deref(), Some("hushed"));
+        assert_eq!(handles.db().await.unwrap().get_all_clips().await.unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn verbose_lists_align_every_column() {
+        let (mut handles, _clipboard) = memory_handles().await;
+        let mut db = handles.db().await.unwrap();
+        let id = db.add_clip("deploy notes for friday", "text").await.unwrap();
+        db.add_tag_to_clip(&id, "work").await.unwrap();
+        let clips = db.get_recent_clips(10).await.unwrap();
+        let now = clips[0].created_at + chrono::Duration::minutes(5);
+
+        let table = verbose_table(&db, &clips, 80, &[], now).await.unwrap();
+        let lines: Vec<&str> = table.lines().collect();
+        assert_eq!(lines.len(), 2, "{}", table);
+        let expected = ["1", &id[..8], "5m ago", "T", "23 B", "work", "deploy notes for friday"];
+        let header = ["#", "ID", "AGE", "T", "SIZE", "TAGS", "CONTENT"];
+        for (name, value) in header.iter().zip(expected) {
+            let column = lines[0].find(&format!(" {}", name)).map_or(0, |at| at + 1);
+            assert_eq!(&lines[1][column..column + value.len()], value, "{} column in\n{}", name, table);
+        }
+    }
+
+    #[tokio::test]
+    async fn last_prints_nothing_for_an_empty_history() {
+        let (mut handles, _clipboard) = memory_handles().await;
+        let mut db = handles.db().await.unwrap();
+        for json in [false, true] {
+            let mut stdout = Vec::new();
+            write_last(&db.get_recent_clips(1).await.unwrap(), json, &mut stdout).unwrap();
+            assert!(stdout.is_empty(), "json: {}", json);
+        }
+
+        db.add_clip("just this", "text").await.unwrap();
+        let mut stdout = Vec::new();
+        write_last(&db.get_recent_clips(1).await.unwrap(), false, &mut stdout).unwrap();
+        assert_eq!(String::from_utf8(stdout).unwrap(), "just this", "no decoration, not even a newline");
+    }
+
+    #[test]
+    fn listing_limits_come_from_the_config_unless_passed() {
+        let path = std::env::temp_dir().join(format!("clipq-limits-{}.toml", uuid::Uuid::new_v4()));
+        std::fs::write(&path, "default_list_limit = 7\ndefault_pick_limit = 9\ndefault_search_limit = 3\n").unwrap();
+        let config = Config::load(path.to_str().unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        // What each arm resolves its limit to
+        let limit = |line: &str| match parse_repl_line(line).unwrap() {
+            Commands::List { limit, .. } => limit.unwrap_or(config.default_list_limit),
+            Commands::Pick { limit, .. } => limit.unwrap_or(config.default_pick_limit),
+            Commands::Search { limit, .. } => limit.unwrap_or(config.default_search_limit),
+            _ => unreachable!(),
+        };
+        assert_eq!(limit("list"), 7);
+        assert_eq!(limit("pick"), 9);
+        assert_eq!(limit("search needle"), 3);
+        assert_eq!(limit("list --limit 40"), 40);
+        assert_eq!(limit("pick -l 1"), 1);
+        assert_eq!(limit("search needle --limit 12"), 12);
+    }
+
+    #[tokio::test]
+    async fn ndjson_exports_hold_one_tagged_clip_per_line() {
+        let (mut handles, _clipboard) = memory_handles().await;
+        {
+            let mut db = handles.db().await.unwrap();
+            let id = db.add_clip("line one\nline two", "text").await.unwrap();
+            db.add_tag_to_clip(&id, "multi").await.unwrap();
+            db.add_clip("{\"looks\": \"like json\"}", "text").await.unwrap();
+        }
+        let path = std::env::temp_dir().join(format!("clipq-export-{}.ndjson", uuid::Uuid::new_v4()));
+        let export = parse_repl_line(&format!("export --format ndjson --output '{}'", path.display())).unwrap();
+        run_command(export, &mut handles, true).await.unwrap();
+
+        let written = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let mut clips: Vec<TaggedClip> = written.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        clips.sort_by(|a, b| a.clip.content.cmp(&b.clip.content));
+        assert_eq!(clips.len(), 2, "{}", written);
+        assert_eq!(clips[0].clip.content, "line one\nline two");
+        assert_eq!(clips[0].tags, vec!["multi"]);
+        assert_eq!(clips[1].clip.content, "{\"looks\": \"like json\"}");
+        assert!(clips[1].tags.is_empty());
+    }
+
+    #[tokio::test]
+    async fn add_files_stores_text_files_tagged_with_their_names() {
+        let dir = std::env::temp_dir().join(format!("clipq-add-files-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        std::fs::write(dir.join("notes.md"), "# Notes\n").unwrap();
+        std::fs::write(dir.join("todo.txt"), "buy milk").unwrap();
+        std::fs::write(dir.join("logo.png"), [0x89, b'P', b'N', b'G', 0, 0, 0xff, 0xfe]).unwrap();
+        let (mut handles, _clipboard) = memory_handles().await;
+
+        let pattern = dir.join("*").to_string_lossy().into_owned();
+        run_command(Commands::AddFiles { patterns: vec![pattern] }, &mut handles, true).await.unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let db = handles.db().await.unwrap();
+        let mut added = Vec::new();
+        for clip in db.get_all_clips().await.unwrap() {
+            added.push((clip.content.clone(), db.get_clip_tags(&clip.id).await.unwrap()));
+        }
+        added.sort();
+        assert_eq!(
+            added,
+            vec![
+                ("# Notes\n".to_string(), vec!["notes.md".to_string()]),
+                ("buy milk".to_string(), vec!["todo.txt".to_string()]),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn compose_without_an_editor_stores_stdin() {
+        let (mut handles, clipboard) = memory_handles().await;
+        compose(&mut handles, None, &mut "   \n".as_bytes(), true).await.unwrap();
+        assert!(handles.db().await.unwrap().get_all_clips().await.unwrap().is_empty());
+
+        compose(&mut handles, None, &mut "Dear team,\n\nthanks!\n".as_bytes(), true).await.unwrap();
+        let clips = handles.db().await.unwrap().get_all_clips().await.unwrap();
+        assert_eq!(clips.len(), 1);
+        assert_eq!(clips[0].content, "Dear team,\n\nthanks!");
+        assert_eq!(clipboard.lock().unwrap().as_deref(), Some("Dear team,\n\nthanks!"));
+    }
+
+    #[tokio::test]
+    async fn read_only_commands_work_without_a_clipboard() {
+        let db = Database::open(":memory:").await.unwrap();
+        let mut handles = Handles::new(db, clipboard::ClipboardManager::unavailable("no display"));
+        handles.db().await.unwrap().add_clip("stored earlier", "text").await.unwrap();
+
+        for line in ["list", "list --verbose", "search stored", "last", "show 1", "stats"] {
+            let command = parse_repl_line(line).unwrap();
+            run_command(command, &mut handles, true).await.unwrap_or_else(|e| panic!("{}: {}", line, e));
+        }
+
+        let error = run_command(parse_repl_line("copy 1").unwrap(), &mut handles, true).await.unwrap_err();
+        assert!(error.is::<clipboard::NoClipboard>(), "{}", error);
+    }
+
+    #[tokio::test]
+    async fn clear_without_yes_refuses_to_run_unattended() {
+        let (mut handles, _clipboard) = memory_handles().await;
+        for i in 0..3 {
+            handles.db().await.unwrap().add_clip(&format!("clip {}", i), "text").await.unwrap();
+        }
+
+        for line in ["clear", "clear --oldest 2", "clear --newest 1"] {
+            let error = run_command(parse_repl_line(line).unwrap(), &mut handles, true).await.unwrap_err();
+            assert!(error.to_string().contains("pass --yes to confirm"), "{}: {}", line, error);
+        }
+        assert_eq!(handles.db().await.unwrap().get_all_clips().await.unwrap().len(), 3);
+
+        run_command(parse_repl_line("clear --yes").unwrap(), &mut handles, true).await.unwrap();
+        assert!(handles.db().await.unwrap().get_all_clips().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn formatting_as_json_updates_the_clip_in_place() {
+        let (mut handles, _clipboard) = memory_handles().await;
+        let id = handles.db().await.unwrap().add_clip(r#"{"b":[1,2],"a":null}"#, "text").await.unwrap();
+        let not_json = handles.db().await.unwrap().add_clip("{oops", "text").await.unwrap();
+
+        run_command(parse_repl_line(&format!("format {} --as json", id)).unwrap(), &mut handles, true).await.unwrap();
+        let error = run_command(parse_repl_line(&format!("format {} --as json", not_json)).unwrap(), &mut handles, true)
+            .await
+            .unwrap_err();
+        assert!(error.to_string().starts_with("Cannot format clip"), "{}", error);
+
+        let db = handles.db().await.unwrap();
+        let clip = db.get_clip_by_id(&id).await.unwrap().unwrap();
+        let expected = serde_json::to_string_pretty(&serde_json::json!({"b": [1, 2], "a": null})).unwrap();
+        assert_eq!(clip.content, expected);
+        assert!(clip.content.contains("\n    1,\n"), "{}", clip.content);
+        assert_eq!(db.get_all_clips().await.unwrap().len(), 2, "updated, not added");
+        assert_eq!(db.get_clip_by_id(&not_json).await.unwrap().unwrap().content, "{oops");
+    }
+
+    #[tokio::test]
+    async fn caret_indexes_count_from_the_newest_clip() {
+        let path = std::env::temp_dir().join(format!("clipq-relative-{}.db", uuid::Uuid::new_v4()));
+        let path = path.to_str().unwrap();
+        let mut db = Database::open(path).await.unwrap();
+        let mut ids = Vec::new();
+        for content in ["oldest", "middle", "newest"] {
+            ids.push(db.add_clip(content, "text").await.unwrap());
+        }
+        // Spread the clips a minute apart so their order is unambiguous
+        let conn = rusqlite::Connection::open(path).unwrap();
+        for (minutes, id) in ids.iter().enumerate() {
+            let at = 1_700_000_000 + 60 * minutes as i64;
+            conn.execute("UPDATE clips SET created_at = ?1, created_at_ms = ?1 * 1000 WHERE id = ?2", rusqlite::params![at, id])
+                .unwrap();
+        }
+
+        assert_eq!(resolve_clip_id(&db, "^1").await.unwrap(), ids[2]);
+        assert_eq!(resolve_clip_id(&db, "^3").await.unwrap(), ids[0]);
+        assert_eq!(resolve_clip_id(&db, "^-1").await.unwrap(), ids[0]);
+        let error = resolve_clip_id(&db, "^99").await.unwrap_err().to_string();
+        assert_eq!(error, "No clip at ^99; history is shorter than that");
+        for bad in ["^0", "^", "^x"] {
+            assert!(resolve_clip_id(&db, bad).await.unwrap_err().to_string().starts_with("Invalid relative index"));
+        }
+        drop(conn);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn stable_exports_are_byte_identical_in_insertion_order() {
+        let (mut handles, _clipboard) = memory_handles().await;
+        {
+            // All within the same second, so created_at can't order them
+            let mut db = handles.db().await.unwrap();
+            for i in 0..20 {
+                let id = db.add_clip(&format!("clip {:02}", i), "text").await.unwrap();
+                db.add_tag_to_clip(&id, if i % 2 == 0 { "even" } else { "odd" }).await.unwrap();
+            }
+        }
+
+        for format in ["json", "ndjson"] {
+            let mut exports = Vec::new();
+            for _ in 0..2 {
+                let path = std::env::temp_dir().join(format!("clipq-stable-{}.{}", uuid::Uuid::new_v4(), format));
+                let export = parse_repl_line(&format!("export --stable --format {} --output '{}'", format, path.display())).unwrap();
+                run_command(export, &mut handles, true).await.unwrap();
+                exports.push(std::fs::read(&path).unwrap());
+                std::fs::remove_file(&path).unwrap();
+            }
+            assert_eq!(exports[0], exports[1], "{} exports differ", format);
+
+            let text = String::from_utf8(exports.swap_remove(0)).unwrap();
+            let clips: Vec<TaggedClip> = if format == "json" {
+                serde_json::from_str(&text).unwrap()
+            } else {
+                text.lines().map(|line| serde_json::from_str(line).unwrap()).collect()
+            };
+            let contents: Vec<String> = clips.into_iter().map(|clip| clip.clip.content).collect();
+            let expected: Vec<String> = (0..20).map(|i| format!("clip {:02}", i)).collect();
+            assert_eq!(contents, expected, "{} export is not in insertion order", format);
+        }
+    }
+}
@@ -0,0 +1,90 @@
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Counters exposed by the web server's `GET /metrics`. Shared through an
+/// `Arc` by everything in the process that adds clips or runs plugins.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    clips_added: AtomicU64,
+    plugin_runs: AtomicU64,
+    plugin_failures: AtomicU64,
+}
+
+impl Metrics {
+    pub fn record_clip_added(&self) {
+        self.clips_added.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_plugin_run(&self, succeeded: bool) {
+        self.plugin_runs.fetch_add(1, Ordering::Relaxed);
+        if !succeeded {
+            self.plugin_failures.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// The counters plus the given database gauges in Prometheus text
+    /// exposition format.
+    pub fn render(&self, total_clips: usize, db_size_bytes: u64) -> String {
+        let mut output = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, value: u64| {
+            let _ = writeln!(output, "# HELP {} {}", name, help);
+            let _ = writeln!(output, "# TYPE {} {}", name, kind);
+            let _ = writeln!(output, "{} {}", name, value);
+        };
+
+        metric("clipq_clips", "gauge", "Clips currently stored.", total_clips as u64);
+        metric(
+            "clipq_clips_added_total",
+            "counter",
+            "Clips added since this process started.",
+            self.clips_added.load(Ordering::Relaxed),
+        );
+        metric(
+            "clipq_plugin_executions_total",
+            "counter",
+            "Plugin runs since this process started.",
+            self.plugin_runs.load(Ordering::Relaxed),
+        );
+        metric(
+            "clipq_plugin_failures_total",
+            "counter",
+            "Plugin runs that failed since this process started.",
+            self.plugin_failures.load(Ordering::Relaxed),
+        );
+        metric("clipq_database_size_bytes", "gauge", "Size of the history database.", db_size_bytes);
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_the_exposition_format() {
+        let metrics = Metrics::default();
+        metrics.record_clip_added();
+        metrics.record_clip_added();
+        metrics.record_plugin_run(true);
+        metrics.record_plugin_run(false);
+
+        let output = metrics.render(7, 4096);
+        for (name, kind, value) in [
+            ("clipq_clips", "gauge", 7),
+            ("clipq_clips_added_total", "counter", 2),
+            ("clipq_plugin_executions_total", "counter", 2),
+            ("clipq_plugin_failures_total", "counter", 1),
+            ("clipq_database_size_bytes", "gauge", 4096),
+        ] {
+            let lines: Vec<&str> = output
+                .lines()
+                .skip_while(|line| *line != format!("# TYPE {} {}", name, kind))
+                .take(2)
+                .collect();
+            assert_eq!(lines.len(), 2, "no TYPE line for {} in\n{}", name, output);
+            assert_eq!(lines[1], format!("{} {}", name, value));
+            assert!(output.contains(&format!("# HELP {} ", name)));
+        }
+        assert!(output.ends_with('\n'));
+    }
+}
@@ -0,0 +1,139 @@
+use crate::database::Clip;
+
+/// Neighbours in length order each clip is compared with. Bounds the work
+/// on huge histories to O(n log n + n * window).
+const COMPARISON_WINDOW: usize = 64;
+
+/// Normalized clips longer than this are only grouped when identical, as
+/// edit distance on them is too slow.
+const MAX_EDIT_DISTANCE_CHARS: usize = 4096;
+
+/// Groups of indexes into `clips` whose contents are at least `threshold`
+/// similar (normalized edit distance, 1.0 = identical) once whitespace at
+/// line ends and in runs is ignored. Each group holds two or more clips in
+/// their original order; groups are ordered by their first clip.
+///
+/// Only clips close in length are compared, since a similar pair can't
+/// differ in length by more than `1 - threshold` of the longer one.
+pub fn group_near_duplicates(clips: &[Clip], threshold: f64) -> Vec<Vec<usize>> {
+    let normalized: Vec<String> = clips.iter().map(|clip| normalize(&clip.content)).collect();
+    let lengths: Vec<usize> = normalized.iter().map(|text| text.chars().count()).collect();
+    let mut by_length: Vec<usize> = (0..clips.len()).collect();
+    by_length.sort_by_key(|&i| lengths[i]);
+
+    let mut groups = UnionFind::new(clips.len());
+    for (position, &a) in by_length.iter().enumerate() {
+        for &b in by_length[position + 1..].iter().take(COMPARISON_WINDOW) {
+            let (shorter, longer) = (lengths[a], lengths[b]);
+            if longer > 0 && (longer - shorter) as f64 / longer as f64 > 1.0 - threshold {
+                // Sorted by length, so every later clip is even further off
+                break;
+            }
+            if similarity(&normalized[a], &normalized[b], longer) >= threshold {
+                groups.union(a, b);
+            }
+        }
+    }
+    groups.sets()
+}
+
+/// Trim each line's trailing whitespace and collapse runs of spaces and tabs.
+fn normalize(text: &str) -> String {
+    text.trim()
+        .lines()
+        .map(|line| line.split([' ', '\t']).filter(|word| !word.is_empty()).collect::<Vec<_>>().join(" "))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Similarity of `a` and `b`, the longer of which is `longer` chars.
+fn similarity(a: &str, b: &str, longer: usize) -> f64 {
+    if a == b {
+        return 1.0;
+    }
+    if longer > MAX_EDIT_DISTANCE_CHARS {
+        return 0.0;
+    }
+    1.0 - strsim::levenshtein(a, b) as f64 / longer as f64
+}
+
+struct UnionFind {
+    parents: Vec<usize>,
+}
+
+impl UnionFind {
+    fn new(size: usize) -> Self {
+        Self {
+            parents: (0..size).collect(),
+        }
+    }
+
+    fn find(&mut self, mut i: usize) -> usize {
+        while self.parents[i] != i {
+            self.parents[i] = self.parents[self.parents[i]];
+            i = self.parents[i];
+        }
+        i
+    }
+
+    fn union(&mut self, a: usize, b: usize) {
+        let (a, b) = (self.find(a), self.find(b));
+        if a != b {
+            self.parents[a.max(b)] = a.min(b);
+        }
+    }
+
+    /// Sets with more than one member, each sorted, ordered by first member.
+    fn sets(mut self) -> Vec<Vec<usize>> {
+        let mut members: Vec<Vec<usize>> = vec![Vec::new(); self.parents.len()];
+        for i in 0..self.parents.len() {
+            let root = self.find(i);
+            members[root].push(i);
+        }
+        members.into_iter().filter(|set| set.len() > 1).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn clips(contents: &[&str]) -> Vec<Clip> {
+        contents
+            .iter()
+            .enumerate()
+            .map(|(i, content)| Clip {
+                id: i.to_string(),
+                content: content.to_string(),
+                clip_type: "text".to_string(),
+                created_at: chrono::Utc::now(),
+                file_path: None,
+                pinned: false,
+                expires_at: None,
+                mime: "text/plain".to_string(),
+                original_name: None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn clips_differing_in_whitespace_or_a_character_are_grouped() {
+        let clips = clips(&[
+            "SELECT * FROM users WHERE id = 42",
+            "the quick brown fox",
+            "SELECT * FROM users WHERE id = 42   \n",
+            "an unrelated note about lunch",
+            "fn main() {\n    run();  \n}",
+            "fn main() {\n    run();\n}\n\n",
+            "the quick brown fox!",
+            "",
+            "",
+        ]);
+
+        let groups = group_near_duplicates(&clips, 0.9);
+        assert_eq!(groups, vec![vec![0, 2], vec![1, 6], vec![4, 5], vec![7, 8]]);
+        // A stricter threshold only groups what is equal once normalized
+        assert_eq!(group_near_duplicates(&clips, 1.0), vec![vec![0, 2], vec![4, 5], vec![7, 8]]);
+        assert!(group_near_duplicates(&clips[..2], 0.9).is_empty());
+    }
+}
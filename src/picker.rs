@@ -1,51 +1,219 @@
 use anyhow::Result;
+use regex::Regex;
+use std::path::{Path, PathBuf};
 use std::process::Stdio;
+use std::sync::Arc;
 use tokio::process::Command as AsyncCommand;
+use uuid::Uuid;
 use which::which;
 
-use crate::database::Database;
+use crate::config::Config;
+use crate::database::{Clip, Database};
+use crate::emitted;
 
-pub async fn show_picker(db: &mut Database, limit: usize) -> Result<Option<String>> {
-    let clips = db.get_recent_clips(limit).await?;
+/// Narrows the clips offered by `show_picker`.
+#[derive(Debug, Clone, Default)]
+pub struct PickFilter {
+    /// Only clips carrying this tag
+    pub tag: Option<String>,
+    /// Only clips of this type, e.g. `text` or `file`
+    pub clip_type: Option<String>,
+}
+
+/// The newest `limit` clips matching `filter`, in the order the picker
+/// numbers them.
+pub async fn filtered_clips(db: &Database, limit: usize, filter: &PickFilter) -> Result<Vec<Clip>> {
+    let clips = match (&filter.tag, &filter.clip_type) {
+        (Some(tag), clip_type) => {
+            let mut clips = db.get_clips_by_tag(tag).await?;
+            if let Some(clip_type) = clip_type {
+                clips.retain(|clip| &clip.clip_type == clip_type);
+            }
+            clips.truncate(limit);
+            clips
+        }
+        (None, Some(clip_type)) => db.get_recent_clips_by_type(clip_type, limit).await?,
+        (None, None) => db.get_recent_clips(limit).await?,
+    };
+    Ok(clips)
+}
+
+pub async fn show_picker(
+    db: &mut Database,
+    limit: usize,
+    filter: &PickFilter,
+    redact_patterns: &[Regex],
+    config: &Config,
+) -> Result<Option<Clip>> {
+    let clips = filtered_clips(db, limit, filter).await?;
     
     if clips.is_empty() {
-        println!("No clipboard history found");
+        if filter.tag.is_some() || filter.clip_type.is_some() {
+            println!("No clips match the filter");
+        } else {
+            println!("No clipboard history found");
+        }
         return Ok(None);
     }
 
     // Try to find fzf or skim
     let picker_cmd = find_picker_command()?;
     
-    // Prepare input for the picker
-    let input = clips
-        .iter()
-        .enumerate()
-        .map(|(i, clip)| {
-            let preview = if clip.content.len() > 100 {
-                format!("{}...", &clip.content[..97])
-            } else {
-                clip.content.clone()
+    // Picker lines are built as they are written, so the picker can show
+    // the first clips while the rest are still on their way. Each line
+    // starts with a hidden, tab-separated field holding that clip's preview
+    // command.
+    let preview_dir = PreviewDir::create()?;
+    let clips = Arc::new(clips);
+    let entries = {
+        let clips = Arc::clone(&clips);
+        let redact_patterns = redact_patterns.to_vec();
+        let config = config.clone();
+        let preview_dir = preview_dir.path().to_path_buf();
+        (0..clips.len()).map(move |i| {
+            let clip = &clips[i];
+            let preview = clip.redacted_preview(100, &redact_patterns);
+            let command = match preview_template(&config, clip) {
+                Some(template) => render_preview_template(template, clip),
+                // Falls back to `clipq show` if the file can't be written
+                None => cached_preview_command(&preview_dir, i, clip).unwrap_or_else(|_| preview_command(&config, clip)),
             };
-            format!("{}: {}", i + 1, preview)
+            picker_entry(&command, i + 1, &preview)
         })
-        .collect::<Vec<_>>()
-        .join("\n");
+    };
 
-    let result = run_picker(&picker_cmd, &input).await?;
+    let result = tokio::select! {
+        result = run_picker(&picker_cmd, entries) => result?,
+        result = interrupted() => {
+            result?;
+            // Returning drops `preview_dir`, which removes it
+            anyhow::bail!("Interrupted");
+        }
+    };
     
-    if let Some(selected_line) = result {
-        // Extract the content from the selected line
-        if let Some(colon_pos) = selected_line.find(':') {
-            let index_str = &selected_line[..colon_pos];
-            if let Ok(index) = index_str.parse::<usize>() {
-                if index > 0 && index <= clips.len() {
-                    return Ok(Some(clips[index - 1].content.clone()));
-                }
-            }
+    // The lines as shown, in case one gets copied from the terminal
+    emitted::record_all(
+        clips
+            .iter()
+            .enumerate()
+            .map(|(i, clip)| format!("{}: {}", i + 1, clip.redacted_preview(100, redact_patterns))),
+    );
+
+    Ok(result.and_then(|selected_line| selected_clip(&clips, &selected_line).cloned()))
+}
+
+/// The picker line for the clip numbered `index`: its hidden preview
+/// `command`, a tab, then `index: preview`, all on one line.
+fn picker_entry(command: &str, index: usize, preview: &str) -> String {
+    format!("{}\t{}: {}", command.replace(['\t', '\n'], " "), index, preview.replace(['\r', '\n'], " "))
+}
+
+/// The clip a line returned by the picker names. The index prefix is the
+/// only part of the line we trust; the preview is collapsed and truncated,
+/// so the full content always comes from the clip.
+fn selected_clip<'a>(clips: &'a [Clip], selected_line: &str) -> Option<&'a Clip> {
+    let entry = selected_line.split_once('\t').map_or(selected_line, |(_, entry)| entry);
+    let index = parse_entry_index(entry)?;
+    index.checked_sub(1).and_then(|i| clips.get(i))
+}
+
+/// Resolves on Ctrl-C or, on Unix, SIGTERM. Waiting for them replaces the
+/// default handlers, which would end the process without running `Drop`.
+async fn interrupted() -> Result<()> {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut terminate = signal(SignalKind::terminate())?;
+        tokio::select! {
+            result = tokio::signal::ctrl_c() => result?,
+            _ = terminate.recv() => {}
         }
     }
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c().await?;
+    Ok(())
+}
+
+/// A temporary directory of clip contents for the preview pane, so showing
+/// a preview is a `cat` rather than a `clipq show` launch per row. It is
+/// removed when dropped, whether the picker was used, cancelled or
+/// interrupted by a signal.
+pub struct PreviewDir {
+    path: PathBuf,
+}
+
+impl PreviewDir {
+    /// Create a fresh directory, readable only by the current user.
+    pub fn create() -> Result<Self> {
+        let path = std::env::temp_dir().join(format!("clipq-preview-{}", Uuid::new_v4()));
+        let mut builder = std::fs::DirBuilder::new();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::DirBuilderExt;
+            builder.mode(0o700);
+        }
+        builder.create(&path)?;
+        Ok(Self { path })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for PreviewDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.path);
+    }
+}
+
+/// Write `clip`'s content to the preview directory as entry `index` and
+/// return the command that prints it.
+fn cached_preview_command(dir: &Path, index: usize, clip: &Clip) -> Result<String> {
+    let file = dir.join(index.to_string());
+    std::fs::write(&file, &clip.content)?;
+    Ok(format!("cat {}", shell_quote(&file.to_string_lossy())))
+}
+
+/// The configured preview template for `clip`'s type, if any.
+fn preview_template<'a>(config: &'a Config, clip: &Clip) -> Option<&'a str> {
+    match clip.clip_type.as_str() {
+        "file" => config.preview_file_cmd.as_deref(),
+        "image" => config.preview_image_cmd.as_deref(),
+        _ => config.preview_text_cmd.as_deref(),
+    }
+}
+
+/// The command the picker's preview pane runs for `clip`: the configured
+/// template for its type with `{id}` and `{path}` filled in, or `clipq show`.
+pub fn preview_command(config: &Config, clip: &Clip) -> String {
+    match preview_template(config, clip) {
+        Some(template) => render_preview_template(template, clip),
+        None => {
+            let exe = std::env::current_exe()
+                .map(|path| path.to_string_lossy().into_owned())
+                .unwrap_or_else(|_| "clipq".to_string());
+            format!("{} show {}", shell_quote(&exe), shell_quote(&clip.id))
+        }
+    }
+}
+
+/// Substitute `{id}` and `{path}` (the file path, or the content for
+/// non-file clips) into `template`, shell-quoted.
+pub fn render_preview_template(template: &str, clip: &Clip) -> String {
+    let path = clip.file_path.as_deref().unwrap_or(&clip.content);
+    template
+        .replace("{id}", &shell_quote(&clip.id))
+        .replace("{path}", &shell_quote(path))
+}
+
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
 
-    Ok(None)
+fn parse_entry_index(line: &str) -> Option<usize> {
+    let (index_str, _) = line.split_once(':')?;
+    index_str.trim().parse().ok()
 }
 
 fn find_picker_command() -> Result<String> {
@@ -72,16 +240,55 @@ fn find_picker_command() -> Result<String> {
     ))
 }
 
-async fn run_picker(cmd: &str, input: &str) -> Result<Option<String>> {
+/// Hide the leading preview-command field, keep it out of matching, and
+/// run it for the preview pane. fzf and skim both quote `{1}` before
+/// substituting it.
+const PREVIEW_ARGS: [&str; 8] = [
+    "--delimiter",
+    "\t",
+    "--with-nth",
+    "2..",
+    "--nth",
+    "2..",
+    "--preview",
+    "sh -c {1}",
+];
+
+/// Write `entries` to `writer` one per line. Buffered writes reach the
+/// picker in chunks as the buffer fills; dropping the writer afterwards
+/// closes stdin so the picker sees the end.
+async fn write_entries<W, I>(writer: W, entries: I) -> std::io::Result<()>
+where
+    W: tokio::io::AsyncWrite + Unpin,
+    I: Iterator<Item = String>,
+{
+    use tokio::io::AsyncWriteExt;
+    let mut writer = tokio::io::BufWriter::new(writer);
+    for entry in entries {
+        writer.write_all(entry.as_bytes()).await?;
+        writer.write_all(b"\n").await?;
+    }
+    writer.flush().await
+}
+
+/// Run the picker over `entries`, one per line, and return the line the
+/// user chose. Entries are streamed to the picker's stdin from a separate
+/// task while it is already running.
+async fn run_picker<I>(cmd: &str, entries: I) -> Result<Option<String>>
+where
+    I: Iterator<Item = String> + Send + 'static,
+{
     let mut command = match cmd {
         "fzf" => {
             let mut cmd = AsyncCommand::new("fzf");
-            cmd.args(&["--height", "40%", "--reverse", "--border"]);
+            cmd.args(["--height", "40%", "--reverse", "--border"]);
+            cmd.args(PREVIEW_ARGS);
             cmd
         }
         "sk" | "skim" => {
             let mut cmd = AsyncCommand::new(cmd);
-            cmd.args(&["--height", "40%", "--reverse", "--border"]);
+            cmd.args(["--height", "40%", "--reverse", "--border"]);
+            cmd.args(PREVIEW_ARGS);
             cmd
         }
         _ => {
@@ -92,17 +299,24 @@ async fn run_picker(cmd: &str, input: &str) -> Result<Option<String>> {
     command
         .stdin(Stdio::piped())
         .stdout(Stdio::piped())
-        .stderr(Stdio::piped());
+        .stderr(Stdio::piped())
+        // An interrupted pick abandons the picker
+        .kill_on_drop(true);
 
     let mut child = command.spawn()?;
     
-    // Write input to stdin
-    if let Some(mut stdin) = child.stdin.take() {
-        use tokio::io::AsyncWriteExt;
-        stdin.write_all(input.as_bytes()).await?;
-    }
+    let stdin = child
+        .stdin
+        .take()
+        .ok_or_else(|| anyhow::anyhow!("Picker stdin is not available"))?;
+    let writer = tokio::spawn(write_entries(stdin, entries));
 
     let output = child.wait_with_output().await?;
+    match writer.await? {
+        // The picker may exit before reading everything, e.g. on an early pick
+        Err(e) if e.kind() == std::io::ErrorKind::BrokenPipe => {}
+        result => result?,
+    }
     
     if output.status.success() {
         let result = String::from_utf8(output.stdout)?;
@@ -123,37 +337,168 @@ async fn run_picker(cmd: &str, input: &str) -> Result<Option<String>> {
 
 pub async fn show_simple_menu(db: &mut Database, limit: usize) -> Result<Option<String>> {
     let clips = db.get_recent_clips(limit).await?;
-    
+    simple_menu(&clips, &mut std::io::stdin().lock(), &mut std::io::stdout().lock())
+}
+
+/// `show_simple_menu` over `clips`, with `input` and `output` standing in
+/// for the standard streams.
+fn simple_menu(clips: &[Clip], input: &mut dyn std::io::BufRead, output: &mut dyn std::io::Write) -> Result<Option<String>> {
     if clips.is_empty() {
-        println!("No clipboard history found");
+        writeln!(output, "No clipboard history found")?;
         return Ok(None);
     }
 
-    println!("\nClipboard History:");
-    println!("==================");
+    writeln!(output, "\nClipboard History:")?;
+    writeln!(output, "==================")?;
     
     for (i, clip) in clips.iter().enumerate() {
-        let preview = if clip.content.len() > 80 {
-            format!("{}...", &clip.content[..77])
-        } else {
-            clip.content.clone()
-        };
-        println!("{}: {}", i + 1, preview);
+        writeln!(output, "{}: {}", i + 1, clip.preview(80))?;
     }
     
-    println!("\nEnter number to select (0 to cancel): ");
+    writeln!(output, "\nEnter number to select (0 to cancel): ")?;
+    output.flush()?;
     
-    use std::io::{self, Write};
-    io::stdout().flush()?;
+    let mut choice = String::new();
+    input.read_line(&mut choice)?;
     
-    let mut input = String::new();
-    io::stdin().read_line(&mut input)?;
-    
-    let choice: usize = input.trim().parse().unwrap_or(0);
+    let choice: usize = choice.trim().parse().unwrap_or(0);
     
     if choice == 0 || choice > clips.len() {
         Ok(None)
     } else {
         Ok(Some(clips[choice - 1].content.clone()))
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn clip(id: &str, content: &str, file_path: Option<&str>) -> Clip {
+        Clip {
+            id: id.to_string(),
+            content: content.to_string(),
+            clip_type: if file_path.is_some() { "file" } else { "text" }.to_string(),
+            created_at: chrono::Utc::now(),
+            file_path: file_path.map(str::to_string),
+            pinned: false,
+            expires_at: None,
+            mime: "text/plain".to_string(),
+            original_name: None,
+        }
+    }
+
+    #[test]
+    fn preview_templates_get_the_id_and_path_shell_quoted() {
+        let file = clip("abc123", "report.pdf", Some("/tmp/my report's.pdf"));
+        assert_eq!(
+            render_preview_template("bat {path} # {id}", &file),
+            r"bat '/tmp/my report'\''s.pdf' # 'abc123'"
+        );
+
+        // Text clips preview their content, which must not reach the shell unquoted
+        let text = clip("def456", "$(rm -rf ~); echo 'hi'", None);
+        let command = render_preview_template("printf %s {path}", &text);
+        let output = std::process::Command::new("sh").arg("-c").arg(&command).output().unwrap();
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), "$(rm -rf ~); echo 'hi'");
+    }
+
+    #[test]
+    fn preview_dirs_are_private_and_removed_on_drop() {
+        let dir = PreviewDir::create().unwrap();
+        let path = dir.path().to_path_buf();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o700);
+        }
+
+        let clip = clip("a", "it's secret", None);
+        let command = cached_preview_command(&path, 0, &clip).unwrap();
+        let output = std::process::Command::new("sh").arg("-c").arg(&command).output().unwrap();
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), "it's secret");
+
+        drop(dir);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn picking_a_multi_line_clip_returns_all_of_it() {
+        let content = "fn main() {\n    println!(\"12: not an index\");\n}\n";
+        let clips = vec![clip("a", "single line", None), clip("b", content, None)];
+
+        let entry = picker_entry("cat '/tmp/x\ty'", 2, &clips[1].preview(100));
+        assert_eq!(entry.lines().count(), 1, "{:?}", entry);
+        assert_eq!(entry.matches('\t').count(), 1, "{:?}", entry);
+
+        // The picker prints back the chosen line as given
+        assert_eq!(selected_clip(&clips, &entry).unwrap().content, content);
+        assert!(selected_clip(&clips, "cat x\t0: nothing").is_none());
+        assert!(selected_clip(&clips, "cat x\t3: nothing").is_none());
+    }
+
+    #[test]
+    fn simple_menu_entries_stay_on_one_line() {
+        let long_line = "word ".repeat(40);
+        let clips = vec![clip("a", "first\nsecond\r\n\tthird", None), clip("b", &long_line, None)];
+        let mut output = Vec::new();
+
+        let chosen = simple_menu(&clips, &mut "1\n".as_bytes(), &mut output).unwrap();
+        assert_eq!(chosen.as_deref(), Some("first\nsecond\r\n\tthird"));
+        let output = String::from_utf8(output).unwrap();
+        let entries: Vec<&str> = output.lines().filter(|line| line.starts_with(|c: char| c.is_ascii_digit())).collect();
+        assert_eq!(entries[0], "1: first second third");
+        assert!(entries[1].starts_with("2: word word") && entries[1].ends_with("..."), "{}", entries[1]);
+        assert_eq!(entries.len(), 2, "{}", output);
+
+        assert_eq!(simple_menu(&clips, &mut "0\n".as_bytes(), &mut Vec::new()).unwrap(), None);
+        assert_eq!(simple_menu(&clips, &mut "3\n".as_bytes(), &mut Vec::new()).unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn picks_can_be_limited_to_a_tag_and_type() {
+        let mut db = Database::open(":memory:").await.unwrap();
+        for (content, tag) in [("deploy steps", Some("work")), ("groceries", None), ("standup notes", Some("work"))] {
+            let id = db.add_clip(content, "text").await.unwrap();
+            if let Some(tag) = tag {
+                db.add_tag_to_clip(&id, tag).await.unwrap();
+            }
+        }
+        let file = db.add_file_clip("/tmp/work-report.pdf").await.unwrap();
+        db.add_tag_to_clip(&file, "work").await.unwrap();
+        let offered = |clips: Vec<Clip>| {
+            let mut contents: Vec<String> = clips.into_iter().map(|clip| clip.content).collect();
+            contents.sort();
+            contents
+        };
+
+        let work = PickFilter { tag: Some("work".to_string()), clip_type: None };
+        assert_eq!(offered(filtered_clips(&db, 10, &work).await.unwrap()).len(), 3);
+        let work_text = PickFilter { clip_type: Some("text".to_string()), ..work.clone() };
+        assert_eq!(offered(filtered_clips(&db, 10, &work_text).await.unwrap()), vec!["deploy steps", "standup notes"]);
+        assert_eq!(filtered_clips(&db, 1, &work_text).await.unwrap().len(), 1);
+        let unused = PickFilter { tag: Some("personal".to_string()), clip_type: None };
+        assert!(filtered_clips(&db, 10, &unused).await.unwrap().is_empty());
+        assert_eq!(filtered_clips(&db, 10, &PickFilter::default()).await.unwrap().len(), 4);
+    }
+
+    #[tokio::test]
+    async fn large_inputs_reach_the_picker_whole() {
+        use tokio::io::AsyncReadExt;
+        let entries: Vec<String> = (0..50_000)
+            .map(|i| picker_entry("sh -c 'cat /tmp/x'", i, &format!("clip number {}", i)))
+            .collect();
+        let expected: String = entries.iter().map(|e| format!("{}\n", e)).collect();
+
+        // A pipe far smaller than the input, read while it is being written
+        let (writer, mut reader) = tokio::io::duplex(4096);
+        let writing = tokio::spawn(write_entries(writer, entries.into_iter()));
+        let mut received = String::new();
+        reader.read_to_string(&mut received).await.unwrap();
+        writing.await.unwrap().unwrap();
+
+        assert_eq!(received.lines().count(), 50_000);
+        assert_eq!(received, expected);
+    }
+}
@@ -1,11 +1,16 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
+use encoding_rs::Encoding;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::process::Command;
 use std::sync::Arc;
-use tokio::sync::Mutex;
+use tokio::process::Command;
+use tokio::sync::Semaphore;
 
-use crate::database::{Database, Clip};
+use crate::clipboard::decode_text;
+use crate::database::Clip;
+
+/// How long a plugin may run unless `set_timeout` says otherwise.
+pub const DEFAULT_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PluginConfig {
@@ -14,6 +19,120 @@ pub struct PluginConfig {
     pub args: Vec<String>,
     pub enabled: bool,
     pub trigger: PluginTrigger,
+    /// Directory the plugin runs in (`~` is expanded); clipq's own when unset
+    #[serde(default)]
+    pub cwd: Option<String>,
+    /// Extra environment variables for the plugin, on top of clipq's own
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+}
+
+impl PluginConfig {
+    /// Start a plugin named `name`: enabled, run manually, with no
+    /// arguments, working directory or extra environment.
+    pub fn builder(name: &str) -> PluginConfigBuilder {
+        PluginConfigBuilder {
+            config: PluginConfig {
+                name: name.to_string(),
+                command: String::new(),
+                args: Vec::new(),
+                enabled: true,
+                trigger: PluginTrigger::Manual,
+                cwd: None,
+                env: HashMap::new(),
+            },
+        }
+    }
+
+    /// Reject plugins without a name or a command.
+    pub fn validate(&self) -> Result<()> {
+        if self.name.trim().is_empty() {
+            anyhow::bail!("Plugin name must not be empty");
+        }
+        if self.command.trim().is_empty() {
+            anyhow::bail!("Plugin {} has no command", self.name);
+        }
+        Ok(())
+    }
+}
+
+/// Builds a `PluginConfig`; see `PluginConfig::builder`.
+#[derive(Debug, Clone)]
+pub struct PluginConfigBuilder {
+    config: PluginConfig,
+}
+
+impl PluginConfigBuilder {
+    pub fn command(mut self, command: &str) -> Self {
+        self.config.command = command.to_string();
+        self
+    }
+
+    pub fn arg(mut self, arg: &str) -> Self {
+        self.config.args.push(arg.to_string());
+        self
+    }
+
+    pub fn args<I, S>(mut self, args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.config.args.extend(args.into_iter().map(Into::into));
+        self
+    }
+
+    pub fn enabled(mut self, enabled: bool) -> Self {
+        self.config.enabled = enabled;
+        self
+    }
+
+    pub fn trigger(mut self, trigger: PluginTrigger) -> Self {
+        self.config.trigger = trigger;
+        self
+    }
+
+    pub fn cwd(mut self, cwd: &str) -> Self {
+        self.config.cwd = Some(cwd.to_string());
+        self
+    }
+
+    pub fn env(mut self, key: &str, value: &str) -> Self {
+        self.config.env.insert(key.to_string(), value.to_string());
+        self
+    }
+
+    /// The finished config, if it passes `PluginConfig::validate`.
+    pub fn build(self) -> Result<PluginConfig> {
+        self.config.validate()?;
+        Ok(self.config)
+    }
+}
+
+/// One run of a plugin. The contract every plugin follows: it is started
+/// as `command args...` with `env` added to clipq's environment, reads the
+/// clip's text on stdin and writes its result to stdout. Exit status 0
+/// means success. stderr is for diagnostics only and never becomes a clip.
+/// Output that isn't UTF-8 is decoded with `fallback_encoding`.
+#[derive(Debug, Clone)]
+pub struct PluginRun {
+    pub stdout: String,
+    pub stderr: String,
+    pub status: std::process::ExitStatus,
+    pub elapsed: std::time::Duration,
+}
+
+impl PluginRun {
+    /// What `clipq plugin-test` prints: status, timing, then both streams.
+    pub fn report(&self) -> String {
+        format!(
+            "Status: {}\nTime: {} ms\n--- stdout ---\n{}\n--- stderr ---\n{}\n",
+            self.status,
+            self.elapsed.as_millis(),
+            self.stdout.trim_end_matches('\n'),
+            self.stderr.trim_end_matches('\n')
+        )
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -28,52 +147,85 @@ pub enum PluginTrigger {
 
 pub struct PluginManager {
     plugins: HashMap<String, PluginConfig>,
-    db: Arc<Mutex<Database>>,
+    /// Bounds how many plugin processes run at once, so a burst of
+    /// triggers can't spawn an unbounded number of children
+    permits: Arc<Semaphore>,
+    /// Tried on plugin output that isn't UTF-8
+    fallback_encoding: Option<&'static Encoding>,
+    /// How long one run may take before the plugin is killed
+    timeout: std::time::Duration,
 }
 
 impl PluginManager {
-    pub fn new(db: Arc<Mutex<Database>>) -> Self {
+    pub fn new(max_concurrent: usize) -> Self {
         Self {
             plugins: HashMap::new(),
-            db,
+            permits: Arc::new(Semaphore::new(max_concurrent.max(1))),
+            fallback_encoding: None,
+            timeout: DEFAULT_TIMEOUT,
         }
     }
 
+    pub fn set_fallback_encoding(&mut self, encoding: Option<&'static Encoding>) {
+        self.fallback_encoding = encoding;
+    }
+
+    pub fn set_timeout(&mut self, timeout: std::time::Duration) {
+        self.timeout = timeout;
+    }
+
     pub fn load_plugins(&mut self) -> Result<()> {
         // Load built-in plugins
-        self.add_plugin(PluginConfig {
-            name: "url_extractor".to_string(),
-            command: "python3".to_string(),
-            args: vec!["-c".to_string(), "import re, sys; print('\\n'.join(re.findall(r'https?://[^\\s]+', sys.stdin.read())))".to_string()],
-            enabled: true,
-            trigger: PluginTrigger::OnClipAdd,
-        })?;
-
-        self.add_plugin(PluginConfig {
-            name: "code_formatter".to_string(),
-            command: "python3".to_string(),
-            args: vec!["-c".to_string(), "import json, sys; data=json.loads(sys.stdin.read()); print(json.dumps(data, indent=2))".to_string()],
-            enabled: true,
-            trigger: PluginTrigger::OnClipAdd,
-        })?;
-
-        self.add_plugin(PluginConfig {
-            name: "password_generator".to_string(),
-            command: "python3".to_string(),
-            args: vec!["-c".to_string(), "import secrets, string; print(''.join(secrets.choice(string.ascii_letters + string.digits) for _ in range(16)))".to_string()],
-            enabled: true,
-            trigger: PluginTrigger::Manual,
-        })?;
+        self.add_plugin(
+            PluginConfig::builder("url_extractor")
+                .command("python3")
+                .args(["-c", "import re, sys; print('\\n'.join(re.findall(r'https?://[^\\s]+', sys.stdin.read())))"])
+                .trigger(PluginTrigger::OnClipAdd)
+                .build()?,
+        )?;
+
+        self.add_plugin(
+            PluginConfig::builder("code_formatter")
+                .command("python3")
+                .args(["-c", "import json, sys; data=json.loads(sys.stdin.read()); print(json.dumps(data, indent=2))"])
+                .trigger(PluginTrigger::OnClipAdd)
+                .build()?,
+        )?;
+
+        self.add_plugin(
+            PluginConfig::builder("password_generator")
+                .command("python3")
+                .args(["-c", "import secrets, string; print(''.join(secrets.choice(string.ascii_letters + string.digits) for _ in range(16)))"])
+                .build()?,
+        )?;
 
         Ok(())
     }
 
     pub fn add_plugin(&mut self, plugin: PluginConfig) -> Result<()> {
+        plugin.validate()?;
         self.plugins.insert(plugin.name.clone(), plugin);
         Ok(())
     }
 
+    /// Run a plugin and return its output. A plugin that ran but exited
+    /// unsuccessfully is an error too; use `run_plugin` to inspect it.
     pub async fn execute_plugin(&self, plugin_name: &str, input: &str) -> Result<String> {
+        let run = self.run_plugin(plugin_name, input).await?;
+        if !run.status.success() {
+            // stderr may echo the clip it was given, so keep it out of the
+            // error (which ends up in warn-level logs)
+            log::debug!("Plugin {} stderr: {}", plugin_name, run.stderr);
+            return Err(anyhow::anyhow!("Plugin {} exited unsuccessfully ({})", plugin_name, run.status));
+        }
+        Ok(run.stdout)
+    }
+
+    /// Run a plugin once, following the contract described on `PluginRun`.
+    /// Errors when the plugin can't be run at all or is killed for running
+    /// past the timeout; a non-zero exit is reported in the returned
+    /// `PluginRun`.
+    pub async fn run_plugin(&self, plugin_name: &str, input: &str) -> Result<PluginRun> {
         let plugin = self.plugins.get(plugin_name)
             .ok_or_else(|| anyhow::anyhow!("Plugin not found: {}", plugin_name))?;
 
@@ -81,45 +233,64 @@ impl PluginManager {
             return Err(anyhow::anyhow!("Plugin is disabled: {}", plugin_name));
         }
 
-        let output = Command::new(&plugin.command)
+        let _permit = self.permits.acquire().await?;
+
+        let started = std::time::Instant::now();
+        let mut command = Command::new(&plugin.command);
+        if let Some(cwd) = &plugin.cwd {
+            command.current_dir(shellexpand::tilde(cwd).into_owned());
+        }
+        let output = command
             .args(&plugin.args)
+            .envs(&plugin.env)
             .stdin(std::process::Stdio::piped())
             .stdout(std::process::Stdio::piped())
             .stderr(std::process::Stdio::piped())
-            .spawn()?;
+            .kill_on_drop(true)
+            .spawn()
+            .with_context(|| format!("Failed to start plugin {} ({})", plugin_name, plugin.command))?;
 
         let mut child = output;
-        if let Some(mut stdin) = child.stdin.take() {
-            use std::io::Write;
-            stdin.write_all(input.as_bytes())?;
-        }
-
-        let output = child.wait_with_output()?;
-        
-        if !output.status.success() {
-            let error = String::from_utf8_lossy(&output.stderr);
-            return Err(anyhow::anyhow!("Plugin execution failed: {}", error));
-        }
+        let finished = tokio::time::timeout(self.timeout, async {
+            if let Some(mut stdin) = child.stdin.take() {
+                use tokio::io::AsyncWriteExt;
+                stdin.write_all(input.as_bytes()).await?;
+            }
+            child.wait_with_output().await
+        });
+        // Dropping the timed-out future drops the child, which kills it
+        let output = finished
+            .await
+            .map_err(|_| anyhow::anyhow!("Plugin {} timed out after {}s", plugin_name, self.timeout.as_secs()))??;
 
-        Ok(String::from_utf8_lossy(&output.stdout).to_string())
+        let stdout = output.stdout;
+        let lossy = String::from_utf8_lossy(&stdout).to_string();
+        Ok(PluginRun {
+            stdout: decode_text(stdout, self.fallback_encoding).map_or(lossy, |decoded| decoded.text),
+            stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+            status: output.status,
+            elapsed: started.elapsed(),
+        })
     }
 
-    pub async fn trigger_plugins(&self, trigger: &PluginTrigger, clip: &Clip) -> Result<()> {
-        for (name, plugin) in &self.plugins {
-            if !plugin.enabled {
-                continue;
-            }
-
-            match (&plugin.trigger, trigger) {
-                (PluginTrigger::OnClipAdd, PluginTrigger::OnClipAdd) => {
-                    if let Err(e) = self.execute_plugin(name, &clip.content).await {
-                        log::warn!("Plugin {} failed: {}", name, e);
-                    }
+    /// Run every enabled plugin registered for `trigger` concurrently, at
+    /// most `max_concurrent` at a time, and return each run's output.
+    /// Failures are logged too.
+    pub async fn trigger_plugins(&self, trigger: &PluginTrigger, clip: &Clip) -> Vec<Result<String>> {
+        let runs = self
+            .plugins
+            .iter()
+            .filter(|(_, plugin)| plugin.enabled)
+            .filter(|(_, plugin)| matches!((&plugin.trigger, trigger), (PluginTrigger::OnClipAdd, PluginTrigger::OnClipAdd)))
+            .map(|(name, _)| async move {
+                let result = self.execute_plugin(name, &clip.content).await;
+                if let Err(e) = &result {
+                    log::warn!("Plugin {} failed: {}", name, e);
                 }
-                _ => {}
-            }
-        }
-        Ok(())
+                result
+            });
+
+        futures::future::join_all(runs).await
     }
 
     pub fn list_plugins(&self) -> Vec<&PluginConfig> {
@@ -175,6 +346,199 @@ pub mod builtin {
         Ok(serde_json::to_string_pretty(&parsed)?)
     }
 
+    /// Decode base64 text, standard or URL-safe, with or without padding.
+    /// The decoded bytes must be UTF-8 since clips hold text.
+    pub fn decode_base64(text: &str) -> Result<String> {
+        use base64::engine::general_purpose::{STANDARD_NO_PAD, URL_SAFE_NO_PAD};
+        use base64::Engine;
+
+        let compact: String = text.split_whitespace().collect();
+        let trimmed = compact.trim_end_matches('=');
+        let bytes = STANDARD_NO_PAD
+            .decode(trimmed)
+            .or_else(|_| URL_SAFE_NO_PAD.decode(trimmed))
+            .map_err(|e| anyhow::anyhow!("Not valid base64: {}", e))?;
+        String::from_utf8(bytes).map_err(|_| anyhow::anyhow!("Decoded base64 is not UTF-8 text"))
+    }
+
+    pub fn encode_base64(text: &str) -> String {
+        use base64::engine::general_purpose::STANDARD;
+        use base64::Engine;
+        STANDARD.encode(text.as_bytes())
+    }
+
+    /// Pretty-printed payload of a JWT. The signature is not verified.
+    pub fn decode_jwt(text: &str) -> Result<String> {
+        let parts: Vec<&str> = text.trim().split('.').collect();
+        if parts.len() != 3 {
+            anyhow::bail!("Not a JWT: expected three dot-separated parts, found {}", parts.len());
+        }
+        let payload = decode_base64(parts[1]).map_err(|e| anyhow::anyhow!("Not a JWT payload: {}", e))?;
+        format_json(&payload).map_err(|e| anyhow::anyhow!("JWT payload is not JSON: {}", e))
+    }
+
+    /// The transforms `clipq format` can apply to a stored clip.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+    pub enum Format {
+        /// Pretty-print JSON
+        Json,
+        /// Decode a JWT to its JSON payload
+        Jwt,
+        /// Decode base64 to text
+        Base64,
+    }
+
+    pub fn apply_format(format: Format, text: &str) -> Result<String> {
+        match format {
+            Format::Json => format_json(text).map_err(|e| anyhow::anyhow!("Not valid JSON: {}", e)),
+            Format::Jwt => decode_jwt(text),
+            Format::Base64 => decode_base64(text),
+        }
+    }
+
+    /// The tag marking a clip as a snippet `clipq expand` may fill.
+    pub const TEMPLATE_TAG: &str = "template";
+
+    /// Whether a clip with `tags` is marked as a template. Tag case is
+    /// configurable, so any casing counts.
+    pub fn is_template(tags: &[String]) -> bool {
+        tags.iter().any(|tag| tag.eq_ignore_ascii_case(TEMPLATE_TAG))
+    }
+
+    /// Fill the `{name}` placeholders of a snippet from `vars`. Placeholders
+    /// without a value are left in place, or with `strict` are an error
+    /// naming all of them.
+    pub fn expand_template(
+        template: &str,
+        vars: &std::collections::HashMap<String, String>,
+        strict: bool,
+    ) -> Result<String> {
+        let placeholder = regex::Regex::new(r"\{(\w+)\}").unwrap();
+        let mut missing: Vec<&str> = Vec::new();
+        for caps in placeholder.captures_iter(template) {
+            let name = caps.get(1).unwrap().as_str();
+            if !vars.contains_key(name) && !missing.contains(&name) {
+                missing.push(name);
+            }
+        }
+        if strict && !missing.is_empty() {
+            anyhow::bail!("No value given for {}", missing.join(", "));
+        }
+
+        Ok(placeholder
+            .replace_all(template, |caps: &regex::Captures| {
+                vars.get(&caps[1]).cloned().unwrap_or_else(|| caps[0].to_string())
+            })
+            .into_owned())
+    }
+
+    /// A cleanup the daemon can apply to every captured clip.
+    pub type Transform = fn(&str) -> String;
+
+    /// Capture transforms by the name `[capture] transforms` uses for them.
+    pub const TRANSFORMS: &[(&str, Transform)] = &[
+        ("strip_tracking_params", strip_tracking_params),
+        ("normalize_quotes", normalize_quotes),
+        ("trim_trailing_whitespace", trim_trailing_whitespace),
+    ];
+
+    pub fn transform(name: &str) -> Option<Transform> {
+        TRANSFORMS
+            .iter()
+            .find(|(known, _)| *known == name)
+            .map(|(_, transform)| *transform)
+    }
+
+    /// Query parameters that only exist to track where a link was clicked.
+    fn is_tracking_param(key: &str) -> bool {
+        key.starts_with("utm_")
+            || matches!(key, "fbclid" | "gclid" | "dclid" | "msclkid" | "mc_cid" | "mc_eid" | "igshid" | "yclid")
+    }
+
+    /// Remove tracking parameters (`utm_*`, `fbclid`, ...) from every URL in
+    /// `text`, dropping the `?` when nothing else is left.
+    pub fn strip_tracking_params(text: &str) -> String {
+        let url_regex = regex::Regex::new(r"https?://[^\s?#]+\?([^\s#]*)").unwrap();
+        url_regex
+            .replace_all(text, |caps: &regex::Captures| {
+                let whole = &caps[0];
+                let base = &whole[..whole.len() - caps[1].len() - 1];
+                let kept: Vec<&str> = caps[1]
+                    .split('&')
+                    .filter(|pair| {
+                        let key = pair.split('=').next().unwrap_or_default();
+                        !key.is_empty() && !is_tracking_param(key)
+                    })
+                    .collect();
+                if kept.is_empty() {
+                    base.to_string()
+                } else {
+                    format!("{}?{}", base, kept.join("&"))
+                }
+            })
+            .into_owned()
+    }
+
+    /// Replace typographic quotes with their plain ASCII forms.
+    pub fn normalize_quotes(text: &str) -> String {
+        text.chars()
+            .map(|c| match c {
+                '\u{2018}' | '\u{2019}' | '\u{201A}' | '\u{201B}' => '\'',
+                '\u{201C}' | '\u{201D}' | '\u{201E}' | '\u{201F}' => '"',
+                c => c,
+            })
+            .collect()
+    }
+
+    /// Strip whitespace from the end of every line.
+    pub fn trim_trailing_whitespace(text: &str) -> String {
+        text.split('\n').map(str::trim_end).collect::<Vec<_>>().join("\n")
+    }
+
+    /// One step of a `clipq transform` pipeline.
+    pub type Step = fn(&str) -> Result<String>;
+
+    /// Pipeline steps by the name `clipq transform` uses for them.
+    pub const PIPELINE_STEPS: &[(&str, Step)] = &[
+        ("trim", |text| Ok(text.trim().to_string())),
+        ("lowercase", |text| Ok(text.to_lowercase())),
+        ("uppercase", |text| Ok(text.to_uppercase())),
+        ("base64_encode", |text| Ok(encode_base64(text))),
+        ("base64_decode", decode_base64),
+        ("format_json", |text| format_json(text).map_err(|e| anyhow::anyhow!("Not valid JSON: {}", e))),
+        ("jwt_decode", decode_jwt),
+        ("sha256", |text| Ok(calculate_hash(text, "sha256"))),
+        ("strip_tracking_params", |text| Ok(strip_tracking_params(text))),
+        ("normalize_quotes", |text| Ok(normalize_quotes(text))),
+        ("trim_trailing_whitespace", |text| Ok(trim_trailing_whitespace(text))),
+    ];
+
+    /// Parse a comma-separated list of step names, failing on the first
+    /// unknown one.
+    pub fn parse_pipeline(spec: &str) -> Result<Vec<(&'static str, Step)>> {
+        spec.split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .map(|name| {
+                PIPELINE_STEPS
+                    .iter()
+                    .find(|(known, _)| *known == name)
+                    .copied()
+                    .ok_or_else(|| {
+                        let known: Vec<&str> = PIPELINE_STEPS.iter().map(|(name, _)| *name).collect();
+                        anyhow::anyhow!("Unknown transform step '{}'. Available: {}", name, known.join(", "))
+                    })
+            })
+            .collect()
+    }
+
+    /// Feed `text` through every step in order.
+    pub fn run_pipeline(steps: &[(&'static str, Step)], text: &str) -> Result<String> {
+        steps.iter().try_fold(text.to_string(), |text, (name, step)| {
+            step(&text).map_err(|e| anyhow::anyhow!("Step '{}' failed: {}", name, e))
+        })
+    }
+
     pub fn generate_password(length: usize) -> String {
         use rand::Rng;
         const CHARSET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789!@#$%^&*";
@@ -205,4 +569,250 @@ pub mod builtin {
             }
         }
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn manager() -> PluginManager {
+        PluginManager::new(1)
+    }
+
+    #[test]
+    fn builders_start_from_sane_defaults() {
+        let plugin = PluginConfig::builder("upper").command("tr").build().unwrap();
+        assert_eq!(plugin.name, "upper");
+        assert_eq!(plugin.command, "tr");
+        assert!(plugin.enabled);
+        assert!(matches!(plugin.trigger, PluginTrigger::Manual));
+        assert!(plugin.args.is_empty() && plugin.cwd.is_none() && plugin.env.is_empty());
+
+        let plugin = PluginConfig::builder("upper")
+            .command("tr")
+            .arg("a-z")
+            .args(["A-Z"])
+            .enabled(false)
+            .trigger(PluginTrigger::OnClipAdd)
+            .cwd("~/scripts")
+            .env("LC_ALL", "C")
+            .build()
+            .unwrap();
+        assert_eq!(plugin.args, vec!["a-z", "A-Z"]);
+        assert!(!plugin.enabled);
+        assert!(matches!(plugin.trigger, PluginTrigger::OnClipAdd));
+        assert_eq!(plugin.cwd.as_deref(), Some("~/scripts"));
+        assert_eq!(plugin.env.get("LC_ALL").map(String::as_str), Some("C"));
+    }
+
+    #[tokio::test]
+    async fn plugins_need_a_name_and_a_command() {
+        let error = PluginConfig::builder("nothing").build().unwrap_err();
+        assert_eq!(error.to_string(), "Plugin nothing has no command");
+        assert!(PluginConfig::builder("  ").command("cat").build().is_err());
+        assert!(PluginConfig::builder("blank").command(" ").build().is_err());
+
+        // Configs built by hand are checked when added
+        let mut plugin = PluginConfig::builder("cat").command("cat").build().unwrap();
+        plugin.command.clear();
+        assert!(manager().add_plugin(plugin).is_err());
+    }
+
+    #[tokio::test]
+    async fn harness_reports_output_and_exit_status() {
+        let mut plugins = manager();
+        plugins
+            .add_plugin(
+                PluginConfig::builder("noisy")
+                    .command("sh")
+                    .args(["-c", "tr a-z A-Z; echo warning >&2; exit 3"])
+                    .build()
+                    .unwrap(),
+            )
+            .unwrap();
+
+        let run = plugins.run_plugin("noisy", "hello\n").await.unwrap();
+        assert_eq!(run.status.code(), Some(3));
+        let report = run.report();
+        let lines: Vec<&str> = report.lines().collect();
+        assert_eq!(lines[0], format!("Status: {}", run.status));
+        assert!(lines[1].starts_with("Time: ") && lines[1].ends_with(" ms"), "{}", lines[1]);
+        assert_eq!(lines[2..], ["--- stdout ---", "HELLO", "--- stderr ---", "warning"]);
+
+        // A plugin that ran and failed is told apart from one that couldn't run
+        let failed = plugins.execute_plugin("noisy", "hello").await.unwrap_err();
+        assert!(failed.to_string().contains("exited unsuccessfully"), "{}", failed);
+        plugins
+            .add_plugin(PluginConfig::builder("missing").command("/no/such/plugin").build().unwrap())
+            .unwrap();
+        let missing = plugins.run_plugin("missing", "").await.unwrap_err();
+        assert!(missing.to_string().contains("Failed to start plugin"), "{}", missing);
+    }
+
+    #[tokio::test]
+    async fn runs_past_the_timeout_are_killed() {
+        let mut plugins = manager();
+        plugins.set_timeout(std::time::Duration::from_millis(200));
+        plugins
+            .add_plugin(PluginConfig::builder("sleepy").command("sleep").arg("10").build().unwrap())
+            .unwrap();
+
+        let started = std::time::Instant::now();
+        let error = plugins.run_plugin("sleepy", "").await.unwrap_err();
+        assert!(error.to_string().contains("timed out"), "{}", error);
+        assert!(started.elapsed() < std::time::Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn runs_within_the_timeout_finish() {
+        let mut plugins = manager();
+        plugins.set_timeout(std::time::Duration::from_secs(10));
+        plugins
+            .add_plugin(PluginConfig::builder("echo").command("cat").build().unwrap())
+            .unwrap();
+
+        assert_eq!(plugins.execute_plugin("echo", "hello").await.unwrap(), "hello");
+    }
+
+    fn vars(pairs: &[(&str, &str)]) -> std::collections::HashMap<String, String> {
+        pairs.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect()
+    }
+
+    #[test]
+    fn templates_fill_placeholders() {
+        let template = "Hi {name}, regarding {ticket} ({name})";
+        let expanded = builtin::expand_template(template, &vars(&[("name", "Alice"), ("ticket", "123")]), true).unwrap();
+        assert_eq!(expanded, "Hi Alice, regarding 123 (Alice)");
+    }
+
+    #[test]
+    fn missing_placeholders_are_kept_unless_strict() {
+        let template = "Hi {name}, regarding {ticket} and {other}";
+        let given = vars(&[("name", "Alice")]);
+        assert_eq!(
+            builtin::expand_template(template, &given, false).unwrap(),
+            "Hi Alice, regarding {ticket} and {other}"
+        );
+
+        let error = builtin::expand_template(template, &given, true).unwrap_err();
+        assert_eq!(error.to_string(), "No value given for ticket, other");
+    }
+
+    #[test]
+    fn templates_are_marked_by_tag() {
+        assert!(builtin::is_template(&["work".to_string(), "Template".to_string()]));
+        assert!(!builtin::is_template(&["templates".to_string()]));
+        assert!(!builtin::is_template(&[]));
+    }
+
+    #[tokio::test]
+    async fn triggers_never_run_more_plugins_at_once_than_the_limit() {
+        let dir = std::env::temp_dir().join(format!("clipq-plugins-{}", uuid::Uuid::new_v4()));
+        let running = dir.join("running");
+        let counts = dir.join("counts");
+        std::fs::create_dir_all(&running).unwrap();
+        // Each run marks itself running, then logs how many runs are
+        let mut plugins = PluginManager::new(2);
+        plugins
+            .add_plugin(
+                PluginConfig::builder("counter")
+                    .command("sh")
+                    .args(["-c", r#"touch "$RUNNING/$$"; sleep 0.2; ls "$RUNNING" | wc -l >> "$COUNTS"; rm "$RUNNING/$$""#])
+                    .trigger(PluginTrigger::OnClipAdd)
+                    .env("RUNNING", &running.to_string_lossy())
+                    .env("COUNTS", &counts.to_string_lossy())
+                    .build()
+                    .unwrap(),
+            )
+            .unwrap();
+        let clip = Clip {
+            id: "a".to_string(),
+            content: "copied".to_string(),
+            clip_type: "text".to_string(),
+            created_at: chrono::Utc::now(),
+            file_path: None,
+            pinned: false,
+            expires_at: None,
+            mime: "text/plain".to_string(),
+            original_name: None,
+        };
+
+        let triggers = (0..8).map(|_| plugins.trigger_plugins(&PluginTrigger::OnClipAdd, &clip));
+        futures::future::join_all(triggers).await;
+
+        let counts: Vec<usize> = std::fs::read_to_string(&counts)
+            .unwrap()
+            .lines()
+            .map(|line| line.trim().parse().unwrap())
+            .collect();
+        assert_eq!(counts.len(), 8);
+        assert!(counts.iter().all(|&running| running <= 2), "{:?}", counts);
+        // Runs did overlap, so it was the limit that held them to two
+        assert!(counts.contains(&2), "{:?}", counts);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn tracking_params_are_stripped_and_quotes_normalized() {
+        assert_eq!(
+            builtin::strip_tracking_params("see https://shop.example/item?id=7&utm_source=news&utm_medium=email&fbclid=abc#reviews"),
+            "see https://shop.example/item?id=7#reviews"
+        );
+        assert_eq!(
+            builtin::strip_tracking_params("https://a.example/?utm_campaign=x and http://b.example/p?fbclid=1&gclid=2 end"),
+            "https://a.example/ and http://b.example/p end"
+        );
+        let untouched = "https://a.example/search?q=utm_source&page=2 and not?a=url";
+        assert_eq!(builtin::strip_tracking_params(untouched), untouched);
+
+        assert_eq!(
+            builtin::normalize_quotes("\u{201C}It\u{2019}s fine,\u{201D} she said \u{201E}ja\u{201C}"),
+            "\"It's fine,\" she said \"ja\""
+        );
+    }
+
+    #[tokio::test]
+    async fn plugins_run_in_their_configured_directory_and_environment() {
+        let dir = std::env::temp_dir().join(format!("clipq-plugins-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let report = |name: &str, cwd: &str| {
+            PluginConfig::builder(name)
+                .command("sh")
+                .args(["-c", r#"printf '%s|%s|%s' "$(pwd -P)" "$GREETING" "$(cat)""#])
+                .cwd(cwd)
+                .env("GREETING", "hello there")
+                .build()
+                .unwrap()
+        };
+        let mut plugins = manager();
+        plugins.add_plugin(report("here", &dir.to_string_lossy())).unwrap();
+        plugins.add_plugin(report("home", "~")).unwrap();
+
+        let output = plugins.execute_plugin("here", "the clip").await.unwrap();
+        let canonical = std::fs::canonicalize(&dir).unwrap();
+        assert_eq!(output, format!("{}|hello there|the clip", canonical.display()));
+
+        let home = std::fs::canonicalize(shellexpand::tilde("~").as_ref()).unwrap();
+        let output = plugins.execute_plugin("home", "x").await.unwrap();
+        assert_eq!(output, format!("{}|hello there|x", home.display()));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn pipelines_run_steps_in_order_and_name_the_bad_one() {
+        let steps = builtin::parse_pipeline("trim, base64_decode").unwrap();
+        let names: Vec<&str> = steps.iter().map(|(name, _)| *name).collect();
+        assert_eq!(names, ["trim", "base64_decode"]);
+        assert_eq!(builtin::run_pipeline(&steps, "  eyJhIjoxfQ==\n").unwrap(), r#"{"a":1}"#);
+        let steps = builtin::parse_pipeline("base64_decode,format_json").unwrap();
+        assert_eq!(builtin::run_pipeline(&steps, "eyJhIjoxfQ==").unwrap(), "{\n  \"a\": 1\n}");
+        // Encoding first leaves format_json with base64 rather than JSON
+        let reversed = builtin::parse_pipeline("base64_encode,format_json").unwrap();
+        let error = builtin::run_pipeline(&reversed, r#"{"a":1}"#).unwrap_err().to_string();
+        assert!(error.starts_with("Step 'format_json' failed"), "{}", error);
+
+        let error = builtin::parse_pipeline("trim,reverse,uppercase").unwrap_err().to_string();
+        assert!(error.starts_with("Unknown transform step 'reverse'"), "{}", error);
+        assert!(error.contains("base64_decode"), "{}", error);
+        assert!(builtin::parse_pipeline("").unwrap().is_empty());
+    }
+}
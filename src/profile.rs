@@ -0,0 +1,135 @@
+use anyhow::Result;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+/// Profile used when `--profile` isn't given. It keeps the original
+/// `~/.clipq/clipboard.db` and `~/.clipq.toml` locations.
+pub const DEFAULT_PROFILE: &str = "default";
+
+static ACTIVE: OnceLock<Profile> = OnceLock::new();
+
+/// A named, independent history with its own database and config file.
+/// Every profile but the default lives in `~/.clipq/profiles/<name>/`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Profile {
+    name: String,
+}
+
+impl Profile {
+    /// A profile by name; names are limited to letters, digits, `-` and `_`
+    /// so they are always a single path component.
+    pub fn new(name: &str) -> Result<Self> {
+        let valid = !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if !valid {
+            anyhow::bail!("Invalid profile name '{}'; use letters, digits, '-' and '_'", name);
+        }
+        Ok(Self { name: name.to_string() })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn is_default(&self) -> bool {
+        self.name == DEFAULT_PROFILE
+    }
+
+    /// Directory holding the profile's database.
+    pub fn dir(&self) -> Result<PathBuf> {
+        Ok(self.dir_in(&clipq_dir()?))
+    }
+
+    /// The profile's directory when clipq's own is `root`.
+    fn dir_in(&self, root: &Path) -> PathBuf {
+        if self.is_default() {
+            root.to_path_buf()
+        } else {
+            root.join("profiles").join(&self.name)
+        }
+    }
+
+    pub fn database_path(&self) -> Result<String> {
+        Ok(self.dir()?.join("clipboard.db").to_string_lossy().to_string())
+    }
+
+    /// The profile's own config file; `None` for the default profile, whose
+    /// config is found by `Config::resolve_path`.
+    pub fn config_path(&self) -> Result<Option<String>> {
+        if self.is_default() {
+            return Ok(None);
+        }
+        Ok(Some(self.dir()?.join("config.toml").to_string_lossy().to_string()))
+    }
+}
+
+impl Default for Profile {
+    fn default() -> Self {
+        Self {
+            name: DEFAULT_PROFILE.to_string(),
+        }
+    }
+}
+
+/// Make `profile` the one `active` returns for the rest of the process.
+/// Only the first call has an effect.
+pub fn set_active(profile: Profile) {
+    let _ = ACTIVE.set(profile);
+}
+
+/// The profile chosen with `set_active`, or the default one.
+pub fn active() -> &'static Profile {
+    ACTIVE.get_or_init(Profile::default)
+}
+
+/// Names of every profile with a directory, the default one first.
+pub fn list() -> Result<Vec<String>> {
+    let mut names = Vec::new();
+    match std::fs::read_dir(clipq_dir()?.join("profiles")) {
+        Ok(entries) => {
+            for entry in entries {
+                let entry = entry?;
+                if entry.file_type()?.is_dir() {
+                    names.push(entry.file_name().to_string_lossy().to_string());
+                }
+            }
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e.into()),
+    }
+    names.sort();
+    names.retain(|name| name != DEFAULT_PROFILE);
+    names.insert(0, DEFAULT_PROFILE.to_string());
+    Ok(names)
+}
+
+fn clipq_dir() -> Result<PathBuf> {
+    let home = dirs::home_dir().ok_or_else(|| anyhow::anyhow!("Could not find home directory"))?;
+    Ok(home.join(".clipq"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::Database;
+
+    async fn open_in(root: &Path, name: &str) -> Database {
+        let path = Profile::new(name).unwrap().dir_in(root).join("clipboard.db");
+        Database::open(&path.to_string_lossy()).await.unwrap()
+    }
+
+    async fn contents(db: &Database) -> Vec<String> {
+        db.get_all_clips().await.unwrap().into_iter().map(|clip| clip.content).collect()
+    }
+
+    #[tokio::test]
+    async fn profiles_keep_independent_histories() {
+        let root = std::env::temp_dir().join(format!("clipq-profiles-{}", uuid::Uuid::new_v4()));
+        open_in(&root, "work").await.add_clip("ticket 123", "text").await.unwrap();
+        open_in(&root, DEFAULT_PROFILE).await.add_clip("grocery list", "text").await.unwrap();
+
+        assert_eq!(contents(&open_in(&root, "work").await).await, vec!["ticket 123"]);
+        assert_eq!(contents(&open_in(&root, DEFAULT_PROFILE).await).await, vec!["grocery list"]);
+        assert!(contents(&open_in(&root, "other").await).await.is_empty());
+        std::fs::remove_dir_all(root).unwrap();
+    }
+}
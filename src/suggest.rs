@@ -0,0 +1,231 @@
+use chrono::{DateTime, Duration, Utc};
+use std::collections::HashSet;
+
+use crate::database::Clip;
+
+/// What suggestions are based on: the current clipboard text and, if it is
+/// already in the history, that clip's tags and capture time.
+#[derive(Debug, Clone, Default)]
+pub struct SuggestContext {
+    pub content: String,
+    pub tags: Vec<String>,
+    pub copied_at: Option<DateTime<Utc>>,
+}
+
+/// How close together two copies must be to count as copied together.
+pub const CO_COPY_WINDOW_SECS: i64 = 600;
+
+/// A history clip being considered, with its tags and how it was used.
+#[derive(Debug, Clone)]
+pub struct Candidate {
+    pub clip: Clip,
+    pub tags: Vec<String>,
+    /// Times it was put back on the clipboard
+    pub use_count: u32,
+    /// Times it was copied within `CO_COPY_WINDOW_SECS` of the context
+    pub co_copies: u32,
+}
+
+/// One signal of relatedness. Scores should fall in `0.0..=1.0`; the
+/// `Suggester` weights and sums them.
+pub trait Scorer {
+    fn score(&self, context: &SuggestContext, candidate: &Candidate) -> f64;
+}
+
+/// Share of the context's tags the candidate also has.
+pub struct SharedTags;
+
+impl Scorer for SharedTags {
+    fn score(&self, context: &SuggestContext, candidate: &Candidate) -> f64 {
+        if context.tags.is_empty() {
+            return 0.0;
+        }
+        let shared = context
+            .tags
+            .iter()
+            .filter(|tag| candidate.tags.iter().any(|other| other.eq_ignore_ascii_case(tag)))
+            .count();
+        shared as f64 / context.tags.len() as f64
+    }
+}
+
+/// Overlap (Jaccard index) of the words in both contents.
+pub struct SimilarContent;
+
+impl Scorer for SimilarContent {
+    fn score(&self, context: &SuggestContext, candidate: &Candidate) -> f64 {
+        let (a, b) = (words(&context.content), words(&candidate.clip.content));
+        let union = a.union(&b).count();
+        if union == 0 {
+            return 0.0;
+        }
+        a.intersection(&b).count() as f64 / union as f64
+    }
+}
+
+/// Clips copied shortly before or after the context, falling off linearly
+/// to nothing at `window` apart.
+pub struct CopiedNearby {
+    pub window: Duration,
+}
+
+impl Scorer for CopiedNearby {
+    fn score(&self, context: &SuggestContext, candidate: &Candidate) -> f64 {
+        let Some(copied_at) = context.copied_at else {
+            return 0.0;
+        };
+        let apart = (candidate.clip.created_at - copied_at).num_seconds().abs() as f64;
+        (1.0 - apart / self.window.num_seconds().max(1) as f64).max(0.0)
+    }
+}
+
+/// Clips that keep getting copied back, reaching the full score at
+/// `saturation` uses.
+pub struct FrequentlyUsed {
+    pub saturation: u32,
+}
+
+impl Scorer for FrequentlyUsed {
+    fn score(&self, _context: &SuggestContext, candidate: &Candidate) -> f64 {
+        (candidate.use_count as f64 / self.saturation.max(1) as f64).min(1.0)
+    }
+}
+
+/// Clips repeatedly copied together with the context, reaching the full
+/// score at `saturation` times.
+pub struct CoCopied {
+    pub saturation: u32,
+}
+
+impl Scorer for CoCopied {
+    fn score(&self, _context: &SuggestContext, candidate: &Candidate) -> f64 {
+        (candidate.co_copies as f64 / self.saturation.max(1) as f64).min(1.0)
+    }
+}
+
+/// A candidate's position in the input and its total score.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Suggestion {
+    pub index: usize,
+    pub score: f64,
+}
+
+/// Weighted sum of scorers.
+pub struct Suggester {
+    scorers: Vec<(f64, Box<dyn Scorer>)>,
+}
+
+impl Suggester {
+    /// A suggester with no scorers; add them with `with`.
+    pub fn new() -> Self {
+        Self { scorers: Vec::new() }
+    }
+
+    pub fn with(mut self, weight: f64, scorer: impl Scorer + 'static) -> Self {
+        self.scorers.push((weight, Box::new(scorer)));
+        self
+    }
+
+    pub fn score(&self, context: &SuggestContext, candidate: &Candidate) -> f64 {
+        self.scorers
+            .iter()
+            .map(|(weight, scorer)| weight * scorer.score(context, candidate))
+            .sum()
+    }
+
+    /// The `limit` best-scoring candidates, best first. Candidates scoring
+    /// nothing are left out; ties keep their input order.
+    pub fn rank(&self, context: &SuggestContext, candidates: &[Candidate], limit: usize) -> Vec<Suggestion> {
+        let mut ranked: Vec<Suggestion> = candidates
+            .iter()
+            .enumerate()
+            .map(|(index, candidate)| Suggestion {
+                index,
+                score: self.score(context, candidate),
+            })
+            .filter(|suggestion| suggestion.score > 0.0)
+            .collect();
+        ranked.sort_by(|a, b| b.score.total_cmp(&a.score));
+        ranked.truncate(limit);
+        ranked
+    }
+}
+
+impl Default for Suggester {
+    /// Shared tags count most, then similar content and copying together,
+    /// then copy time, then how often a clip is used at all.
+    fn default() -> Self {
+        Self::new()
+            .with(2.0, SharedTags)
+            .with(1.5, SimilarContent)
+            .with(1.5, CoCopied { saturation: 3 })
+            .with(1.0, CopiedNearby { window: Duration::seconds(CO_COPY_WINDOW_SECS) })
+            .with(0.5, FrequentlyUsed { saturation: 5 })
+    }
+}
+
+/// Lowercased words of three or more letters or digits.
+fn words(text: &str) -> HashSet<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| word.chars().count() >= 3)
+        .map(str::to_lowercase)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidate(content: &str, tags: &[&str], minutes_ago: i64, use_count: u32, co_copies: u32) -> Candidate {
+        Candidate {
+            clip: Clip {
+                id: content.to_string(),
+                content: content.to_string(),
+                clip_type: "text".to_string(),
+                created_at: now() - Duration::minutes(minutes_ago),
+                file_path: None,
+                pinned: false,
+                expires_at: None,
+                mime: "text/plain".to_string(),
+                original_name: None,
+            },
+            tags: tags.iter().map(|tag| tag.to_string()).collect(),
+            use_count,
+            co_copies,
+        }
+    }
+
+    fn now() -> DateTime<Utc> {
+        "2024-05-01T12:00:00Z".parse().unwrap()
+    }
+
+    #[test]
+    fn a_clearly_related_clip_ranks_first() {
+        let context = SuggestContext {
+            content: "ssh deploy@staging.example.com".to_string(),
+            tags: vec!["servers".to_string()],
+            copied_at: Some(now()),
+        };
+        let candidates = vec![
+            candidate("grocery list: eggs, milk", &[], 1, 0, 0),
+            candidate("https://news.example.org/story", &["reading"], 3, 9, 0),
+            candidate("ssh deploy@production.example.com", &["servers"], 240, 2, 3),
+            candidate("lorem ipsum dolor sit amet", &[], 2000, 0, 0),
+        ];
+
+        let ranked = Suggester::default().rank(&context, &candidates, 3);
+        assert_eq!(ranked[0].index, 2);
+        assert!(ranked.iter().all(|suggestion| suggestion.index != 3));
+    }
+
+    #[test]
+    fn usage_scores_saturate() {
+        let context = SuggestContext::default();
+        let scorer = FrequentlyUsed { saturation: 5 };
+        assert_eq!(scorer.score(&context, &candidate("a", &[], 0, 0, 0)), 0.0);
+        assert_eq!(scorer.score(&context, &candidate("a", &[], 0, 2, 0)), 0.4);
+        assert_eq!(scorer.score(&context, &candidate("a", &[], 0, 50, 0)), 1.0);
+        let scorer = CoCopied { saturation: 3 };
+        assert_eq!(scorer.score(&context, &candidate("a", &[], 0, 0, 3)), 1.0);
+    }
+}
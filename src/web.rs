@@ -1,10 +1,22 @@
 use anyhow::Result;
+use futures::executor::block_on;
+use futures::{SinkExt, StreamExt};
 use serde::{Deserialize, Serialize};
-use std::sync::Arc;
+use std::future::Future;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, OnceLock};
 use tokio::sync::Mutex;
 use warp::Filter;
 
-use crate::database::{Database, Clip};
+/// How often `/api/events` sockets check for new events.
+const EVENT_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(500);
+
+use crate::clipboard::ClipboardManager;
+use crate::config::Timezone;
+use crate::database::{Database, Clip, TagInfo, TagNameError, MAX_ACTIVITY_DAYS};
+use crate::duration;
+use crate::metrics::Metrics;
+use crate::plugins::PluginManager;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct WebClip {
@@ -13,73 +25,349 @@ pub struct WebClip {
     pub clip_type: String,
     pub created_at: String,
     pub file_path: Option<String>,
-    pub tags: Vec<String>,
+    pub mime: String,
+    pub tags: Vec<TagInfo>,
+    #[serde(default)]
+    pub pinned: bool,
+    /// When the clip deletes itself, if it has a TTL
+    #[serde(default)]
+    pub expires_at: Option<String>,
+    /// Set for file clips whose file no longer exists
+    #[serde(default)]
+    pub stale: bool,
+    /// Set when `content` was cut short by a `?preview=N` request
+    #[serde(default)]
+    pub truncated: bool,
 }
 
 impl From<Clip> for WebClip {
     fn from(clip: Clip) -> Self {
+        let stale = clip.is_stale();
         Self {
             id: clip.id,
             content: clip.content,
             clip_type: clip.clip_type,
             created_at: clip.created_at.format("%Y-%m-%d %H:%M:%S").to_string(),
             file_path: clip.file_path,
+            mime: clip.mime,
             tags: Vec::new(), // Will be populated separately
+            pinned: clip.pinned,
+            expires_at: clip.expires_at.map(|time| time.format("%Y-%m-%d %H:%M:%S").to_string()),
+            stale,
+            truncated: false,
+        }
+    }
+}
+
+impl WebClip {
+    /// Cut `content` down to at most `max_chars` characters for list views.
+    fn truncate(&mut self, max_chars: usize) {
+        if let Some((byte_index, _)) = self.content.char_indices().nth(max_chars) {
+            self.content.truncate(byte_index);
+            self.truncated = true;
         }
     }
 }
 
+/// Body of `GET /health`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct HealthStatus {
+    pub status: String,
+    pub clips: usize,
+    pub version: String,
+}
+
+impl HealthStatus {
+    pub fn is_ok(&self) -> bool {
+        self.status == "ok"
+    }
+}
+
 pub struct WebServer {
     db: Arc<Mutex<Database>>,
-    port: u16,
+    plugins: Arc<PluginManager>,
+    addr: SocketAddr,
+    timezone: Timezone,
+    metrics: Arc<Metrics>,
+    /// Connected on first use, so a headless server never touches it
+    clipboard: Arc<Mutex<ClipboardManager>>,
+    /// Every `/api` request must carry this, as a bearer token or a
+    /// `?token=` query parameter
+    token: Arc<str>,
+    /// `Origin`s `/api` accepts: the server's own, known once it is bound
+    allowed_origins: Arc<OnceLock<Vec<String>>>,
+}
+
+/// `/api` request without the server's token.
+#[derive(Debug)]
+struct Unauthorized;
+
+impl warp::reject::Reject for Unauthorized {}
+
+/// `/api` request sent by a page the server didn't serve.
+#[derive(Debug)]
+struct ForeignOrigin;
+
+impl warp::reject::Reject for ForeignOrigin {}
+
+/// A random token for a server started without a configured one.
+pub fn generate_token() -> String {
+    use rand::distributions::{Alphanumeric, DistString};
+    Alphanumeric.sample_string(&mut rand::thread_rng(), 32)
+}
+
+/// Parse the `web_bind` config value into the address to listen on.
+///
+/// Wildcard addresses (`0.0.0.0`, `::`) are refused: they would expose the
+/// whole history to the network, with the API token sent in plain HTTP.
+pub fn parse_bind_addr(bind: &str, port: u16) -> Result<SocketAddr> {
+    let ip: IpAddr = bind
+        .trim()
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .parse()
+        .map_err(|_| anyhow::anyhow!("Invalid web_bind address: {}", bind))?;
+
+    if ip.is_unspecified() {
+        anyhow::bail!(
+            "Refusing to bind to {}; bind a specific interface address instead",
+            ip
+        );
+    }
+
+    Ok(SocketAddr::new(ip, port))
+}
+
+/// The address to point a browser at for a server bound to `addr`.
+pub fn server_url(addr: SocketAddr) -> String {
+    format!("http://{}/", addr)
+}
+
+/// `server_url` with the API token the UI picks up from the query string.
+pub fn authorized_url(addr: SocketAddr, token: &str) -> String {
+    format!("{}?token={}", server_url(addr), token)
+}
+
+/// The `Origin` values a browser sends for pages served from `addr`.
+fn own_origins(addr: SocketAddr) -> Vec<String> {
+    let mut origins = vec![format!("http://{}", addr)];
+    if addr.ip().is_loopback() {
+        origins.push(format!("http://localhost:{}", addr.port()));
+    }
+    origins
+}
+
+/// Open `url` in the default browser. Fails when no browser launcher is
+/// available, e.g. on a headless machine.
+pub fn open_browser(url: &str) -> Result<()> {
+    let mut command = if cfg!(target_os = "macos") {
+        std::process::Command::new("open")
+    } else if cfg!(target_os = "windows") {
+        let mut command = std::process::Command::new("cmd");
+        command.args(["/C", "start", ""]);
+        command
+    } else {
+        std::process::Command::new("xdg-open")
+    };
+    let status = command
+        .arg(url)
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .status()?;
+    if !status.success() {
+        anyhow::bail!("Browser launcher exited with {}", status);
+    }
+    Ok(())
 }
 
 impl WebServer {
-    pub fn new(db: Arc<Mutex<Database>>, port: u16) -> Self {
-        Self { db, port }
+    pub fn new(
+        db: Arc<Mutex<Database>>,
+        plugins: Arc<PluginManager>,
+        addr: SocketAddr,
+        timezone: Timezone,
+    ) -> Self {
+        Self {
+            db,
+            plugins,
+            addr,
+            timezone,
+            metrics: Arc::default(),
+            clipboard: Arc::new(Mutex::new(ClipboardManager::new())),
+            token: generate_token().into(),
+            allowed_origins: Arc::default(),
+        }
+    }
+
+    /// Require `token` on `/api` requests instead of a random one.
+    pub fn with_token(mut self, token: String) -> Self {
+        self.token = token.into();
+        self
+    }
+
+    pub fn token(&self) -> &str {
+        &self.token
+    }
+
+    /// Report into `metrics` instead of counters of the server's own, e.g.
+    /// to share them with a daemon in the same process.
+    pub fn with_metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        self.metrics = metrics;
+        self
     }
 
     pub async fn start(&self) -> Result<()> {
+        self.serve(|_| {}).await
+    }
+
+    /// Bind the server, hand the address it actually got (port 0 picks a
+    /// free one) to `on_ready`, then serve until Ctrl-C.
+    pub async fn serve(&self, on_ready: impl FnOnce(SocketAddr)) -> Result<()> {
+        let (addr, server) = warp::serve(self.routes())
+            .try_bind_with_graceful_shutdown(self.addr, async {
+                let _ = tokio::signal::ctrl_c().await;
+            })
+            .map_err(|e| anyhow::anyhow!("Cannot listen on {}: {}", self.addr, e))?;
+        let _ = self.allowed_origins.set(own_origins(addr));
+        println!("Web server running at {}", authorized_url(addr, &self.token));
+        on_ready(addr);
+        server.await;
+
+        Ok(())
+    }
+
+    /// Every route the server answers. `/api` routes need the token and
+    /// refuse requests from other origins; no CORS headers are sent, so
+    /// browsers won't let other pages read the responses either.
+    fn routes(&self) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone + Send + Sync + 'static {
         let db = Arc::clone(&self.db);
-        
-        // CORS filter
-        let cors = warp::cors()
-            .allow_any_origin()
-            .allow_headers(vec!["content-type"])
-            .allow_methods(vec!["GET", "POST", "DELETE"]);
-
-        // Routes
-        let clips = warp::path("api")
-            .and(warp::path("clips"))
+
+        let clips = warp::path("clips")
+            .and(warp::path::end())
             .and(warp::get())
+            .and(warp::query::<ListQuery>())
             .and(with_db(db.clone()))
             .and_then(get_clips);
 
-        let search = warp::path("api")
-            .and(warp::path("search"))
+        let get_clip = warp::path("clips")
+            .and(warp::path::param::<String>())
+            .and(warp::path::end())
+            .and(warp::get())
+            .and(with_db(db.clone()))
+            .and_then(get_clip);
+
+        let search = warp::path("search")
             .and(warp::query::<SearchQuery>())
             .and(with_db(db.clone()))
             .and_then(search_clips);
 
-        let add_clip = warp::path("api")
-            .and(warp::path("clips"))
+        let add_clip = warp::path("clips")
+            .and(warp::path::end())
             .and(warp::post())
             .and(warp::body::json())
             .and(with_db(db.clone()))
+            .and(with_metrics(self.metrics.clone()))
+            .and(with_clipboard(self.clipboard.clone()))
             .and_then(add_clip);
 
-        let delete_clip = warp::path("api")
-            .and(warp::path("clips"))
+        let delete_clip = warp::path("clips")
             .and(warp::path::param::<String>())
             .and(warp::delete())
             .and(with_db(db.clone()))
             .and_then(delete_clip);
 
-        let stats = warp::path("api")
-            .and(warp::path("stats"))
+        let update_clip = warp::path("clips")
+            .and(warp::path::param::<String>())
+            .and(warp::path::end())
+            .and(warp::put())
+            .and(warp::body::json())
+            .and(with_db(db.clone()))
+            .and_then(update_clip);
+
+        let pin_clip = warp::path("clips")
+            .and(warp::path::param::<String>())
+            .and(warp::path("pin"))
+            .and(warp::path::end())
+            .and(warp::post())
+            .and(warp::any().map(|| true))
+            .and(with_db(db.clone()))
+            .and_then(set_clip_pinned);
+
+        let unpin_clip = warp::path("clips")
+            .and(warp::path::param::<String>())
+            .and(warp::path("unpin"))
+            .and(warp::path::end())
+            .and(warp::post())
+            .and(warp::any().map(|| false))
+            .and(with_db(db.clone()))
+            .and_then(set_clip_pinned);
+
+        let expire_clip = warp::path("clips")
+            .and(warp::path::param::<String>())
+            .and(warp::path("expire"))
+            .and(warp::path::end())
+            .and(warp::post())
+            .and(warp::body::json())
+            .and(with_db(db.clone()))
+            .and_then(set_clip_expiry);
+
+        let stats = warp::path("stats")
+            .and(warp::path::end())
             .and(with_db(db.clone()))
             .and_then(get_stats);
 
+        let timezone = self.timezone;
+        let activity = warp::path("stats")
+            .and(warp::path("activity"))
+            .and(warp::path::end())
+            .and(warp::get())
+            .and(warp::query::<ActivityQuery>())
+            .and(warp::any().map(move || timezone))
+            .and(with_db(db.clone()))
+            .and_then(get_activity);
+
+        let hourly = warp::path("stats")
+            .and(warp::path("hourly"))
+            .and(warp::path::end())
+            .and(warp::get())
+            .and(warp::any().map(move || timezone))
+            .and(with_db(db.clone()))
+            .and_then(get_hourly);
+
+        let list_plugins = warp::path("plugins")
+            .and(warp::path::end())
+            .and(warp::get())
+            .and(with_plugins(self.plugins.clone()))
+            .and_then(list_plugins);
+
+        let run_plugin = warp::path("plugins")
+            .and(warp::path::param::<String>())
+            .and(warp::path::end())
+            .and(warp::post())
+            .and(warp::body::json())
+            .and(with_plugins(self.plugins.clone()))
+            .and(with_metrics(self.metrics.clone()))
+            .and_then(run_plugin);
+
+        let health = warp::path("health")
+            .and(warp::path::end())
+            .and(warp::get())
+            .and(with_db(db.clone()))
+            .and_then(get_health);
+
+        let events = warp::path("events")
+            .and(warp::path::end())
+            .and(warp::ws())
+            .and(with_db(db.clone()))
+            .map(|ws: warp::ws::Ws, db| ws.on_upgrade(move |socket| stream_events(socket, db)));
+
+        let metrics = warp::path("metrics")
+            .and(warp::path::end())
+            .and(warp::get())
+            .and(with_db(db.clone()))
+            .and(with_metrics(self.metrics.clone()))
+            .and_then(get_metrics);
+
         // Serve static files
         let static_files = warp::path("static")
             .and(warp::fs::dir("web/static/"));
@@ -89,85 +377,918 @@ impl WebServer {
             .and(warp::path::end())
             .and(warp::fs::file("web/index.html"));
 
-        let routes = clips
+        let api_routes = clips
+            .or(get_clip)
             .or(search)
             .or(add_clip)
             .or(delete_clip)
+            .or(update_clip)
+            .or(pin_clip)
+            .or(unpin_clip)
+            .or(expire_clip)
             .or(stats)
+            .or(activity)
+            .or(hourly)
+            .or(events)
+            .or(list_plugins)
+            .or(run_plugin);
+        let api = warp::path("api")
+            .and(same_origin(self.allowed_origins.clone()))
+            .and(authorized(self.token.clone()))
+            .and(api_routes);
+
+        api.or(health)
+            .or(metrics)
             .or(static_files)
             .or(index)
-            .with(cors);
+            .recover(handle_rejection)
+    }
+}
 
-        println!("Starting web server on http://localhost:{}", self.port);
-        warp::serve(routes)
-            .run(([127, 0, 0, 1], self.port))
-            .await;
+#[derive(Debug, Deserialize)]
+struct TokenQuery {
+    token: Option<String>,
+}
 
-        Ok(())
-    }
+#[derive(Debug, Deserialize)]
+struct ListQuery {
+    preview: Option<usize>,
 }
 
 #[derive(Debug, Deserialize)]
 struct SearchQuery {
     q: String,
     limit: Option<usize>,
+    tag: Option<String>,
+    preview: Option<usize>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ActivityQuery {
+    days: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+struct DayActivity {
+    date: String,
+    clips: usize,
+}
+
+#[derive(Debug, Serialize)]
+struct HourActivity {
+    hour: usize,
+    clips: usize,
+}
+
+#[derive(Debug, Serialize)]
+struct PluginInfo {
+    name: String,
+    enabled: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct RunPluginRequest {
+    input: String,
 }
 
 #[derive(Debug, Deserialize)]
 struct AddClipRequest {
     content: String,
     clip_type: String,
+    /// Also put the content on the server machine's clipboard
+    #[serde(default)]
+    set_clipboard: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct UpdateClipRequest {
+    content: Option<String>,
+    tags: Option<Vec<String>>,
+}
+
+/// Body of `POST /api/clips/{id}/expire`
+#[derive(Debug, Deserialize)]
+struct ExpireRequest {
+    /// Duration such as `10m` or `7d` from now; null clears the expiry
+    expire_in: Option<String>,
 }
 
 fn with_db(db: Arc<Mutex<Database>>) -> impl Filter<Extract = (Arc<Mutex<Database>>,), Error = std::convert::Infallible> + Clone {
     warp::any().map(move || db.clone())
 }
 
-fn get_clips(db: Arc<Mutex<Database>>) -> Result<impl warp::Reply, warp::Rejection> {
-    let rt = tokio::runtime::Handle::current();
-    let db = rt.block_on(db.lock());
-    let clips = rt.block_on(db.get_recent_clips(50)).map_err(|_| warp::reject::reject())?;
-    
-    let mut web_clips = Vec::new();
-    for clip in clips {
-        let mut web_clip = WebClip::from(clip.clone());
-        web_clip.tags = rt.block_on(db.get_clip_tags(&clip.id)).unwrap_or_default();
-        web_clips.push(web_clip);
+fn with_plugins(plugins: Arc<PluginManager>) -> impl Filter<Extract = (Arc<PluginManager>,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || plugins.clone())
+}
+
+fn with_metrics(metrics: Arc<Metrics>) -> impl Filter<Extract = (Arc<Metrics>,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || metrics.clone())
+}
+
+fn with_clipboard(
+    clipboard: Arc<Mutex<ClipboardManager>>,
+) -> impl Filter<Extract = (Arc<Mutex<ClipboardManager>>,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || clipboard.clone())
+}
+
+/// Pass requests carrying `token` as `Authorization: Bearer <token>` or a
+/// `?token=` query parameter (for WebSockets, which can't set headers).
+fn authorized(token: Arc<str>) -> impl Filter<Extract = (), Error = warp::Rejection> + Clone {
+    warp::header::optional::<String>("authorization")
+        .and(warp::query::<TokenQuery>())
+        .and_then(move |header: Option<String>, query: TokenQuery| {
+            let token = token.clone();
+            async move {
+                let given = header
+                    .as_deref()
+                    .and_then(|header| header.strip_prefix("Bearer "))
+                    .or(query.token.as_deref());
+                match given {
+                    Some(given) if constant_time_eq(given.as_bytes(), token.as_bytes()) => Ok(()),
+                    _ => Err(warp::reject::custom(Unauthorized)),
+                }
+            }
+        })
+        .untuple_one()
+}
+
+/// Pass requests without an `Origin` header (non-browser clients) or from
+/// one of `allowed`.
+fn same_origin(allowed: Arc<OnceLock<Vec<String>>>) -> impl Filter<Extract = (), Error = warp::Rejection> + Clone {
+    warp::header::optional::<String>("origin")
+        .and_then(move |origin: Option<String>| {
+            let allowed = allowed.clone();
+            async move {
+                match origin {
+                    Some(origin) if !allowed.get().is_some_and(|allowed| allowed.contains(&origin)) => {
+                        Err(warp::reject::custom(ForeignOrigin))
+                    }
+                    _ => Ok(()),
+                }
+            }
+        })
+        .untuple_one()
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// Answer auth failures with a JSON error; anything else keeps warp's
+/// default handling.
+async fn handle_rejection(rejection: warp::Rejection) -> Result<warp::reply::Response, warp::Rejection> {
+    use warp::http::StatusCode;
+
+    let (message, status) = if rejection.find::<Unauthorized>().is_some() {
+        ("Missing or wrong API token", StatusCode::UNAUTHORIZED)
+    } else if rejection.find::<ForeignOrigin>().is_some() {
+        ("Requests from other origins are not allowed", StatusCode::FORBIDDEN)
+    } else {
+        return Err(rejection);
+    };
+    let body = serde_json::json!({"error": message});
+    Ok(warp::reply::Reply::into_response(warp::reply::with_status(warp::reply::json(&body), status)))
+}
+
+/// Database futures never actually suspend, but they borrow a `!Sync`
+/// connection and so can't be held across an `.await` in a warp handler.
+/// Drive them to completion on the spot instead.
+fn run<T>(future: impl Future<Output = anyhow::Result<T>>) -> Result<T, warp::Rejection> {
+    block_on(future).map_err(|_| warp::reject::reject())
+}
+
+/// Run a database query on the blocking pool, so neither waiting for the
+/// lock nor the query itself stalls the runtime thread driving a socket.
+async fn query_blocking<T: Send + 'static>(
+    db: &Arc<Mutex<Database>>,
+    query: impl FnOnce(&Database) -> anyhow::Result<T> + Send + 'static,
+) -> anyhow::Result<T> {
+    let db = Arc::clone(db);
+    tokio::task::spawn_blocking(move || query(&db.blocking_lock())).await?
+}
+
+/// Send every history event recorded after the socket opened as a JSON
+/// `events::ClipEventRecord` text message, until the client goes away.
+async fn stream_events(mut socket: warp::ws::WebSocket, db: Arc<Mutex<Database>>) {
+    let Ok(mut last_seq) = query_blocking(&db, |db| block_on(db.latest_event_seq())).await else {
+        return;
+    };
+    let mut poll = tokio::time::interval(EVENT_POLL_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = poll.tick() => {}
+            message = socket.next() => match message {
+                // Clients have nothing to say; only a close matters
+                Some(Ok(message)) if !message.is_close() => continue,
+                _ => return,
+            },
+        }
+        let after = last_seq;
+        let Ok(records) = query_blocking(&db, move |db| block_on(db.events_after(after, 100))).await else {
+            return;
+        };
+        for record in records {
+            last_seq = record.seq;
+            let Ok(text) = serde_json::to_string(&record) else {
+                continue;
+            };
+            if socket.send(warp::ws::Message::text(text)).await.is_err() {
+                return;
+            }
+        }
     }
+}
+
+fn to_web_clip(db: &Database, clip: Clip) -> WebClip {
+    let tags = block_on(db.get_clip_tag_infos(&clip.id)).unwrap_or_default();
+    let mut web_clip = WebClip::from(clip);
+    web_clip.tags = tags;
+    web_clip
+}
+
+fn to_web_clips(db: &Database, clips: Vec<Clip>, preview: Option<usize>) -> Vec<WebClip> {
+    clips
+        .into_iter()
+        .map(|clip| {
+            let mut web_clip = to_web_clip(db, clip);
+            if let Some(max_chars) = preview {
+                web_clip.truncate(max_chars);
+            }
+            web_clip
+        })
+        .collect()
+}
+
+async fn get_clips(query: ListQuery, db: Arc<Mutex<Database>>) -> Result<impl warp::Reply, warp::Rejection> {
+    let db = db.lock().await;
+    let clips = run(db.get_recent_clips(50))?;
     
-    Ok(warp::reply::json(&web_clips))
+    Ok(warp::reply::json(&to_web_clips(&db, clips, query.preview)))
+}
+
+async fn get_clip(clip_id: String, db: Arc<Mutex<Database>>) -> Result<impl warp::Reply, warp::Rejection> {
+    let db = db.lock().await;
+    let clip = run(db.get_clip_by_id(&clip_id))?.ok_or_else(warp::reject::not_found)?;
+    Ok(warp::reply::json(&to_web_clip(&db, clip)))
 }
 
 async fn search_clips(query: SearchQuery, db: Arc<Mutex<Database>>) -> Result<impl warp::Reply, warp::Rejection> {
     let db = db.lock().await;
     let limit = query.limit.unwrap_or(20);
-    let clips = db.search_clips(&query.q, limit).await.map_err(|_| warp::reject::reject())?;
+    let clips = match &query.tag {
+        Some(tag) => run(db.search_clips_by_tag(&query.q, tag, limit))?,
+        None => run(db.search_clips(&query.q, limit))?,
+    };
     
-    let mut web_clips = Vec::new();
-    for clip in clips {
-        let mut web_clip = WebClip::from(clip.clone());
-        web_clip.tags = db.get_clip_tags(&clip.id).await.unwrap_or_default();
-        web_clips.push(web_clip);
-    }
-    
-    Ok(warp::reply::json(&web_clips))
+    Ok(warp::reply::json(&to_web_clips(&db, clips, query.preview)))
 }
 
-async fn add_clip(request: AddClipRequest, db: Arc<Mutex<Database>>) -> Result<impl warp::Reply, warp::Rejection> {
-    let mut db = db.lock().await;
-    db.add_clip(&request.content, &request.clip_type).await.map_err(|_| warp::reject::reject())?;
+async fn add_clip(
+    request: AddClipRequest,
+    db: Arc<Mutex<Database>>,
+    metrics: Arc<Metrics>,
+    clipboard: Arc<Mutex<ClipboardManager>>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    {
+        let mut db = db.lock().await;
+        run(db.add_clip(&request.content, &request.clip_type))?;
+    }
+    metrics.record_clip_added();
+
+    // The clip is stored either way; a machine without a clipboard only
+    // gets a warning back
+    if request.set_clipboard {
+        if let Err(e) = clipboard.lock().await.set_text(&request.content) {
+            log::warn!("Failed to set the clipboard for an added clip: {}", e);
+            return Ok(warp::reply::json(&serde_json::json!({
+                "status": "success",
+                "warning": format!("Clip stored, but the clipboard could not be set: {}", e),
+            })));
+        }
+    }
     Ok(warp::reply::json(&serde_json::json!({"status": "success"})))
 }
 
 async fn delete_clip(clip_id: String, db: Arc<Mutex<Database>>) -> Result<impl warp::Reply, warp::Rejection> {
     let mut db = db.lock().await;
-    // Note: We'd need to implement delete_clip in the database module
-    // For now, just return success
+    if !run(db.delete_clip(&clip_id))? {
+        return Err(warp::reject::not_found());
+    }
     Ok(warp::reply::json(&serde_json::json!({"status": "success"})))
 }
 
+async fn set_clip_pinned(clip_id: String, pinned: bool, db: Arc<Mutex<Database>>) -> Result<impl warp::Reply, warp::Rejection> {
+    let mut db = db.lock().await;
+    if !run(db.set_pinned(&clip_id, pinned))? {
+        return Err(warp::reject::not_found());
+    }
+
+    let clip = run(db.get_clip_by_id(&clip_id))?.ok_or_else(warp::reject::not_found)?;
+    Ok(warp::reply::json(&to_web_clip(&db, clip)))
+}
+
+async fn set_clip_expiry(clip_id: String, request: ExpireRequest, db: Arc<Mutex<Database>>) -> Result<warp::reply::Response, warp::Rejection> {
+    use warp::http::StatusCode;
+
+    let expires_at = match request
+        .expire_in
+        .as_deref()
+        .map(|text| duration::parse_duration(text).and_then(duration::from_now))
+        .transpose()
+    {
+        Ok(expires_at) => expires_at,
+        Err(e) => {
+            let body = serde_json::json!({"error": e.to_string()});
+            return Ok(warp::reply::Reply::into_response(warp::reply::with_status(
+                warp::reply::json(&body),
+                StatusCode::BAD_REQUEST,
+            )));
+        }
+    };
+
+    let mut db = db.lock().await;
+    if !run(db.set_expiry(&clip_id, expires_at))? {
+        return Err(warp::reject::not_found());
+    }
+
+    let clip = run(db.get_clip_by_id(&clip_id))?.ok_or_else(warp::reject::not_found)?;
+    Ok(warp::reply::Reply::into_response(warp::reply::json(&to_web_clip(&db, clip))))
+}
+
+async fn update_clip(clip_id: String, request: UpdateClipRequest, db: Arc<Mutex<Database>>) -> Result<warp::reply::Response, warp::Rejection> {
+    use warp::http::StatusCode;
+
+    let mut db = db.lock().await;
+    let updated = match block_on(db.edit_clip(&clip_id, request.content.as_deref(), request.tags.as_deref())) {
+        Ok(updated) => updated,
+        Err(e) => match e.downcast_ref::<TagNameError>() {
+            Some(e) => {
+                let body = serde_json::json!({"error": e.to_string()});
+                return Ok(warp::reply::Reply::into_response(warp::reply::with_status(
+                    warp::reply::json(&body),
+                    StatusCode::BAD_REQUEST,
+                )));
+            }
+            None => return Err(warp::reject::reject()),
+        },
+    };
+    if !updated {
+        return Err(warp::reject::not_found());
+    }
+
+    let clip = run(db.get_clip_by_id(&clip_id))?.ok_or_else(warp::reject::not_found)?;
+    Ok(warp::reply::Reply::into_response(warp::reply::json(&to_web_clip(&db, clip))))
+}
+
+async fn get_health(db: Arc<Mutex<Database>>) -> Result<impl warp::Reply, warp::Rejection> {
+    let db = db.lock().await;
+    let stats = run(db.get_statistics())?;
+    Ok(warp::reply::json(&HealthStatus {
+        status: "ok".to_string(),
+        clips: stats.total_clips,
+        version: env!("CARGO_PKG_VERSION").to_string(),
+    }))
+}
+
+async fn get_activity(query: ActivityQuery, timezone: Timezone, db: Arc<Mutex<Database>>) -> Result<impl warp::Reply, warp::Rejection> {
+    let db = db.lock().await;
+    let days = query.days.unwrap_or(14).min(MAX_ACTIVITY_DAYS);
+    let days = run(db.clips_per_day(days, &timezone))?;
+    let days: Vec<DayActivity> = days
+        .into_iter()
+        .map(|(date, clips)| DayActivity {
+            date: date.format("%Y-%m-%d").to_string(),
+            clips,
+        })
+        .collect();
+    Ok(warp::reply::json(&days))
+}
+
+async fn get_hourly(timezone: Timezone, db: Arc<Mutex<Database>>) -> Result<impl warp::Reply, warp::Rejection> {
+    let db = db.lock().await;
+    let hours: Vec<HourActivity> = run(db.clips_per_hour(&timezone))?
+        .into_iter()
+        .enumerate()
+        .map(|(hour, clips)| HourActivity { hour, clips })
+        .collect();
+    Ok(warp::reply::json(&hours))
+}
+
+async fn list_plugins(plugins: Arc<PluginManager>) -> Result<impl warp::Reply, warp::Rejection> {
+    let mut list: Vec<PluginInfo> = plugins
+        .list_plugins()
+        .into_iter()
+        .map(|plugin| PluginInfo {
+            name: plugin.name.clone(),
+            enabled: plugin.enabled,
+        })
+        .collect();
+    list.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(warp::reply::json(&list))
+}
+
+async fn run_plugin(
+    name: String,
+    request: RunPluginRequest,
+    plugins: Arc<PluginManager>,
+    metrics: Arc<Metrics>,
+) -> Result<warp::reply::Response, warp::Rejection> {
+    use warp::http::StatusCode;
+
+    // An explicit 404: a not_found rejection would lose out to the
+    // method-mismatch rejections of the other /api routes
+    let (body, status) = if !plugins.list_plugins().iter().any(|plugin| plugin.name == name) {
+        (serde_json::json!({"error": format!("Plugin not found: {}", name)}), StatusCode::NOT_FOUND)
+    } else {
+        let result = plugins.execute_plugin(&name, &request.input).await;
+        metrics.record_plugin_run(result.is_ok());
+        match result {
+            Ok(output) => (serde_json::json!({"output": output}), StatusCode::OK),
+            Err(e) => (serde_json::json!({"error": e.to_string()}), StatusCode::UNPROCESSABLE_ENTITY),
+        }
+    };
+    Ok(warp::reply::Reply::into_response(warp::reply::with_status(warp::reply::json(&body), status)))
+}
+
+async fn get_metrics(db: Arc<Mutex<Database>>, metrics: Arc<Metrics>) -> Result<impl warp::Reply, warp::Rejection> {
+    let db = db.lock().await;
+    let (total_clips, _) = run(db.clip_counts())?;
+    let size = run(db.size_bytes())?;
+    Ok(warp::reply::with_header(
+        metrics.render(total_clips, size),
+        "content-type",
+        "text/plain; version=0.0.4",
+    ))
+}
+
 async fn get_stats(db: Arc<Mutex<Database>>) -> Result<impl warp::Reply, warp::Rejection> {
     let db = db.lock().await;
-    let stats = db.get_statistics().await.map_err(|_| warp::reject::reject())?;
+    let stats = run(db.get_statistics())?;
     Ok(warp::reply::json(&stats))
-}
\ No newline at end of file
+}
+
+/// Query a running server's `/health` endpoint. `url` is the server's base
+/// URL, e.g. `http://localhost:8080`.
+pub async fn check_health(url: &str) -> Result<HealthStatus> {
+    let uri: hyper::Uri = format!("{}/health", url.trim_end_matches('/')).parse()?;
+    let response = hyper::Client::new().get(uri).await?;
+    if !response.status().is_success() {
+        anyhow::bail!("Server responded with {}", response.status());
+    }
+
+    let body = hyper::body::to_bytes(response.into_body()).await?;
+    Ok(serde_json::from_slice(&body)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use warp::http::StatusCode;
+
+    async fn test_server() -> WebServer {
+        let db = Arc::new(Mutex::new(Database::open(":memory:").await.unwrap()));
+        let plugins = Arc::new(PluginManager::new(1));
+        let addr = "127.0.0.1:8080".parse().unwrap();
+        WebServer::new(db, plugins, addr, Timezone::Local).with_token("secret".to_string())
+    }
+
+    #[tokio::test]
+    async fn api_requires_the_token() {
+        let server = test_server().await;
+        let routes = server.routes();
+
+        let response = warp::test::request().path("/api/clips").reply(&routes).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let response = warp::test::request()
+            .path("/api/clips")
+            .header("authorization", "Bearer wrong")
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let response = warp::test::request()
+            .path("/api/clips")
+            .header("authorization", "Bearer secret")
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = warp::test::request().path("/api/clips?token=secret").reply(&routes).await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn health_needs_no_token() {
+        let server = test_server().await;
+        let response = warp::test::request().path("/health").reply(&server.routes()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn health_reports_status_clip_count_and_version() {
+        let server = test_server().await;
+        server.db.lock().await.add_clip("one", "text").await.unwrap();
+        let response = warp::test::request().path("/health").reply(&server.routes()).await;
+
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!({"status": "ok", "clips": 1, "version": env!("CARGO_PKG_VERSION")})
+        );
+    }
+
+    #[tokio::test]
+    async fn health_checks_reach_a_running_server() {
+        let mut server = test_server().await;
+        server.addr = "127.0.0.1:0".parse().unwrap();
+        let (ready, bound) = tokio::sync::oneshot::channel();
+        let client = async {
+            let addr = bound.await.unwrap();
+            check_health(&server_url(addr)).await.unwrap()
+        };
+
+        tokio::select! {
+            result = server.serve(|addr| {
+                let _ = ready.send(addr);
+            }) => panic!("server stopped: {:?}", result),
+            health = client => {
+                assert!(health.is_ok());
+                assert_eq!(health.clips, 0);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn api_refuses_other_origins() {
+        let server = test_server().await;
+        server.allowed_origins.set(own_origins(server.addr)).unwrap();
+        let routes = server.routes();
+
+        let response = warp::test::request()
+            .path("/api/clips")
+            .header("authorization", "Bearer secret")
+            .header("origin", "http://evil.example")
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        let response = warp::test::request()
+            .path("/api/clips")
+            .header("authorization", "Bearer secret")
+            .header("origin", "http://localhost:8080")
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(response.headers().get("access-control-allow-origin").is_none());
+    }
+
+    #[tokio::test]
+    async fn add_clip_sets_the_clipboard_only_when_asked() {
+        let mut server = test_server().await;
+        let clipboard = Arc::new(std::sync::Mutex::new(None));
+        server.clipboard = Arc::new(Mutex::new(ClipboardManager::in_memory(Arc::clone(&clipboard))));
+        let routes = server.routes();
+
+        let response = warp::test::request()
+            .method("POST")
+            .path("/api/clips")
+            .json(&serde_json::json!({"content": "sneaky", "clip_type": "text", "set_clipboard": true}))
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(*clipboard.lock().unwrap(), None);
+
+        let response = warp::test::request()
+            .method("POST")
+            .path("/api/clips")
+            .header("authorization", "Bearer secret")
+            .json(&serde_json::json!({"content": "stored", "clip_type": "text"}))
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(*clipboard.lock().unwrap(), None);
+
+        let response = warp::test::request()
+            .method("POST")
+            .path("/api/clips")
+            .header("authorization", "Bearer secret")
+            .json(&serde_json::json!({"content": "copied", "clip_type": "text", "set_clipboard": true}))
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body, serde_json::json!({"status": "success"}));
+        assert_eq!(clipboard.lock().unwrap().as_deref(), Some("copied"));
+
+        let db = server.db.lock().await;
+        for content in ["stored", "copied"] {
+            assert!(db.find_clip_by_content(content).await.unwrap().is_some());
+        }
+        assert!(db.find_clip_by_content("sneaky").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn plugins_need_the_token_and_time_out() {
+        let db = Arc::new(Mutex::new(Database::open(":memory:").await.unwrap()));
+        let mut plugins = PluginManager::new(1);
+        plugins.set_timeout(std::time::Duration::from_millis(200));
+        plugins
+            .add_plugin(crate::plugins::PluginConfig::builder("sleepy").command("sleep").arg("10").build().unwrap())
+            .unwrap();
+        let addr = "127.0.0.1:8080".parse().unwrap();
+        let server = WebServer::new(db, Arc::new(plugins), addr, Timezone::Local).with_token("secret".to_string());
+        let routes = server.routes();
+
+        let response = warp::test::request()
+            .method("POST")
+            .path("/api/plugins/sleepy")
+            .json(&serde_json::json!({"input": ""}))
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let response = warp::test::request()
+            .method("POST")
+            .path("/api/plugins/sleepy")
+            .header("authorization", "Bearer secret")
+            .json(&serde_json::json!({"input": ""}))
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert!(body["error"].as_str().unwrap().contains("timed out"));
+    }
+
+    #[tokio::test]
+    async fn plugins_are_listed_and_run_on_the_posted_input() {
+        use crate::plugins::PluginConfig;
+
+        let db = Arc::new(Mutex::new(Database::open(":memory:").await.unwrap()));
+        let mut plugins = PluginManager::new(1);
+        plugins
+            .add_plugin(PluginConfig::builder("upper").command("tr").arg("a-z").arg("A-Z").build().unwrap())
+            .unwrap();
+        plugins
+            .add_plugin(PluginConfig::builder("off").command("cat").enabled(false).build().unwrap())
+            .unwrap();
+        let addr = "127.0.0.1:8080".parse().unwrap();
+        let server = WebServer::new(db, Arc::new(plugins), addr, Timezone::Local).with_token("secret".to_string());
+        let routes = server.routes();
+
+        let response = warp::test::request()
+            .path("/api/plugins")
+            .header("authorization", "Bearer secret")
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!([{"name": "off", "enabled": false}, {"name": "upper", "enabled": true}])
+        );
+
+        let response = warp::test::request()
+            .method("POST")
+            .path("/api/plugins/upper")
+            .header("authorization", "Bearer secret")
+            .json(&serde_json::json!({"input": "shout this"}))
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body, serde_json::json!({"output": "SHOUT THIS"}));
+
+        let response = warp::test::request()
+            .method("POST")
+            .path("/api/plugins/missing")
+            .header("authorization", "Bearer secret")
+            .json(&serde_json::json!({"input": ""}))
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn events_socket_needs_the_token_and_own_origin() {
+        let server = test_server().await;
+        server.allowed_origins.set(own_origins(server.addr)).unwrap();
+        let routes = server.routes();
+
+        assert!(warp::test::ws().path("/api/events").handshake(routes.clone()).await.is_err());
+        assert!(warp::test::ws()
+            .path("/api/events?token=secret")
+            .header("origin", "http://evil.example")
+            .handshake(routes.clone())
+            .await
+            .is_err());
+
+        let mut client = warp::test::ws()
+            .path("/api/events?token=secret")
+            .header("origin", "http://127.0.0.1:8080")
+            .handshake(routes)
+            .await
+            .unwrap();
+        // The feed starts after whatever was newest once the socket was set
+        // up, which may still be in progress; add clips until one comes through
+        let mut added = Vec::new();
+        let message = loop {
+            assert!(added.len() < 10, "no event arrived");
+            let content = format!("clip {}", added.len());
+            added.push(server.db.lock().await.add_clip(&content, "text").await.unwrap());
+            if let Ok(message) = tokio::time::timeout(std::time::Duration::from_secs(1), client.recv()).await {
+                break message.unwrap();
+            }
+        };
+        let record: crate::events::ClipEventRecord = serde_json::from_str(message.to_str().unwrap()).unwrap();
+        assert!(matches!(&record.event, crate::events::ClipEvent::Added { clip_id } if added.contains(clip_id)));
+    }
+
+    /// Wait up to 5s for the number of handles on `db` to satisfy `done`.
+    async fn wait_for_handles(db: &Arc<Mutex<Database>>, done: impl Fn(usize) -> bool, what: &str) {
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+        while !done(Arc::strong_count(db)) {
+            assert!(std::time::Instant::now() < deadline, "{}", what);
+            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn events_socket_stops_when_the_client_leaves() {
+        let server = test_server().await;
+        let routes = server.routes();
+        let idle = Arc::strong_count(&server.db);
+
+        let client = warp::test::ws()
+            .path("/api/events?token=secret")
+            .handshake(routes)
+            .await
+            .unwrap();
+        // The streaming task holds a handle on the database until it ends
+        wait_for_handles(&server.db, |count| count > idle, "event stream never started").await;
+        drop(client);
+        wait_for_handles(&server.db, |count| count <= idle, "event stream outlived its client").await;
+    }
+
+    #[tokio::test]
+    async fn pin_and_expire_need_the_token_and_reject_huge_durations() {
+        let server = test_server().await;
+        let id = server.db.lock().await.add_clip("hello", "text").await.unwrap();
+        let routes = server.routes();
+
+        for action in ["pin", "unpin"] {
+            let response = warp::test::request()
+                .method("POST")
+                .path(&format!("/api/clips/{}/{}", id, action))
+                .reply(&routes)
+                .await;
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        }
+        let response = warp::test::request()
+            .method("POST")
+            .path(&format!("/api/clips/{}/expire", id))
+            .json(&serde_json::json!({"expire_in": "1h"}))
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert!(server.db.lock().await.get_clip_by_id(&id).await.unwrap().unwrap().expires_at.is_none());
+
+        let response = warp::test::request()
+            .method("POST")
+            .path(&format!("/api/clips/{}/expire", id))
+            .header("authorization", "Bearer secret")
+            .json(&serde_json::json!({"expire_in": "100000000d"}))
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let response = warp::test::request()
+            .method("POST")
+            .path(&format!("/api/clips/{}/expire", id))
+            .header("authorization", "Bearer secret")
+            .json(&serde_json::json!({"expire_in": "1h"}))
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let clip: WebClip = serde_json::from_slice(response.body()).unwrap();
+        assert!(clip.expires_at.is_some());
+    }
+
+    #[tokio::test]
+    async fn metrics_count_what_the_daemon_records() {
+        let metrics = Arc::new(Metrics::default());
+        let server = test_server().await.with_metrics(Arc::clone(&metrics));
+        let routes = server.routes();
+        // As the daemon does for each capture
+        metrics.record_clip_added();
+
+        let response = warp::test::request().path("/metrics").reply(&routes).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["content-type"], "text/plain; version=0.0.4");
+        let body = String::from_utf8(response.body().to_vec()).unwrap();
+        assert!(body.contains("# TYPE clipq_clips_added_total counter\nclipq_clips_added_total 1\n"), "{}", body);
+        assert!(body.contains("\nclipq_clips 0\n"), "{}", body);
+    }
+
+    #[tokio::test]
+    async fn bind_addresses_must_name_an_interface_and_still_need_the_token() {
+        for wildcard in ["0.0.0.0", "::", "[::]"] {
+            let error = parse_bind_addr(wildcard, 8080).unwrap_err();
+            assert!(error.to_string().starts_with("Refusing to bind"), "{}", error);
+        }
+        assert!(parse_bind_addr("localhost", 8080).is_err());
+        assert_eq!(parse_bind_addr("127.0.0.1", 8080).unwrap(), "127.0.0.1:8080".parse().unwrap());
+        let addr = parse_bind_addr("[::1]", 8080).unwrap();
+        assert_eq!(addr, "[::1]:8080".parse().unwrap());
+
+        let db = Arc::new(Mutex::new(Database::open(":memory:").await.unwrap()));
+        let server = WebServer::new(db, Arc::new(PluginManager::new(1)), addr, Timezone::Local).with_token("secret".to_string());
+        let routes = server.routes();
+        let response = warp::test::request().path("/api/clips").reply(&routes).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let response = warp::test::request()
+            .path("/api/clips")
+            .header("authorization", "Bearer secret")
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn clips_carry_their_mime_type() {
+        let server = test_server().await;
+        let id = server.db.lock().await.add_clip_with_mime("<b>bold</b>", "text", "text/html").await.unwrap();
+        let routes = server.routes();
+
+        let response = warp::test::request()
+            .path(&format!("/api/clips/{}", id))
+            .header("authorization", "Bearer secret")
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body["mime"], "text/html");
+    }
+
+    #[tokio::test]
+    async fn previews_cut_long_clips_and_flag_them() {
+        let server = test_server().await;
+        {
+            let mut db = server.db.lock().await;
+            db.add_clip("short", "text").await.unwrap();
+            db.add_clip("ünïcödé is longer", "text").await.unwrap();
+        }
+        let routes = server.routes();
+
+        let clips = |path: &'static str| {
+            let routes = routes.clone();
+            async move {
+                let response = warp::test::request()
+                    .path(path)
+                    .header("authorization", "Bearer secret")
+                    .reply(&routes)
+                    .await;
+                assert_eq!(response.status(), StatusCode::OK);
+                let body: Vec<WebClip> = serde_json::from_slice(response.body()).unwrap();
+                let mut clips: Vec<(String, bool)> = body.into_iter().map(|clip| (clip.content, clip.truncated)).collect();
+                clips.sort();
+                clips
+            }
+        };
+        assert_eq!(
+            clips("/api/clips?preview=7").await,
+            vec![("short".to_string(), false), ("ünïcödé".to_string(), true)]
+        );
+        assert_eq!(
+            clips("/api/clips").await,
+            vec![("short".to_string(), false), ("ünïcödé is longer".to_string(), false)]
+        );
+    }
+
+    #[tokio::test]
+    async fn port_zero_picks_a_free_port_for_the_printed_url() {
+        let mut server = test_server().await;
+        server.addr = parse_bind_addr("127.0.0.1", 0).unwrap();
+        let (ready, bound) = tokio::sync::oneshot::channel();
+        // The URL is checked while the server is still up
+        let client = async {
+            let addr = bound.await.unwrap();
+            let health = check_health(&server_url(addr)).await.unwrap();
+            (addr, health)
+        };
+
+        tokio::select! {
+            result = server.serve(|addr| {
+                let _ = ready.send(addr);
+            }) => panic!("server stopped: {:?}", result),
+            (addr, health) = client => {
+                assert_ne!(addr.port(), 0);
+                assert!(health.is_ok());
+                let url = authorized_url(addr, server.token());
+                assert_eq!(url, format!("http://127.0.0.1:{}/?token=secret", addr.port()));
+            }
+        }
+
+        let v6 = parse_bind_addr("::1", 8080).unwrap();
+        assert_eq!(server_url(v6), "http://[::1]:8080/");
+        assert_eq!(own_origins(v6), vec!["http://[::1]:8080", "http://localhost:8080"]);
+    }
+}
@@ -27,7 +27,7 @@ echo "3. Testing config command..."
 
 echo ""
 echo "4. Testing clear command..."
-./target/release/clipq clear
+./target/release/clipq clear --yes
 ./target/release/clipq list
 
 echo ""
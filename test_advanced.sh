@@ -42,7 +42,7 @@ echo "5. Testing tags..."
 echo ""
 echo "6. Testing export/import..."
 ./target/release/clipq export --format json --output test_export.json
-./target/release/clipq clear
+./target/release/clipq clear --yes
 ./target/release/clipq list
 ./target/release/clipq import --format json --input test_export.json
 ./target/release/clipq list
@@ -51,7 +51,7 @@ rm test_export.json
 echo ""
 echo "7. Testing backup/restore..."
 ./target/release/clipq backup --output test_backup.db
-./target/release/clipq clear
+./target/release/clipq clear --yes
 ./target/release/clipq list
 ./target/release/clipq restore --input test_backup.db
 ./target/release/clipq list
@@ -67,6 +67,23 @@ echo "9. Testing built-in utilities..."
 ./target/release/clipq generate-password --length 20
 ./target/release/clipq hash "Hello, World!" --algorithm sha256
 
+echo ""
+echo "Testing JSON pretty-print in place..."
+./target/release/clipq add '{"name":"clipq","tags":["a","b"]}'
+./target/release/clipq format 1 --as json
+formatted=$(./target/release/clipq show 1)
+expected='{
+  "name": "clipq",
+  "tags": [
+    "a",
+    "b"
+  ]
+}'
+if [ "$formatted" != "$expected" ]; then
+    echo "format --as json did not update the clip in place"
+    exit 1
+fi
+
 echo ""
 echo "10. Testing web interface (will start server - press Ctrl+C to stop)..."
 echo "Starting web server on http://localhost:8080"